@@ -6,8 +6,10 @@ use alloy::primitives::{Address, U256};
 use fly::arb::{
     cycle::Cycle,
     pool::{Pool, PoolId},
+    swap::DEFAULT_FEE,
     token::TokenId,
 };
+use fly::utils::constants::BASE_CHAIN_ID;
 
 /// Generate a new random token address
 fn generate_random_address() -> String {
@@ -23,7 +25,7 @@ fn generate_benchmark_pools(pool_count: usize, token_count: usize) -> Vec<Pool>
 
     // Create token IDs
     let tokens: Vec<TokenId> = (0..token_count)
-        .map(|i| TokenId::try_from(generate_random_address()).unwrap())
+        .map(|i| TokenId::parse(BASE_CHAIN_ID, &generate_random_address()).unwrap())
         .collect();
 
     // Generate random pools
@@ -42,11 +44,12 @@ fn generate_benchmark_pools(pool_count: usize, token_count: usize) -> Vec<Pool>
         let reserve1 = U256::from(rng.random_range(1000..1_000_000));
 
         let pool = Pool::new(
-            PoolId::try_from(generate_random_address()).unwrap(),
+            PoolId::parse(BASE_CHAIN_ID, &generate_random_address()).unwrap(),
             tokens[idx1].clone(),
             tokens[idx2].clone(),
             Some(reserve0),
             Some(reserve1),
+            DEFAULT_FEE,
         );
 
         pools.push(pool);
@@ -0,0 +1,9 @@
+-- Your SQL goes here
+-- Precomputed cycles. Each cycle is an ordered list of pairs and swap directions:
+-- zero_for_one[i] is true when token0 of pair_ids[i] is swapped for token1.
+CREATE TABLE cycles (
+    id SERIAL PRIMARY KEY,
+    pair_ids INTEGER[] NOT NULL,
+    zero_for_one BOOLEAN[] NOT NULL,
+    CHECK (cardinality(pair_ids) = cardinality(zero_for_one))
+);
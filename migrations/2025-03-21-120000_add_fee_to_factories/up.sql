@@ -0,0 +1,3 @@
+-- Your SQL goes here
+-- Swap fee in basis points (30 is 0.3%). NULL until detected by `sync::fees`.
+ALTER TABLE factories ADD COLUMN fee INTEGER CHECK (fee >= 0 AND fee < 10000);
@@ -0,0 +1,3 @@
+-- Your SQL goes here
+-- Solidly (Aerodrome, Velodrome) stable pools use the `x^3 * y + y^3 * x` curve instead of `x * y`
+ALTER TABLE pairs ADD COLUMN stable BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,12 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE factories DROP CONSTRAINT factories_chain_id_address_key;
+ALTER TABLE tokens DROP CONSTRAINT tokens_chain_id_address_key;
+ALTER TABLE pairs DROP CONSTRAINT pairs_chain_id_address_key;
+
+ALTER TABLE factories ADD CONSTRAINT factories_address_key UNIQUE (address);
+ALTER TABLE tokens ADD CONSTRAINT tokens_address_key UNIQUE (address);
+ALTER TABLE pairs ADD CONSTRAINT pairs_address_key UNIQUE (address);
+
+ALTER TABLE factories DROP COLUMN chain_id;
+ALTER TABLE tokens DROP COLUMN chain_id;
+ALTER TABLE pairs DROP COLUMN chain_id;
@@ -0,0 +1,13 @@
+-- Your SQL goes here
+-- Addresses are only unique per chain. Everything synced so far is on Base (8453).
+ALTER TABLE factories ADD COLUMN chain_id BIGINT NOT NULL DEFAULT 8453;
+ALTER TABLE tokens ADD COLUMN chain_id BIGINT NOT NULL DEFAULT 8453;
+ALTER TABLE pairs ADD COLUMN chain_id BIGINT NOT NULL DEFAULT 8453;
+
+ALTER TABLE factories DROP CONSTRAINT factories_address_key;
+ALTER TABLE tokens DROP CONSTRAINT tokens_address_key;
+ALTER TABLE pairs DROP CONSTRAINT pairs_address_key;
+
+ALTER TABLE factories ADD CONSTRAINT factories_chain_id_address_key UNIQUE (chain_id, address);
+ALTER TABLE tokens ADD CONSTRAINT tokens_chain_id_address_key UNIQUE (chain_id, address);
+ALTER TABLE pairs ADD CONSTRAINT pairs_chain_id_address_key UNIQUE (chain_id, address);
@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE pairs DROP COLUMN reserves_log_index;
+ALTER TABLE pairs DROP COLUMN reserves_block_number;
@@ -0,0 +1,5 @@
+-- Your SQL goes here
+-- Position of the reserves in the chain: the block number and log index of the `Sync` event that set them.
+-- Reserves read with `getReserves` are the state at the end of the block and get the highest log index.
+ALTER TABLE pairs ADD COLUMN reserves_block_number BIGINT;
+ALTER TABLE pairs ADD COLUMN reserves_log_index INTEGER;
@@ -0,0 +1,9 @@
+-- Your SQL goes here
+-- Last block a log backfill has processed, so it can resume from there. `name` identifies the
+-- backfill, e.g. `sync_events`.
+CREATE TABLE checkpoints (
+    chain_id BIGINT NOT NULL,
+    name VARCHAR NOT NULL,
+    block_number BIGINT NOT NULL,
+    PRIMARY KEY (chain_id, name)
+);
@@ -0,0 +1,4 @@
+-- Your SQL goes here
+-- Block the factory contract was deployed in, where a `PairCreated` backfill starts. NULL until
+-- looked up by the backfill.
+ALTER TABLE factories ADD COLUMN deployment_block BIGINT;
@@ -0,0 +1,3 @@
+-- Your SQL goes here
+-- Set by `sync::pair_tokens` when the name, symbol or decimals of a token cannot be read or decoded.
+ALTER TABLE tokens ADD COLUMN metadata_invalid BOOLEAN NOT NULL DEFAULT FALSE;
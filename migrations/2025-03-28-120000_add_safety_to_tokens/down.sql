@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE tokens DROP COLUMN transfer_tax;
+ALTER TABLE tokens DROP COLUMN safety;
+DROP TYPE token_safety;
@@ -0,0 +1,7 @@
+-- Your SQL goes here
+-- Result of simulating a buy, a transfer and a sell of the token, set by `sync::token_safety`.
+-- NULL means not yet checked.
+CREATE TYPE token_safety AS ENUM ('SAFE', 'TRANSFER_TAX', 'REBASING', 'HONEYPOT');
+ALTER TABLE tokens ADD COLUMN safety token_safety;
+-- Highest tax of the simulated transfers in basis points
+ALTER TABLE tokens ADD COLUMN transfer_tax INTEGER CHECK (transfer_tax >= 0 AND transfer_tax <= 10000);
@@ -0,0 +1,3 @@
+-- Your SQL goes here
+-- Name of the `sync::exchange_rates` source that produced `exchange_rate`, e.g. `moralis` or `pools`.
+ALTER TABLE tokens ADD COLUMN exchange_rate_source VARCHAR;
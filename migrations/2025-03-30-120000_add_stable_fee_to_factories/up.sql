@@ -0,0 +1,4 @@
+-- Your SQL goes here
+-- Swap fee of the stable pools of a Solidly factory in basis points, `fee` is the fee of its
+-- volatile pools. NULL until detected by `sync::fees`.
+ALTER TABLE factories ADD COLUMN stable_fee INTEGER CHECK (stable_fee >= 0 AND stable_fee < 10000);
@@ -0,0 +1,3 @@
+-- Your SQL goes here
+-- Set by `sync::pair_tokens` when the `token0` or `token1` call of a pair reverts, so it is not read again.
+ALTER TABLE pairs ADD COLUMN tokens_unreadable BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,8 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE tokens DROP COLUMN safety_reason;
+-- Enum values cannot be dropped, the type is created again without `UNKNOWN`
+UPDATE tokens SET safety = NULL WHERE safety = 'UNKNOWN';
+ALTER TYPE token_safety RENAME TO token_safety_old;
+CREATE TYPE token_safety AS ENUM ('SAFE', 'TRANSFER_TAX', 'REBASING', 'HONEYPOT');
+ALTER TABLE tokens ALTER COLUMN safety TYPE token_safety USING safety::TEXT::token_safety;
+DROP TYPE token_safety_old;
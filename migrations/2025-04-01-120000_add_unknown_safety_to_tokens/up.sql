@@ -0,0 +1,5 @@
+-- Your SQL goes here
+-- Tokens `sync::token_safety` could not simulate, e.g. their largest pair holds too little of them.
+ALTER TYPE token_safety ADD VALUE 'UNKNOWN';
+-- Why the safety of the token is `UNKNOWN`
+ALTER TABLE tokens ADD COLUMN safety_reason VARCHAR;
@@ -0,0 +1,7 @@
+-- Your SQL goes here
+-- Fingerprint of the pair set, unsafe tokens and cycle settings the saved cycles were found for, so
+-- `sync::cycles` only regenerates them when one of these changes. Holds at most one row.
+CREATE TABLE cycles_fingerprint (
+    id BOOLEAN PRIMARY KEY DEFAULT TRUE CHECK (id),
+    fingerprint VARCHAR NOT NULL
+);
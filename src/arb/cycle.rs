@@ -13,7 +13,16 @@ use itertools::Itertools;
 use log::error;
 
 use super::cycle_quote::CycleQuote;
-use super::swap::Swap;
+use super::portfolio::Portfolio;
+use super::swap::{Swap, FEE_DENOMINATOR};
+
+/// Reserves are scaled up to this many bits before folding the cycle into a virtual pool to
+/// reduce rounding errors. Uniswap V2 reserves are `uint112`, so products of two scaled reserves
+/// (including the fee factor) still fit into U256.
+const VIRTUAL_POOL_BITS: usize = 120;
+
+/// The binary search derivative increment is the first swap's `reserve_in` shifted right by this
+const DELTA_RESERVE_SHIFT: usize = 20;
 
 /// A cycle of swaps that starts and ends at the same token
 #[derive(Clone)]
@@ -21,6 +30,10 @@ pub struct Cycle {
     /// Sequence of swap sides forming the cycle
     pub swaps: Vec<Swap>,
 
+    /// Cached sum of swap log rates. `None` if any of the swaps has no reserves.
+    /// Adjusted incrementally by `update_swap` rather than recomputed from scratch.
+    log_rate: Option<i64>,
+
     /// Cached best quote for this cycle
     best_quote: RefCell<Option<CycleQuote>>,
 }
@@ -90,13 +103,53 @@ impl Cycle {
     pub fn new(mut swaps: Vec<Swap>) -> Result<Self> {
         Self::validate_swaps(&swaps)?;
         Self::normalize_swaps(&mut swaps);
+        let log_rate = Self::calculated_log_rate(&swaps);
         let cycle = Self {
             swaps,
+            log_rate,
             best_quote: RefCell::new(None),
         };
         Ok(cycle)
     }
 
+    /// Replace the matching swap (same pool and direction) with the updated one.
+    /// The cached log rate is adjusted by the difference between the new and the old swap log
+    /// rates and the cached best quote is dropped.
+    ///
+    /// Returns `false` if the cycle does not contain the swap.
+    pub fn update_swap(&mut self, swap: &Swap) -> bool {
+        let Some(position) = self.swaps.iter().position(|s| s == swap) else {
+            return false;
+        };
+
+        let old_swap = &self.swaps[position];
+        self.log_rate = match self.log_rate {
+            Some(log_rate) if old_swap.has_reserves() && swap.has_reserves() => {
+                Some(log_rate + swap.log_rate() - old_swap.log_rate())
+            }
+            _ => None,
+        };
+
+        self.swaps[position] = swap.clone();
+
+        // The swap may have just gotten its reserves, so the sum has to be recalculated
+        if self.log_rate.is_none() {
+            self.log_rate = Self::calculated_log_rate(&self.swaps);
+        }
+
+        *self.best_quote.borrow_mut() = None;
+        true
+    }
+
+    /// Sum of swap log rates or `None` if any of the swaps has no reserves
+    fn calculated_log_rate(swaps: &[Swap]) -> Option<i64> {
+        if swaps.iter().all(Swap::has_reserves) {
+            Some(swaps.iter().map(Swap::log_rate).sum())
+        } else {
+            None
+        }
+    }
+
     /// Normalizes the swaps by rotating them so the smallest swap is first
     /// This is used for equality comparison and hashing
     fn normalize_swaps(swaps: &mut [Swap]) {
@@ -135,16 +188,17 @@ impl Cycle {
     }
 
     /// The swap rate of the cycle (a product of all swap rates in the cycle)
-    fn log_rate(&self) -> i64 {
+    pub fn log_rate(&self) -> i64 {
         assert!(
             self.has_all_reserves(),
             "All swaps must have reserves to calculate log rate"
         );
-        self.swaps.iter().map(super::swap::Swap::log_rate).sum()
+        self.log_rate.unwrap()
     }
 
     /// The optimal `amount_in` to get the maximum `amount_out`
-    /// This is using binary search to find the maximum `amount_out`
+    /// Cycles of constant product swaps are solved analytically, other cycles fall back to
+    /// binary search.
     /// Memoized for efficiency since this is an expensive calculation
     pub fn best_quote(&self) -> Result<CycleQuote, Error> {
         // Check if we already have a cached result
@@ -152,10 +206,148 @@ impl Cycle {
             return Ok(cached.clone());
         }
 
+        let best_quote = Self::best_quote_for(&self.swaps, None)?;
+
+        // Cache the result
+        *self.best_quote.borrow_mut() = Some(best_quote);
+
+        Ok(self.best_quote.borrow().as_ref().unwrap().clone())
+    }
+
+    /// The best quote we can fund from the portfolio.
+    ///
+    /// The cycle is rotated to start at the first token we hold (in the cycle order) and the
+    /// optimal `amount_in` is clamped to the balance of that token. Profit is concave in
+    /// `amount_in`, so the clamped amount is the best one we can afford.
+    /// Not memoized since the portfolio changes between calls.
+    ///
+    /// Returns `None` if we do not hold any of the cycle tokens.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the optimization fails to converge
+    pub fn best_quote_with(&self, portfolio: &Portfolio) -> Result<Option<CycleQuote>, Error> {
+        let Some((start, balance)) = self.swaps.iter().enumerate().find_map(|(index, swap)| {
+            portfolio
+                .balance(&swap.token_in)
+                .filter(|balance| !balance.is_zero())
+                .map(|balance| (index, balance))
+        }) else {
+            return Ok(None);
+        };
+
+        let mut swaps = self.swaps.clone();
+        swaps.rotate_left(start);
+
+        Self::best_quote_for(&swaps, Some(balance)).map(Some)
+    }
+
+    /// The best quote for the swaps in the given order, with `amount_in` of at most
+    /// `max_amount_in`. Cycles of constant product swaps are solved analytically, other cycles
+    /// and cycles the closed form cannot fold fall back to binary search.
+    fn best_quote_for(swaps: &[Swap], max_amount_in: Option<U256>) -> Result<CycleQuote, Error> {
+        if swaps.iter().all(Swap::is_constant_product) {
+            if let Some(quote) = Self::closed_form_best_quote(swaps, max_amount_in) {
+                return Ok(quote);
+            }
+        }
+        Self::binary_search_best_quote(swaps, max_amount_in)
+    }
+
+    /// The best quote for a cycle of constant product swaps.
+    ///
+    /// The cycle is folded into a single virtual pool `(Ea, Eb)` which has the optimal amount in
+    /// of `(sqrt(Ea * Eb * r) - Ea) / r` where `r` is the fee factor. The result is checked
+    /// against the exact `CycleQuote`: if it is not profitable (e.g. due to rounding) we get a
+    /// zero quote. `None` if the cycle cannot be folded, see `optimal_amount_in`.
+    fn closed_form_best_quote(swaps: &[Swap], max_amount_in: Option<U256>) -> Option<CycleQuote> {
+        let mut amount_in = Self::optimal_amount_in(swaps)?;
+        if let Some(max_amount_in) = max_amount_in {
+            amount_in = amount_in.min(max_amount_in);
+        }
+        if amount_in.is_zero() {
+            return Some(CycleQuote::from_swaps(swaps, U256::ZERO));
+        }
+
+        let quote = CycleQuote::from_swaps(swaps, amount_in);
+        if quote.is_profitable() {
+            Some(quote)
+        } else {
+            Some(CycleQuote::from_swaps(swaps, U256::ZERO))
+        }
+    }
+
+    /// Optimal amount in for a cycle of constant product swaps.
+    ///
+    /// Swap fees are moved into the reserves (`reserve_in / r`) so each swap becomes a fee-less
+    /// `x * y = k` pool. Two fee-less pools `(Ea, Eb)` and `(Rin, Rout)` fold into
+    /// `(Ea * Rin / (Rin + Eb), Eb * Rout / (Rin + Eb))`, and the optimum of the resulting virtual
+    /// pool is `sqrt(Ea * Eb) - Ea`.
+    ///
+    /// Reserves are scaled to `VIRTUAL_POOL_BITS` for precision, wider ones such as the `uint256`
+    /// reserves of Solidly pools are scaled down to it. `None` if the scaled reserves of a swap
+    /// are zero.
+    fn optimal_amount_in(swaps: &[Swap]) -> Option<U256> {
+        let max_reserve = swaps
+            .iter()
+            .flat_map(|swap| [swap.reserve_in(), swap.reserve_out()])
+            .max()
+            .unwrap_or_default();
+        let bit_len = max_reserve.bit_len();
+        let scale = |reserve: U256| {
+            if bit_len > VIRTUAL_POOL_BITS {
+                reserve >> (bit_len - VIRTUAL_POOL_BITS)
+            } else {
+                reserve << (VIRTUAL_POOL_BITS - bit_len)
+            }
+        };
+
+        let fee_denominator = U256::from(FEE_DENOMINATOR);
+
+        let mut virtual_pool: Option<(U256, U256)> = None;
+        for swap in swaps {
+            let reserve_in =
+                scale(swap.reserve_in()) * fee_denominator / U256::from(swap.fee_numerator());
+            let reserve_out = scale(swap.reserve_out());
+
+            virtual_pool = Some(match virtual_pool {
+                None => (reserve_in, reserve_out),
+                Some((ea, eb)) => {
+                    let denominator = reserve_in + eb;
+                    (
+                        (ea * reserve_in).checked_div(denominator)?,
+                        (eb * reserve_out).checked_div(denominator)?,
+                    )
+                }
+            });
+        }
+
+        let Some((ea, eb)) = virtual_pool else {
+            return Some(U256::ZERO);
+        };
+
+        // The rate of the virtual pool is below 1 - there is no profit at any amount in
+        let root = (ea * eb).root(2);
+        if root <= ea {
+            return Some(U256::ZERO);
+        }
+
+        Some(if bit_len > VIRTUAL_POOL_BITS {
+            (root - ea) << (bit_len - VIRTUAL_POOL_BITS)
+        } else {
+            (root - ea) >> (VIRTUAL_POOL_BITS - bit_len)
+        })
+    }
+
+    /// The best quote found using binary search on the profit derivative
+    fn binary_search_best_quote(
+        swaps: &[Swap],
+        max_amount_in: Option<U256>,
+    ) -> Result<CycleQuote, Error> {
         // Increment in derivative calculation. Too small of a delta can cause
-        // the binary search to take into an infinite loop (f(x+dx) - f(x) = 0)
-        // Maybe make it adjustable?
-        let delta = U256::from(100);
+        // the binary search to take into an infinite loop (f(x+dx) - f(x) = 0) or follow rounding
+        // noise with large reserves, so it grows with the reserves.
+        let delta = (swaps[0].reserve_in() >> DELTA_RESERVE_SHIFT).max(U256::from(100));
 
         // This should really be gas cost, but not worth optimizing
         let mut amount_in_left = U256::from(0);
@@ -165,9 +357,12 @@ impl Cycle {
         // first swap's reserve0. This is arbitrary, but probably still higher than any realistic
         // amount in. This results in 50% slippage at the max amount in. There has to be some
         // really crazy arbitrage to get anywhere near this.
-        let mut amount_in_right = self.swaps[0].reserve_in();
+        let mut amount_in_right = swaps[0].reserve_in();
+        if let Some(max_amount_in) = max_amount_in {
+            amount_in_right = amount_in_right.min(max_amount_in);
+        }
 
-        let mut best_quote = CycleQuote::new(self, U256::from(0));
+        let mut best_quote = CycleQuote::from_swaps(swaps, U256::from(0));
 
         let precision = U256::from(1);
 
@@ -189,9 +384,8 @@ impl Cycle {
             let amount_in = (amount_in_left + amount_in_right) / U256::from(2);
             let amount_in_delta = amount_in + delta;
 
-            let quote = self.quote(amount_in);
-            let quote_delta = self.quote(amount_in_delta);
-            // dbg!(&quote, &quote_delta);
+            let quote = CycleQuote::from_swaps(swaps, amount_in);
+            let quote_delta = CycleQuote::from_swaps(swaps, amount_in_delta);
 
             if quote_delta.profit() > quote.profit() {
                 // Rising profit curve
@@ -206,13 +400,10 @@ impl Cycle {
 
         // We are down to the `precision` from the zero - it's the zero.
         if best_quote.amount_in() == precision {
-            best_quote = CycleQuote::new(self, U256::from(0));
+            best_quote = CycleQuote::from_swaps(swaps, U256::from(0));
         }
 
-        // Cache the result
-        *self.best_quote.borrow_mut() = Some(best_quote);
-
-        Ok(self.best_quote.borrow().as_ref().unwrap().clone())
+        Ok(best_quote)
     }
 
     fn validate_swaps(swaps: &Vec<Swap>) -> Result<()> {
@@ -295,6 +486,9 @@ mod tests {
     use alloy::primitives::I256;
 
     use super::*;
+    use crate::arb::pool::Pool;
+    use crate::arb::swap::DEFAULT_FEE;
+    use crate::arb::swap_quote::SwapQuote;
     use crate::arb::test_helpers::*;
 
     #[test]
@@ -391,6 +585,45 @@ mod tests {
         assert_eq!(cycle.log_rate(), 299_725 - 478_426);
     }
 
+    #[test]
+    fn test_update_swap() {
+        let mut cycle = cycle(&[("F1", "A", "B", 100, 200), ("F2", "B", "A", 300, 100)]).unwrap();
+        assert_eq!(cycle.log_rate(), 299_725 - 478_426);
+
+        // Not part of the cycle
+        assert!(!cycle.update_swap(&swap("F3", "A", "B", 100, 300)));
+
+        assert!(cycle.update_swap(&swap("F2", "B", "A", 100, 100)));
+        assert_eq!(cycle.log_rate(), 299_725 - 1_304);
+        assert_eq!(cycle.swaps[1].reserve_in(), U256::from(100));
+    }
+
+    #[test]
+    fn test_update_swap_with_no_reserves() {
+        let mut cycle = Cycle::new(vec![
+            swap("F1", "A", "B", 100, 200),
+            bare_swap("F2", "B", "A"),
+        ])
+        .unwrap();
+        assert!(!cycle.has_all_reserves());
+
+        assert!(cycle.update_swap(&swap("F2", "B", "A", 300, 100)));
+        assert_eq!(cycle.log_rate(), 299_725 - 478_426);
+    }
+
+    #[test]
+    fn test_update_swap_resets_best_quote() {
+        let mut cycle = cycle(&[
+            ("F1", "A", "B", 1_000_000, 2_000_000),
+            ("F2", "B", "A", 3_000_000, 3_000_000),
+        ])
+        .unwrap();
+        assert!(cycle.best_quote().unwrap().is_profitable());
+
+        assert!(cycle.update_swap(&swap("F2", "B", "A", 3_000_000, 1_000_000)));
+        assert!(!cycle.best_quote().unwrap().is_profitable());
+    }
+
     #[test]
     fn test_best_quote_not_exploitable() {
         let cycle = cycle(&[
@@ -419,10 +652,10 @@ mod tests {
             "Cycle should be profitable for this test"
         );
 
-        let amount_in = 248_054;
-        let mid_amount = 396_549;
-        let amount_out = 349_323;
-        let profit = 101_269;
+        let amount_in = 247_019;
+        let mid_amount = 395_221;
+        let amount_out = 348_289;
+        let profit = 101_270;
 
         let cycle_clone = cycle_instance;
         let best_quote = cycle_clone.best_quote().unwrap();
@@ -443,6 +676,198 @@ mod tests {
         assert_eq!(best_quote.profit(), I256::from_raw(U256::from(profit)));
     }
 
+    #[test]
+    fn test_best_quote_closed_form_beats_binary_search() {
+        let cycles = [
+            cycle(&[
+                ("F1", "A", "B", 1_000_000, 2_000_000),
+                ("F2", "B", "A", 3_000_000, 3_000_000),
+            ]),
+            cycle(&[
+                ("F1", "A", "B", 100_000_000, 200_000_000),
+                ("F2", "B", "A", 200_000_000, 101_000_000),
+            ]),
+            cycle(&[
+                ("F1", "A", "B", 5_000_000, 10_000_000),
+                ("F2", "B", "C", 20_000_000, 40_000_000),
+                ("F3", "C", "A", 80_000_000, 30_000_000),
+            ]),
+        ];
+
+        for cycle in cycles {
+            let cycle = cycle.unwrap();
+            let closed_form = cycle.best_quote().unwrap();
+            let binary_search = Cycle::binary_search_best_quote(&cycle.swaps, None).unwrap();
+
+            assert!(closed_form.is_profitable());
+            assert!(closed_form.profit() >= binary_search.profit());
+        }
+    }
+
+    #[test]
+    fn test_best_quote_with_fees() {
+        let quote = |fee0, fee1| {
+            Cycle::new(vec![
+                swap_with_fee("F1", "A", "B", 1_000_000, 2_000_000, fee0),
+                swap_with_fee("F2", "B", "A", 3_000_000, 3_000_000, fee1),
+            ])
+            .unwrap()
+            .best_quote()
+            .unwrap()
+        };
+
+        let default_fees = quote(30, 30);
+        let low_fees = quote(20, 25);
+        let high_fees = quote(30, 100);
+
+        assert!(low_fees.profit() > default_fees.profit());
+        assert!(high_fees.profit() < default_fees.profit());
+        assert!(high_fees.is_profitable());
+    }
+
+    #[test]
+    fn test_best_quote_with() {
+        let cycle = cycle(&[
+            ("F1", "A", "B", 1_000_000, 2_000_000),
+            ("F2", "B", "A", 3_000_000, 3_000_000),
+        ])
+        .unwrap();
+        let best_quote = cycle.best_quote().unwrap();
+        assert_eq!(best_quote.amount_in(), U256::from(247_019));
+
+        // We hold none of the cycle tokens
+        let portfolio = Portfolio::new(HashMap::from([(token("C").id, U256::from(1_000))]));
+        assert!(cycle.best_quote_with(&portfolio).unwrap().is_none());
+
+        // A zero balance does not count
+        let portfolio = Portfolio::new(HashMap::from([(token("A").id, U256::ZERO)]));
+        assert!(cycle.best_quote_with(&portfolio).unwrap().is_none());
+
+        // Plenty of A: the optimum is not clamped
+        let portfolio = Portfolio::new(HashMap::from([(token("A").id, U256::from(1_000_000))]));
+        let quote = cycle.best_quote_with(&portfolio).unwrap().unwrap();
+        assert_eq!(quote.token(), token("A").id);
+        assert_eq!(quote.amount_in(), best_quote.amount_in());
+        assert_eq!(quote.profit(), best_quote.profit());
+
+        // Little A: the optimum is clamped to the balance
+        let portfolio = Portfolio::new(HashMap::from([(token("A").id, U256::from(10_000))]));
+        let quote = cycle.best_quote_with(&portfolio).unwrap().unwrap();
+        assert_eq!(quote.token(), token("A").id);
+        assert_eq!(quote.amount_in(), U256::from(10_000));
+        assert!(quote.is_profitable());
+
+        // Only B: the cycle is rotated to start with B
+        let portfolio = Portfolio::new(HashMap::from([(token("B").id, U256::from(10_000))]));
+        let quote = cycle.best_quote_with(&portfolio).unwrap().unwrap();
+        assert_eq!(quote.token(), token("B").id);
+        assert_eq!(quote.amount_in(), U256::from(10_000));
+        assert!(quote.is_profitable());
+    }
+
+    #[test]
+    fn test_best_quote_mixed_uniswap_v2_v3() {
+        // V2 pool sells B at 1.1 per A, the V3 pool buys it back at ~1 with two positions
+        let e18 = 1_000_000_000_000_000_000_i128;
+        let v2_pool = pool(
+            "F1",
+            "A",
+            "B",
+            1_000_000_000_000_000_000,
+            1_100_000_000_000_000_000,
+        );
+        let v3_pool = uniswap_v3_pool(
+            "F2",
+            "A",
+            "B",
+            0,
+            e18.unsigned_abs(),
+            &[(-600, e18), (120, 2 * e18), (600, -e18), (1200, -2 * e18)],
+        );
+        let cycle = Cycle::new(vec![Swap::forward(&v2_pool), Swap::reverse(&v3_pool)]).unwrap();
+        assert!(cycle.is_positive());
+
+        let best_quote = cycle.best_quote().unwrap();
+        assert!(best_quote.is_profitable());
+
+        // The quote goes through the V3 tick math
+        let quotes = best_quote.swap_quotes();
+        assert_eq!(
+            quotes[1].amount_out(),
+            SwapQuote::new(&cycle.swaps[1], quotes[0].amount_out()).amount_out()
+        );
+
+        // Moving away from the best amount in loses profit
+        for amount_in in [
+            best_quote.amount_in() * U256::from(95) / U256::from(100),
+            best_quote.amount_in() * U256::from(105) / U256::from(100),
+        ] {
+            assert!(cycle.quote(amount_in).profit() < best_quote.profit());
+        }
+    }
+
+    #[test]
+    fn test_best_quote_with_solidly_stable() {
+        // V2 pool sells B at 1.01 per A, the stable pool buys it back at ~1
+        let v2_pool = pool("F1", "A", "B", 1_000_000_000_000, 1_010_000_000_000);
+        let stable_pool =
+            solidly_stable_pool("F2", "A", "B", 1_000_000_000_000, 1_000_000_000_000, (6, 6));
+        let cycle = Cycle::new(vec![Swap::forward(&v2_pool), Swap::reverse(&stable_pool)]).unwrap();
+        assert!(cycle.is_positive());
+
+        let best_quote = cycle.best_quote().unwrap();
+        assert!(best_quote.is_profitable());
+        assert_eq!(best_quote.amount_in(), U256::from(3_237_071_405_u64));
+        assert_eq!(best_quote.profit(), I256::from_raw(U256::from(10_451_589)));
+    }
+
+    #[test]
+    fn test_optimal_amount_in_unprofitable() {
+        let cycle = cycle(&[
+            ("F1", "A", "B", 100_000_000, 200_000_000),
+            ("F2", "B", "A", 200_000_000, 100_000_000),
+        ])
+        .unwrap();
+
+        assert_eq!(Cycle::optimal_amount_in(&cycle.swaps), Some(U256::ZERO));
+    }
+
+    #[test]
+    fn test_best_quote_overflowing_reserves() {
+        // Solidly volatile pools have uint256 reserves, the closed form scales them down
+        let large = U256::from(1) << 200;
+        let f1 = Pool::new(
+            pool_id("F1"),
+            token_id("A"),
+            token_id("B"),
+            Some(large),
+            Some(large * U256::from(2)),
+            DEFAULT_FEE,
+        );
+        let f2 = Pool::new(
+            pool_id("F2"),
+            token_id("A"),
+            token_id("B"),
+            Some(large * U256::from(11) / U256::from(10)),
+            Some(large * U256::from(2)),
+            DEFAULT_FEE,
+        );
+        let cycle = Cycle::new(vec![Swap::forward(&f1), Swap::reverse(&f2)]).unwrap();
+
+        let amount_in = Cycle::optimal_amount_in(&cycle.swaps).unwrap();
+        let best_quote = cycle.best_quote().unwrap();
+        assert_eq!(best_quote.amount_in(), amount_in);
+        assert!(best_quote.is_profitable());
+
+        // Moving away from the best amount in loses profit
+        for amount_in in [
+            amount_in * U256::from(99) / U256::from(100),
+            amount_in * U256::from(101) / U256::from(100),
+        ] {
+            assert!(cycle.quote(amount_in).profit() < best_quote.profit());
+        }
+    }
+
     #[test]
     fn test_best_quote_with_wild_exchange_rate() {
         let cycle_instance = cycle(&[
@@ -465,20 +890,20 @@ mod tests {
         );
         let quotes = best_quote.swap_quotes();
         assert_eq!(quotes.len(), 2);
-        assert_eq!(quotes[0].amount_in(), U256::from(204_322));
+        assert_eq!(quotes[0].amount_in(), U256::from(205_911));
         assert_eq!(
             quotes[0].amount_out(),
-            U256::from(338_468_896_130_258_668_u64)
+            U256::from(340_652_806_450_963_108_u64)
         );
         assert_eq!(
             quotes[1].amount_in(),
-            U256::from(338_468_896_130_258_668_u64)
+            U256::from(340_652_806_450_963_108_u64)
         );
-        assert_eq!(quotes[1].amount_out(), U256::from(288_736));
+        assert_eq!(quotes[1].amount_out(), U256::from(290_328));
 
-        assert_eq!(best_quote.amount_in(), U256::from(204_322));
-        assert_eq!(best_quote.profit(), I256::from_raw(U256::from(84_414)));
-        assert_eq!(best_quote.profit_margin(), 4131);
+        assert_eq!(best_quote.amount_in(), U256::from(205_911));
+        assert_eq!(best_quote.profit(), I256::from_raw(U256::from(84_417)));
+        assert_eq!(best_quote.profit_margin(), 4099);
     }
 
     fn hash(cycle: &Cycle) -> u64 {
@@ -1,25 +1,44 @@
 use alloy::primitives::{I256, U256};
 
 use crate::arb::cycle::Cycle;
+use crate::arb::gas_model::GasModel;
+use crate::arb::swap::Swap;
 use crate::arb::swap_quote::SwapQuote;
+use crate::arb::token::TokenId;
 
 #[derive(Debug, Clone)]
 #[allow(dead_code)]
 pub struct CycleQuote {
+    /// The token the cycle starts and ends with: `amount_in`, `amount_out` and profit are in it
+    token: TokenId,
+
     /// The quotes for each swap in the cycle
     swap_quotes: Vec<SwapQuote>,
 }
 
 impl CycleQuote {
     pub fn new(cycle: &Cycle, amount_in: U256) -> Self {
-        let mut swap_quotes = Vec::with_capacity(cycle.swaps.len() + 1);
-        cycle.swaps.iter().fold(amount_in, |amount, swap_side| {
+        Self::from_swaps(&cycle.swaps, amount_in)
+    }
+
+    /// Quote for the swaps of a cycle in the given order. Used for cycles rotated to start at a
+    /// different token.
+    pub fn from_swaps(swaps: &[Swap], amount_in: U256) -> Self {
+        let mut swap_quotes = Vec::with_capacity(swaps.len() + 1);
+        swaps.iter().fold(amount_in, |amount, swap_side| {
             let swap_quote = SwapQuote::new(swap_side, amount);
             swap_quotes.push(swap_quote.clone());
             swap_quote.amount_out()
         });
 
-        Self { swap_quotes }
+        Self {
+            token: swaps[0].token_in,
+            swap_quotes,
+        }
+    }
+
+    pub const fn token(&self) -> TokenId {
+        self.token
     }
 
     pub fn swap_quotes(&self) -> Vec<SwapQuote> {
@@ -66,6 +85,28 @@ impl CycleQuote {
         self.profit().is_positive()
     }
 
+    /// Estimated gas cost of executing this cycle quote in the cycle token.
+    /// `None` if the gas model does not know the exchange rate of the token.
+    pub fn gas_cost(&self, gas_model: &GasModel) -> Option<U256> {
+        gas_model.gas_cost_in(&self.token, self.swap_quotes.len())
+    }
+
+    /// Profit net of the estimated gas cost in the cycle token.
+    /// `None` if the gas model does not know the exchange rate of the token.
+    pub fn net_profit(&self, gas_model: &GasModel) -> Option<I256> {
+        let gas_cost = self.gas_cost(gas_model)?;
+        Some(
+            self.profit()
+                .saturating_sub(I256::try_from(gas_cost).unwrap_or(I256::MAX)),
+        )
+    }
+
+    /// Whether this cycle quote is still profitable after paying for gas
+    pub fn is_net_profitable(&self, gas_model: &GasModel) -> bool {
+        self.net_profit(gas_model)
+            .is_some_and(|net_profit| net_profit.is_positive())
+    }
+
     pub fn amount_in(&self) -> U256 {
         self.swap_quotes.first().unwrap().amount_in()
     }
@@ -77,9 +118,54 @@ impl CycleQuote {
 
 #[cfg(test)]
 mod tests {
+    use std::collections::HashMap;
+
     use super::*;
     use crate::arb::test_helpers::*;
 
+    #[test]
+    fn test_net_profit() {
+        let cycle = cycle(&[
+            ("F1", "A", "B", 1_000_000, 2_000_000),
+            ("F2", "B", "A", 3_000_000, 3_000_000),
+        ])
+        .unwrap();
+        let quote = CycleQuote::new(&cycle, U256::from(247_019));
+        assert_eq!(quote.token(), token("A").id);
+        assert_eq!(quote.profit(), I256::try_from(101_270).unwrap());
+
+        // Free gas, 1 A = 1 wei
+        let gas_model = GasModel::new(
+            0,
+            0,
+            HashMap::from([(token("A").id, U256::from(10_u64.pow(18)))]),
+        );
+        assert_eq!(quote.gas_cost(&gas_model), Some(U256::ZERO));
+        assert_eq!(
+            quote.net_profit(&gas_model),
+            Some(I256::try_from(101_270).unwrap())
+        );
+        assert!(quote.is_net_profitable(&gas_model));
+
+        // 386k gas at 1 wei per gas
+        let gas_model = GasModel::new(
+            1,
+            0,
+            HashMap::from([(token("A").id, U256::from(10_u64.pow(18)))]),
+        );
+        assert_eq!(quote.gas_cost(&gas_model), Some(U256::from(386_000)));
+        assert_eq!(
+            quote.net_profit(&gas_model),
+            Some(I256::try_from(-284_730).unwrap())
+        );
+        assert!(!quote.is_net_profitable(&gas_model));
+
+        // Unknown exchange rate
+        let gas_model = GasModel::new(1, 0, HashMap::new());
+        assert_eq!(quote.net_profit(&gas_model), None);
+        assert!(!quote.is_net_profitable(&gas_model));
+    }
+
     #[test]
     fn test_quotes_not_exploitable() {
         let cycle = cycle(&[
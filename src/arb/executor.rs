@@ -0,0 +1,260 @@
+/// `SimpleExecutor.run` calls for cycle quotes, see contracts/src/SimpleExecutor.sol
+use alloy::primitives::{Address, Bytes, U256};
+use alloy::sol;
+use alloy::sol_types::SolCall;
+use eyre::{bail, Result};
+
+use super::cycle_quote::CycleQuote;
+use super::swap::{Direction, FEE_DENOMINATOR};
+use super::swap_quote::SwapQuote;
+
+sol! {
+    #[sol(rpc)]
+    interface ISimpleExecutor {
+        struct Pair {
+            address contractAddress;
+            uint256 amountOut;
+            bool isToken0;
+        }
+
+        error ProfitTargetNotMet(uint256 minimumProfit, int256 actualProfit);
+
+        function run(
+            address token0Address,
+            uint256 token0AmountIn,
+            uint256 minimumProfitInToken0,
+            Pair[] calldata pairs,
+            bool skipProfitCheck
+        ) external payable;
+    }
+}
+
+/// The `Pair` of a swap: the pair sends `amount_out` of the output token to the next pair
+///
+/// # Errors
+/// * If the swap is through a Uniswap V3 pool, `run` only calls the `swap` of V2 style pairs
+pub fn run_pair(swap_quote: &SwapQuote) -> Result<ISimpleExecutor::Pair> {
+    let swap_id = swap_quote.swap_id();
+    if swap_quote.is_uniswap_v3() {
+        bail!("Swap {swap_id} is through a Uniswap V3 pool, SimpleExecutor cannot run it");
+    }
+    Ok(ISimpleExecutor::Pair {
+        contractAddress: swap_id.pool_id.address(),
+        amountOut: swap_quote.amount_out(),
+        // A one for zero swap sends token0
+        isToken0: swap_id.direction == Direction::OneForZero,
+    })
+}
+
+/// The `Pair`s of the swaps of a quote, in the cycle order
+///
+/// # Errors
+/// * If a swap is through a Uniswap V3 pool
+pub fn run_pairs(quote: &CycleQuote) -> Result<Vec<ISimpleExecutor::Pair>> {
+    quote.swap_quotes().iter().map(run_pair).collect()
+}
+
+/// Encodes `SimpleExecutor.run` calls for quotes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RunEncoder {
+    /// Share of the quoted profit the run may miss without reverting, in basis points
+    slippage_bps: u32,
+}
+
+impl Default for RunEncoder {
+    fn default() -> Self {
+        Self {
+            slippage_bps: Self::DEFAULT_SLIPPAGE_BPS,
+        }
+    }
+}
+
+impl RunEncoder {
+    /// 10% of the quoted profit
+    pub const DEFAULT_SLIPPAGE_BPS: u32 = 1_000;
+
+    /// # Errors
+    /// * If `slippage_bps` is more than 100%
+    pub fn new(slippage_bps: u32) -> Result<Self> {
+        if slippage_bps > FEE_DENOMINATOR {
+            bail!("Slippage must be at most {FEE_DENOMINATOR} bps, got {slippage_bps}");
+        }
+        Ok(Self { slippage_bps })
+    }
+
+    pub const fn slippage_bps(&self) -> u32 {
+        self.slippage_bps
+    }
+
+    /// The quoted profit less the slippage haircut, zero for an unprofitable quote
+    pub fn min_profit(&self, quote: &CycleQuote) -> U256 {
+        let profit = quote.profit();
+        if !profit.is_positive() {
+            return U256::ZERO;
+        }
+        profit.into_raw() * U256::from(FEE_DENOMINATOR - self.slippage_bps)
+            / U256::from(FEE_DENOMINATOR)
+    }
+
+    /// The call that swaps through the pairs of the quote in order, reverting unless it makes
+    /// `min_profit`
+    ///
+    /// # Errors
+    /// * If a swap is through a Uniswap V3 pool
+    pub fn run_call(&self, quote: &CycleQuote) -> Result<ISimpleExecutor::runCall> {
+        Ok(ISimpleExecutor::runCall {
+            token0Address: quote.token().address,
+            token0AmountIn: quote.amount_in(),
+            minimumProfitInToken0: self.min_profit(quote),
+            pairs: run_pairs(quote)?,
+            skipProfitCheck: false,
+        })
+    }
+
+    /// Calldata of `run_call`
+    ///
+    /// # Errors
+    /// * If a swap is through a Uniswap V3 pool
+    pub fn encode(&self, quote: &CycleQuote) -> Result<Bytes> {
+        Ok(self.run_call(quote)?.abi_encode().into())
+    }
+}
+
+/// Calldata of a `run` call with the profit check, for routes that do not come from a quote
+pub fn encode_run(
+    token: Address,
+    amount_in: U256,
+    min_profit: U256,
+    pairs: Vec<ISimpleExecutor::Pair>,
+) -> Bytes {
+    ISimpleExecutor::runCall {
+        token0Address: token,
+        token0AmountIn: amount_in,
+        minimumProfitInToken0: min_profit,
+        pairs,
+        skipProfitCheck: false,
+    }
+    .abi_encode()
+    .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arb::swap::Swap;
+    use crate::arb::test_helpers::*;
+    use alloy::hex;
+    use alloy::primitives::keccak256;
+
+    /// A 32 byte ABI word of a number
+    fn word(value: U256) -> String {
+        hex::encode(value.to_be_bytes::<32>())
+    }
+
+    /// A 32 byte ABI word of an address
+    fn address_word(address: &str) -> String {
+        format!("{:0>64}", hex::encode(address_from_str(address)))
+    }
+
+    fn quote() -> CycleQuote {
+        CycleQuote::from_swaps(
+            &[
+                swap("F1", "A", "B", 1_000_000, 2_000_000),
+                swap("F2", "B", "A", 2_000_000, 1_100_000),
+            ],
+            U256::from(1_000),
+        )
+    }
+
+    #[test]
+    fn test_new() {
+        assert_eq!(RunEncoder::new(500).unwrap().slippage_bps(), 500);
+        assert!(RunEncoder::new(FEE_DENOMINATOR).is_ok());
+        assert!(RunEncoder::new(FEE_DENOMINATOR + 1).is_err());
+    }
+
+    #[test]
+    fn test_min_profit() {
+        let quote = quote();
+        let profit = quote.profit().into_raw();
+        assert!(profit > U256::ZERO);
+
+        assert_eq!(RunEncoder::new(0).unwrap().min_profit(&quote), profit);
+        assert_eq!(
+            RunEncoder::new(2_500).unwrap().min_profit(&quote),
+            profit * U256::from(3) / U256::from(4)
+        );
+        assert_eq!(
+            RunEncoder::new(FEE_DENOMINATOR).unwrap().min_profit(&quote),
+            U256::ZERO
+        );
+
+        // A losing quote has nothing to haircut
+        let losing = CycleQuote::from_swaps(
+            &[
+                swap("F1", "A", "B", 1_000_000, 1_000_000),
+                swap("F2", "B", "A", 1_000_000, 1_000_000),
+            ],
+            U256::from(1_000),
+        );
+        assert_eq!(RunEncoder::default().min_profit(&losing), U256::ZERO);
+    }
+
+    #[test]
+    fn test_encode() {
+        let quote = quote();
+        let swap_quotes = quote.swap_quotes();
+        let encoder = RunEncoder::new(2_000).unwrap();
+        let min_profit = quote.profit().into_raw() * U256::from(8) / U256::from(10);
+
+        let selector =
+            &keccak256("run(address,uint256,uint256,(address,uint256,bool)[],bool)")[..4];
+        let expected = [
+            hex::encode(selector),
+            address_word("A"),
+            word(U256::from(1_000)),
+            word(min_profit),
+            // Offset of the pairs after the 5 head words
+            word(U256::from(5 * 32)),
+            // skipProfitCheck
+            word(U256::ZERO),
+            // The pairs: length, then each static tuple in place
+            word(U256::from(2)),
+            // A -> B sends token1
+            address_word("F1"),
+            word(swap_quotes[0].amount_out()),
+            word(U256::ZERO),
+            // B -> A sends token0
+            address_word("F2"),
+            word(swap_quotes[1].amount_out()),
+            word(U256::from(1)),
+        ]
+        .concat();
+
+        assert_eq!(hex::encode(encoder.encode(&quote).unwrap()), expected);
+        assert_eq!(
+            encode_run(
+                quote.token().address,
+                quote.amount_in(),
+                min_profit,
+                run_pairs(&quote).unwrap()
+            ),
+            encoder.encode(&quote).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_encode_rejects_uniswap_v3() {
+        let v3_pool = uniswap_v3_pool("F2", "A", "B", 0, 1_000_000_000_000, &[]);
+        let quote = CycleQuote::from_swaps(
+            &[
+                swap("F1", "A", "B", 1_000_000, 2_000_000),
+                Swap::reverse(&v3_pool),
+            ],
+            U256::from(1_000),
+        );
+        assert!(run_pair(&quote.swap_quotes()[0]).is_ok());
+        assert!(run_pair(&quote.swap_quotes()[1]).is_err());
+        assert!(RunEncoder::default().encode(&quote).is_err());
+    }
+}
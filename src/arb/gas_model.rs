@@ -0,0 +1,126 @@
+/// Estimates the gas cost of executing a cycle and converts it into cycle tokens
+use std::collections::HashMap;
+
+use alloy::primitives::U256;
+
+use super::token::TokenId;
+use crate::utils::constants::ETHER;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GasModel {
+    /// Current block base fee in wei per gas
+    base_fee: u128,
+
+    /// Priority fee we pay on top of the base fee in wei per gas
+    priority_fee: u128,
+
+    /// Exchange rates: how many token units (in the token's own decimals) 1 ETH buys.
+    /// Gas cost can only be converted into tokens listed here.
+    token_per_eth: HashMap<TokenId, U256>,
+}
+
+impl GasModel {
+    /// Intrinsic gas of any transaction
+    pub const TRANSACTION_GAS: u64 = 21_000;
+
+    /// `SimpleExecutor` overhead regardless of the number of swaps: calldata, balance checks
+    /// and the final transfer
+    pub const EXECUTOR_GAS: u64 = 65_000;
+
+    /// A single `swap` call including the token transfer to the pair.
+    /// A 2 swap cycle is ~385k gas in the worst case.
+    pub const SWAP_GAS: u64 = 150_000;
+
+    pub const fn new(
+        base_fee: u128,
+        priority_fee: u128,
+        token_per_eth: HashMap<TokenId, U256>,
+    ) -> Self {
+        Self {
+            base_fee,
+            priority_fee,
+            token_per_eth,
+        }
+    }
+
+    pub const fn base_fee(&self) -> u128 {
+        self.base_fee
+    }
+
+    pub const fn priority_fee(&self) -> u128 {
+        self.priority_fee
+    }
+
+    /// Estimated gas units of a cycle with `legs` swaps
+    pub const fn gas_units(legs: usize) -> u64 {
+        Self::TRANSACTION_GAS + Self::EXECUTOR_GAS + Self::SWAP_GAS * legs as u64
+    }
+
+    /// Estimated gas cost of a cycle with `legs` swaps in wei
+    pub fn gas_cost(&self, legs: usize) -> U256 {
+        U256::from(Self::gas_units(legs)) * U256::from(self.base_fee + self.priority_fee)
+    }
+
+    /// Estimated gas cost of a cycle with `legs` swaps in `token` units.
+    /// `None` if we do not know the exchange rate of the token.
+    pub fn gas_cost_in(&self, token: &TokenId, legs: usize) -> Option<U256> {
+        let token_per_eth = self.token_per_eth.get(token)?;
+        Some(self.gas_cost(legs) * token_per_eth / ETHER)
+    }
+
+    /// Converts `amount` of `token` into wei, so amounts of different tokens can be compared.
+    /// `None` if we do not know the exchange rate of the token.
+    pub fn to_wei(&self, token: &TokenId, amount: U256) -> Option<U256> {
+        let token_per_eth = self.token_per_eth.get(token)?;
+        if token_per_eth.is_zero() {
+            return None;
+        }
+        Some(amount * ETHER / token_per_eth)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arb::test_helpers::*;
+
+    #[test]
+    fn test_gas_units() {
+        assert_eq!(GasModel::gas_units(2), 386_000);
+        assert_eq!(GasModel::gas_units(3), 536_000);
+    }
+
+    #[test]
+    fn test_gas_cost_in() {
+        // 1 ETH = 2000 USDC (6 decimals), 0.25 gwei base fee, 0.01 gwei priority fee
+        let weth = token("A").id;
+        let usdc = token("B").id;
+        let gas_model = GasModel::new(
+            250_000_000,
+            10_000_000,
+            HashMap::from([(weth, ETHER), (usdc, U256::from(2_000_000_000_u64))]),
+        );
+
+        // 386k gas * 0.26 gwei
+        assert_eq!(gas_model.gas_cost(2), U256::from(100_360_000_000_000_u64));
+        assert_eq!(
+            gas_model.gas_cost_in(&weth, 2),
+            Some(U256::from(100_360_000_000_000_u64))
+        );
+        // ~$0.20
+        assert_eq!(gas_model.gas_cost_in(&usdc, 2), Some(U256::from(200_720)));
+        assert_eq!(gas_model.gas_cost_in(&token("C").id, 2), None);
+    }
+
+    #[test]
+    fn test_to_wei() {
+        let usdc = token("B").id;
+        let gas_model = GasModel::new(0, 0, HashMap::from([(usdc, U256::from(2_000_000_000_u64))]));
+
+        assert_eq!(
+            gas_model.to_wei(&usdc, U256::from(2_000_000)),
+            Some(U256::from(1_000_000_000_000_000_u64))
+        );
+        assert_eq!(gas_model.to_wei(&token("C").id, U256::from(1)), None);
+    }
+}
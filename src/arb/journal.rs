@@ -0,0 +1,162 @@
+/// Undo log of the last blocks, so state derived from `Sync` events can be rolled back when a block
+/// is reorged out. Each block records the values it overwrote.
+use std::collections::VecDeque;
+
+use alloy::primitives::B256;
+use eyre::{bail, Result};
+
+/// Blocks kept in a journal. Base reorgs are a few blocks deep at most.
+pub const DEFAULT_DEPTH: usize = 64;
+
+#[derive(Debug, Clone)]
+struct JournalBlock<T> {
+    number: u64,
+    hash: B256,
+    previous: Vec<T>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Journal<T> {
+    depth: usize,
+    blocks: VecDeque<JournalBlock<T>>,
+    /// Highest block dropped from the journal, we cannot roll back to a block before it
+    pruned: Option<u64>,
+}
+
+impl<T> Default for Journal<T> {
+    fn default() -> Self {
+        Self::new(DEFAULT_DEPTH)
+    }
+}
+
+impl<T> Journal<T> {
+    pub const fn new(depth: usize) -> Self {
+        Self {
+            depth,
+            blocks: VecDeque::new(),
+            pruned: None,
+        }
+    }
+
+    /// Records the values block `number` overwrote. Blocks are recorded in order, recording the
+    /// latest block again adds to it.
+    pub fn record(&mut self, number: u64, hash: B256, previous: impl IntoIterator<Item = T>) {
+        if let Some(block) = self.blocks.back_mut() {
+            if block.number == number && block.hash == hash {
+                block.previous.extend(previous);
+                return;
+            }
+        }
+
+        self.blocks.push_back(JournalBlock {
+            number,
+            hash,
+            previous: previous.into_iter().collect(),
+        });
+        while self.blocks.len() > self.depth {
+            if let Some(block) = self.blocks.pop_front() {
+                self.pruned = Some(block.number);
+            }
+        }
+    }
+
+    /// Hash of a recorded block
+    pub fn hash(&self, number: u64) -> Option<B256> {
+        self.blocks
+            .iter()
+            .rev()
+            .find(|block| block.number == number)
+            .map(|block| block.hash)
+    }
+
+    /// Number and hash of the recorded blocks, latest first
+    pub fn blocks(&self) -> impl Iterator<Item = (u64, B256)> + '_ {
+        self.blocks
+            .iter()
+            .rev()
+            .map(|block| (block.number, block.hash))
+    }
+
+    /// Number of the latest recorded block
+    pub fn latest(&self) -> Option<u64> {
+        self.blocks.back().map(|block| block.number)
+    }
+
+    /// Removes the blocks after `number` and returns the values they overwrote: the latest block
+    /// first, each block in reverse order. Writing them back in this order restores the state as of
+    /// block `number`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if blocks after `number` were already dropped from the journal
+    pub fn rollback(&mut self, number: u64) -> Result<Vec<T>> {
+        if let Some(pruned) = self.pruned {
+            if number < pruned {
+                bail!(
+                    "Cannot roll back to block {number}, the journal starts after block {pruned}"
+                );
+            }
+        }
+
+        let mut previous = Vec::new();
+        while self
+            .blocks
+            .back()
+            .is_some_and(|block| block.number > number)
+        {
+            if let Some(block) = self.blocks.pop_back() {
+                previous.extend(block.previous.into_iter().rev());
+            }
+        }
+        Ok(previous)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hash(byte: u8) -> B256 {
+        B256::repeat_byte(byte)
+    }
+
+    #[test]
+    fn test_rollback() {
+        let mut journal = Journal::default();
+        journal.record(10, hash(10), ["a9"]);
+        journal.record(11, hash(11), ["a10", "b10"]);
+        journal.record(11, hash(11), ["a11"]);
+        journal.record(12, hash(12), ["b11"]);
+
+        assert_eq!(journal.latest(), Some(12));
+        assert_eq!(journal.hash(11), Some(hash(11)));
+        assert_eq!(journal.hash(13), None);
+        assert_eq!(
+            journal.blocks().collect::<Vec<_>>(),
+            vec![(12, hash(12)), (11, hash(11)), (10, hash(10))]
+        );
+
+        // The oldest value of a key is written back last
+        assert_eq!(
+            journal.rollback(10).unwrap(),
+            vec!["b11", "a11", "b10", "a10"]
+        );
+        assert_eq!(journal.latest(), Some(10));
+        assert_eq!(journal.rollback(10).unwrap(), Vec::<&str>::new());
+    }
+
+    #[test]
+    fn test_rollback_pruned() {
+        let mut journal = Journal::new(2);
+        journal.record(10, hash(10), [1]);
+        journal.record(11, hash(11), [2]);
+        journal.record(12, hash(12), [3]);
+
+        assert_eq!(journal.hash(10), None);
+        assert_eq!(
+            journal.rollback(9).unwrap_err().to_string(),
+            "Cannot roll back to block 9, the journal starts after block 10"
+        );
+        assert_eq!(journal.rollback(10).unwrap(), vec![3, 2]);
+    }
+}
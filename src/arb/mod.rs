@@ -1,11 +1,18 @@
 pub mod cycle;
-mod cycle_quote;
+pub mod cycle_quote;
+pub mod executor;
+pub mod gas_model;
+pub mod journal;
 pub mod pool;
-mod portfolio;
-mod swap;
+pub mod portfolio;
+pub mod pricing;
+pub mod solidly_stable;
+pub mod swap;
 pub mod swap_quote;
-mod test_helpers;
+pub(crate) mod test_helpers;
 pub mod token;
 mod types;
+pub mod uniswap_v3;
 pub mod world;
+pub mod world_config;
 mod world_update;
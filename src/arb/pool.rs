@@ -1,73 +1,94 @@
 /// Market expects Pools to be this.
 use std::fmt::{self, Debug, Display};
 use std::hash::{Hash, Hasher};
+use std::sync::Arc;
 
-use alloy::primitives::{Address, U256};
+use alloy::primitives::{Address, ChainId, U256};
 use eyre::Result;
 
+use super::solidly_stable::SolidlyStable;
 use super::token::TokenId;
+use super::uniswap_v3::{UniswapV3State, FEE_DENOMINATOR as V3_FEE_DENOMINATOR};
+use crate::arb::swap::FEE_DENOMINATOR;
 
-/// A unique identifier for a pool
-/// This is just an Address for now, but, in the future, it will also include a chain id
+/// A unique identifier for a pool: its address on a chain
 #[derive(Clone, Eq, PartialEq, Hash, Ord, PartialOrd)]
-pub struct PoolId(Address);
-
-impl Debug for PoolId {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let hex = format!("{}", self.0);
-        let hex = hex.trim_start_matches("0x").to_uppercase();
-        let zeros = hex.chars().rev().take_while(|&c| c == '0').count();
-        if zeros > 10 {
-            let trimmed = hex.trim_end_matches('0');
-            write!(f, "{trimmed}")
-        } else {
-            write!(f, "{hex}")
-        }
-    }
+pub struct PoolId {
+    chain_id: ChainId,
+    address: Address,
 }
 
-impl TryFrom<&str> for PoolId {
-    type Error = eyre::Error;
+impl PoolId {
+    pub const fn new(chain_id: ChainId, address: Address) -> Self {
+        Self { chain_id, address }
+    }
 
-    fn try_from(s: &str) -> Result<Self> {
-        // Parse the string as an Address
+    /// Parse a checksummed pool address on the given chain
+    pub fn parse(chain_id: ChainId, s: &str) -> Result<Self> {
         Address::parse_checksummed(s, None)
-            .map(Self)
+            .map(|address| Self::new(chain_id, address))
             .map_err(|e| eyre::eyre!("Invalid pool address: {e}"))
     }
-}
 
-impl TryFrom<String> for PoolId {
-    type Error = eyre::Error;
+    pub const fn chain_id(&self) -> ChainId {
+        self.chain_id
+    }
 
-    fn try_from(s: String) -> Result<Self> {
-        Self::try_from(s.as_str())
+    pub const fn address(&self) -> Address {
+        self.address
     }
 }
 
-impl From<Address> for PoolId {
-    fn from(addr: Address) -> Self {
-        Self(addr)
+/// Short address only, like `TokenId`
+impl Debug for PoolId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let hex = format!("{}", self.address);
+        let hex = hex.trim_start_matches("0x").to_uppercase();
+        let zeros = hex.chars().rev().take_while(|&c| c == '0').count();
+        if zeros > 10 {
+            let trimmed = hex.trim_end_matches('0');
+            write!(f, "{trimmed}")
+        } else {
+            write!(f, "{hex}")
+        }
     }
 }
 
 impl Display for PoolId {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}", self.0)
+        write!(f, "{}@{}", self.address, self.chain_id)
     }
 }
 
+/// How a pool prices swaps
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum PoolKind {
+    /// Uniswap V2 constant product pair priced by its reserves
+    #[default]
+    UniswapV2,
+    /// Uniswap V3 concentrated liquidity pool. The state is shared by both swaps of the pool.
+    UniswapV3(Arc<UniswapV3State>),
+    /// Solidly (Aerodrome, Velodrome) stable pool priced by its reserves on the
+    /// `x^3 * y + y^3 * x` curve
+    SolidlyStable(SolidlyStable),
+}
+
 /// Pool as it comes from the database or Sync events
 #[derive(Debug, Clone, Eq)]
 pub struct Pool {
     pub id: PoolId,
     pub token0: TokenId,
     pub token1: TokenId,
+    /// Reserves of a V2 pool. For V3 pools these are the virtual reserves at the current price,
+    /// they are used for the log rate only.
     pub reserve0: Option<U256>,
     pub reserve1: Option<U256>,
+    /// Swap fee in basis points, set by the pool factory
+    pub fee: u32,
+    pub kind: PoolKind,
 }
 
-/// Two pools are equal if they have the same address
+/// Two pools are equal if they have the same id
 /// This is for `HashSet` operations
 impl PartialEq for Pool {
     fn eq(&self, other: &Self) -> bool {
@@ -75,7 +96,7 @@ impl PartialEq for Pool {
     }
 }
 
-/// Hash the pool by its address
+/// Hash the pool by its id
 /// This is for `HashSet` operations
 impl Hash for Pool {
     fn hash<H: Hasher>(&self, state: &mut H) {
@@ -90,6 +111,7 @@ impl Pool {
         token1: TokenId,
         reserve0: Option<U256>,
         reserve1: Option<U256>,
+        fee: u32,
     ) -> Self {
         Self {
             id,
@@ -97,6 +119,52 @@ impl Pool {
             token1,
             reserve0,
             reserve1,
+            fee,
+            kind: PoolKind::UniswapV2,
+        }
+    }
+
+    /// Creates a Solidly stable pool. Its fee is taken from `amount_in` before the swap.
+    pub const fn solidly_stable(
+        id: PoolId,
+        token0: TokenId,
+        token1: TokenId,
+        reserve0: Option<U256>,
+        reserve1: Option<U256>,
+        fee: u32,
+        stable: SolidlyStable,
+    ) -> Self {
+        Self {
+            id,
+            token0,
+            token1,
+            reserve0,
+            reserve1,
+            fee,
+            kind: PoolKind::SolidlyStable(stable),
+        }
+    }
+
+    /// Creates a Uniswap V3 pool. Pools without active liquidity have no (virtual) reserves.
+    pub fn uniswap_v3(id: PoolId, token0: TokenId, token1: TokenId, state: UniswapV3State) -> Self {
+        let (reserve0, reserve1) = match state.virtual_reserves() {
+            (reserve0, reserve1) if !reserve0.is_zero() && !reserve1.is_zero() => {
+                (Some(reserve0), Some(reserve1))
+            }
+            _ => (None, None),
+        };
+
+        // V3 fees are in hundredths of a basis point, the log rate only needs an approximation
+        let fee = state.fee().div_ceil(V3_FEE_DENOMINATOR / FEE_DENOMINATOR);
+
+        Self {
+            id,
+            token0,
+            token1,
+            reserve0,
+            reserve1,
+            fee,
+            kind: PoolKind::UniswapV3(Arc::new(state)),
         }
     }
 }
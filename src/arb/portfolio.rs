@@ -1,4 +1,4 @@
-#![allow(dead_code)]
+/// Token balances we hold and can use as cycle `amount_in`
 use alloy::primitives::U256;
 use std::collections::HashMap;
 
@@ -0,0 +1,249 @@
+/// USD prices of tokens derived from the pool graph
+///
+/// Stablecoins are the anchors of the graph. Every other token is priced through the path from an
+/// anchor whose shallowest pool is the deepest (widest path), so a token with a thin pool to WETH
+/// and a deep one to USDC is priced by the USDC pool. Prices are in USD per raw unit of the token,
+/// so only the decimals of the anchors need to be known.
+use std::collections::{BinaryHeap, HashMap, HashSet};
+
+use alloy::primitives::{ChainId, U256};
+
+use super::pool::{Pool, PoolKind};
+use super::token::TokenId;
+use crate::utils::constants::{BASE_CHAIN_ID, DAI, USDC, USDT};
+
+/// Pools with less than this much USD on the priced side do not price the other token
+pub const DEFAULT_MIN_LIQUIDITY_USD: f64 = 1_000.0;
+
+/// USD prices of the tokens reachable from the anchors
+#[derive(Debug, Clone, Default)]
+pub struct Prices {
+    /// USD per raw unit
+    prices: HashMap<TokenId, f64>,
+}
+
+impl Prices {
+    /// Prices the tokens of `pools` starting from the `anchors` prices
+    ///
+    /// # Arguments
+    /// * `anchors` - Tokens with a known price in USD per raw unit, see `stablecoin_anchors`
+    /// * `min_liquidity_usd` - Pools with less USD on the priced side are not followed
+    pub fn derive(
+        pools: &HashSet<Pool>,
+        anchors: &HashMap<TokenId, f64>,
+        min_liquidity_usd: f64,
+    ) -> Self {
+        let mut token_pools: HashMap<TokenId, Vec<&Pool>> = HashMap::new();
+        for pool in pools {
+            token_pools.entry(pool.token0).or_default().push(pool);
+            token_pools.entry(pool.token1).or_default().push(pool);
+        }
+
+        let mut prices = anchors.clone();
+        // Depth of the best path to a token in whole USD, anchors are infinitely deep
+        let mut depths: HashMap<TokenId, u64> = HashMap::new();
+        let mut queue = BinaryHeap::new();
+        for &token in anchors.keys() {
+            depths.insert(token, u64::MAX);
+            queue.push((u64::MAX, token));
+        }
+
+        let mut priced = HashSet::new();
+        while let Some((depth, token)) = queue.pop() {
+            if !priced.insert(token) {
+                continue;
+            }
+            let price = prices[&token];
+
+            for pool in token_pools.get(&token).into_iter().flatten() {
+                let zero_for_one = pool.token0 == token;
+                let other = if zero_for_one {
+                    pool.token1
+                } else {
+                    pool.token0
+                };
+                if priced.contains(&other) {
+                    continue;
+                }
+                let Some((reserve_in, rate)) = marginal_rate(pool, zero_for_one) else {
+                    continue;
+                };
+
+                let liquidity_usd = reserve_in * price;
+                if liquidity_usd < min_liquidity_usd {
+                    continue;
+                }
+
+                // Saturates, the depth only orders the paths
+                let path_depth = depth.min(liquidity_usd as u64);
+                if path_depth > depths.get(&other).copied().unwrap_or_default() {
+                    depths.insert(other, path_depth);
+                    prices.insert(other, price / rate);
+                    queue.push((path_depth, other));
+                }
+            }
+        }
+
+        Self { prices }
+    }
+
+    /// USD per raw unit of the token, `None` if it is not connected to an anchor by deep enough
+    /// pools
+    pub fn get(&self, token: &TokenId) -> Option<f64> {
+        self.prices.get(token).copied()
+    }
+
+    /// USD value of the reserves of the pool. A side without a price counts as 0, `None` if
+    /// neither side has a price.
+    pub fn pool_usd(&self, pool: &Pool) -> Option<f64> {
+        let value = |token: &TokenId, reserve: Option<U256>| {
+            Some(self.get(token)? * f64::from(reserve.unwrap_or_default()))
+        };
+
+        match (
+            value(&pool.token0, pool.reserve0),
+            value(&pool.token1, pool.reserve1),
+        ) {
+            (None, None) => None,
+            (value0, value1) => Some(value0.unwrap_or_default() + value1.unwrap_or_default()),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.prices.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.prices.is_empty()
+    }
+}
+
+/// Stablecoins of the chain at $1, in USD per raw unit
+pub fn stablecoin_anchors(chain_id: ChainId) -> HashMap<TokenId, f64> {
+    let stablecoins = match chain_id {
+        BASE_CHAIN_ID => vec![(USDC, 6), (USDT, 6), (DAI, 18)],
+        _ => Vec::new(),
+    };
+
+    stablecoins
+        .into_iter()
+        .map(|(address, decimals)| (TokenId::new(chain_id, address), 10_f64.powi(-decimals)))
+        .collect()
+}
+
+/// Reserve of the input token and the marginal rate (raw output units per raw input unit)
+/// without the fee, `None` if the pool has no reserves
+fn marginal_rate(pool: &Pool, zero_for_one: bool) -> Option<(f64, f64)> {
+    let (reserve_in, reserve_out) = match (pool.reserve0, pool.reserve1) {
+        (Some(reserve0), Some(reserve1)) if !reserve0.is_zero() && !reserve1.is_zero() => {
+            if zero_for_one {
+                (reserve0, reserve1)
+            } else {
+                (reserve1, reserve0)
+            }
+        }
+        _ => return None,
+    };
+
+    let rate = match &pool.kind {
+        PoolKind::SolidlyStable(stable) => {
+            10_f64.powf(stable.log10_rate(reserve_in, reserve_out, zero_for_one))
+        }
+        // V3 pools carry their virtual reserves at the current price
+        PoolKind::UniswapV2 | PoolKind::UniswapV3(_) => {
+            f64::from(reserve_out) / f64::from(reserve_in)
+        }
+    };
+
+    Some((f64::from(reserve_in), rate))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arb::test_helpers::{pool, token_id};
+
+    /// Anchor A at $1 per raw unit
+    fn anchors() -> HashMap<TokenId, f64> {
+        HashMap::from([(token_id("A"), 1.0)])
+    }
+
+    fn assert_price(prices: &Prices, token: &str, expected: f64) {
+        let price = prices.get(&token_id(token)).unwrap();
+        assert!(
+            (price - expected).abs() < expected * 1e-9,
+            "{token}: {price} != {expected}"
+        );
+    }
+
+    #[test]
+    fn test_derive() {
+        let pools = HashSet::from([
+            pool("F1", "A", "B", 10_000, 5_000),
+            pool("F2", "B", "C", 5_000, 50_000),
+        ]);
+        let prices = Prices::derive(&pools, &anchors(), 1_000.0);
+
+        assert_price(&prices, "A", 1.0);
+        assert_price(&prices, "B", 2.0);
+        assert_price(&prices, "C", 0.2);
+        assert_eq!(prices.len(), 3);
+    }
+
+    #[test]
+    fn test_derive_deepest_path() {
+        let pools = HashSet::from([
+            // Shallow direct pool at a different price
+            pool("F1", "A", "C", 2_000, 2_000),
+            // Deep path through B
+            pool("F2", "A", "B", 100_000, 50_000),
+            pool("F3", "B", "C", 50_000, 1_000_000),
+        ]);
+        let prices = Prices::derive(&pools, &anchors(), 1_000.0);
+
+        assert_price(&prices, "B", 2.0);
+        assert_price(&prices, "C", 0.1);
+    }
+
+    #[test]
+    fn test_derive_min_liquidity() {
+        let pools = HashSet::from([
+            pool("F1", "A", "B", 500, 500),
+            pool("F2", "C", "D", 10_000, 10_000),
+        ]);
+        let prices = Prices::derive(&pools, &anchors(), 1_000.0);
+
+        // Too shallow to price B, C and D are not connected to an anchor
+        assert_eq!(prices.get(&token_id("B")), None);
+        assert_eq!(prices.get(&token_id("C")), None);
+        assert_eq!(prices.len(), 1);
+    }
+
+    #[test]
+    fn test_pool_usd() {
+        let pools = HashSet::from([
+            pool("F1", "A", "B", 10_000, 5_000),
+            pool("F2", "B", "C", 100, 1_000),
+        ]);
+        let prices = Prices::derive(&pools, &anchors(), 1_000.0);
+
+        assert_eq!(
+            prices.pool_usd(&pool("F1", "A", "B", 10_000, 5_000)),
+            Some(20_000.0)
+        );
+        // C is only reachable through a shallow pool, its side counts as 0
+        assert_eq!(
+            prices.pool_usd(&pool("F2", "B", "C", 100, 1_000)),
+            Some(200.0)
+        );
+        assert_eq!(prices.pool_usd(&pool("F3", "C", "D", 100, 100)), None);
+    }
+
+    #[test]
+    fn test_stablecoin_anchors() {
+        let anchors = stablecoin_anchors(BASE_CHAIN_ID);
+        assert_eq!(anchors[&TokenId::new(BASE_CHAIN_ID, USDC)], 1e-6);
+        assert_eq!(anchors[&TokenId::new(BASE_CHAIN_ID, DAI)], 1e-18);
+        assert!(stablecoin_anchors(1).is_empty());
+    }
+}
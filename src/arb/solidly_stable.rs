@@ -0,0 +1,280 @@
+/// Solidly stable pool swap math, as used by Aerodrome and Velodrome stable pools.
+/// The pools keep `x^3 * y + y^3 * x >= k` on reserves normalized to 18 decimals. This is a port of
+/// `Pool.getAmountOut` and `Pool._get_y`, so quotes match the pool to the wei.
+use alloy::primitives::U256;
+use eyre::{bail, Result};
+
+use super::swap::FEE_DENOMINATOR;
+
+/// Default Aerodrome stable pool fee in basis points. Volatile pools default to 30.
+pub const DEFAULT_STABLE_FEE: u32 = 5;
+
+/// Highest token decimals for which `10^decimals` fits into U256
+const MAX_DECIMALS: u8 = 77;
+
+/// `_get_y` gives up after this many Newton iterations
+const MAX_ITERATIONS: usize = 255;
+
+/// 1e18
+const PRECISION: U256 = U256::from_limbs([1_000_000_000_000_000_000, 0, 0, 0]);
+
+/// Token decimals of a stable pool. Reserves and amounts are normalized to 18 decimals before the
+/// invariant is applied, so the decimals are part of the pricing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SolidlyStable {
+    decimals0: u8,
+    decimals1: u8,
+}
+
+impl SolidlyStable {
+    /// # Errors
+    ///
+    /// Returns an error if `10^decimals` of either token does not fit into U256
+    pub fn new(decimals0: u8, decimals1: u8) -> Result<Self> {
+        if decimals0 > MAX_DECIMALS || decimals1 > MAX_DECIMALS {
+            bail!("Token decimals must be at most {MAX_DECIMALS}, got {decimals0} and {decimals1}");
+        }
+
+        Ok(Self {
+            decimals0,
+            decimals1,
+        })
+    }
+
+    pub const fn decimals0(&self) -> u8 {
+        self.decimals0
+    }
+
+    pub const fn decimals1(&self) -> u8 {
+        self.decimals1
+    }
+
+    /// `Pool.getAmountOut`: the fee (in basis points) is taken from `amount_in` first, the rest is
+    /// swapped along the curve. Zero if the pool would revert.
+    pub fn amount_out(
+        &self,
+        amount_in: U256,
+        reserve_in: U256,
+        reserve_out: U256,
+        zero_for_one: bool,
+        fee: u32,
+    ) -> U256 {
+        amount_in
+            .checked_mul(U256::from(fee))
+            .map(|fee_amount| amount_in - fee_amount / U256::from(FEE_DENOMINATOR))
+            .and_then(|amount_in| {
+                self.amount_out_without_fee(amount_in, reserve_in, reserve_out, zero_for_one)
+            })
+            .unwrap_or(U256::ZERO)
+    }
+
+    /// `log10` of the marginal rate (`token_out` per `token_in` in token units) at the given
+    /// reserves, without the fee. The curve is flat around the `1:1` (normalized) point, so this is
+    /// close to `1:1` for balanced pools no matter the reserves.
+    pub fn log10_rate(&self, reserve_in: U256, reserve_out: U256, zero_for_one: bool) -> f64 {
+        let (decimals_in, decimals_out) = self.decimals(zero_for_one);
+        let decimals_in = f64::from(decimals_in);
+        let decimals_out = f64::from(decimals_out);
+
+        // Normalized y / x
+        let log10_ratio =
+            (reserve_out.approx_log10() - decimals_out) - (reserve_in.approx_log10() - decimals_in);
+        let ratio = 10_f64.powf(log10_ratio);
+
+        // dy/dx of x^3 * y + y^3 * x = k is (3x^2y + y^3) / (x^3 + 3xy^2)
+        let rate = ratio * (3.0 + ratio * ratio) / (1.0 + 3.0 * ratio * ratio);
+
+        rate.log10() + decimals_out - decimals_in
+    }
+
+    /// `Pool._getAmountOut` for a stable pool
+    fn amount_out_without_fee(
+        &self,
+        amount_in: U256,
+        reserve_in: U256,
+        reserve_out: U256,
+        zero_for_one: bool,
+    ) -> Option<U256> {
+        let (reserve0, reserve1) = if zero_for_one {
+            (reserve_in, reserve_out)
+        } else {
+            (reserve_out, reserve_in)
+        };
+        let xy = self.k(reserve0, reserve1)?;
+
+        let (unit_in, unit_out) = self.units(zero_for_one)?;
+        let reserve_a = normalize(reserve_in, unit_in)?;
+        let reserve_b = normalize(reserve_out, unit_out)?;
+        let amount_in = normalize(amount_in, unit_in)?;
+
+        let y = reserve_b.checked_sub(self.get_y(
+            amount_in.checked_add(reserve_a)?,
+            xy,
+            reserve_b,
+        )?)?;
+        Some(y.checked_mul(unit_out)? / PRECISION)
+    }
+
+    /// `Pool._get_y`: solves `f(x0, y) = xy` for `y` with Newton's method starting at `y`
+    fn get_y(&self, x0: U256, xy: U256, mut y: U256) -> Option<U256> {
+        for _ in 0..MAX_ITERATIONS {
+            let k = f(x0, y)?;
+            if k < xy {
+                let mut dy = (xy - k).checked_mul(PRECISION)?.checked_div(d(x0, y)?)?;
+                if dy.is_zero() {
+                    if k == xy {
+                        return Some(y);
+                    }
+                    // The pool checks with `_k`, which normalizes the arguments again
+                    if self.k(x0, y.checked_add(U256::from(1))?)? > xy {
+                        return y.checked_add(U256::from(1));
+                    }
+                    dy = U256::from(1);
+                }
+                y = y.checked_add(dy)?;
+            } else {
+                let mut dy = (k - xy).checked_mul(PRECISION)?.checked_div(d(x0, y)?)?;
+                if dy.is_zero() {
+                    if k == xy || f(x0, y.checked_sub(U256::from(1))?)? < xy {
+                        return Some(y);
+                    }
+                    dy = U256::from(1);
+                }
+                y = y.checked_sub(dy)?;
+            }
+        }
+
+        // The pool reverts with "!y"
+        None
+    }
+
+    /// `Pool._k` for a stable pool: the invariant of the token0 and token1 reserves
+    fn k(&self, x: U256, y: U256) -> Option<U256> {
+        let (unit0, unit1) = self.units(true)?;
+        f(normalize(x, unit0)?, normalize(y, unit1)?)
+    }
+
+    /// `(decimals_in, decimals_out)` in the swap direction
+    const fn decimals(&self, zero_for_one: bool) -> (u8, u8) {
+        if zero_for_one {
+            (self.decimals0, self.decimals1)
+        } else {
+            (self.decimals1, self.decimals0)
+        }
+    }
+
+    /// `(10^decimals_in, 10^decimals_out)` in the swap direction
+    fn units(&self, zero_for_one: bool) -> Option<(U256, U256)> {
+        let (decimals_in, decimals_out) = self.decimals(zero_for_one);
+        let ten = U256::from(10);
+        Some((
+            ten.checked_pow(U256::from(decimals_in))?,
+            ten.checked_pow(U256::from(decimals_out))?,
+        ))
+    }
+}
+
+/// Scale an amount in token units to 18 decimals
+fn normalize(amount: U256, unit: U256) -> Option<U256> {
+    Some(amount.checked_mul(PRECISION)? / unit)
+}
+
+/// `Pool._f`: `x0^3 * y + y^3 * x0` in 18 decimals
+fn f(x0: U256, y: U256) -> Option<U256> {
+    let a = x0.checked_mul(y)? / PRECISION;
+    let b = (x0.checked_mul(x0)? / PRECISION).checked_add(y.checked_mul(y)? / PRECISION)?;
+    Some(a.checked_mul(b)? / PRECISION)
+}
+
+/// `Pool._d`: the derivative of `_f` by `y`
+fn d(x0: U256, y: U256) -> Option<U256> {
+    let a = U256::from(3)
+        .checked_mul(x0)?
+        .checked_mul(y.checked_mul(y)? / PRECISION)?
+        / PRECISION;
+    let b = (x0.checked_mul(x0)? / PRECISION).checked_mul(x0)? / PRECISION;
+    a.checked_add(b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const E18: u128 = 1_000_000_000_000_000_000;
+
+    fn amount(units: u128, decimals: u32) -> U256 {
+        U256::from(units * 10_u128.pow(decimals))
+    }
+
+    #[test]
+    fn test_new_invalid() {
+        assert_eq!(
+            SolidlyStable::new(18, 78).err().unwrap().to_string(),
+            "Token decimals must be at most 77, got 18 and 78"
+        );
+    }
+
+    #[test]
+    fn test_amount_out() {
+        let stable = SolidlyStable::new(18, 18).unwrap();
+        let reserve = amount(1_000_000, 18);
+        for (amount_in, fee, expected) in [
+            // Almost 1:1 and the fee
+            (amount(1_000, 18), 5, 999_499_999_500_999_250_748_u128),
+            (amount(1_000, 18), 0, 999_999_999_500_000_000_500),
+            // 10% of the reserves still has little slippage
+            (amount(100_000, 18), 5, 99_900_151_543_813_496_655_819),
+        ] {
+            assert_eq!(
+                stable.amount_out(amount_in, reserve, reserve, true, fee),
+                U256::from(expected)
+            );
+        }
+
+        // Imbalanced pool
+        let reserve0 = amount(2_000_000, 18);
+        let reserve1 = amount(1_000_000, 18);
+        assert_eq!(
+            stable.amount_out(amount(1_000, 18), reserve0, reserve1, true, 5),
+            U256::from(927_910_345_003_529_416_251_u128)
+        );
+        assert_eq!(
+            stable.amount_out(amount(1_000, 18), reserve1, reserve0, false, 5),
+            U256::from(1_076_139_454_284_903_988_107_u128)
+        );
+    }
+
+    #[test]
+    fn test_amount_out_with_decimals() {
+        // USDC (6 decimals) / DAI (18 decimals)
+        let stable = SolidlyStable::new(6, 18).unwrap();
+        let reserve0 = amount(1_000_000, 6);
+        let reserve1 = amount(1_000_000, 18);
+
+        assert_eq!(
+            stable.amount_out(amount(1_000, 6), reserve0, reserve1, true, 5),
+            U256::from(999_499_999_500_999_250_748_u128)
+        );
+        assert_eq!(
+            stable.amount_out(amount(1_000, 18), reserve1, reserve0, false, 5),
+            U256::from(999_499_999)
+        );
+    }
+
+    #[test]
+    fn test_log10_rate() {
+        let stable = SolidlyStable::new(6, 18).unwrap();
+        let reserve0 = amount(1_000_000, 6);
+        let reserve1 = amount(1_000_000, 18);
+
+        // 1 USDC unit buys 1e12 DAI units
+        assert!((stable.log10_rate(reserve0, reserve1, true) - 12.0).abs() < 1e-6);
+        assert!((stable.log10_rate(reserve1, reserve0, false) + 12.0).abs() < 1e-6);
+
+        // Twice the reserves of token0 is a much smaller price move than in a constant product
+        // pool: 0.93 instead of 0.5
+        let stable = SolidlyStable::new(18, 18).unwrap();
+        let rate = stable.log10_rate(U256::from(2 * E18), U256::from(E18), true);
+        assert!((rate - 0.928_571_f64.log10()).abs() < 1e-6);
+    }
+}
@@ -6,9 +6,15 @@ use std::hash::{Hash, Hasher};
 use alloy::primitives::U256;
 use eyre::{bail, Error};
 
-use super::pool::{Pool, PoolId};
+use super::pool::{Pool, PoolId, PoolKind};
 use super::token::TokenId;
 
+/// Swap fees are in basis points: 30 is 0.3%
+pub const FEE_DENOMINATOR: u32 = 10_000;
+
+/// The Uniswap V2 fee, used when a factory fee is not known
+pub const DEFAULT_FEE: u32 = 30;
+
 /// The direction of a swap
 #[derive(Clone, Eq, PartialEq, Hash, Ord, PartialOrd)]
 pub enum Direction {
@@ -68,7 +74,9 @@ pub struct Swap {
     pub token_out: TokenId,
     reserve_in: Option<U256>,
     reserve_out: Option<U256>,
+    fee: u32,
     log_rate: Option<i64>,
+    kind: PoolKind,
 }
 
 /// We compare `SwapSide`s by their `token0`, `token1`, and `id` only. Note, that reserves
@@ -144,11 +152,16 @@ impl Swap {
         token_out: TokenId,
         reserve_in: Option<U256>,
         reserve_out: Option<U256>,
+        fee: u32,
     ) -> Result<Self, Error> {
         if token_in == token_out {
             bail!("Swap token0 and token1 must be different");
         }
 
+        if fee >= FEE_DENOMINATOR {
+            bail!("Swap fee must be less than {FEE_DENOMINATOR} basis points, got {fee}");
+        }
+
         assert!(
             reserve_in.is_none() && reserve_out.is_none()
                 || reserve_in.is_some() && reserve_out.is_some(),
@@ -157,7 +170,7 @@ impl Swap {
 
         let log_rate = match (reserve_in, reserve_out) {
             (Some(reserve_in), Some(reserve_out)) => {
-                let log_rate = Self::calculated_log_rate(reserve_in, reserve_out);
+                let log_rate = Self::calculated_log_rate(reserve_in, reserve_out, fee);
                 Some(log_rate)
             }
             _ => None,
@@ -169,10 +182,25 @@ impl Swap {
             token_out,
             reserve_in,
             reserve_out,
+            fee,
             log_rate,
+            kind: PoolKind::UniswapV2,
         })
     }
 
+    /// Sets the kind of the pool the swap is in. Stable pools are not priced by the reserve
+    /// ratio, so their log rate is recalculated.
+    fn with_kind(mut self, kind: PoolKind) -> Self {
+        if let (PoolKind::SolidlyStable(stable), Some(reserve_in), Some(reserve_out)) =
+            (&kind, self.reserve_in, self.reserve_out)
+        {
+            let log10_rate = stable.log10_rate(reserve_in, reserve_out, self.is_zero_for_one());
+            self.log_rate = Some(Self::scaled_log_rate(log10_rate, self.fee));
+        }
+        self.kind = kind;
+        self
+    }
+
     const fn assert_reserves(&self) {
         assert!(self.has_reserves(), "Swap must have reserves");
     }
@@ -192,6 +220,16 @@ impl Swap {
         self.reserve_out.unwrap()
     }
 
+    /// Swap fee in basis points
+    pub const fn fee(&self) -> u32 {
+        self.fee
+    }
+
+    /// The part of `amount_in` that is actually swapped, out of `FEE_DENOMINATOR`
+    pub const fn fee_numerator(&self) -> u32 {
+        FEE_DENOMINATOR - self.fee
+    }
+
     pub const fn has_reserves(&self) -> bool {
         self.reserve_in.is_some() && self.reserve_out.is_some()
     }
@@ -200,6 +238,17 @@ impl Swap {
         self.reserve_in.is_none() || self.reserve_out.is_none()
     }
 
+    /// How the pool of the swap prices it
+    pub const fn kind(&self) -> &PoolKind {
+        &self.kind
+    }
+
+    /// Whether the swap follows the Uniswap V2 constant product formula (`x * y = k`).
+    /// Uniswap V3 swaps only do within the current tick range, stable swaps never do.
+    pub const fn is_constant_product(&self) -> bool {
+        matches!(self.kind, PoolKind::UniswapV2)
+    }
+
     /// Create a new swap side for the forward direction: token0 -> token1
     pub fn forward(pool: &Pool) -> Self {
         let token_in = pool.token0;
@@ -210,7 +259,16 @@ impl Swap {
             pool_id: pool.id.clone(),
             direction: Direction::ZeroForOne,
         };
-        Self::new(swap_id, token_in, token_out, reserve_in, reserve_out).unwrap()
+        Self::new(
+            swap_id,
+            token_in,
+            token_out,
+            reserve_in,
+            reserve_out,
+            pool.fee,
+        )
+        .unwrap()
+        .with_kind(pool.kind.clone())
     }
 
     /// Create a new swap side for the reverse direction: token1 -> token0
@@ -223,7 +281,16 @@ impl Swap {
             pool_id: pool.id.clone(),
             direction: Direction::OneForZero,
         };
-        Self::new(swap_id, token_in, token_out, reserve_in, reserve_out).unwrap()
+        Self::new(
+            swap_id,
+            token_in,
+            token_out,
+            reserve_in,
+            reserve_out,
+            pool.fee,
+        )
+        .unwrap()
+        .with_kind(pool.kind.clone())
     }
 
     /// Returns true if the swap side is the `OneForZero` direction
@@ -243,25 +310,21 @@ impl Swap {
         self.id.pool_id == other.id.pool_id && self.id.direction.is_opposite(&other.id.direction)
     }
 
-    /// Estimated gas cost of the swap in WETH
-    /// This is a rough estimate and should not be relied on really.
-    /// This is based on 150k gas for our contract overhead.
-    /// TODO: review, maybe use gas price oracle?
-    pub const fn estimated_gas_cost_in_weth() -> f64 {
-        0.0001
-    }
-
     /// Calculate the log rate of a swap for faster computation
     /// We replace rate multiplication with log addition
-    /// Takes into account the swap fee (default 0.3%)
+    /// Takes into account the swap fee (0.997 fee factor for the default 0.3%)
+    fn calculated_log_rate(reserve0: U256, reserve1: U256, fee: u32) -> i64 {
+        Self::scaled_log_rate(reserve1.approx_log10() - reserve0.approx_log10(), fee)
+    }
+
+    /// Scale a `log10` rate to an integer log rate and adjust it by the swap fee
     #[allow(clippy::cast_possible_truncation)]
-    fn calculated_log_rate(reserve0: U256, reserve1: U256) -> i64 {
+    fn scaled_log_rate(log10_rate: f64, fee: u32) -> i64 {
         const SCALE: f64 = 1_000_000.0;
-        // Apply fee factor (0.997 for 0.3% fee)
-        const FEE_FACTOR: f64 = 0.997;
+        let fee_factor = f64::from(FEE_DENOMINATOR - fee) / f64::from(FEE_DENOMINATOR);
 
         // Calculate log rate with fee adjustment
-        ((reserve1.approx_log10() - reserve0.approx_log10() + FEE_FACTOR.log10()) * SCALE) as i64
+        ((log10_rate + fee_factor.log10()) * SCALE) as i64
     }
 }
 
@@ -272,22 +335,21 @@ mod tests {
 
     use alloy::primitives::U256;
 
-    use crate::arb::pool::PoolId;
-    use crate::arb::swap::{Direction, Swap, SwapId};
+    use crate::arb::swap::{Direction, Swap, SwapId, DEFAULT_FEE};
     use crate::arb::test_helpers::*;
-    use crate::arb::token::TokenId;
 
     #[test]
     fn test_same_tokens() {
         let swap = Swap::new(
             SwapId {
-                pool_id: PoolId::from(address_from_str("F1")),
+                pool_id: pool_id("F1"),
                 direction: Direction::ZeroForOne,
             },
-            TokenId::from(address_from_str("A")),
-            TokenId::from(address_from_str("A")),
+            token_id("A"),
+            token_id("A"),
             Some(U256::from(100)),
             Some(U256::from(200)),
+            DEFAULT_FEE,
         );
         assert_eq!(
             swap.err().unwrap().to_string(),
@@ -295,6 +357,25 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_invalid_fee() {
+        let swap = Swap::new(
+            SwapId {
+                pool_id: pool_id("F1"),
+                direction: Direction::ZeroForOne,
+            },
+            token_id("A"),
+            token_id("B"),
+            Some(U256::from(100)),
+            Some(U256::from(200)),
+            10_000,
+        );
+        assert_eq!(
+            swap.err().unwrap().to_string(),
+            "Swap fee must be less than 10000 basis points, got 10000"
+        );
+    }
+
     #[test]
     fn test_log_rate() {
         for (reserve_in, reserve_out, expected) in &[
@@ -308,6 +389,21 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_log_rate_with_fee() {
+        for (fee, expected) in &[
+            // fee, expected
+            (0, 301_029),
+            (25, 299_942),
+            (30, 299_725),
+            (100, 296_665),
+        ] {
+            let test_swap = swap_with_fee("F1", "A", "B", 100, 200, *fee);
+            assert_eq!(test_swap.fee(), *fee);
+            assert_eq!(test_swap.log_rate, Some(*expected));
+        }
+    }
+
     #[test]
     fn test_equality_and_hash() {
         let swap1 = swap("F1", "A", "B", 100, 200);
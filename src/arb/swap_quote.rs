@@ -1,13 +1,22 @@
-use alloy::primitives::U256;
+use alloy::primitives::{Uint, U256};
 
-use super::swap::Swap;
+use super::pool::PoolKind;
+use super::swap::{Swap, SwapId, FEE_DENOMINATOR};
+
+/// Wide enough for the product of an amount, a fee and a reserve, all up to 256 bits
+type U768 = Uint<768, 12>;
 
 /// A quote for a swap: the amount of tokens we get out of the swap given an amount of tokens we put in.
 ///
-/// This is simply the implementation of the Uniswap v2 formula. This is returned by the `Cycle`
+/// This is the Uniswap V2 formula with the swap fee, the Uniswap V3 swap loop crossing
+/// initialized ticks, or the Solidly stable curve. This is returned by the `Cycle`
 /// optimizer. We need complete quotes for each swap in a cycle (both amount in and amount out).
 #[derive(Debug, Clone)]
 pub struct SwapQuote {
+    /// The swap this quote is for, so it can be executed
+    swap_id: SwapId,
+    /// Whether the pool is a Uniswap V3 pool, which has no pair `swap` to execute it with
+    uniswap_v3: bool,
     amount_in: U256,
     amount_out: U256,
 }
@@ -26,6 +35,8 @@ impl SwapQuote {
         let amount_out = Self::calculated_amount_out(swap, amount_in);
 
         Self {
+            swap_id: swap.id.clone(),
+            uniswap_v3: matches!(swap.kind(), PoolKind::UniswapV3(_)),
             amount_in,
             amount_out,
         }
@@ -39,6 +50,14 @@ impl SwapQuote {
         amount_out_f64 / amount_in_f64
     }
 
+    pub const fn swap_id(&self) -> &SwapId {
+        &self.swap_id
+    }
+
+    pub const fn is_uniswap_v3(&self) -> bool {
+        self.uniswap_v3
+    }
+
     pub const fn amount_in(&self) -> U256 {
         self.amount_in
     }
@@ -48,7 +67,7 @@ impl SwapQuote {
     }
 
     /// The amount of tokens we get out of the swap given an amount of tokens we put in
-    /// Uses the rate which already includes the fee calculation
+    /// Uses the swap fee in basis points for V2 and the pool state for V3 and stable pools
     #[allow(clippy::cast_precision_loss)]
     fn calculated_amount_out(swap: &Swap, amount_in: U256) -> U256 {
         assert!(
@@ -56,20 +75,53 @@ impl SwapQuote {
             "Swap must have reserves to calculate amount out"
         );
 
-        let fee_numerator = U256::from(997);
-        let fee_denominator = U256::from(1000);
+        match swap.kind() {
+            PoolKind::UniswapV2 => {}
+            PoolKind::UniswapV3(state) => {
+                return state.amount_out(amount_in, swap.is_zero_for_one());
+            }
+            PoolKind::SolidlyStable(stable) => {
+                return stable.amount_out(
+                    amount_in,
+                    swap.reserve_in(),
+                    swap.reserve_out(),
+                    swap.is_zero_for_one(),
+                    swap.fee(),
+                );
+            }
+        }
+
+        uniswap_v2_amount_out(amount_in, swap.reserve_in(), swap.reserve_out(), swap.fee())
+    }
+}
 
-        let amount_in_with_fee = amount_in * fee_numerator;
-        let numerator = amount_in_with_fee * swap.reserve_out();
-        let denominator = (swap.reserve_in() * fee_denominator) + amount_in_with_fee;
+/// Uniswap V2 `getAmountOut` with a fee in basis points
+///
+/// Solidly volatile pools have `uint256` reserves, the product of the amount and a reserve may not
+/// fit 512 bits either. The amount out is less than the reserve out, so it fits 256 bits.
+pub fn uniswap_v2_amount_out(
+    amount_in: U256,
+    reserve_in: U256,
+    reserve_out: U256,
+    fee: u32,
+) -> U256 {
+    let amount_in_with_fee = U768::from(amount_in) * U768::from(FEE_DENOMINATOR - fee);
+    let numerator = amount_in_with_fee * U768::from(reserve_out);
+    let denominator = U768::from(reserve_in) * U768::from(FEE_DENOMINATOR) + amount_in_with_fee;
 
-        numerator / denominator
+    // Nothing in and nothing to trade against
+    if denominator.is_zero() {
+        return U256::ZERO;
     }
+
+    U256::from(numerator / denominator)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::arb::pool::Pool;
+    use crate::arb::swap::DEFAULT_FEE;
     use crate::arb::test_helpers::*;
 
     #[test]
@@ -98,4 +150,87 @@ mod tests {
             assert_eq!(swap_quote.amount_out(), U256::from(*expected));
         }
     }
+
+    #[test]
+    fn test_amount_out_with_fee() {
+        for (fee, expected) in &[
+            // fee, expected
+            (0, 9_900_990),
+            (20, 9_881_383),
+            (25, 9_876_482),
+            (30, 9_871_580),
+        ] {
+            let swap = swap_with_fee("F1", "A", "B", 1_000_000_000, 1_000_000_000, *fee);
+            let swap_quote = SwapQuote::new(&swap, U256::from(10_000_000));
+            assert_eq!(swap_quote.amount_out(), U256::from(*expected));
+        }
+    }
+
+    #[test]
+    fn test_amount_out_wide_reserves() {
+        // Solidly volatile pools have `uint256` reserves, the products do not fit 256 bits
+        let large = U256::from(1) << 250;
+        let wide_pool = Pool::new(
+            pool_id("F1"),
+            token_id("A"),
+            token_id("B"),
+            Some(large),
+            Some(large),
+            DEFAULT_FEE,
+        );
+
+        // Half the reserve in gets `large * 9_970 / 19_970` out
+        let swap_quote = SwapQuote::new(&Swap::forward(&wide_pool), large);
+        assert_eq!(
+            swap_quote.amount_out(),
+            "903266720155266077532707436574094298711427881757351983814309051513906721418"
+                .parse::<U256>()
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn test_amount_out_uniswap_v3() {
+        // Within the current tick range a V3 pool is a constant product pool with its virtual
+        // reserves, rounding aside
+        let e18 = 1_000_000_000_000_000_000_i128;
+        let v3_pool = uniswap_v3_pool(
+            "F1",
+            "A",
+            "B",
+            0,
+            e18.unsigned_abs(),
+            &[(-600, e18), (600, -e18)],
+        );
+        let v2_pool = pool(
+            "F2",
+            "A",
+            "B",
+            1_000_000_000_000_000_000,
+            1_000_000_000_000_000_000,
+        );
+
+        let amount_in = U256::from(1_000_000_000_000_000_u64);
+        let v3_quote = SwapQuote::new(&Swap::forward(&v3_pool), amount_in);
+        let v2_quote = SwapQuote::new(&Swap::forward(&v2_pool), amount_in);
+
+        assert_eq!(v3_quote.amount_out(), U256::from(996_006_981_039_903_u64));
+        assert_eq!(v2_quote.amount_out(), U256::from(996_006_981_039_903_u64));
+    }
+
+    #[test]
+    fn test_amount_out_solidly_stable() {
+        // 1M USDC-like tokens with 6 decimals on both sides
+        let stable_pool =
+            solidly_stable_pool("F1", "A", "B", 1_000_000_000_000, 1_000_000_000_000, (6, 6));
+        let v2_pool = pool("F2", "A", "B", 1_000_000_000_000, 1_000_000_000_000);
+
+        let amount_in = U256::from(100_000_000_000_u64);
+        let stable_quote = SwapQuote::new(&Swap::forward(&stable_pool), amount_in);
+        let v2_quote = SwapQuote::new(&Swap::forward(&v2_pool), amount_in);
+
+        // 10% of the reserves: the stable pool barely moves, the V2 pool slips 9%
+        assert_eq!(stable_quote.amount_out(), U256::from(99_900_151_543_u64));
+        assert_eq!(v2_quote.amount_out(), U256::from(90_661_089_388_u64));
+    }
 }
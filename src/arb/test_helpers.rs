@@ -5,12 +5,20 @@ use alloy::primitives::{Address, U256};
 
 use super::cycle::Cycle;
 use super::pool::PoolId;
-use super::swap::{Direction, SwapId};
+use super::solidly_stable::{SolidlyStable, DEFAULT_STABLE_FEE};
+use super::swap::{Direction, SwapId, DEFAULT_FEE};
 use super::swap_quote::SwapQuote;
 use super::token::{Token, TokenId};
+use super::uniswap_v3::{sqrt_ratio_at_tick, UniswapV3State};
+use super::world_config::WorldConfig;
 use super::{swap::Swap, world::World};
+use crate::utils::constants::BASE_CHAIN_ID;
 
 pub fn world(pool_args: &[(&str, &str, &str, u64, u64)]) -> World {
+    world_with_config(pool_args, WorldConfig::default())
+}
+
+pub fn world_with_config(pool_args: &[(&str, &str, &str, u64, u64)], config: WorldConfig) -> World {
     let pools: std::collections::HashSet<_> = pool_args
         .iter()
         .map(|(id, token0, token1, reserve0, reserve1)| {
@@ -18,11 +26,21 @@ pub fn world(pool_args: &[(&str, &str, &str, u64, u64)]) -> World {
         })
         .collect();
 
-    World::new(&pools)
+    World::new(&pools, config)
 }
 
 pub fn token(id: &str) -> Token {
-    Token::new(TokenId::from(address_from_str(id)))
+    Token::new(token_id(id))
+}
+
+/// Token id of a test token on Base
+pub fn token_id(id: &str) -> TokenId {
+    TokenId::new(BASE_CHAIN_ID, address_from_str(id))
+}
+
+/// Pool id of a test pool on Base
+pub fn pool_id(id: &str) -> PoolId {
+    PoolId::new(BASE_CHAIN_ID, address_from_str(id))
 }
 
 /// Create a swap from a pool id, token0, token1, and reserves
@@ -34,6 +52,25 @@ pub fn swap(
     token_out: &str,
     reserve_in: u64,
     reserve_out: u64,
+) -> Swap {
+    swap_with_fee(
+        pool_id,
+        token_in,
+        token_out,
+        reserve_in,
+        reserve_out,
+        DEFAULT_FEE,
+    )
+}
+
+/// Create a swap like `swap` with a custom fee in basis points
+pub fn swap_with_fee(
+    pool_id: &str,
+    token_in: &str,
+    token_out: &str,
+    reserve_in: u64,
+    reserve_out: u64,
+    fee: u32,
 ) -> Swap {
     make_swap(
         pool_id,
@@ -41,27 +78,29 @@ pub fn swap(
         token_out,
         Some(reserve_in),
         Some(reserve_out),
+        fee,
     )
 }
 
 pub fn bare_swap(pool_id: &str, token_in: &str, token_out: &str) -> Swap {
-    make_swap(pool_id, token_in, token_out, None, None)
+    make_swap(pool_id, token_in, token_out, None, None, DEFAULT_FEE)
 }
 
 fn make_swap(
-    pool_id: &str,
+    pool: &str,
     token_in: &str,
     token_out: &str,
     reserve_in: Option<u64>,
     reserve_out: Option<u64>,
+    fee: u32,
 ) -> Swap {
     assert!(
         (token_in != token_out),
         "Token0 and token1 must be different"
     );
 
-    let token0_id = TokenId(address_from_str(token_in));
-    let token1_id = TokenId(address_from_str(token_out));
+    let token0_id = token_id(token_in);
+    let token1_id = token_id(token_out);
 
     let direction = if token0_id < token1_id {
         Direction::ZeroForOne
@@ -69,7 +108,7 @@ fn make_swap(
         Direction::OneForZero
     };
 
-    let pool_id = PoolId::from(address_from_str(pool_id));
+    let pool_id = pool_id(pool);
 
     // Convert reserve_in to Option<U256> based on whether it's None or Some
     let reserve_in_u256 = reserve_in.map(U256::from);
@@ -79,10 +118,11 @@ fn make_swap(
 
     Swap::new(
         SwapId { pool_id, direction },
-        TokenId::from(address_from_str(token_in)),
-        TokenId::from(address_from_str(token_out)),
+        token0_id,
+        token1_id,
         reserve_in_u256,
         reserve_out_u256,
+        fee,
     )
     .unwrap()
 }
@@ -129,27 +169,88 @@ pub fn swap_quote(
 }
 
 pub fn pool(symbol: &str, token0: &str, token1: &str, reserve0: u64, reserve1: u64) -> Pool {
+    pool_with_fee(symbol, token0, token1, reserve0, reserve1, DEFAULT_FEE)
+}
+
+/// Create a pool like `pool` with a custom fee in basis points
+pub fn pool_with_fee(
+    symbol: &str,
+    token0: &str,
+    token1: &str,
+    reserve0: u64,
+    reserve1: u64,
+    fee: u32,
+) -> Pool {
     assert!(token0 < token1, "Token0 must be less than token1");
 
     Pool::new(
-        PoolId::from(address_from_str(symbol)),
-        TokenId::from(address_from_str(token0)),
-        TokenId::from(address_from_str(token1)),
+        pool_id(symbol),
+        token_id(token0),
+        token_id(token1),
         Some(U256::from(reserve0)),
         Some(U256::from(reserve1)),
+        fee,
     )
 }
 
 pub fn bare_pool(symbol: &str, token0: &str, token1: &str) -> Pool {
     Pool::new(
-        PoolId::from(address_from_str(symbol)),
-        TokenId::from(address_from_str(token0)),
-        TokenId::from(address_from_str(token1)),
+        pool_id(symbol),
+        token_id(token0),
+        token_id(token1),
         None,
         None,
+        DEFAULT_FEE,
+    )
+}
+
+/// Create a Solidly stable pool with the default stable fee and the given token decimals
+pub fn solidly_stable_pool(
+    symbol: &str,
+    token0: &str,
+    token1: &str,
+    reserve0: u64,
+    reserve1: u64,
+    decimals: (u8, u8),
+) -> Pool {
+    assert!(token0 < token1, "Token0 must be less than token1");
+
+    Pool::solidly_stable(
+        pool_id(symbol),
+        token_id(token0),
+        token_id(token1),
+        Some(U256::from(reserve0)),
+        Some(U256::from(reserve1)),
+        DEFAULT_STABLE_FEE,
+        SolidlyStable::new(decimals.0, decimals.1).unwrap(),
     )
 }
 
+/// Create a Uniswap V3 pool with a 0.3% fee and a tick spacing of 60, priced at `tick`.
+/// `ticks` are the initialized ticks with their liquidity net.
+pub fn uniswap_v3_pool(
+    symbol: &str,
+    token0: &str,
+    token1: &str,
+    tick: i32,
+    liquidity: u128,
+    ticks: &[(i32, i128)],
+) -> Pool {
+    assert!(token0 < token1, "Token0 must be less than token1");
+
+    let state = UniswapV3State::new(
+        sqrt_ratio_at_tick(tick).unwrap(),
+        tick,
+        liquidity,
+        3_000,
+        60,
+        ticks,
+    )
+    .unwrap();
+
+    Pool::uniswap_v3(pool_id(symbol), token_id(token0), token_id(token1), state)
+}
+
 pub fn swap_by_index(market: &World, index: usize) -> &Swap {
     &market.swap_vec[index]
 }
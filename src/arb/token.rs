@@ -1,21 +1,26 @@
 /// A token is what we are trading
 /// Here, mostly for type safety.
-use alloy::primitives::Address;
+use alloy::primitives::{Address, ChainId};
 use core::fmt::{self, Debug};
 use eyre::Result;
 use std::fmt::Display;
 
 /// Globally unique identifier for a token to distinguish between different chains
 #[derive(Clone, Copy, Default, PartialEq, Eq, Hash, Ord, PartialOrd)]
-pub struct TokenId(pub Address);
+pub struct TokenId {
+    pub chain_id: ChainId,
+    pub address: Address,
+}
 
-impl TryFrom<&str> for TokenId {
-    type Error = eyre::Error;
+impl TokenId {
+    pub const fn new(chain_id: ChainId, address: Address) -> Self {
+        Self { chain_id, address }
+    }
 
-    fn try_from(s: &str) -> Result<Self> {
-        // Parse the string as an Address
+    /// Parse a checksummed token address on the given chain
+    pub fn parse(chain_id: ChainId, s: &str) -> Result<Self> {
         Address::parse_checksummed(s, None)
-            .map(Self)
+            .map(|address| Self::new(chain_id, address))
             .map_err(|e| eyre::eyre!("Invalid token address: {e}"))
     }
 }
@@ -26,9 +31,10 @@ impl TryFrom<&str> for TokenId {
 /// However, we don't want to print the full 40 character hex string when debugging.
 /// There is is '10 zeros test' in case we have some real address that have trailing zeros.
 /// We consider the odds of 10+ trailing zeros to be so low that we can safely truncate.
+/// The chain id is left out, a `World` only has tokens of one chain.
 impl Debug for TokenId {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let hex = format!("{}", self.0);
+        let hex = format!("{}", self.address);
         let hex = hex.trim_start_matches("0x").to_uppercase();
         let zeros = hex.chars().rev().take_while(|&c| c == '0').count();
         if zeros > 10 {
@@ -40,23 +46,9 @@ impl Debug for TokenId {
     }
 }
 
-impl TryFrom<String> for TokenId {
-    type Error = eyre::Error;
-
-    fn try_from(s: String) -> Result<Self> {
-        Self::try_from(s.as_str())
-    }
-}
-
-impl From<Address> for TokenId {
-    fn from(address: Address) -> Self {
-        Self(address)
-    }
-}
-
 impl Display for TokenId {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}", self.0)
+        write!(f, "{}@{}", self.address, self.chain_id)
     }
 }
 
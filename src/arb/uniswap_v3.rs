@@ -0,0 +1,699 @@
+/// Uniswap V3 pool state and swap math.
+/// This is a port of `TickMath`, `SqrtPriceMath`, `SwapMath`, `TickBitmap` and the
+/// `UniswapV3Pool.swap` loop, so quotes match the pool to the wei.
+use std::collections::BTreeMap;
+use std::fmt::{self, Debug};
+
+use alloy::primitives::{U256, U512};
+use eyre::{bail, Result};
+
+/// The minimum tick that may be passed to `sqrt_ratio_at_tick`
+pub const MIN_TICK: i32 = -887_272;
+
+/// The maximum tick that may be passed to `sqrt_ratio_at_tick`
+pub const MAX_TICK: i32 = 887_272;
+
+/// `sqrt_ratio_at_tick(MIN_TICK)`
+pub const MIN_SQRT_RATIO: U256 = U256::from_limbs([4_295_128_739, 0, 0, 0]);
+
+/// `sqrt_ratio_at_tick(MAX_TICK)`
+pub const MAX_SQRT_RATIO: U256 = U256::from_limbs([
+    0x5d95_1d52_6398_8d26,
+    0xefd1_fc6a_5064_8849,
+    0x0000_0000_fffd_8963,
+    0,
+]);
+
+/// Fees are in hundredths of a basis point: 3000 is 0.3%
+pub const FEE_DENOMINATOR: u32 = 1_000_000;
+
+const RESOLUTION: usize = 96;
+
+/// Uniswap V3 pool state needed to quote swaps: `slot0`, the active liquidity and the
+/// initialized ticks.
+#[derive(Clone, PartialEq, Eq)]
+pub struct UniswapV3State {
+    sqrt_price_x96: U256,
+    tick: i32,
+    liquidity: u128,
+    fee: u32,
+    tick_spacing: i32,
+    /// Bitmap of initialized ticks (compressed by `tick_spacing`), 256 ticks per word
+    tick_bitmap: BTreeMap<i16, U256>,
+    /// Liquidity added (removed if negative) when an initialized tick is crossed left to right
+    liquidity_net: BTreeMap<i32, i128>,
+}
+
+impl Debug for UniswapV3State {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "UniswapV3State(sqrt_price_x96: {}, tick: {}, liquidity: {}, fee: {}, ticks: {})",
+            self.sqrt_price_x96,
+            self.tick,
+            self.liquidity,
+            self.fee,
+            self.liquidity_net.len()
+        )
+    }
+}
+
+impl UniswapV3State {
+    /// Creates a new pool state
+    ///
+    /// `fee` is in hundredths of a basis point, `ticks` are the initialized ticks with their
+    /// `liquidityNet`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if:
+    /// - `sqrt_price_x96` is out of the `MIN_SQRT_RATIO..MAX_SQRT_RATIO` range
+    /// - `fee` is not less than `FEE_DENOMINATOR`
+    /// - `tick_spacing` is not positive
+    /// - a tick is out of range or not a multiple of `tick_spacing`
+    pub fn new(
+        sqrt_price_x96: U256,
+        tick: i32,
+        liquidity: u128,
+        fee: u32,
+        tick_spacing: i32,
+        ticks: &[(i32, i128)],
+    ) -> Result<Self> {
+        if sqrt_price_x96 < MIN_SQRT_RATIO || sqrt_price_x96 >= MAX_SQRT_RATIO {
+            bail!("Sqrt price {sqrt_price_x96} is out of range");
+        }
+
+        if fee >= FEE_DENOMINATOR {
+            bail!("Fee must be less than {FEE_DENOMINATOR}, got {fee}");
+        }
+
+        if tick_spacing <= 0 {
+            bail!("Tick spacing must be positive, got {tick_spacing}");
+        }
+
+        let mut tick_bitmap = BTreeMap::new();
+        let mut liquidity_net = BTreeMap::new();
+        for &(initialized_tick, net) in ticks {
+            if !(MIN_TICK..=MAX_TICK).contains(&initialized_tick) {
+                bail!("Tick {initialized_tick} is out of range");
+            }
+            if initialized_tick % tick_spacing != 0 {
+                bail!("Tick {initialized_tick} is not a multiple of tick spacing {tick_spacing}");
+            }
+
+            let (word_position, bit_position) = position(initialized_tick / tick_spacing);
+            let word = tick_bitmap.entry(word_position).or_insert(U256::ZERO);
+            *word |= U256::from(1) << bit_position;
+            liquidity_net.insert(initialized_tick, net);
+        }
+
+        Ok(Self {
+            sqrt_price_x96,
+            tick,
+            liquidity,
+            fee,
+            tick_spacing,
+            tick_bitmap,
+            liquidity_net,
+        })
+    }
+
+    pub const fn sqrt_price_x96(&self) -> U256 {
+        self.sqrt_price_x96
+    }
+
+    pub const fn tick(&self) -> i32 {
+        self.tick
+    }
+
+    pub const fn liquidity(&self) -> u128 {
+        self.liquidity
+    }
+
+    /// Fee in hundredths of a basis point
+    pub const fn fee(&self) -> u32 {
+        self.fee
+    }
+
+    pub const fn tick_spacing(&self) -> i32 {
+        self.tick_spacing
+    }
+
+    /// Virtual reserves `(x, y)` of a constant product pool with the same liquidity at the
+    /// current price: `x = L / sqrt(P)`, `y = L * sqrt(P)`
+    pub fn virtual_reserves(&self) -> (U256, U256) {
+        let liquidity = U256::from(self.liquidity);
+        let q96 = U256::from(1) << RESOLUTION;
+        (
+            mul_div(liquidity, q96, self.sqrt_price_x96).unwrap_or(U256::MAX),
+            mul_div(liquidity, self.sqrt_price_x96, q96).unwrap_or(U256::MAX),
+        )
+    }
+
+    /// Amount out of an exact input swap, crossing initialized ticks as needed.
+    /// Zero if the pool would revert or does not have the liquidity to take all of `amount_in`.
+    pub fn amount_out(&self, amount_in: U256, zero_for_one: bool) -> U256 {
+        self.swap(amount_in, zero_for_one).unwrap_or(U256::ZERO)
+    }
+
+    /// The `UniswapV3Pool.swap` loop for an exact input with no price limit
+    fn swap(&self, amount_in: U256, zero_for_one: bool) -> Option<U256> {
+        let sqrt_price_limit_x96 = if zero_for_one {
+            MIN_SQRT_RATIO + U256::from(1)
+        } else {
+            MAX_SQRT_RATIO - U256::from(1)
+        };
+
+        let mut amount_remaining = amount_in;
+        let mut amount_out = U256::ZERO;
+        let mut sqrt_price_x96 = self.sqrt_price_x96;
+        let mut tick = self.tick;
+        let mut liquidity = self.liquidity;
+
+        while !amount_remaining.is_zero() && sqrt_price_x96 != sqrt_price_limit_x96 {
+            let (tick_next, initialized) =
+                self.next_initialized_tick_within_one_word(tick, self.tick_spacing, zero_for_one);
+            let tick_next = tick_next.clamp(MIN_TICK, MAX_TICK);
+            let sqrt_price_next_x96 = sqrt_ratio_at_tick(tick_next)?;
+
+            let sqrt_price_target_x96 = if zero_for_one {
+                sqrt_price_next_x96.max(sqrt_price_limit_x96)
+            } else {
+                sqrt_price_next_x96.min(sqrt_price_limit_x96)
+            };
+
+            let step = compute_swap_step(
+                sqrt_price_x96,
+                sqrt_price_target_x96,
+                liquidity,
+                amount_remaining,
+                self.fee,
+            )?;
+            sqrt_price_x96 = step.sqrt_price_next_x96;
+            amount_remaining = amount_remaining.checked_sub(step.amount_in + step.fee_amount)?;
+            amount_out = amount_out.checked_add(step.amount_out)?;
+
+            if sqrt_price_x96 == sqrt_price_next_x96 {
+                if initialized {
+                    let mut liquidity_net = self.liquidity_net[&tick_next];
+                    if zero_for_one {
+                        liquidity_net = liquidity_net.checked_neg()?;
+                    }
+                    liquidity = liquidity.checked_add_signed(liquidity_net)?;
+                }
+                tick = if zero_for_one {
+                    tick_next - 1
+                } else {
+                    tick_next
+                };
+            }
+            // Otherwise the whole input is used up and the loop ends
+        }
+
+        // The price limit was hit: the pool would only take part of the input
+        if !amount_remaining.is_zero() {
+            return None;
+        }
+
+        Some(amount_out)
+    }
+
+    /// `TickBitmap.nextInitializedTickWithinOneWord`: the next initialized tick in the same word
+    /// as `tick` to the left (`lte`) or to the right, or the word boundary if there is none.
+    fn next_initialized_tick_within_one_word(
+        &self,
+        tick: i32,
+        tick_spacing: i32,
+        lte: bool,
+    ) -> (i32, bool) {
+        // Round towards negative infinity
+        let compressed = tick.div_euclid(tick_spacing);
+
+        if lte {
+            let (word_position, bit_position) = position(compressed);
+            // All the 1s at or to the right of the current bit position
+            let mask =
+                (U256::from(1) << bit_position) - U256::from(1) + (U256::from(1) << bit_position);
+            let masked = self.word(word_position) & mask;
+
+            if masked.is_zero() {
+                ((compressed - i32::from(bit_position)) * tick_spacing, false)
+            } else {
+                let most_significant_bit = masked.bit_len() - 1;
+                (
+                    (compressed - (i32::from(bit_position) - most_significant_bit as i32))
+                        * tick_spacing,
+                    true,
+                )
+            }
+        } else {
+            let (word_position, bit_position) = position(compressed + 1);
+            // All the 1s at or to the left of the bit position
+            let mask = !((U256::from(1) << bit_position) - U256::from(1));
+            let masked = self.word(word_position) & mask;
+
+            if masked.is_zero() {
+                (
+                    (compressed + 1 + (255 - i32::from(bit_position))) * tick_spacing,
+                    false,
+                )
+            } else {
+                let least_significant_bit = masked.trailing_zeros() as i32;
+                (
+                    (compressed + 1 + (least_significant_bit - i32::from(bit_position)))
+                        * tick_spacing,
+                    true,
+                )
+            }
+        }
+    }
+
+    fn word(&self, word_position: i16) -> U256 {
+        self.tick_bitmap
+            .get(&word_position)
+            .copied()
+            .unwrap_or_default()
+    }
+}
+
+/// `TickBitmap.position`: word and bit position of a compressed tick
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+const fn position(compressed: i32) -> (i16, u8) {
+    ((compressed >> 8) as i16, (compressed & 0xff) as u8)
+}
+
+/// `TickMath.getSqrtRatioAtTick`: `sqrt(1.0001^tick) * 2^96`
+/// `None` if the tick is out of range
+pub fn sqrt_ratio_at_tick(tick: i32) -> Option<U256> {
+    const MULTIPLIERS: [u128; 19] = [
+        0xfff9_7272_373d_4132_59a4_6990_580e_213a,
+        0xfff2_e50f_5f65_6932_ef12_357c_f3c7_fdcc,
+        0xffe5_caca_7e10_e4e6_1c36_24ea_a094_1cd0,
+        0xffcb_9843_d60f_6159_c9db_5883_5c92_6644,
+        0xff97_3b41_fa98_c081_472e_6896_dfb2_54c0,
+        0xff2e_a164_66c9_6a38_43ec_78b3_26b5_2861,
+        0xfe5d_ee04_6a99_a2a8_11c4_61f1_969c_3053,
+        0xfcbe_86c7_900a_88ae_dcff_c83b_479a_a3a4,
+        0xf987_a725_3ac4_1317_6f2b_074c_f781_5e54,
+        0xf339_2b08_22b7_0005_940c_7a39_8e4b_70f3,
+        0xe715_9475_a2c2_9b74_43b2_9c7f_a6e8_89d9,
+        0xd097_f3bd_fd20_22b8_845a_d8f7_92aa_5825,
+        0xa9f7_4646_2d87_0fdf_8a65_dc1f_90e0_61e5,
+        0x70d8_69a1_56d2_a1b8_90bb_3df6_2baf_32f7,
+        0x31be_135f_97d0_8fd9_8123_1505_542f_cfa6,
+        0x09aa_508b_5b7a_84e1_c677_de54_f3e9_9bc9,
+        0x005d_6af8_dedb_8119_6699_c329_225e_e604,
+        0x0000_2216_e584_f5fa_1ea9_2604_1bed_fe98,
+        0x0000_0000_048a_1703_91f7_dc42_444e_8fa2,
+    ];
+
+    let abs_tick = tick.unsigned_abs();
+    if abs_tick > MAX_TICK.unsigned_abs() {
+        return None;
+    }
+
+    let mut ratio = if abs_tick & 0x1 == 0 {
+        U256::from(1) << 128
+    } else {
+        U256::from(0xfffc_b933_bd6f_ad37_aa2d_162d_1a59_4001_u128)
+    };
+    for (bit, multiplier) in MULTIPLIERS.iter().enumerate() {
+        if abs_tick & (0x2 << bit) != 0 {
+            ratio = (ratio * U256::from(*multiplier)) >> 128;
+        }
+    }
+
+    if tick > 0 {
+        ratio = U256::MAX / ratio;
+    }
+
+    // Round up to go from Q128.128 to Q128.96
+    let remainder = ratio & U256::from(u32::MAX);
+    Some((ratio >> 32) + U256::from(u8::from(!remainder.is_zero())))
+}
+
+/// Result of a single `SwapMath.computeSwapStep`
+struct SwapStep {
+    sqrt_price_next_x96: U256,
+    amount_in: U256,
+    amount_out: U256,
+    fee_amount: U256,
+}
+
+/// `SwapMath.computeSwapStep` for an exact input
+fn compute_swap_step(
+    sqrt_price_current_x96: U256,
+    sqrt_price_target_x96: U256,
+    liquidity: u128,
+    amount_remaining: U256,
+    fee: u32,
+) -> Option<SwapStep> {
+    let zero_for_one = sqrt_price_current_x96 >= sqrt_price_target_x96;
+    let fee_denominator = U256::from(FEE_DENOMINATOR);
+    let fee_complement = U256::from(FEE_DENOMINATOR - fee);
+
+    let amount_remaining_less_fee = mul_div(amount_remaining, fee_complement, fee_denominator)?;
+    let amount_in_to_target = if zero_for_one {
+        amount0_delta(
+            sqrt_price_target_x96,
+            sqrt_price_current_x96,
+            liquidity,
+            true,
+        )?
+    } else {
+        amount1_delta(
+            sqrt_price_current_x96,
+            sqrt_price_target_x96,
+            liquidity,
+            true,
+        )?
+    };
+
+    let sqrt_price_next_x96 = if amount_remaining_less_fee >= amount_in_to_target {
+        sqrt_price_target_x96
+    } else {
+        next_sqrt_price_from_input(
+            sqrt_price_current_x96,
+            liquidity,
+            amount_remaining_less_fee,
+            zero_for_one,
+        )?
+    };
+
+    let max = sqrt_price_target_x96 == sqrt_price_next_x96;
+
+    let (amount_in, amount_out) = if zero_for_one {
+        (
+            if max {
+                amount_in_to_target
+            } else {
+                amount0_delta(sqrt_price_next_x96, sqrt_price_current_x96, liquidity, true)?
+            },
+            amount1_delta(
+                sqrt_price_next_x96,
+                sqrt_price_current_x96,
+                liquidity,
+                false,
+            )?,
+        )
+    } else {
+        (
+            if max {
+                amount_in_to_target
+            } else {
+                amount1_delta(sqrt_price_current_x96, sqrt_price_next_x96, liquidity, true)?
+            },
+            amount0_delta(
+                sqrt_price_current_x96,
+                sqrt_price_next_x96,
+                liquidity,
+                false,
+            )?,
+        )
+    };
+
+    let fee_amount = if max {
+        mul_div_rounding_up(amount_in, U256::from(fee), fee_complement)?
+    } else {
+        // We didn't reach the target, so take the remainder of the maximum input as fee
+        amount_remaining.checked_sub(amount_in)?
+    };
+
+    Some(SwapStep {
+        sqrt_price_next_x96,
+        amount_in,
+        amount_out,
+        fee_amount,
+    })
+}
+
+/// `SqrtPriceMath.getNextSqrtPriceFromInput`
+fn next_sqrt_price_from_input(
+    sqrt_price_x96: U256,
+    liquidity: u128,
+    amount_in: U256,
+    zero_for_one: bool,
+) -> Option<U256> {
+    if sqrt_price_x96.is_zero() || liquidity == 0 {
+        return None;
+    }
+
+    if zero_for_one {
+        next_sqrt_price_from_amount0_rounding_up(sqrt_price_x96, liquidity, amount_in)
+    } else {
+        next_sqrt_price_from_amount1_rounding_down(sqrt_price_x96, liquidity, amount_in)
+    }
+}
+
+/// `SqrtPriceMath.getNextSqrtPriceFromAmount0RoundingUp` when adding token0
+fn next_sqrt_price_from_amount0_rounding_up(
+    sqrt_price_x96: U256,
+    liquidity: u128,
+    amount: U256,
+) -> Option<U256> {
+    if amount.is_zero() {
+        return Some(sqrt_price_x96);
+    }
+
+    let numerator1 = U256::from(liquidity) << RESOLUTION;
+
+    if let Some(product) = amount.checked_mul(sqrt_price_x96) {
+        if let Some(denominator) = numerator1.checked_add(product) {
+            return mul_div_rounding_up(numerator1, sqrt_price_x96, denominator);
+        }
+    }
+
+    Some(div_rounding_up(
+        numerator1,
+        (numerator1 / sqrt_price_x96).checked_add(amount)?,
+    ))
+}
+
+/// `SqrtPriceMath.getNextSqrtPriceFromAmount1RoundingDown` when adding token1
+fn next_sqrt_price_from_amount1_rounding_down(
+    sqrt_price_x96: U256,
+    liquidity: u128,
+    amount: U256,
+) -> Option<U256> {
+    let quotient = if amount.bit_len() <= 160 {
+        (amount << RESOLUTION) / U256::from(liquidity)
+    } else {
+        mul_div(amount, U256::from(1) << RESOLUTION, U256::from(liquidity))?
+    };
+
+    let next = sqrt_price_x96.checked_add(quotient)?;
+    (next.bit_len() <= 160).then_some(next)
+}
+
+/// `SqrtPriceMath.getAmount0Delta`: token0 amount between two prices
+fn amount0_delta(
+    sqrt_ratio_a_x96: U256,
+    sqrt_ratio_b_x96: U256,
+    liquidity: u128,
+    round_up: bool,
+) -> Option<U256> {
+    let (sqrt_ratio_a_x96, sqrt_ratio_b_x96) = if sqrt_ratio_a_x96 > sqrt_ratio_b_x96 {
+        (sqrt_ratio_b_x96, sqrt_ratio_a_x96)
+    } else {
+        (sqrt_ratio_a_x96, sqrt_ratio_b_x96)
+    };
+
+    if sqrt_ratio_a_x96.is_zero() {
+        return None;
+    }
+
+    let numerator1 = U256::from(liquidity) << RESOLUTION;
+    let numerator2 = sqrt_ratio_b_x96 - sqrt_ratio_a_x96;
+
+    if round_up {
+        Some(div_rounding_up(
+            mul_div_rounding_up(numerator1, numerator2, sqrt_ratio_b_x96)?,
+            sqrt_ratio_a_x96,
+        ))
+    } else {
+        Some(mul_div(numerator1, numerator2, sqrt_ratio_b_x96)? / sqrt_ratio_a_x96)
+    }
+}
+
+/// `SqrtPriceMath.getAmount1Delta`: token1 amount between two prices
+fn amount1_delta(
+    sqrt_ratio_a_x96: U256,
+    sqrt_ratio_b_x96: U256,
+    liquidity: u128,
+    round_up: bool,
+) -> Option<U256> {
+    let (sqrt_ratio_a_x96, sqrt_ratio_b_x96) = if sqrt_ratio_a_x96 > sqrt_ratio_b_x96 {
+        (sqrt_ratio_b_x96, sqrt_ratio_a_x96)
+    } else {
+        (sqrt_ratio_a_x96, sqrt_ratio_b_x96)
+    };
+
+    let q96 = U256::from(1) << RESOLUTION;
+    let liquidity = U256::from(liquidity);
+    let difference = sqrt_ratio_b_x96 - sqrt_ratio_a_x96;
+
+    if round_up {
+        mul_div_rounding_up(liquidity, difference, q96)
+    } else {
+        mul_div(liquidity, difference, q96)
+    }
+}
+
+/// `FullMath.mulDiv`: `a * b / denominator` with a 512-bit intermediate product.
+/// `None` if the denominator is zero or the result does not fit into 256 bits.
+fn mul_div(a: U256, b: U256, denominator: U256) -> Option<U256> {
+    mul_div_rem(a, b, denominator).map(|(quotient, _)| quotient)
+}
+
+/// `FullMath.mulDivRoundingUp`
+fn mul_div_rounding_up(a: U256, b: U256, denominator: U256) -> Option<U256> {
+    let (quotient, remainder) = mul_div_rem(a, b, denominator)?;
+    if remainder.is_zero() {
+        Some(quotient)
+    } else {
+        quotient.checked_add(U256::from(1))
+    }
+}
+
+fn mul_div_rem(a: U256, b: U256, denominator: U256) -> Option<(U256, U256)> {
+    if denominator.is_zero() {
+        return None;
+    }
+
+    let product: U512 = a.widening_mul(b);
+    let (quotient, remainder) = product.div_rem(U512::from(denominator));
+    let quotient = U256::checked_from_limbs_slice(quotient.as_limbs())?;
+    let remainder = U256::checked_from_limbs_slice(remainder.as_limbs())?;
+    Some((quotient, remainder))
+}
+
+/// `UnsafeMath.divRoundingUp`
+fn div_rounding_up(a: U256, b: U256) -> U256 {
+    let (quotient, remainder) = a.div_rem(b);
+    quotient + U256::from(u8::from(!remainder.is_zero()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const E18: u128 = 1_000_000_000_000_000_000;
+
+    /// Tick 0 (price 1) with two positions: `[-600, 600)` with 1e18 liquidity and `[120, 1200)`
+    /// with 2e18 liquidity
+    fn state() -> UniswapV3State {
+        let e18 = E18 as i128;
+        UniswapV3State::new(
+            U256::from(1) << 96,
+            0,
+            E18,
+            3_000,
+            60,
+            &[(-600, e18), (120, 2 * e18), (600, -e18), (1200, -2 * e18)],
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_sqrt_ratio_at_tick() {
+        assert_eq!(sqrt_ratio_at_tick(0), Some(U256::from(1) << 96));
+        assert_eq!(sqrt_ratio_at_tick(MIN_TICK), Some(MIN_SQRT_RATIO));
+        assert_eq!(sqrt_ratio_at_tick(MAX_TICK), Some(MAX_SQRT_RATIO));
+        assert_eq!(
+            sqrt_ratio_at_tick(1),
+            Some(U256::from(79_232_123_823_359_799_118_286_999_568_u128))
+        );
+        assert_eq!(
+            sqrt_ratio_at_tick(-1),
+            Some(U256::from(79_224_201_403_219_477_170_569_942_574_u128))
+        );
+        assert_eq!(
+            sqrt_ratio_at_tick(600),
+            Some(U256::from(81_640_896_826_356_156_310_682_304_526_u128))
+        );
+        assert_eq!(sqrt_ratio_at_tick(MIN_TICK - 1), None);
+        assert_eq!(sqrt_ratio_at_tick(MAX_TICK + 1), None);
+    }
+
+    #[test]
+    fn test_new_invalid() {
+        let price = U256::from(1) << 96;
+        for (sqrt_price_x96, fee, tick_spacing, ticks, expected) in [
+            (
+                MAX_SQRT_RATIO,
+                3_000,
+                60,
+                vec![],
+                format!("Sqrt price {MAX_SQRT_RATIO} is out of range"),
+            ),
+            (
+                price,
+                1_000_000,
+                60,
+                vec![],
+                "Fee must be less than 1000000, got 1000000".to_string(),
+            ),
+            (
+                price,
+                3_000,
+                0,
+                vec![],
+                "Tick spacing must be positive, got 0".to_string(),
+            ),
+            (
+                price,
+                3_000,
+                60,
+                vec![(90, 1)],
+                "Tick 90 is not a multiple of tick spacing 60".to_string(),
+            ),
+        ] {
+            let state = UniswapV3State::new(sqrt_price_x96, 0, 1, fee, tick_spacing, &ticks);
+            assert_eq!(state.err().unwrap().to_string(), expected);
+        }
+    }
+
+    #[test]
+    fn test_virtual_reserves() {
+        let e18 = U256::from(E18);
+        assert_eq!(state().virtual_reserves(), (e18, e18));
+    }
+
+    #[test]
+    fn test_amount_out_within_range() {
+        // Same as a 1e18/1e18 constant product pool with a 0.3% fee
+        let state = state();
+        let amount_in = U256::from(1_000_000_000_000_000_u64);
+        let expected = U256::from(996_006_981_039_903_u64);
+        assert_eq!(state.amount_out(amount_in, true), expected);
+        assert_eq!(state.amount_out(amount_in, false), expected);
+    }
+
+    #[test]
+    fn test_amount_out_crossing_ticks() {
+        let state = state();
+
+        // Crosses tick 120 where liquidity triples
+        assert_eq!(
+            state.amount_out(U256::from(50_000_000_000_000_000_u64), false),
+            U256::from(48_671_191_425_415_780_u64)
+        );
+
+        // Crosses ticks 120 and 600
+        assert_eq!(
+            state.amount_out(U256::from(140_000_000_000_000_000_u64), false),
+            U256::from(131_831_118_730_350_405_u64)
+        );
+    }
+
+    #[test]
+    fn test_amount_out_out_of_liquidity() {
+        // Below tick -600 and above tick 1200 there is no liquidity to take all the input
+        let state = state();
+        assert_eq!(
+            state.amount_out(U256::from(100_000_000_000_000_000_u64), true),
+            U256::ZERO
+        );
+        assert_eq!(state.amount_out(U256::from(E18 * 100), false), U256::ZERO);
+    }
+}
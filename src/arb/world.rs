@@ -11,16 +11,22 @@
 /// one of supported tokens in our balances.
 use std::collections::{HashMap, HashSet};
 
+use alloy::primitives::B256;
+use eyre::Result;
+
 use super::{
     cycle::Cycle,
+    journal::Journal,
     pool::Pool,
-    swap::{Swap, SwapId},
+    swap::{Direction, Swap, SwapId},
     token::{Token, TokenId},
+    world_config::WorldConfig,
     world_update::WorldUpdate,
 };
 
 pub type TokenIndex = usize;
 pub type SwapIndex = usize;
+pub type CycleIndex = usize;
 
 #[derive(Debug, Clone, Default)]
 pub struct World {
@@ -39,17 +45,87 @@ pub struct World {
     /// Adjacency list of `TokenId` (Vertex) to a list of `SwapId` (outgoing edges)
     pub graph: Vec<Vec<SwapIndex>>,
 
-    /// All cycles
+    /// All cycles indexed by `CycleIndex`
     pub cycle_vec: Vec<Cycle>,
+
+    /// Reverse index of `SwapIndex` to the cycles that contain the swap
+    pub cycle_map: HashMap<SwapIndex, Vec<CycleIndex>>,
+
+    /// Cycle enumeration settings
+    pub config: WorldConfig,
+
+    /// Swaps as they were before the last blocks updated them, for `rollback`
+    journal: Journal<(SwapIndex, Swap)>,
 }
 
 impl World {
     /// Create a new market from a set of pools loaded from the database
     /// Called at startup
-    pub fn new(pools: &HashSet<Pool>) -> Self {
+    pub fn new(pools: &HashSet<Pool>, config: WorldConfig) -> Self {
+        let mut market = Self::without_cycles(pools, config);
+
+        // Find all cycles once during initialization
+        market.cycle_vec = market.cycle_vec();
+        market.cycle_map = market.cycle_map();
+
+        market
+    }
+
+    /// Create a new market from a set of pools and cycles precomputed earlier, skipping the
+    /// cycle search. Each cycle is a list of `SwapId`s in the cycle order.
+    /// Cycles that reference unknown pools or are no longer valid are skipped, the order of the
+    /// rest is kept.
+    pub fn from_persisted(
+        pools: &HashSet<Pool>,
+        cycles: &[Vec<SwapId>],
+        config: WorldConfig,
+    ) -> Self {
+        let mut market = Self::without_cycles(pools, config);
+
+        // Even though Cycle itself is mutable, the way we calculate hash is immutable
+        #[allow(clippy::mutable_key_type)]
+        let mut cycle_set: HashSet<Cycle> = HashSet::new();
+        let mut cycle_vec = Vec::new();
+
+        // Keep the persisted order so cycle indices are stable between runs
+        for swap_ids in cycles {
+            let Some(swaps) = swap_ids
+                .iter()
+                .map(|swap_id| {
+                    market
+                        .swap_map
+                        .get(swap_id)
+                        .map(|&swap_index| market.swap_vec[swap_index].clone())
+                })
+                .collect::<Option<Vec<_>>>()
+            else {
+                continue;
+            };
+
+            if let Ok(cycle) = Cycle::new(swaps) {
+                if cycle_set.insert(cycle.clone()) {
+                    cycle_vec.push(cycle);
+                }
+            }
+        }
+
+        market.cycle_vec = cycle_vec;
+        market.cycle_map = market.cycle_map();
+
+        market
+    }
+
+    /// Build tokens, swaps and the graph, but leave the cycles empty
+    fn without_cycles(pools: &HashSet<Pool>, config: WorldConfig) -> Self {
+        // Pools of excluded tokens are not part of the world at all
+        let pools: Vec<&Pool> = pools
+            .iter()
+            .filter(|pool| !config.is_excluded(pool))
+            .collect();
+
         // Build token_vec with deduplication
         let mut token_set = HashSet::new();
-        for pool in pools {
+        for pool in &pools {
             token_set.insert(pool.token0);
             token_set.insert(pool.token1);
         }
@@ -67,7 +143,7 @@ impl World {
 
         // Build swap_vec with capacity
         let mut swap_vec = Vec::with_capacity(num_swaps);
-        for pool in pools {
+        for pool in &pools {
             swap_vec.push(Swap::forward(pool));
             swap_vec.push(Swap::reverse(pool));
         }
@@ -91,29 +167,154 @@ impl World {
             graph[token_index].push(swap_id); // Add outgoing edges based on input token
         }
 
-        let mut market = Self {
+        Self {
             token_vec,
             token_map,
             swap_vec,
             swap_map,
             graph,
             cycle_vec: Vec::new(),
-        };
-
-        // Find all cycles once during initialization
-        market.cycle_vec = market.cycle_vec();
-
-        market
+            cycle_map: HashMap::new(),
+            config,
+            journal: Journal::default(),
+        }
     }
 
-    /// Update the market with new pool reserves and balances and return affected cycles
-    /// Call this once per block with new pools and balances
+    /// Update the market with new pool reserves and return the updated cycles that are positive.
+    /// A cycle that was positive before the update is returned again: its reserves changed, so it
+    /// has to be quoted again. Cycles the pools are not part of are not returned.
+    /// Call this once per block with new pools
     pub fn update(&mut self, pools: &HashSet<Pool>) -> WorldUpdate {
         let updated_swaps = self.update_swaps(pools.clone());
         let updated_cycles = self.update_cycles(&updated_swaps);
         WorldUpdate::new(updated_cycles)
     }
 
+    /// `update` for the pools synced in a block. The swaps it overwrites are journaled, so the
+    /// block can be undone with `rollback` if it is reorged out.
+    pub fn update_block(&mut self, number: u64, hash: B256, pools: &HashSet<Pool>) -> WorldUpdate {
+        let previous: Vec<_> = pools
+            .iter()
+            .flat_map(|pool| {
+                [Direction::ZeroForOne, Direction::OneForZero].map(|direction| SwapId {
+                    pool_id: pool.id.clone(),
+                    direction,
+                })
+            })
+            .filter_map(|swap_id| {
+                let &swap_index = self.swap_map.get(&swap_id)?;
+                Some((swap_index, self.swap_vec[swap_index].clone()))
+            })
+            .collect();
+        self.journal.record(number, hash, previous);
+
+        self.update(pools)
+    }
+
+    /// Restore the swaps as of block `number`, undoing the blocks after it that were passed to
+    /// `update_block`. Returns the cycles that are positive after the rollback, like `update`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the blocks after `number` are no longer journaled
+    pub fn rollback(&mut self, number: u64) -> Result<WorldUpdate> {
+        let previous = self.journal.rollback(number)?;
+
+        let mut restored_swaps = Vec::with_capacity(previous.len());
+        for (swap_index, swap) in previous {
+            self.swap_vec[swap_index] = swap.clone();
+            restored_swaps.push(swap);
+        }
+
+        Ok(WorldUpdate::new(self.update_cycles(&restored_swaps)))
+    }
+
+    /// Stops excluding `tokens` and adds their pools among `pools` to the world, with the cycles
+    /// through them, without searching the whole graph again. Pools already in the world and
+    /// pools of tokens that are still excluded are skipped. Returns the number of cycles added.
+    pub fn include_tokens<'a>(
+        &mut self,
+        tokens: &HashSet<TokenId>,
+        pools: impl IntoIterator<Item = &'a Pool>,
+    ) -> usize {
+        self.config.include_tokens(tokens);
+
+        let pools: Vec<&Pool> = pools
+            .into_iter()
+            .filter(|pool| tokens.contains(&pool.token0) || tokens.contains(&pool.token1))
+            .filter(|pool| !self.config.is_excluded(pool))
+            .filter(|pool| !self.swap_map.contains_key(&Swap::forward(pool).id))
+            .collect();
+
+        for pool in &pools {
+            for token_id in [pool.token0, pool.token1] {
+                if !self.token_map.contains_key(&token_id) {
+                    self.token_map.insert(token_id, self.token_vec.len());
+                    self.token_vec.push(Token::new(token_id));
+                    self.graph.push(Vec::new());
+                }
+            }
+        }
+
+        let mut added_swaps = Vec::with_capacity(pools.len() * 2);
+        for pool in &pools {
+            for swap in [Swap::forward(pool), Swap::reverse(pool)] {
+                let swap_index = self.swap_vec.len();
+                self.swap_map.insert(swap.id.clone(), swap_index);
+                self.graph[self.token_map[&swap.token_in]].push(swap_index);
+                self.swap_vec.push(swap);
+                added_swaps.push(swap_index);
+            }
+        }
+
+        // Every new cycle goes through an added swap, so it is found by closing each of them
+        #[allow(clippy::mutable_key_type)]
+        let mut cycles: HashSet<Cycle> = HashSet::new();
+        for swap_index in added_swaps {
+            let swap = &self.swap_vec[swap_index];
+            let mut visited = HashSet::from([swap_index]);
+            let mut path = vec![swap.clone()];
+            let mut cycles_found = 0;
+
+            self.dfs_find_cycles(
+                self.token_map[&swap.token_in],
+                self.token_map[&swap.token_out],
+                &mut visited,
+                &mut path,
+                &mut cycles,
+                &mut cycles_found,
+            );
+        }
+
+        // Same as starting the search from the anchor tokens: the cycle has to go through one
+        let mut cycles: Vec<Cycle> = cycles
+            .into_iter()
+            .filter(|cycle| {
+                self.config.anchor_tokens().is_none_or(|anchor_tokens| {
+                    cycle
+                        .swaps
+                        .iter()
+                        .any(|swap| anchor_tokens.contains(&swap.token_in))
+                })
+            })
+            .collect();
+        cycles.sort();
+
+        let added_cycles = cycles.len();
+        for cycle in cycles {
+            let cycle_index = self.cycle_vec.len();
+            for swap in &cycle.swaps {
+                self.cycle_map
+                    .entry(self.swap_map[&swap.id])
+                    .or_default()
+                    .push(cycle_index);
+            }
+            self.cycle_vec.push(cycle);
+        }
+
+        added_cycles
+    }
+
     // Update the swaps in the market and return the updated swaps
     fn update_swaps(&mut self, updated_pools: HashSet<Pool>) -> Vec<Swap> {
         let mut updated_swaps = Vec::with_capacity(updated_pools.len() * 2);
@@ -135,35 +336,60 @@ impl World {
         updated_swaps
     }
 
-    // Update the cycles in the market and return the updated cycles
-    fn update_cycles(&self, updated_swaps: &[Swap]) -> Vec<Cycle> {
-        // Filter all_cycles to only include cycles with at least one updated swap
-        let updated_set: HashSet<Swap> = updated_swaps.iter().cloned().collect();
+    // Update the cycles containing the updated swaps and return the ones with a positive log rate.
+    // Only the cycles found in `cycle_map` are touched, their cached log rates are adjusted by the
+    // swap log rate difference.
+    fn update_cycles(&mut self, updated_swaps: &[Swap]) -> Vec<Cycle> {
+        let mut updated_cycle_indexes = HashSet::new();
 
-        self.cycle_vec
-            .iter()
-            .filter(|cycle| {
-                cycle.swaps.iter().any(|swap| {
-                    if let Some(&swap_id) = self.swap_map.get(&swap.id) {
-                        updated_set.contains(&self.swap_vec[swap_id])
-                    } else {
-                        false
-                    }
-                })
-            })
+        for swap in updated_swaps {
+            let Some(swap_index) = self.swap_map.get(&swap.id) else {
+                continue;
+            };
+            let Some(cycle_indexes) = self.cycle_map.get(swap_index) else {
+                continue;
+            };
+
+            for &cycle_index in cycle_indexes {
+                self.cycle_vec[cycle_index].update_swap(swap);
+                updated_cycle_indexes.insert(cycle_index);
+            }
+        }
+
+        let mut updated_cycle_indexes = updated_cycle_indexes.into_iter().collect::<Vec<_>>();
+        updated_cycle_indexes.sort_unstable();
+
+        updated_cycle_indexes
+            .into_iter()
+            .map(|cycle_index| &self.cycle_vec[cycle_index])
+            .filter(|cycle| cycle.has_all_reserves() && cycle.is_positive())
             .cloned()
             .collect()
     }
 
+    /// Build the reverse index of swaps to the cycles they are part of
+    fn cycle_map(&self) -> HashMap<SwapIndex, Vec<CycleIndex>> {
+        let mut cycle_map: HashMap<SwapIndex, Vec<CycleIndex>> = HashMap::new();
+        for (cycle_index, cycle) in self.cycle_vec.iter().enumerate() {
+            for swap in &cycle.swaps {
+                if let Some(&swap_index) = self.swap_map.get(&swap.id) {
+                    cycle_map.entry(swap_index).or_default().push(cycle_index);
+                }
+            }
+        }
+        cycle_map
+    }
+
     fn cycle_vec(&self) -> Vec<Cycle> {
         // Even though Cycle itself is mutable, the way we calculate hash is immutable
         #[allow(clippy::mutable_key_type)]
         let mut cycles: HashSet<Cycle> = HashSet::new();
 
-        // For each token, find cycles starting from that token
-        for token_idx in 0..self.token_vec.len() {
+        // For each start token, find cycles starting from that token
+        for token_idx in self.start_tokens() {
             let mut visited = HashSet::new();
             let mut path = Vec::new();
+            let mut cycles_found = 0;
 
             self.dfs_find_cycles(
                 token_idx,
@@ -171,8 +397,7 @@ impl World {
                 &mut visited,
                 &mut path,
                 &mut cycles,
-                0,
-                3, // max cycle depth
+                &mut cycles_found,
             );
         }
         let mut cycles_vec = cycles.into_iter().collect::<Vec<_>>();
@@ -180,8 +405,26 @@ impl World {
         cycles_vec
     }
 
+    /// Tokens to start the cycle search from: either the configured anchor tokens or all tokens.
+    /// Anchor tokens that are not part of any pool are skipped.
+    fn start_tokens(&self) -> Vec<TokenIndex> {
+        match self.config.anchor_tokens() {
+            Some(anchor_tokens) => {
+                let mut start_tokens = anchor_tokens
+                    .iter()
+                    .filter_map(|token_id| self.token_map.get(token_id).copied())
+                    .collect::<Vec<_>>();
+                start_tokens.sort_unstable();
+                start_tokens.dedup();
+                start_tokens
+            }
+            None => (0..self.token_vec.len()).collect(),
+        }
+    }
+
     /// Find all cycles in the graph using DFS
-    #[allow(clippy::too_many_arguments)]
+    /// Stops at `WorldConfig::max_cycle_length` depth and after
+    /// `WorldConfig::max_cycles_per_token` cycles found from `start_token`
     #[allow(clippy::mutable_key_type)]
     fn dfs_find_cycles(
         &self,
@@ -190,20 +433,30 @@ impl World {
         visited: &mut HashSet<SwapIndex>,
         path: &mut Vec<Swap>,
         cycles: &mut HashSet<Cycle>,
-        depth: usize,
-        max_depth: usize,
+        cycles_found: &mut usize,
     ) {
+        // Stop if we hit the cycle cap for this start token
+        if self
+            .config
+            .max_cycles_per_token()
+            .is_some_and(|max_cycles| *cycles_found >= max_cycles)
+        {
+            return;
+        }
+
         // Check if we found a cycle back to start
-        if depth > 0 && current_token == start_token {
+        if !path.is_empty() && current_token == start_token {
             // Create a new cycle with the current path
             if let Ok(cycle) = Cycle::new(path.clone()) {
-                cycles.insert(cycle);
+                if cycles.insert(cycle) {
+                    *cycles_found += 1;
+                }
             }
             return;
         }
 
         // Stop if we hit max depth
-        if depth >= max_depth {
+        if path.len() >= self.config.max_cycle_length() {
             return;
         }
 
@@ -230,15 +483,7 @@ impl World {
             visited.insert(swap_id);
             path.push(swap.clone());
 
-            self.dfs_find_cycles(
-                start_token,
-                next_token,
-                visited,
-                path,
-                cycles,
-                depth + 1,
-                max_depth,
-            );
+            self.dfs_find_cycles(start_token, next_token, visited, path, cycles, cycles_found);
 
             path.pop();
             visited.remove(&swap_id);
@@ -250,9 +495,10 @@ impl World {
 mod tests {
     use super::*;
     use alloy::primitives::map::HashMap;
+    use alloy::primitives::U256;
 
     use crate::arb::pool::PoolId;
-    use crate::arb::swap::Direction;
+    use crate::arb::swap::{Direction, DEFAULT_FEE};
     use crate::arb::test_helpers::*;
 
     #[test]
@@ -280,14 +526,14 @@ mod tests {
             HashMap::from([
                 (
                     SwapId {
-                        pool_id: PoolId::from(address_from_str("F1")),
+                        pool_id: pool_id("F1"),
                         direction: Direction::ZeroForOne,
                     },
                     0
                 ),
                 (
                     SwapId {
-                        pool_id: PoolId::from(address_from_str("F1")),
+                        pool_id: pool_id("F1"),
                         direction: Direction::OneForZero,
                     },
                     1
@@ -389,7 +635,7 @@ mod tests {
     #[test]
     fn test_update_swaps() {
         let original_pool = pool("F1", "A", "B", 100, 200);
-        let mut world = World::new(&HashSet::from([original_pool]));
+        let mut world = World::new(&HashSet::from([original_pool]), WorldConfig::default());
 
         let updated_pool = pool("F1", "A", "B", 100, 300);
 
@@ -425,6 +671,329 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_max_cycle_length() {
+        let pools = [
+            ("F1", "A", "B", 100, 200),
+            ("F2", "B", "C", 200, 300),
+            ("F3", "A", "C", 120, 300),
+            ("F4", "A", "B", 100, 300),
+        ];
+
+        // 2 two-swap cycles (F1/F4) and 4 triangles (F1 or F4 with F2 and F3, both directions)
+        assert_eq!(world(&pools).cycle_vec.len(), 6);
+
+        let world = world_with_config(&pools, WorldConfig::new(2, None, None).unwrap());
+        assert_eq!(
+            world.cycle_vec,
+            vec![
+                cycle(&[("F1", "A", "B", 100, 200), ("F4", "B", "A", 300, 100)]).unwrap(),
+                cycle(&[("F4", "A", "B", 100, 300), ("F1", "B", "A", 200, 100)]).unwrap(),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_anchor_tokens() {
+        let pools = [
+            ("F1", "A", "B", 100, 200),
+            ("F2", "A", "B", 100, 300),
+            ("F3", "C", "D", 100, 200),
+            ("F4", "C", "D", 100, 300),
+        ];
+
+        assert_eq!(world(&pools).cycle_vec.len(), 4);
+
+        let world = world_with_config(
+            &pools,
+            WorldConfig::new(3, Some(vec![token("C").id, token("E").id]), None).unwrap(),
+        );
+        assert_eq!(
+            world.cycle_vec,
+            vec![
+                cycle(&[("F3", "C", "D", 100, 200), ("F4", "D", "C", 300, 100)]).unwrap(),
+                cycle(&[("F4", "C", "D", 100, 300), ("F3", "D", "C", 200, 100)]).unwrap(),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_max_cycles_per_token() {
+        let pools = [
+            ("F1", "A", "B", 100, 200),
+            ("F2", "A", "B", 100, 300),
+            ("F3", "A", "B", 100, 400),
+        ];
+
+        // Each pair of pools makes two cycles
+        assert_eq!(world(&pools).cycle_vec.len(), 6);
+
+        let world = world_with_config(
+            &pools,
+            WorldConfig::new(3, Some(vec![token("A").id]), Some(4)).unwrap(),
+        );
+        assert_eq!(world.cycle_vec.len(), 4);
+    }
+
+    #[test]
+    fn test_from_persisted() {
+        let pools = HashSet::from([
+            pool("F1", "A", "B", 100, 200),
+            pool("F2", "B", "C", 200, 300),
+            pool("F3", "A", "C", 120, 300),
+            pool("F4", "A", "B", 100, 300),
+        ]);
+        let world = World::new(&pools, WorldConfig::default());
+
+        let mut cycles = world
+            .cycle_vec
+            .iter()
+            .map(|cycle| {
+                cycle
+                    .swaps
+                    .iter()
+                    .map(|swap| swap.id.clone())
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+
+        // A cycle through a pool that no longer exists is skipped
+        cycles.push(vec![
+            swap("F1", "A", "B", 100, 200).id,
+            swap("F5", "B", "A", 100, 200).id,
+        ]);
+
+        // An invalid cycle is skipped
+        cycles.push(vec![
+            swap("F1", "A", "B", 100, 200).id,
+            swap("F1", "B", "A", 200, 100).id,
+        ]);
+
+        let persisted_world = World::from_persisted(&pools, &cycles, WorldConfig::default());
+        assert_eq!(persisted_world.swap_vec, world.swap_vec);
+        assert_eq!(persisted_world.graph, world.graph);
+        assert_eq!(persisted_world.cycle_vec, world.cycle_vec);
+        assert_eq!(persisted_world.cycle_map, world.cycle_map);
+    }
+
+    #[test]
+    fn test_cycle_map() {
+        let world = world(&[("F1", "A", "B", 100, 200), ("F2", "A", "B", 100, 300)]);
+
+        // Swaps: 0: F1 A->B, 1: F2 A->B, 2: F1 B->A, 3: F2 B->A
+        // Cycles: 0: F1 A->B + F2 B->A, 1: F2 A->B + F1 B->A
+        assert_eq!(
+            world.cycle_map,
+            HashMap::from([(0, vec![0]), (3, vec![0]), (1, vec![1]), (2, vec![1])])
+        );
+    }
+
+    #[test]
+    fn test_update() {
+        let mut world = world(&[
+            ("F1", "A", "B", 100, 200),
+            ("F2", "A", "B", 100, 300),
+            ("F3", "C", "D", 100, 200),
+        ]);
+
+        // Only F2 A->B + F1 B->A is positive to begin with
+        assert!(!world.cycle_vec[0].is_positive());
+        assert!(world.cycle_vec[1].is_positive());
+
+        // F1 price moves enough to flip both cycles
+        let world_update = world.update(&HashSet::from([pool("F1", "A", "B", 100, 400)]));
+        let expected_cycle =
+            cycle(&[("F1", "A", "B", 100, 400), ("F2", "B", "A", 300, 100)]).unwrap();
+        assert_eq!(world_update.cycles(), &vec![expected_cycle.clone()]);
+        assert_eq!(
+            world_update.cycles()[0].log_rate(),
+            expected_cycle.log_rate()
+        );
+        assert_eq!(
+            world_update.cycles()[0].swaps[0].reserve_out(),
+            U256::from(400)
+        );
+        assert!(!world.cycle_vec[1].is_positive());
+
+        // Pools that are not part of any cycle do not produce any cycles
+        let world_update = world.update(&HashSet::from([pool("F3", "C", "D", 100, 300)]));
+        assert!(world_update.cycles().is_empty());
+    }
+
+    #[test]
+    fn test_update_still_positive() {
+        let mut world = world(&[("F1", "A", "B", 100, 200), ("F2", "A", "B", 100, 300)]);
+        assert!(world.cycle_vec[1].is_positive());
+
+        // F2 A->B + F1 B->A stays positive, it is returned although its rate did not cross zero
+        let world_update = world.update(&HashSet::from([pool("F2", "A", "B", 100, 310)]));
+        assert_eq!(
+            world_update.cycles(),
+            &vec![cycle(&[("F2", "A", "B", 100, 310), ("F1", "B", "A", 200, 100)]).unwrap()]
+        );
+    }
+
+    #[test]
+    fn test_update_uniswap_v3() {
+        let e18 = 1_000_000_000_000_000_000_i128;
+        let ticks = [(-600, e18), (600, -e18)];
+        let v3_pool = |tick| uniswap_v3_pool("F2", "A", "B", tick, e18.unsigned_abs(), &ticks);
+
+        let mut world = World::new(
+            &HashSet::from([
+                pool(
+                    "F1",
+                    "A",
+                    "B",
+                    1_000_000_000_000_000_000,
+                    1_000_000_000_000_000_000,
+                ),
+                v3_pool(0),
+            ]),
+            WorldConfig::default(),
+        );
+        assert_eq!(world.cycle_vec.len(), 2);
+        assert!(!world.cycle_vec.iter().any(Cycle::is_positive));
+
+        // B gets ~3% cheaper in the V3 pool
+        let world_update = world.update(&HashSet::from([v3_pool(-300)]));
+        assert_eq!(world_update.cycles().len(), 1);
+
+        let best_quote = world_update.cycles()[0].best_quote().unwrap();
+        assert!(best_quote.is_profitable());
+    }
+
+    #[test]
+    fn test_rollback() {
+        let mut world = world(&[("F1", "A", "B", 100, 200), ("F2", "A", "B", 100, 200)]);
+        let reserves = |world: &World| {
+            world
+                .swap_vec
+                .iter()
+                .map(|swap| (swap.id.clone(), swap.reserve_in()))
+                .collect::<Vec<_>>()
+        };
+        let original_reserves = reserves(&world);
+
+        let world_update = world.update_block(
+            1,
+            B256::repeat_byte(1),
+            &HashSet::from([pool("F2", "A", "B", 100, 300)]),
+        );
+        assert_eq!(world_update.cycles().len(), 1);
+        world.update_block(
+            2,
+            B256::repeat_byte(2),
+            &HashSet::from([
+                pool("F1", "A", "B", 100, 250),
+                pool("F2", "A", "B", 100, 400),
+            ]),
+        );
+
+        // Block 2 is reorged out
+        let world_update = world.rollback(1).unwrap();
+        assert_eq!(world_update.cycles().len(), 1);
+        assert_eq!(
+            world.swap_vec[world.swap_map[&SwapId {
+                pool_id: pool_id("F2"),
+                direction: Direction::OneForZero,
+            }]]
+                .reserve_in(),
+            U256::from(300)
+        );
+
+        // And block 1 as well
+        let world_update = world.rollback(0).unwrap();
+        assert!(world_update.cycles().is_empty());
+        assert_eq!(reserves(&world), original_reserves);
+        assert!(!world.cycle_vec.iter().any(Cycle::is_positive));
+    }
+
+    #[test]
+    fn test_new_multiple_chains() {
+        use crate::utils::constants::ETHEREUM_CHAIN_ID;
+
+        // Same pool and token addresses as F1, but on Ethereum and at a different price
+        let ethereum_pool = Pool::new(
+            PoolId::new(ETHEREUM_CHAIN_ID, address_from_str("F1")),
+            TokenId::new(ETHEREUM_CHAIN_ID, address_from_str("A")),
+            TokenId::new(ETHEREUM_CHAIN_ID, address_from_str("B")),
+            Some(U256::from(100)),
+            Some(U256::from(300)),
+            DEFAULT_FEE,
+        );
+        let pools = HashSet::from([pool("F1", "A", "B", 100, 200), ethereum_pool]);
+
+        // Tokens are not shared across chains, so there is nothing to arbitrage
+        let world = World::new(&pools, WorldConfig::default());
+        assert_eq!(world.swap_vec.len(), 4);
+        assert_eq!(world.token_map.len(), 4);
+        assert!(world.cycle_vec.is_empty());
+    }
+
+    #[test]
+    fn test_new_excluded_tokens() {
+        let pools = [
+            ("F1", "A", "B", 100, 200),
+            ("F2", "A", "B", 100, 300),
+            ("F3", "A", "C", 100, 200),
+            ("F4", "B", "C", 100, 200),
+        ];
+        let config = WorldConfig::default().with_excluded_tokens(HashSet::from([token("C").id]));
+        let world = world_with_config(&pools, config);
+
+        // The pools of C and the cycles through it are left out
+        assert_eq!(world.swap_vec.len(), 4);
+        assert!(!world.token_map.contains_key(&token("C").id));
+        assert_eq!(world.cycle_vec.len(), 2);
+
+        // Updates of excluded pools are ignored
+        let mut world = world;
+        let world_update = world.update(&HashSet::from([pool("F3", "A", "C", 100, 400)]));
+        assert!(world_update.cycles().is_empty());
+    }
+
+    #[test]
+    fn test_include_tokens() {
+        let pools = HashSet::from([
+            pool("F1", "A", "B", 100, 200),
+            pool("F2", "A", "B", 100, 300),
+            pool("F3", "A", "C", 100, 200),
+            pool("F4", "B", "C", 100, 200),
+            pool("F5", "C", "D", 100, 200),
+        ]);
+        let excluded_tokens = HashSet::from([token("C").id, token("D").id]);
+        let config = WorldConfig::default().with_excluded_tokens(excluded_tokens);
+        let mut world = World::new(&pools, config);
+        assert_eq!(world.cycle_vec.len(), 2);
+
+        // D is still excluded, so F5 stays out
+        let added_cycles = world.include_tokens(&HashSet::from([token("C").id]), &pools);
+        let expected = World::new(
+            &pools,
+            WorldConfig::default().with_excluded_tokens(HashSet::from([token("D").id])),
+        );
+        assert_eq!(added_cycles, expected.cycle_vec.len() - 2);
+        assert_eq!(world.swap_vec.len(), 8);
+        assert!(!world.token_map.contains_key(&token("D").id));
+
+        #[allow(clippy::mutable_key_type)]
+        let cycles: HashSet<_> = world.cycle_vec.iter().cloned().collect();
+        #[allow(clippy::mutable_key_type)]
+        let expected_cycles: HashSet<_> = expected.cycle_vec.into_iter().collect();
+        assert_eq!(cycles, expected_cycles);
+
+        // The added cycles are updated with their pools
+        let world_update = world.update(&HashSet::from([pool("F3", "A", "C", 100, 400)]));
+        assert!(!world_update.cycles().is_empty());
+
+        // Including them again adds nothing
+        assert_eq!(
+            world.include_tokens(&HashSet::from([token("C").id]), &pools),
+            0
+        );
+    }
+
     // #[test]
     // fn test_profitable_but_not_exploitable_cycles() {
     //     let market = market(
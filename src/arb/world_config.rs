@@ -0,0 +1,296 @@
+/// Settings that control how `World` enumerates cycles
+use std::collections::HashSet;
+use std::env;
+use std::str::FromStr;
+
+use alloy::primitives::{Address, ChainId};
+use eyre::{bail, eyre, Result};
+
+use super::pool::Pool;
+use super::token::TokenId;
+use crate::utils::constants::BASE_CHAIN_ID;
+
+/// Maximum number of swaps in a cycle, 3 if not set
+const MAX_CYCLE_LENGTH_ENV: &str = "FLY_MAX_CYCLE_LENGTH";
+
+/// Comma-separated tokens cycles must start from, every token if not set. A token is an address on
+/// Base or `<chain id>:<address>` on another chain.
+const ANCHOR_TOKENS_ENV: &str = "FLY_ANCHOR_TOKENS";
+
+/// Maximum number of cycles enumerated per start token, no limit if not set
+const MAX_CYCLES_PER_TOKEN_ENV: &str = "FLY_MAX_CYCLES_PER_TOKEN";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WorldConfig {
+    /// Maximum number of swaps in a cycle
+    max_cycle_length: usize,
+
+    /// Tokens that cycles must start from. `None` means every token is a start token.
+    anchor_tokens: Option<Vec<TokenId>>,
+
+    /// Maximum number of cycles enumerated per start token. `None` means no limit.
+    max_cycles_per_token: Option<usize>,
+
+    /// Tokens whose pools are left out of the world, e.g. tokens with a transfer tax that would
+    /// make `SimpleExecutor.run` revert
+    excluded_tokens: HashSet<TokenId>,
+}
+
+impl Default for WorldConfig {
+    fn default() -> Self {
+        Self {
+            max_cycle_length: 3,
+            anchor_tokens: None,
+            max_cycles_per_token: None,
+            excluded_tokens: HashSet::new(),
+        }
+    }
+}
+
+impl WorldConfig {
+    /// The shortest cycle is two swaps in two different pools
+    pub const MIN_CYCLE_LENGTH: usize = 2;
+
+    /// `SimpleExecutor.run` accepts up to 5 pairs
+    pub const MAX_CYCLE_LENGTH: usize = 5;
+
+    /// Creates a new config
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if:
+    /// - `max_cycle_length` is not within `MIN_CYCLE_LENGTH..=MAX_CYCLE_LENGTH`
+    /// - `anchor_tokens` is empty
+    /// - `max_cycles_per_token` is zero
+    pub fn new(
+        max_cycle_length: usize,
+        anchor_tokens: Option<Vec<TokenId>>,
+        max_cycles_per_token: Option<usize>,
+    ) -> Result<Self> {
+        if !(Self::MIN_CYCLE_LENGTH..=Self::MAX_CYCLE_LENGTH).contains(&max_cycle_length) {
+            bail!(
+                "Max cycle length must be between {} and {}, got {}",
+                Self::MIN_CYCLE_LENGTH,
+                Self::MAX_CYCLE_LENGTH,
+                max_cycle_length
+            );
+        }
+
+        if anchor_tokens.as_ref().is_some_and(Vec::is_empty) {
+            bail!("Anchor tokens must not be empty");
+        }
+
+        if max_cycles_per_token == Some(0) {
+            bail!("Max cycles per token must be greater than zero");
+        }
+
+        Ok(Self {
+            max_cycle_length,
+            anchor_tokens,
+            max_cycles_per_token,
+            excluded_tokens: HashSet::new(),
+        })
+    }
+
+    /// Reads `FLY_MAX_CYCLE_LENGTH`, `FLY_ANCHOR_TOKENS` and `FLY_MAX_CYCLES_PER_TOKEN`, the
+    /// defaults are used for the variables that are not set. An anchor token is only an anchor on
+    /// its own chain, Base unless it is prefixed with another chain id.
+    ///
+    /// # Errors
+    /// * If a variable is malformed or out of range, see `new`
+    pub fn from_env() -> Result<Self> {
+        Self::from_vars(
+            env::var(MAX_CYCLE_LENGTH_ENV).ok().as_deref(),
+            env::var(ANCHOR_TOKENS_ENV).ok().as_deref(),
+            env::var(MAX_CYCLES_PER_TOKEN_ENV).ok().as_deref(),
+        )
+    }
+
+    fn from_vars(
+        max_cycle_length: Option<&str>,
+        anchor_tokens: Option<&str>,
+        max_cycles_per_token: Option<&str>,
+    ) -> Result<Self> {
+        let max_cycle_length = match max_cycle_length {
+            Some(value) => value
+                .trim()
+                .parse()
+                .map_err(|e| eyre!("Invalid {MAX_CYCLE_LENGTH_ENV} {value}: {e}"))?,
+            None => Self::default().max_cycle_length,
+        };
+
+        let anchor_tokens = anchor_tokens
+            .map(|value| {
+                value
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|token| !token.is_empty())
+                    .map(parse_anchor_token)
+                    .collect::<Result<Vec<_>>>()
+            })
+            .transpose()?;
+
+        let max_cycles_per_token = max_cycles_per_token
+            .map(|value| {
+                value
+                    .trim()
+                    .parse()
+                    .map_err(|e| eyre!("Invalid {MAX_CYCLES_PER_TOKEN_ENV} {value}: {e}"))
+            })
+            .transpose()?;
+
+        Self::new(max_cycle_length, anchor_tokens, max_cycles_per_token)
+    }
+
+    /// Leaves the pools of `excluded_tokens` out of the world
+    pub fn with_excluded_tokens(mut self, excluded_tokens: HashSet<TokenId>) -> Self {
+        self.excluded_tokens = excluded_tokens;
+        self
+    }
+
+    /// Stops excluding `tokens`, e.g. once they are classified as safe
+    pub fn include_tokens(&mut self, tokens: &HashSet<TokenId>) {
+        self.excluded_tokens
+            .retain(|token_id| !tokens.contains(token_id));
+    }
+
+    pub const fn max_cycle_length(&self) -> usize {
+        self.max_cycle_length
+    }
+
+    pub const fn anchor_tokens(&self) -> Option<&Vec<TokenId>> {
+        self.anchor_tokens.as_ref()
+    }
+
+    pub const fn max_cycles_per_token(&self) -> Option<usize> {
+        self.max_cycles_per_token
+    }
+
+    pub const fn excluded_tokens(&self) -> &HashSet<TokenId> {
+        &self.excluded_tokens
+    }
+
+    /// Whether a token of the pool is excluded
+    pub fn is_excluded(&self, pool: &Pool) -> bool {
+        self.excluded_tokens.contains(&pool.token0) || self.excluded_tokens.contains(&pool.token1)
+    }
+}
+
+/// An anchor token of `FLY_ANCHOR_TOKENS`: an address on Base or `<chain id>:<address>`
+fn parse_anchor_token(token: &str) -> Result<TokenId> {
+    let (chain_id, address) = match token.split_once(':') {
+        Some((chain_id, address)) => (
+            chain_id
+                .trim()
+                .parse::<ChainId>()
+                .map_err(|e| eyre!("Invalid {ANCHOR_TOKENS_ENV} chain id {chain_id}: {e}"))?,
+            address.trim(),
+        ),
+        None => (BASE_CHAIN_ID, token),
+    };
+    let address = Address::from_str(address)
+        .map_err(|e| eyre!("Invalid {ANCHOR_TOKENS_ENV} address {address}: {e}"))?;
+    Ok(TokenId::new(chain_id, address))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arb::test_helpers::*;
+    use crate::utils::constants::{ETHEREUM_CHAIN_ID, WETH};
+    use alloy::primitives::address;
+
+    #[test]
+    fn test_new() {
+        let config = WorldConfig::new(4, Some(vec![token("A").id]), Some(10)).unwrap();
+        assert_eq!(config.max_cycle_length(), 4);
+        assert_eq!(config.anchor_tokens(), Some(&vec![token("A").id]));
+        assert_eq!(config.max_cycles_per_token(), Some(10));
+        assert!(config.excluded_tokens().is_empty());
+    }
+
+    #[test]
+    fn test_from_vars() {
+        let config = WorldConfig::from_vars(None, None, None).unwrap();
+        assert_eq!(config, WorldConfig::default());
+
+        let config = WorldConfig::from_vars(
+            Some("4"),
+            Some("0x4200000000000000000000000000000000000006, "),
+            Some("100"),
+        )
+        .unwrap();
+        assert_eq!(config.max_cycle_length(), 4);
+        // Addresses without a chain id are Base tokens only
+        assert_eq!(
+            config.anchor_tokens(),
+            Some(&vec![TokenId::new(BASE_CHAIN_ID, WETH)])
+        );
+        assert_eq!(config.max_cycles_per_token(), Some(100));
+
+        let config = WorldConfig::from_vars(
+            None,
+            Some(
+                "0x4200000000000000000000000000000000000006, \
+                 1:0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2",
+            ),
+            None,
+        )
+        .unwrap();
+        assert_eq!(
+            config.anchor_tokens(),
+            Some(&vec![
+                TokenId::new(BASE_CHAIN_ID, WETH),
+                TokenId::new(
+                    ETHEREUM_CHAIN_ID,
+                    address!("0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2")
+                ),
+            ])
+        );
+
+        assert!(WorldConfig::from_vars(Some("6"), None, None).is_err());
+        assert!(WorldConfig::from_vars(None, Some("WETH"), None).is_err());
+        assert!(WorldConfig::from_vars(None, Some(""), None).is_err());
+        assert!(WorldConfig::from_vars(
+            None,
+            Some("x:0x4200000000000000000000000000000000000006"),
+            None
+        )
+        .is_err());
+        assert!(WorldConfig::from_vars(None, None, Some("0")).is_err());
+    }
+
+    #[test]
+    fn test_is_excluded() {
+        let config = WorldConfig::default().with_excluded_tokens(HashSet::from([token("B").id]));
+        assert!(config.is_excluded(&pool("F1", "A", "B", 100, 200)));
+        assert!(config.is_excluded(&pool("F2", "B", "C", 100, 200)));
+        assert!(!config.is_excluded(&pool("F3", "A", "C", 100, 200)));
+    }
+
+    #[test]
+    fn test_new_invalid() {
+        assert_eq!(
+            WorldConfig::new(1, None, None).err().unwrap().to_string(),
+            "Max cycle length must be between 2 and 5, got 1"
+        );
+        assert_eq!(
+            WorldConfig::new(6, None, None).err().unwrap().to_string(),
+            "Max cycle length must be between 2 and 5, got 6"
+        );
+        assert_eq!(
+            WorldConfig::new(3, Some(vec![]), None)
+                .err()
+                .unwrap()
+                .to_string(),
+            "Anchor tokens must not be empty"
+        );
+        assert_eq!(
+            WorldConfig::new(3, None, Some(0))
+                .err()
+                .unwrap()
+                .to_string(),
+            "Max cycles per token must be greater than zero"
+        );
+    }
+}
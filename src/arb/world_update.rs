@@ -2,6 +2,8 @@ use eyre::Error;
 
 use super::cycle::Cycle;
 use super::cycle_quote::CycleQuote;
+use super::gas_model::GasModel;
+use super::portfolio::Portfolio;
 use super::swap::Swap;
 
 pub struct WorldUpdate {
@@ -51,40 +53,79 @@ impl WorldUpdate {
             .collect()
     }
 
-    /// Profitable cycles - the cycles that have a positive rate and are exploitable
+    /// Profitable cycles - the cycles that have a positive rate and are exploitable. Cycles that
+    /// cannot be quoted are skipped.
     fn profitable_cycles(&self) -> Vec<Cycle> {
         assert!(self.has_all_reserves(), "All cycles must have reserves");
 
         self.positive_cycles()
-            .iter()
-            .filter(|cycle| cycle.best_quote().unwrap().is_profitable())
-            .cloned()
+            .into_iter()
+            .filter(|cycle| quote_is_profitable(cycle) == Some(true))
             .collect()
     }
 
+    /// Cycles that are not exploitable. Cycles that cannot be quoted are skipped.
     pub fn unprofitable_cycles(&self) -> Vec<Cycle> {
         assert!(self.has_all_reserves(), "All cycles must have reserves");
 
         self.cycles()
             .iter()
-            .filter(|cycle| !cycle.best_quote().unwrap().is_profitable())
+            .filter(|cycle| quote_is_profitable(cycle) == Some(false))
             .cloned()
             .collect()
     }
 
-    pub fn profitable_cycle_quotes(&self) -> Vec<Result<CycleQuote, Error>> {
-        self.profitable_cycles()
+    /// Best quotes of the cycles we can fund from the portfolio that are profitable after paying
+    /// for gas, the most profitable first. Profits in different tokens are compared in ETH.
+    pub fn profitable_cycle_quotes(
+        &self,
+        portfolio: &Portfolio,
+        gas_model: &GasModel,
+    ) -> Vec<CycleQuote> {
+        let mut quotes: Vec<_> = self
+            .profitable_cycles()
             .iter()
-            .map(super::cycle::Cycle::best_quote)
-            .collect()
+            .filter_map(|cycle| match cycle.best_quote_with(portfolio) {
+                Ok(quote) => quote,
+                Err(e) => {
+                    log::error!("Failed to quote cycle {cycle:?}: {e}");
+                    None
+                }
+            })
+            .filter_map(|quote| {
+                let net_profit = quote.net_profit(gas_model)?;
+                if !net_profit.is_positive() {
+                    return None;
+                }
+                let net_profit_in_wei = gas_model.to_wei(&quote.token(), net_profit.into_raw())?;
+                Some((net_profit_in_wei, quote))
+            })
+            .collect();
+
+        quotes.sort_by(|(a, _), (b, _)| b.cmp(a));
+        quotes.into_iter().map(|(_, quote)| quote).collect()
+    }
+}
+
+/// Whether the best quote of a cycle is profitable, `None` if it cannot be quoted, e.g. the
+/// binary search of a stable pool does not converge
+fn quote_is_profitable(cycle: &Cycle) -> Option<bool> {
+    match cycle.best_quote() {
+        Ok(quote) => Some(quote.is_profitable()),
+        Err(e) => {
+            log::error!("Failed to quote cycle {cycle:?}: {e}");
+            None
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::collections::HashMap;
+
     use alloy::primitives::{I256, U256};
 
-    use crate::arb::test_helpers::{bare_swap, cycle, swap};
+    use crate::arb::test_helpers::{bare_swap, cycle, swap, token};
 
     use super::*;
 
@@ -160,9 +201,9 @@ mod tests {
 
         let best_quote = cycle.best_quote().unwrap();
         assert!(best_quote.is_profitable());
-        assert_eq!(best_quote.amount_in(), U256::from(13354));
-        assert_eq!(best_quote.amount_out(), U256::from(13403));
-        assert_eq!(best_quote.profit(), I256::from_raw(U256::from(49)));
+        assert_eq!(best_quote.amount_in(), U256::from(99075));
+        assert_eq!(best_quote.amount_out(), U256::from(99270));
+        assert_eq!(best_quote.profit(), I256::from_raw(U256::from(195)));
 
         // Unprofitable cycle
         assert_eq!(world_update.unprofitable_cycles().len(), 1);
@@ -176,4 +217,105 @@ mod tests {
         assert_eq!(best_quote.amount_out(), U256::from(0));
         assert_eq!(best_quote.profit(), I256::from_raw(U256::from(0)));
     }
+
+    #[test]
+    fn test_profitable_cycle_quotes() {
+        let world_update = WorldUpdate::new(vec![
+            // ~195 A profit
+            cycle(&[
+                ("F1", "A", "B", 100_000_000, 200_000_000),
+                ("F2", "B", "A", 200_000_000, 101_000_000),
+            ])
+            .unwrap(),
+            // ~101k C profit
+            cycle(&[
+                ("F3", "C", "D", 1_000_000, 2_000_000),
+                ("F4", "D", "C", 3_000_000, 3_000_000),
+            ])
+            .unwrap(),
+            // Unprofitable
+            cycle(&[
+                ("F1", "B", "A", 200_000_000, 100_000_000),
+                ("F2", "A", "B", 101_000_000, 200_000_000),
+            ])
+            .unwrap(),
+        ]);
+
+        // 1 A = 1 C = 1 wei, free gas
+        let rates = HashMap::from([
+            (token("A").id, U256::from(10_u64.pow(18))),
+            (token("C").id, U256::from(10_u64.pow(18))),
+        ]);
+        let portfolio = Portfolio::new(HashMap::from([
+            (token("A").id, U256::from(1_000_000)),
+            (token("C").id, U256::from(1_000_000)),
+        ]));
+        let gas_model = GasModel::new(0, 0, rates.clone());
+        let quotes = world_update.profitable_cycle_quotes(&portfolio, &gas_model);
+        assert_eq!(quotes.len(), 2);
+        assert_eq!(quotes[0].token(), token("C").id);
+        assert_eq!(quotes[1].token(), token("A").id);
+
+        // 1 C = 0.001 wei: the A cycle is now more profitable
+        let gas_model = GasModel::new(
+            0,
+            0,
+            HashMap::from([
+                (token("A").id, U256::from(10_u64.pow(18))),
+                (token("C").id, U256::from(10_u128.pow(21))),
+            ]),
+        );
+        let quotes = world_update.profitable_cycle_quotes(&portfolio, &gas_model);
+        assert_eq!(quotes.len(), 2);
+        assert_eq!(quotes[0].token(), token("A").id);
+
+        // 386k gas at 1 wei per gas is too much for both cycles
+        let gas_model = GasModel::new(0, 1, rates.clone());
+        let quotes = world_update.profitable_cycle_quotes(&portfolio, &gas_model);
+        assert!(quotes.is_empty());
+
+        // No exchange rates - no way to tell
+        let gas_model = GasModel::new(0, 0, HashMap::new());
+        assert!(world_update
+            .profitable_cycle_quotes(&portfolio, &gas_model)
+            .is_empty());
+    }
+
+    #[test]
+    fn test_profitable_cycle_quotes_with_portfolio() {
+        let world_update = WorldUpdate::new(vec![
+            cycle(&[
+                ("F1", "A", "B", 1_000_000, 2_000_000),
+                ("F2", "B", "A", 3_000_000, 3_000_000),
+            ])
+            .unwrap(),
+            cycle(&[
+                ("F3", "C", "D", 1_000_000, 2_000_000),
+                ("F4", "D", "C", 3_000_000, 3_000_000),
+            ])
+            .unwrap(),
+        ]);
+        let gas_model = GasModel::new(
+            0,
+            0,
+            HashMap::from([
+                (token("A").id, U256::from(10_u64.pow(18))),
+                (token("B").id, U256::from(10_u64.pow(18))),
+                (token("C").id, U256::from(10_u64.pow(18))),
+            ]),
+        );
+
+        // Nothing to fund the cycles with
+        let portfolio = Portfolio::new(HashMap::new());
+        assert!(world_update
+            .profitable_cycle_quotes(&portfolio, &gas_model)
+            .is_empty());
+
+        // Only the first cycle is funded, by B, so it starts with B
+        let portfolio = Portfolio::new(HashMap::from([(token("B").id, U256::from(1_000))]));
+        let quotes = world_update.profitable_cycle_quotes(&portfolio, &gas_model);
+        assert_eq!(quotes.len(), 1);
+        assert_eq!(quotes[0].token(), token("B").id);
+        assert_eq!(quotes[0].amount_in(), U256::from(1_000));
+    }
 }
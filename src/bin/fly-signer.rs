@@ -0,0 +1,57 @@
+//! Reference signer daemon for the core's Unix socket, see `fly::signer_daemon`
+
+use std::env;
+use std::path::PathBuf;
+
+use alloy::primitives::ChainId;
+use alloy::providers::RootProvider;
+use alloy::signers::local::PrivateKeySigner;
+use clap::Parser;
+use eyre::{eyre, Result};
+use fly::signer_daemon::{Policy, SignerDaemon};
+use fly::utils::app_context::SIGNER_SOCKET_PATH;
+use fly::utils::constants::BASE_CHAIN_ID;
+use fly::utils::logger::setup_logger;
+use url::Url;
+
+/// Password of the keystore
+const KEYSTORE_PASSWORD_ENV: &str = "FLY_SIGNER_KEYSTORE_PASSWORD";
+
+#[derive(Parser)]
+#[command(author, version, about = "Signs the orders of the fly core", long_about = None)]
+struct Cli {
+    /// Encrypted JSON keystore of the signing key, unlocked with `FLY_SIGNER_KEYSTORE_PASSWORD`
+    #[arg(long)]
+    keystore: PathBuf,
+    /// JSON file with the policy limits of the orders
+    #[arg(long)]
+    policy: PathBuf,
+    /// Unix socket to listen on
+    #[arg(long, default_value = SIGNER_SOCKET_PATH)]
+    socket: String,
+    #[arg(long, default_value_t = BASE_CHAIN_ID)]
+    chain_id: ChainId,
+    /// Broadcast the transactions through this RPC instead of returning them to the core
+    #[arg(long)]
+    rpc_url: Option<Url>,
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    setup_logger().expect("Failed to set up logger");
+
+    let cli = Cli::parse();
+    let password = env::var(KEYSTORE_PASSWORD_ENV)
+        .map_err(|_| eyre!("{KEYSTORE_PASSWORD_ENV} not found in environment variables"))?;
+    let wallet = PrivateKeySigner::decrypt_keystore(&cli.keystore, password)?;
+    let policy = Policy::from_file(&cli.policy)?;
+
+    let mut daemon = SignerDaemon::new(wallet, cli.chain_id, policy);
+    if let Some(rpc_url) = cli.rpc_url {
+        daemon = daemon
+            .with_broadcast(RootProvider::new_http(rpc_url))
+            .await?;
+    }
+
+    daemon.serve(&cli.socket).await
+}
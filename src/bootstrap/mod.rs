@@ -1,35 +1,42 @@
 pub mod types;
 
-use crate::arb::pool::Pool;
+use crate::arb::pool::{Pool, PoolId, PoolKind};
+use crate::arb::solidly_stable::{SolidlyStable, DEFAULT_STABLE_FEE};
+use crate::arb::swap::{Direction, SwapId, DEFAULT_FEE};
+use crate::arb::token::TokenId;
 use crate::bootstrap::types::{PairInfo, Reserves};
+use crate::models::cycle::Cycle;
+use crate::models::pair::DBAddress;
+use crate::models::token::TokenSafety;
+use crate::schemas::{cycles, pairs, tokens};
 use crate::utils::app_context::AppContext;
-use crate::utils::constants::UNISWAP_V2_BATCH_QUERY_ADDRESS;
+use crate::utils::constants::{BASE_CHAIN_ID, UNISWAP_V2_BATCH_QUERY_ADDRESS};
 
 use alloy::{
-    primitives::{Address, U256},
+    eips::BlockId,
+    primitives::{Address, ChainId, U256},
+    providers::MULTICALL3_ADDRESS,
     sol,
+    sol_types::{SolCall, SolValue},
 };
-use bigdecimal::BigDecimal;
+use diesel::sql_types::{BigInt, Bool, Integer, Nullable, Text};
+use diesel::{ExpressionMethods, PgExpressionMethods, QueryDsl, QueryableByName, SelectableHelper};
+use diesel_async::RunQueryDsl;
 use eyre::Error;
-use std::collections::HashSet;
+use futures::{StreamExt, TryStreamExt};
+use indicatif::{ProgressBar, ProgressStyle};
+use std::collections::{HashMap, HashSet};
 use std::str::FromStr;
+use std::time::{Duration, Instant};
 
 sol!(
     #[sol(rpc)]
     "contracts/src/UniswapQuery.sol"
 );
 
-/// Convert a U256 to a f64
-///
-/// # Arguments
-/// * `value` - The U256 value to convert
-///
-/// # Returns
-/// The f64 value
-#[allow(dead_code)]
-fn u256_to_f64(value: U256) -> f64 {
-    let s: String = value.to_string(); // Convert U256 to string
-    s.parse::<f64>().unwrap_or(0.0) // Parse it into f64
+sol! {
+    #[sol(rpc)]
+    "contracts/src/interfaces/IMulticall3.sol"
 }
 
 /// Retrieves pairs within a specified index range from a factory contract
@@ -55,7 +62,7 @@ pub async fn fetch_pairs_v2_by_range(
     to: U256,
 ) -> Result<Vec<PairInfo>, Error> {
     let uniswap_v2_batch_request =
-        UniswapQuery::new(UNISWAP_V2_BATCH_QUERY_ADDRESS, &ctx.base_provider);
+        UniswapQuery::new(UNISWAP_V2_BATCH_QUERY_ADDRESS, ctx.base_provider());
 
     Ok(uniswap_v2_batch_request
         .getPairsByIndexRange(factory, from, to)
@@ -68,263 +75,520 @@ pub async fn fetch_pairs_v2_by_range(
         .collect())
 }
 
-/// Calculate reserves and USD value for a pair
+/// Retrieves reserves for a list of pairs at the end of a block
 ///
 /// # Arguments
-/// * `pair` - Pair information
-/// * `reserve` - Reserves for the pair
+/// * `pairs` - Vector of pair addresses
+/// * `block_number` - Block to read the reserves at, so all of them are from the same state
+///
+/// Every pair is called on its own through `IMulticall3.aggregate3`, so a pair that reverts does
+/// not fail the others. The return data is decoded as three `uint256`, which reads UniswapV2 pairs
+/// as well as Aerodrome pools whose reserves do not fit `uint112`.
 ///
 /// # Returns
-/// Tuple containing token0 reserve, token1 reserve, and USD value
-#[allow(dead_code)]
-fn calculate_reserves_and_usd(
-    pair: &PairInfo,
-    reserve: &Reserves,
-) -> (BigDecimal, BigDecimal, i32) {
-    // Calculate human-readable reserve values
-    let reserve0_decimal = u256_to_f64(reserve.reserve0) / 10_f64.powi(pair.token0.decimals());
-    let reserve1_decimal = u256_to_f64(reserve.reserve1) / 10_f64.powi(pair.token1.decimals());
-
-    // Convert to BigDecimal for database storage
-    let token0_reserve =
-        BigDecimal::from_str(&reserve0_decimal.to_string()).unwrap_or_else(|_| BigDecimal::from(0));
-    let token1_reserve =
-        BigDecimal::from_str(&reserve1_decimal.to_string()).unwrap_or_else(|_| BigDecimal::from(0));
-
-    // Calculate USD value
-    let mut usd_value: i32 = 0;
-
-    // Hardcoded token addresses and prices
-    let weth_address = "0x4200000000000000000000000000000000000006".to_lowercase();
-    let usdc_address = "0xd9fcd98c322942075a5c3860693e9f4f03aae07b".to_lowercase();
-    let usdt_address = "0x2f4d3d3f2f3d3f2f4d3d3f2f4d3d3f2f4d3d3f2f".to_lowercase();
-    let dai_address = "0x50c5725949a6f0c72e6c4a641f24049a917db0cb".to_lowercase();
-
-    // Check token0
-    let token0_address = pair.token0.address().to_string().to_lowercase();
-    let token0_symbol = pair.token0.symbol().unwrap_or_default().to_uppercase();
-
-    let token0_price = match token0_address.as_str() {
-        addr if addr == weth_address || token0_symbol == "WETH" => 2118.14,
-        addr if addr == usdc_address || token0_symbol == "USDC" => 1.0,
-        addr if addr == usdt_address || token0_symbol == "USDT" => 1.0,
-        addr if addr == dai_address || token0_symbol == "DAI" => 1.0,
-        _ => 0.0,
-    };
-
-    if token0_price > 0.0 {
-        let token0_usd = reserve0_decimal * token0_price;
-        // Multiply by 2 to represent total reserve
-        let total_usd = token0_usd * 2.0;
-        usd_value = total_usd as i32; // Store as whole dollars
+/// `Reserves` of each pair in the order of `pairs`, `None` for the pairs whose `getReserves`
+/// reverted or returned something else
+///
+/// # Errors
+/// * If the RPC connection fails
+pub async fn fetch_reserves_by_range(
+    ctx: &AppContext,
+    pairs: Vec<Address>,
+    block_number: u64,
+) -> Result<Vec<Option<Reserves>>, eyre::Report> {
+    let multicall = IMulticall3::new(MULTICALL3_ADDRESS, ctx.base_provider());
+    let calls: Vec<IMulticall3::Call3> = pairs
+        .into_iter()
+        .map(|pair| IMulticall3::Call3 {
+            target: pair,
+            allowFailure: true,
+            callData: IUniswapV2Pair::getReservesCall::new(()).abi_encode().into(),
+        })
+        .collect();
+
+    Ok(multicall
+        .aggregate3(calls)
+        .gas(3_000_000_000)
+        .block(BlockId::number(block_number))
+        .call()
+        .await?
+        .returnData
+        .iter()
+        .map(decode_reserves)
+        .collect())
+}
+
+/// Reserves of a `getReserves` result, `None` if the call failed
+fn decode_reserves(result: &IMulticall3::Result) -> Option<Reserves> {
+    if !result.success {
+        return None;
     }
+    <[U256; 3]>::abi_decode(&result.returnData, false)
+        .ok()
+        .map(Reserves::from)
+}
 
-    // Check token1 if token0 didn't match
-    if usd_value == 0 {
-        let token1_address = pair.token1.address().to_string().to_lowercase();
-        let token1_symbol = pair.token1.symbol().unwrap_or_default().to_uppercase();
-
-        let token1_price = match token1_address.as_str() {
-            addr if addr == weth_address || token1_symbol == "WETH" => 2118.14,
-            addr if addr == usdc_address || token1_symbol == "USDC" => 1.0,
-            addr if addr == usdt_address || token1_symbol == "USDT" => 1.0,
-            addr if addr == dai_address || token1_symbol == "DAI" => 1.0,
-            _ => 0.0,
-        };
+/// Pool kind of a pair from the `pairs.stable` flag and the `tokens.decimals` of its tokens
+///
+/// # Returns
+/// `None` if the pair is a stable pool and the decimals of a token are not known (yet) or invalid:
+/// stable pools cannot be priced without them.
+pub fn pool_kind(stable: bool, decimals0: Option<i32>, decimals1: Option<i32>) -> Option<PoolKind> {
+    if !stable {
+        return Some(PoolKind::UniswapV2);
+    }
 
-        if token1_price > 0.0 {
-            let token1_usd = reserve1_decimal * token1_price;
-            // Multiply by 2 to represent total reserve
-            let total_usd = token1_usd * 2.0;
-            usd_value = total_usd as i32; // Store as whole dollars
+    let decimals0 = u8::try_from(decimals0?).ok()?;
+    let decimals1 = u8::try_from(decimals1?).ok()?;
+    SolidlyStable::new(decimals0, decimals1)
+        .ok()
+        .map(PoolKind::SolidlyStable)
+}
+
+#[derive(QueryableByName, Debug)]
+struct PoolRow {
+    #[diesel(sql_type = BigInt)]
+    chain_id: i64,
+    #[diesel(sql_type = Text)]
+    address: String,
+    #[diesel(sql_type = Text)]
+    token0_address: String,
+    #[diesel(sql_type = Text)]
+    token1_address: String,
+    #[diesel(sql_type = Nullable<Integer>)]
+    decimals0: Option<i32>,
+    #[diesel(sql_type = Nullable<Integer>)]
+    decimals1: Option<i32>,
+    #[diesel(sql_type = Bool)]
+    stable: bool,
+    /// Fee of the factory for the kind of the pool, stable or volatile
+    #[diesel(sql_type = Nullable<Integer>)]
+    fee: Option<i32>,
+}
+
+impl PoolRow {
+    /// `None` if an address is malformed, a reserve is zero or the pool kind is unknown
+    fn pool(&self, reserves: &Reserves) -> Option<Pool> {
+        if reserves.reserve0.is_zero() || reserves.reserve1.is_zero() {
+            return None;
         }
+
+        let chain_id = self.chain_id as ChainId;
+        let fee = match self.fee {
+            Some(fee) => u32::try_from(fee).ok()?,
+            None if self.stable => DEFAULT_STABLE_FEE,
+            None => DEFAULT_FEE,
+        };
+
+        let mut pool = Pool::new(
+            PoolId::parse(chain_id, &self.address).ok()?,
+            TokenId::parse(chain_id, &self.token0_address).ok()?,
+            TokenId::parse(chain_id, &self.token1_address).ok()?,
+            Some(reserves.reserve0),
+            Some(reserves.reserve1),
+            fee,
+        );
+        pool.kind = pool_kind(self.stable, self.decimals0, self.decimals1)?;
+        Some(pool)
     }
+}
 
-    (token0_reserve, token1_reserve, usd_value)
+#[derive(QueryableByName, Debug)]
+struct Count {
+    #[diesel(sql_type = BigInt)]
+    count: i64,
 }
 
-/// Retrieves reserves for a list of pairs
+/// Base pairs with both tokens and at least `$1` USD of liquidity. Pairs without a USD value
+/// count as 0.
+const POOLS_FROM_WHERE: &str = "FROM pairs
+     JOIN tokens token0 ON token0.id = pairs.token0_id
+     JOIN tokens token1 ON token1.id = pairs.token1_id
+     LEFT JOIN factories ON factories.id = pairs.factory_id
+     WHERE pairs.chain_id = $1 AND COALESCE(pairs.usd, 0) >= $2";
+
+/// Number of reserve chunks fetched at the same time
+const FETCH_CONCURRENCY: usize = 8;
+
+/// Attempts per reserve chunk before its pairs are skipped. A reverted multicall is not retried.
+const FETCH_ATTEMPTS: u32 = 3;
+
+/// Delay before retrying a failed chunk, multiplied by the attempt number
+const RETRY_DELAY: Duration = Duration::from_millis(500);
+
+/// Loads all Base pools with both tokens known from the database with their current reserves, to
+/// be passed to `World::new`
+///
+/// Pairs are streamed from the database in chunks of `batch_size` and the reserves of each chunk
+/// are fetched with a single multicall, `FETCH_CONCURRENCY` chunks at a time. Pairs whose
+/// `getReserves` reverts are skipped on their own, chunks whose request failed are retried. All
+/// reserves are read at the end of `block_number`, so the pools are a consistent snapshot of that
+/// block.
 ///
 /// # Arguments
-/// * `pairs` - Vector of pair addresses
+/// * `block_number` - Block to read the reserves at
+/// * `batch_size` - Number of pairs per multicall
+/// * `min_usd` - Minimum liquidity of a pair in USD (`pairs.usd`)
 ///
 /// # Returns
-/// Vector of `Reserves` containing reserve information for each pair
+/// Pools priced by their current reserves. Pairs with malformed addresses, unreadable or zero
+/// reserves or an unknown pool kind are skipped, as are chunks that failed every attempt.
 ///
 /// # Errors
-/// * If contract call to get reserves fails
-/// * If batch request initialization fails
-/// * If the RPC connection fails
-///
-/// # Panics
-/// * If contract call to get reserves fails
-/// * If batch request contract initialization fails
-pub async fn fetch_reserves_by_range(
+/// * If database connection fails
+/// * If database queries fail
+pub async fn fetch_all_pools(
     ctx: &AppContext,
-    pairs: Vec<Address>,
-) -> Result<Vec<Reserves>, eyre::Report> {
-    let uniswap_v2_batch_request =
-        UniswapQuery::new(UNISWAP_V2_BATCH_QUERY_ADDRESS, &ctx.base_provider);
+    block_number: u64,
+    batch_size: usize,
+    min_usd: i32,
+) -> Result<HashSet<Pool>, Error> {
+    let mut conn = ctx.db.get().await?;
+    let started_at = Instant::now();
+
+    let total = diesel::sql_query(format!("SELECT COUNT(*) AS count {POOLS_FROM_WHERE}"))
+        .bind::<BigInt, _>(BASE_CHAIN_ID as i64)
+        .bind::<Integer, _>(min_usd)
+        .get_result::<Count>(&mut conn)
+        .await?
+        .count;
+
+    let progress_bar = ProgressBar::new(u64::try_from(total).unwrap_or_default());
+    progress_bar.set_style(
+        ProgressStyle::default_bar()
+            .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {pos}/{len} ({percent}%) - {msg}")?
+            .progress_chars("#>-"),
+    );
+    progress_bar.set_message(format!("Fetching pool reserves at block {block_number}..."));
+
+    let rows = diesel::sql_query(format!(
+        "SELECT pairs.chain_id, pairs.address,
+                token0.address AS token0_address, token1.address AS token1_address,
+                token0.decimals AS decimals0, token1.decimals AS decimals1,
+                pairs.stable,
+                CASE WHEN pairs.stable THEN factories.stable_fee ELSE factories.fee END AS fee
+         {POOLS_FROM_WHERE}
+         ORDER BY pairs.id"
+    ))
+    .bind::<BigInt, _>(BASE_CHAIN_ID as i64)
+    .bind::<Integer, _>(min_usd)
+    .load_stream::<PoolRow>(&mut conn)
+    .await?;
+
+    let mut chunks = rows
+        .try_chunks(batch_size)
+        .map_err(|e| e.1)
+        .map(|chunk| async {
+            let chunk = chunk?;
+            let reserves = fetch_chunk_reserves(ctx, &chunk, block_number).await;
+            progress_bar.inc(chunk.len() as u64);
+            Ok::<_, Error>((chunk, reserves))
+        })
+        .buffer_unordered(FETCH_CONCURRENCY);
+
+    let mut pools = HashSet::new();
+    let mut failed_count = 0;
+    let mut skipped_count = 0;
+    while let Some(result) = chunks.next().await {
+        let (chunk, reserves) = result?;
+        let Some(reserves) = reserves else {
+            failed_count += chunk.len();
+            continue;
+        };
 
-    Ok(uniswap_v2_batch_request
-        .getReservesByPairs(pairs)
-        .gas(3_000_000_000)
-        .call()
+        for (row, reserves) in chunk.iter().zip(&reserves) {
+            match reserves.as_ref().and_then(|reserves| row.pool(reserves)) {
+                Some(pool) => {
+                    pools.insert(pool);
+                }
+                None => skipped_count += 1,
+            }
+        }
+        progress_bar.set_message(format!(
+            "Loaded {} pools, skipped {}, failed {}",
+            pools.len(),
+            skipped_count,
+            failed_count
+        ));
+    }
+
+    progress_bar.finish_with_message(format!(
+        "Loaded {} pools in {:.2}s, skipped {}, failed {}",
+        pools.len(),
+        started_at.elapsed().as_secs_f64(),
+        skipped_count,
+        failed_count
+    ));
+
+    Ok(pools)
+}
+
+/// Reserves of the chunk pairs in the chunk order, `None` if every attempt failed. Pairs with a
+/// malformed address are not fetched and have no reserves.
+async fn fetch_chunk_reserves(
+    ctx: &AppContext,
+    chunk: &[PoolRow],
+    block_number: u64,
+) -> Option<Vec<Option<Reserves>>> {
+    let addresses: Vec<Option<Address>> = chunk
+        .iter()
+        .map(|row| match DBAddress::from_str(&row.address) {
+            Ok(address) => Some(address.value),
+            Err(_) => {
+                log::warn!(
+                    "bootstrap: Skipping pair with malformed address {}",
+                    row.address
+                );
+                None
+            }
+        })
+        .collect();
+    let valid_addresses: Vec<Address> = addresses.iter().flatten().copied().collect();
+    if valid_addresses.is_empty() {
+        return Some(align_reserves(&addresses, Vec::new()));
+    }
+
+    for attempt in 1..=FETCH_ATTEMPTS {
+        match fetch_reserves_by_range(ctx, valid_addresses.clone(), block_number).await {
+            Ok(reserves) if reserves.len() == valid_addresses.len() => {
+                return Some(align_reserves(&addresses, reserves));
+            }
+            Ok(reserves) => {
+                log::warn!(
+                    "bootstrap: Got {} reserves for {} pairs (attempt {attempt}/{FETCH_ATTEMPTS})",
+                    reserves.len(),
+                    valid_addresses.len()
+                );
+            }
+            Err(e) if is_revert(&e) => {
+                // The same call at the same block reverts every time
+                log::warn!("bootstrap: Multicall of reserves reverted: {e}");
+                break;
+            }
+            Err(e) => {
+                log::warn!(
+                    "bootstrap: Failed to fetch reserves (attempt {attempt}/{FETCH_ATTEMPTS}): {e}"
+                );
+            }
+        }
+        tokio::time::sleep(RETRY_DELAY * attempt).await;
+    }
+
+    log::error!(
+        "bootstrap: Skipping {} pairs starting with {}",
+        chunk.len(),
+        chunk[0].address
+    );
+    None
+}
+
+/// Whether a failed call reverted, rather than the request failing
+fn is_revert(error: &eyre::Report) -> bool {
+    error
+        .downcast_ref::<alloy::contract::Error>()
+        .is_some_and(|error| match error {
+            alloy::contract::Error::TransportError(e) => e
+                .as_error_resp()
+                .is_some_and(|resp| resp.as_revert_data().is_some()),
+            _ => false,
+        })
+}
+
+/// Places the reserves fetched for the valid `addresses` at their positions, `None` for the
+/// malformed ones
+fn align_reserves(
+    addresses: &[Option<Address>],
+    reserves: Vec<Option<Reserves>>,
+) -> Vec<Option<Reserves>> {
+    let mut reserves = reserves.into_iter();
+    addresses
+        .iter()
+        .map(|address| address.and_then(|_| reserves.next().flatten()))
+        .collect()
+}
+
+/// Loads cycles precomputed by `sync::cycles`, to be passed to `World::from_persisted`
+///
+/// # Returns
+/// Cycles as lists of `SwapId`s in the cycle order. Malformed cycles and cycles that reference
+/// unknown pairs are skipped.
+///
+/// # Errors
+/// * If database connection fails
+/// * If database queries fail
+pub async fn fetch_persisted_cycles(ctx: &AppContext) -> Result<Vec<Vec<SwapId>>, Error> {
+    let mut conn = ctx.db.get().await?;
+
+    let pool_ids: HashMap<i32, PoolId> = pairs::table
+        .select((pairs::id, pairs::chain_id, pairs::address))
+        .load::<(i32, i64, String)>(&mut conn)
         .await?
-        ._0
         .into_iter()
-        .map(Into::into)
+        .filter_map(|(id, chain_id, address)| {
+            DBAddress::from_str(&address)
+                .ok()
+                .map(|address| (id, PoolId::new(chain_id as ChainId, address.value)))
+        })
+        .collect();
+
+    let cycles = cycles::table
+        .select(Cycle::as_select())
+        .order(cycles::id.asc())
+        .load::<Cycle>(&mut conn)
+        .await?;
+
+    Ok(cycles
+        .iter()
+        .filter_map(|cycle| {
+            cycle
+                .swaps()?
+                .into_iter()
+                .map(|(pair_id, zero_for_one)| {
+                    Some(SwapId {
+                        pool_id: pool_ids.get(&pair_id)?.clone(),
+                        direction: if zero_for_one {
+                            Direction::ZeroForOne
+                        } else {
+                            Direction::OneForZero
+                        },
+                    })
+                })
+                .collect()
+        })
         .collect())
 }
 
-/// Retrieves reserves for all pairs in the database
-///
-/// # Arguments
-/// * `batch_size` - Number of pairs to process in each batch
+/// Loads the tokens not classified as safe by `sync::token_safety`, to be excluded from the world
+/// with `WorldConfig::with_excluded_tokens`
 ///
 /// # Returns
-/// Vector of tuples containing pair address and its reserves
+/// Ids of the unsafe tokens. Tokens that are not classified yet are included, until they are
+/// known to be safe.
 ///
-/// # Panics
+/// # Errors
 /// * If database connection fails
-/// * If HTTP provider creation fails
-/// * If contract calls fail
-/// * If pair addresses cannot be parsed
-pub async fn fetch_all_pools(_ctx: &mut AppContext, _batch_size: usize) -> HashSet<Pool> {
-    todo!();
-    // Create context in a block to drop PgConnection before async operations
-    // let pools = PairService::load_all_pools(&mut ctx.pg_connection);
-    // let pools_clone: Vec<Pool> = pools.iter().cloned().collect();
-    // let mut pools_to_replace = Vec::new();
-    // let mut result_pools = pools;
-
-    // let total_pools = pools_clone.len();
-
-    // // Create a progress bar
-    // let progress_bar = ProgressBar::new(total_pools as u64);
-
-    // // Set a custom style for the progress bar
-    // progress_bar.set_style(
-    //     ProgressStyle::default_bar()
-    //         .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {pos}/{len} ({percent}%) - {msg}")
-    //         .unwrap()
-    //         .progress_chars("#>-")
-    // );
-
-    // // Set initial message
-    // progress_bar.set_message("Starting pool reserve updates...");
-
-    // // Track start time
-    // let start_time = Instant::now();
-    // let mut updated_count = 0;
-    // let mut error_count = 0;
-
-    // // Process pairs in batches sequentially
-    // for (chunk_index, pool_chunk) in pools_clone.chunks(batch_size).enumerate() {
-    //     let chunk_start = chunk_index * batch_size;
-    //     let chunk_end = chunk_start + pool_chunk.len();
-
-    //     progress_bar.set_message(format!(
-    //         "Fetching reserves for batch {}/{} (pools {}-{})",
-    //         chunk_index + 1,
-    //         (total_pools as f64 / batch_size as f64).ceil() as usize,
-    //         chunk_start,
-    //         chunk_end
-    //     ));
-
-    //     let addresses: Vec<Address> = pool_chunk
-    //         .iter()
-    //         .map(|pair| Address::from_str(&pair.id.to_string()).unwrap())
-    //         .collect();
-
-    //     // Process single batch
-    //     let reserves = match fetch_reserves_by_range(ctx, addresses).await {
-    //         Ok(reserves) => reserves,
-    //         Err(e) => {
-    //             error!("Error fetching reserves: {e}");
-    //             // Update progress bar for skipped pools
-    //             progress_bar.inc(pool_chunk.len() as u64);
-    //             error_count += pool_chunk.len();
-    //             progress_bar.set_message(format!(
-    //                 "Error fetching reserves for batch {}: {}",
-    //                 chunk_index + 1,
-    //                 e
-    //             ));
-    //             continue;
-    //         }
-    //     };
-
-    //     progress_bar.set_message(format!(
-    //         "Processing batch {}/{} (pools {}-{})",
-    //         chunk_index + 1,
-    //         (total_pools as f64 / batch_size as f64).ceil() as usize,
-    //         chunk_start,
-    //         chunk_end
-    //     ));
-
-    //     for (i, pool) in pool_chunk.iter().enumerate() {
-    //         if i >= reserves.len() {
-    //             progress_bar.inc(1);
-    //             error_count += 1;
-    //             continue;
-    //         }
-
-    //         let new_reserves = &reserves[i];
-
-    //         // Remove old pool and insert updated one
-    //         if result_pools.remove(pool) {
-    //             let mut updated_pool = pool.clone();
-    //             updated_pool.reserve0 = Some(new_reserves.reserve0);
-    //             updated_pool.reserve1 = Some(new_reserves.reserve1);
-    //             pools_to_replace.push(updated_pool);
-    //             updated_count += 1;
-    //         } else {
-    //             error_count += 1;
-    //         }
-
-    //         // Update progress
-    //         progress_bar.inc(1);
-
-    //         // Calculate and display stats
-    //         let elapsed = start_time.elapsed();
-    //         let processed = progress_bar.position();
-    //         if processed > 0 {
-    //             let pools_per_second = processed as f64 / elapsed.as_secs_f64();
-    //             let remaining = total_pools as u64 - processed;
-    //             let eta_seconds = if pools_per_second > 0.0 {
-    //                 remaining as f64 / pools_per_second
-    //             } else {
-    //                 0.0
-    //             };
-
-    //             progress_bar.set_message(format!(
-    //                 "Batch {}/{} | Speed: {:.2} pools/sec | Updated: {} | Errors: {} | ETA: {:.2} min",
-    //                 chunk_index + 1,
-    //                 (total_pools as f64 / batch_size as f64).ceil() as usize,
-    //                 pools_per_second,
-    //                 updated_count,
-    //                 error_count,
-    //                 eta_seconds / 60.0
-    //             ));
-    //         }
-    //     }
-
-    //     // Add delay between batches
-    //     tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
-    // }
-
-    // Finish the progress bar
-    // progress_bar.finish_with_message(format!(
-    //     "Pool reserve updates completed in {:.2} minutes! Updated: {} pools, Errors: {} pools",
-    //     start_time.elapsed().as_secs_f64() / 60.0,
-    //     updated_count,
-    //     error_count
-    // ));
-
-    // // Insert all updated pools at once (after iteration)
-    // for pool in pools_to_replace {
-    //     result_pools.insert(pool);
-    // }
-
-    // result_pools
+/// * If database queries fail
+pub async fn fetch_unsafe_tokens(ctx: &AppContext) -> Result<HashSet<TokenId>, Error> {
+    let mut conn = ctx.db.get().await?;
+
+    let tokens = tokens::table
+        .filter(tokens::safety.is_distinct_from(TokenSafety::Safe))
+        .select((tokens::chain_id, tokens::address))
+        .load::<(i64, String)>(&mut conn)
+        .await?;
+
+    Ok(tokens
+        .iter()
+        .filter_map(|(chain_id, address)| TokenId::parse(*chain_id as ChainId, address).ok())
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arb::test_helpers::address_from_str;
+
+    fn row(stable: bool, fee: Option<i32>) -> PoolRow {
+        PoolRow {
+            chain_id: BASE_CHAIN_ID as i64,
+            address: address_from_str("F1").to_string(),
+            token0_address: address_from_str("A").to_string(),
+            token1_address: address_from_str("B").to_string(),
+            decimals0: Some(6),
+            decimals1: Some(18),
+            stable,
+            fee,
+        }
+    }
+
+    fn reserves(reserve0: u64, reserve1: u64) -> Reserves {
+        Reserves::from([U256::from(reserve0), U256::from(reserve1), U256::ZERO])
+    }
+
+    #[test]
+    fn test_pool_row() {
+        let pool = row(false, Some(25)).pool(&reserves(100, 200)).unwrap();
+        assert_eq!(pool.id.chain_id(), BASE_CHAIN_ID);
+        assert_eq!(pool.reserve0, Some(U256::from(100)));
+        assert_eq!(pool.reserve1, Some(U256::from(200)));
+        assert_eq!(pool.fee, 25);
+        assert_eq!(pool.kind, PoolKind::UniswapV2);
+
+        // Factories with an unknown fee get the default fee of the pool kind
+        assert_eq!(
+            row(false, None).pool(&reserves(100, 200)).unwrap().fee,
+            DEFAULT_FEE
+        );
+        let pool = row(true, None).pool(&reserves(100, 200)).unwrap();
+        assert_eq!(pool.fee, DEFAULT_STABLE_FEE);
+        assert_eq!(
+            pool.kind,
+            PoolKind::SolidlyStable(SolidlyStable::new(6, 18).unwrap())
+        );
+    }
+
+    #[test]
+    fn test_align_reserves() {
+        let addresses = [
+            Some(address_from_str("F1")),
+            None,
+            Some(address_from_str("F2")),
+        ];
+        let aligned = align_reserves(&addresses, vec![Some(reserves(1, 2)), Some(reserves(3, 4))]);
+        assert_eq!(aligned.len(), 3);
+        assert_eq!(aligned[0].as_ref().unwrap().reserve0, U256::from(1));
+        assert!(aligned[1].is_none());
+        assert_eq!(aligned[2].as_ref().unwrap().reserve0, U256::from(3));
+
+        // A pair that failed stays in place
+        let aligned = align_reserves(&addresses, vec![None, Some(reserves(3, 4))]);
+        assert!(aligned[0].is_none());
+        assert_eq!(aligned[2].as_ref().unwrap().reserve0, U256::from(3));
+
+        assert!(align_reserves(&[None, None], Vec::new())
+            .iter()
+            .all(Option::is_none));
+    }
+
+    #[test]
+    fn test_decode_reserves() {
+        let result = |success: bool, data: Vec<u8>| IMulticall3::Result {
+            success,
+            returnData: data.into(),
+        };
+
+        // A UniswapV2 pair pads its uint112 reserves to words
+        let uniswap = IUniswapV2Pair::getReservesCall::abi_encode_returns(&(
+            alloy::primitives::Uint::<112, 2>::from(100),
+            alloy::primitives::Uint::<112, 2>::from(200),
+            7u32,
+        ));
+        let decoded = decode_reserves(&result(true, uniswap)).unwrap();
+        assert_eq!(decoded.reserve0, U256::from(100));
+        assert_eq!(decoded.reserve1, U256::from(200));
+        assert_eq!(decoded.block_timestamp_last, U256::from(7));
+
+        // An Aerodrome pool returns uint256 reserves over uint112
+        let large = U256::from(1) << 120;
+        let aerodrome = [large, U256::from(200), U256::from(7)].abi_encode();
+        let decoded = decode_reserves(&result(true, aerodrome.clone())).unwrap();
+        assert_eq!(decoded.reserve0, large);
+
+        assert!(decode_reserves(&result(false, aerodrome)).is_none());
+        assert!(decode_reserves(&result(true, Vec::new())).is_none());
+    }
+
+    #[test]
+    fn test_pool_row_skipped() {
+        assert!(row(false, None).pool(&reserves(0, 200)).is_none());
+        assert!(row(false, Some(-1)).pool(&reserves(100, 200)).is_none());
+
+        let mut stable_row = row(true, None);
+        stable_row.decimals1 = None;
+        assert!(stable_row.pool(&reserves(100, 200)).is_none());
+
+        let mut lowercase_row = row(false, None);
+        lowercase_row.address = lowercase_row.address.to_lowercase();
+        assert!(lowercase_row.pool(&reserves(100, 200)).is_none());
+    }
 }
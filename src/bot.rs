@@ -4,6 +4,7 @@ use eyre::Result;
 pub use serde_json::Value;
 use tokio::sync::mpsc;
 
+use crate::pipeline::Pipeline;
 use crate::sync;
 use crate::utils::app_context::AppContext;
 
@@ -129,6 +130,42 @@ pub async fn start(ctx: AppContext) -> Result<()> {
         }
     });
 
+    // Spawn cycles sync task
+    let ctx8 = Arc::clone(&ctx);
+    tokio::spawn(async move {
+        if let Err(e) = sync::cycles(&ctx8).await {
+            log::error!("{}", e);
+        }
+    });
+
+    // Spawn factory fees sync task
+    let ctx9 = Arc::clone(&ctx);
+    tokio::spawn(async move {
+        if let Err(e) = sync::fees(&ctx9).await {
+            log::error!("{}", e);
+        }
+    });
+
+    // Spawn token safety sync task
+    let ctx10 = Arc::clone(&ctx);
+    tokio::spawn(async move {
+        if let Err(e) = sync::token_safety(&ctx10).await {
+            log::error!("{}", e);
+        }
+    });
+
+    // Spawn the arbitrage pipeline
+    let ctx11 = Arc::clone(&ctx);
+    tokio::spawn(async move {
+        let result = match Pipeline::new(&ctx11).await {
+            Ok(mut pipeline) => pipeline.run(&ctx11).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = result {
+            log::error!("{}", e);
+        }
+    });
+
     // Wait for all spawned tasks to complete
     tokio::signal::ctrl_c().await?;
     log::info!("Received shutdown signal, waiting for tasks to complete...");
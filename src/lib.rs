@@ -5,7 +5,10 @@ pub mod bootstrap;
 pub mod config;
 pub mod db_service;
 pub mod models;
+pub mod pipeline;
+pub mod preflight;
 pub mod schemas;
+pub mod signer_daemon;
 pub mod sync;
 pub mod utils;
 pub mod benchmark;
@@ -2,6 +2,7 @@
 
 use crate::utils::app_context::AppContext;
 use crate::utils::logger::setup_logger;
+use alloy::primitives::Address;
 use clap::{Parser, Subcommand};
 use eyre::Result;
 
@@ -12,6 +13,8 @@ mod config;
 mod db_service;
 mod models;
 mod notify;
+mod pipeline;
+mod preflight;
 mod schemas;
 mod sync;
 mod utils;
@@ -25,8 +28,15 @@ struct Cli {
 
 #[derive(Subcommand)]
 enum Commands {
-    /// [DEBUG] Sync Sync events
-    SyncSyncEvents,
+    /// [DEBUG] Sync Sync events. With a block range, backfills the range and exits.
+    SyncSyncEvents {
+        /// First block to backfill, the block after the checkpoint if only `--to-block` is set
+        #[arg(long)]
+        from_block: Option<u64>,
+        /// Last block to backfill, the latest block if not set
+        #[arg(long)]
+        to_block: Option<u64>,
+    },
     /// [DEBUG] Sync pairs with missing reserves
     SyncReserves,
     /// [DEBUG] Sync pairs tokens
@@ -38,9 +48,22 @@ enum Commands {
     /// [DEBUG] Sync USD values for pairs
     SyncUsd,
     /// [DEBUG] Sync PairCreated events
-    SyncPairCreatedEvents,
+    SyncPairCreatedEvents {
+        /// Backfill the pairs of the factories from their deployment block and exit
+        #[arg(long)]
+        backfill: bool,
+        /// Only backfill this factory
+        #[arg(long, requires = "backfill")]
+        factory: Option<Address>,
+    },
     /// [DEBUG] Sync exchange rates
     SyncExchangeRates,
+    /// [DEBUG] Sync precomputed cycles
+    SyncCycles,
+    /// [DEBUG] Sync factory fees
+    SyncFees,
+    /// [DEBUG] Sync token safety
+    SyncTokenSafety,
     /// [DEBUG] Benchmark Modified Bellman Ford
     BenchmarkMBF,
     /// [DEBUG] Benchmark DFS
@@ -57,9 +80,18 @@ async fn main() -> Result<()> {
 
     let cli = Cli::parse();
     match cli.command {
-        Some(Commands::SyncSyncEvents) => {
+        Some(Commands::SyncSyncEvents {
+            from_block: None,
+            to_block: None,
+        }) => {
             sync::events(&ctx).await?;
         }
+        Some(Commands::SyncSyncEvents {
+            from_block,
+            to_block,
+        }) => {
+            sync::backfill_events(&ctx, from_block, to_block).await?;
+        }
         Some(Commands::SyncReserves) => {
             sync::reserves(&ctx).await?;
         }
@@ -75,15 +107,30 @@ async fn main() -> Result<()> {
         Some(Commands::SyncUsd) => {
             sync::usd(&ctx).await?;
         }
-        Some(Commands::SyncPairCreatedEvents) => {
+        Some(Commands::SyncPairCreatedEvents {
+            backfill: false, ..
+        }) => {
             sync::pair_created_events(&ctx).await?;
         }
+        Some(Commands::SyncPairCreatedEvents {
+            backfill: true,
+            factory,
+        }) => {
+            sync::backfill_pair_created_events(&ctx, factory).await?;
+        }
         Some(Commands::SyncExchangeRates) => {
             sync::exchange_rates(&ctx).await?;
         }
-        Some(Commands::BenchmarkMBF) => {
-            
+        Some(Commands::SyncCycles) => {
+            sync::cycles(&ctx).await?;
+        }
+        Some(Commands::SyncFees) => {
+            sync::fees(&ctx).await?;
+        }
+        Some(Commands::SyncTokenSafety) => {
+            sync::token_safety(&ctx).await?;
         }
+        Some(Commands::BenchmarkMBF) => {}
         Some(Commands::Start) => {
             bot::start(ctx).await?;
         }
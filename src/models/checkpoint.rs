@@ -0,0 +1,80 @@
+use alloy::primitives::ChainId;
+use diesel::{ExpressionMethods, OptionalExtension, QueryDsl};
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use eyre::Result;
+
+use crate::schemas::checkpoints;
+
+/// Last block processed by the `name` backfill, `None` if it has never run
+///
+/// # Errors
+/// * If the database query fails
+pub async fn find_checkpoint(
+    conn: &mut AsyncPgConnection,
+    chain_id: ChainId,
+    name: &str,
+) -> Result<Option<u64>> {
+    let block_number = checkpoints::table
+        .filter(checkpoints::chain_id.eq(i64::try_from(chain_id)?))
+        .filter(checkpoints::name.eq(name))
+        .select(checkpoints::block_number)
+        .first::<i64>(conn)
+        .await
+        .optional()?;
+
+    Ok(block_number.map(u64::try_from).transpose()?)
+}
+
+/// Saves `block_number` as the last block processed by the `name` backfill. A checkpoint never
+/// moves back, backfilling an old range keeps the later checkpoint.
+///
+/// # Errors
+/// * If the block number does not fit into the database column
+/// * If the database query fails
+pub async fn save_checkpoint(
+    conn: &mut AsyncPgConnection,
+    chain_id: ChainId,
+    name: &str,
+    block_number: u64,
+) -> Result<()> {
+    diesel::insert_into(checkpoints::table)
+        .values((
+            checkpoints::chain_id.eq(i64::try_from(chain_id)?),
+            checkpoints::name.eq(name),
+            checkpoints::block_number.eq(i64::try_from(block_number)?),
+        ))
+        .on_conflict((checkpoints::chain_id, checkpoints::name))
+        .do_update()
+        .set(
+            checkpoints::block_number.eq(diesel::dsl::sql::<diesel::sql_types::BigInt>(
+                "GREATEST(checkpoints.block_number, EXCLUDED.block_number)",
+            )),
+        )
+        .execute(conn)
+        .await?;
+
+    Ok(())
+}
+
+/// Moves the `name` checkpoint back to `block_number`, for blocks that were reorged out. A
+/// checkpoint before `block_number` is kept.
+///
+/// # Errors
+/// * If the block number does not fit into the database column
+/// * If the database query fails
+pub async fn rewind_checkpoint(
+    conn: &mut AsyncPgConnection,
+    chain_id: ChainId,
+    name: &str,
+    block_number: u64,
+) -> Result<()> {
+    diesel::update(checkpoints::table)
+        .filter(checkpoints::chain_id.eq(i64::try_from(chain_id)?))
+        .filter(checkpoints::name.eq(name))
+        .filter(checkpoints::block_number.gt(i64::try_from(block_number)?))
+        .set(checkpoints::block_number.eq(i64::try_from(block_number)?))
+        .execute(conn)
+        .await?;
+
+    Ok(())
+}
@@ -0,0 +1,88 @@
+use diesel::{Insertable, Queryable, Selectable};
+
+/// A precomputed cycle: ordered pairs and the direction of the swap in each of them
+#[derive(Queryable, Selectable, Debug)]
+#[diesel(table_name = crate::schemas::cycles)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct Cycle {
+    id: i32,
+    pair_ids: Vec<Option<i32>>,
+    zero_for_one: Vec<Option<bool>>,
+}
+
+impl Cycle {
+    pub fn id(&self) -> i32 {
+        self.id
+    }
+
+    /// Pair ids and swap directions (`true` for token0 -> token1) in the cycle order.
+    /// Returns `None` if the arrays are malformed, i.e. have NULLs or different lengths.
+    pub fn swaps(&self) -> Option<Vec<(i32, bool)>> {
+        if self.pair_ids.len() != self.zero_for_one.len() {
+            return None;
+        }
+
+        self.pair_ids
+            .iter()
+            .zip(&self.zero_for_one)
+            .map(|(pair_id, zero_for_one)| Some(((*pair_id)?, (*zero_for_one)?)))
+            .collect()
+    }
+}
+
+#[derive(Insertable, Clone, Debug)]
+#[diesel(table_name = crate::schemas::cycles)]
+pub struct NewCycle {
+    pair_ids: Vec<i32>,
+    zero_for_one: Vec<bool>,
+}
+
+impl NewCycle {
+    /// Creates a new cycle from pair ids and swap directions (`true` for token0 -> token1)
+    pub fn new(swaps: &[(i32, bool)]) -> Self {
+        Self {
+            pair_ids: swaps.iter().map(|(pair_id, _)| *pair_id).collect(),
+            zero_for_one: swaps
+                .iter()
+                .map(|(_, zero_for_one)| *zero_for_one)
+                .collect(),
+        }
+    }
+
+    pub fn pair_ids(&self) -> &Vec<i32> {
+        &self.pair_ids
+    }
+
+    pub fn zero_for_one(&self) -> &Vec<bool> {
+        &self.zero_for_one
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_swaps() {
+        let cycle = Cycle {
+            id: 1,
+            pair_ids: vec![Some(1), Some(2)],
+            zero_for_one: vec![Some(true), Some(false)],
+        };
+        assert_eq!(cycle.swaps(), Some(vec![(1, true), (2, false)]));
+
+        let cycle = Cycle {
+            id: 1,
+            pair_ids: vec![Some(1), None],
+            zero_for_one: vec![Some(true), Some(false)],
+        };
+        assert_eq!(cycle.swaps(), None);
+
+        let cycle = Cycle {
+            id: 1,
+            pair_ids: vec![Some(1), Some(2)],
+            zero_for_one: vec![Some(true)],
+        };
+        assert_eq!(cycle.swaps(), None);
+    }
+}
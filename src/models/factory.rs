@@ -1,6 +1,6 @@
 use std::str::FromStr;
 
-use alloy::primitives::Address;
+use alloy::primitives::{Address, ChainId};
 use diesel::deserialize::{self, FromSql, FromSqlRow};
 use diesel::expression::AsExpression;
 use diesel::pg::Pg;
@@ -75,15 +75,23 @@ pub struct Factory {
     address: DBAddress,
     last_pair_id: i32,
     status: FactoryStatus,
+    fee: Option<i32>,
+    chain_id: i64,
+    deployment_block: Option<i64>,
+    stable_fee: Option<i32>,
 }
 
 impl Factory {
-    pub fn new(id: i32, address: Address) -> Self {
+    pub fn new(id: i32, chain_id: ChainId, address: Address) -> Self {
         Self {
             id,
             address: DBAddress::new(address),
             last_pair_id: 0,
             status: FactoryStatus::Unsynced,
+            fee: None,
+            chain_id: chain_id as i64,
+            deployment_block: None,
+            stable_fee: None,
         }
     }
 
@@ -103,6 +111,26 @@ impl Factory {
         self.status
     }
 
+    /// Swap fee of the volatile pools in basis points, `None` until detected
+    pub fn fee(&self) -> Option<i32> {
+        self.fee
+    }
+
+    /// Swap fee of the Solidly stable pools in basis points, `None` until detected
+    pub fn stable_fee(&self) -> Option<i32> {
+        self.stable_fee
+    }
+
+    pub fn chain_id(&self) -> ChainId {
+        self.chain_id as ChainId
+    }
+
+    /// Block the factory was deployed in, `None` until looked up
+    pub fn deployment_block(&self) -> Option<u64> {
+        self.deployment_block
+            .map(|block_number| block_number as u64)
+    }
+
     /// Update the status of the factory
     pub async fn update_status(
         &mut self,
@@ -117,6 +145,45 @@ impl Factory {
 
         Ok(())
     }
+
+    /// Update the swap fee (in basis points) of the stable or the volatile pools of the factory
+    pub async fn update_fee(
+        &mut self,
+        conn: &mut AsyncPgConnection,
+        stable: bool,
+        fee: i32,
+    ) -> Result<(), Error> {
+        let update = diesel::update(factories::table).filter(factories::id.eq(self.id()));
+        if stable {
+            update
+                .set(factories::stable_fee.eq(fee))
+                .execute(conn)
+                .await?;
+            self.stable_fee = Some(fee);
+        } else {
+            update.set(factories::fee.eq(fee)).execute(conn).await?;
+            self.fee = Some(fee);
+        }
+
+        Ok(())
+    }
+
+    /// Update the block the factory was deployed in
+    pub async fn update_deployment_block(
+        &mut self,
+        conn: &mut AsyncPgConnection,
+        block_number: u64,
+    ) -> Result<(), Error> {
+        diesel::update(factories::table)
+            .filter(factories::id.eq(self.id()))
+            .set(factories::deployment_block.eq(block_number as i64))
+            .execute(conn)
+            .await?;
+
+        self.deployment_block = Some(block_number as i64);
+
+        Ok(())
+    }
 }
 
 #[derive(Insertable, Clone, Debug)]
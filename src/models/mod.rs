@@ -1,3 +1,5 @@
+pub mod checkpoint;
+pub mod cycle;
 pub mod factory;
 pub mod pair;
 pub mod token;
@@ -1,14 +1,16 @@
-use alloy::primitives::Address;
+use alloy::primitives::{Address, ChainId, U256};
 use bigdecimal::BigDecimal;
 use diesel::deserialize::{self, FromSql, FromSqlRow};
 use diesel::expression::AsExpression;
 use diesel::pg::{Pg, PgValue};
-use diesel::sql_types::Text;
+use diesel::sql_types::{Array, BigInt, Integer, Nullable, Text};
 use diesel::{
     serialize::{self, IsNull, Output, ToSql},
-    Insertable, Queryable, Selectable,
+    Insertable, Queryable, QueryableByName, Selectable,
 };
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
 use eyre::Error;
+use std::collections::{HashMap, HashSet};
 use std::io::Write;
 use std::str::FromStr;
 
@@ -24,6 +26,11 @@ pub struct Pair {
     pub reserve0: Option<BigDecimal>,
     pub reserve1: Option<BigDecimal>,
     pub usd: Option<i32>,
+    pub stable: bool,
+    pub chain_id: i64,
+    pub reserves_block_number: Option<i64>,
+    pub reserves_log_index: Option<i32>,
+    pub tokens_unreadable: bool,
 }
 
 impl Pair {
@@ -58,6 +65,276 @@ impl Pair {
     pub fn usd(&self) -> Option<i32> {
         self.usd
     }
+
+    /// Whether this is a Solidly stable pool
+    pub fn stable(&self) -> bool {
+        self.stable
+    }
+
+    pub fn chain_id(&self) -> ChainId {
+        self.chain_id as ChainId
+    }
+
+    /// Whether the `token0` or `token1` call of the pair reverted, see `sync::pair_tokens`
+    pub fn tokens_unreadable(&self) -> bool {
+        self.tokens_unreadable
+    }
+
+    /// Where in the chain the reserves were read, `None` if they were never synced
+    pub fn reserves_position(&self) -> Option<ReservesPosition> {
+        Some(ReservesPosition::new(
+            u64::try_from(self.reserves_block_number?).ok()?,
+            u64::try_from(self.reserves_log_index?).ok()?,
+        ))
+    }
+}
+
+/// Position of reserves in the chain: the block number and log index of the `Sync` event that
+/// set them. Reserves read with `getReserves` are the state at the end of a block. Later positions
+/// compare greater.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ReservesPosition {
+    block_number: u64,
+    log_index: u64,
+}
+
+impl ReservesPosition {
+    /// Log index of reserves read at the end of a block, after any `Sync` event of the block
+    const END_OF_BLOCK_LOG_INDEX: u64 = i32::MAX as u64;
+
+    pub const fn new(block_number: u64, log_index: u64) -> Self {
+        Self {
+            block_number,
+            log_index,
+        }
+    }
+
+    /// Reserves read with `getReserves` at the given block
+    pub const fn end_of_block(block_number: u64) -> Self {
+        Self::new(block_number, Self::END_OF_BLOCK_LOG_INDEX)
+    }
+
+    pub const fn block_number(&self) -> u64 {
+        self.block_number
+    }
+
+    pub const fn log_index(&self) -> u64 {
+        self.log_index
+    }
+}
+
+/// Reserves of a pair with the position they were read at
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PairReserves {
+    pub reserve0: U256,
+    pub reserve1: U256,
+    pub position: ReservesPosition,
+}
+
+/// Reserves columns of a pair as they are stored, to be written back by `restore_reserves`. Each
+/// can be NULL, e.g. reserves read before positions were tracked have no position.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct StoredReserves {
+    pub reserve0: Option<U256>,
+    pub reserve1: Option<U256>,
+    pub block_number: Option<i64>,
+    pub log_index: Option<i32>,
+}
+
+#[derive(QueryableByName, Debug)]
+struct StoredReservesRow {
+    #[diesel(sql_type = Text)]
+    address: String,
+    #[diesel(sql_type = Nullable<Text>)]
+    reserve0: Option<String>,
+    #[diesel(sql_type = Nullable<Text>)]
+    reserve1: Option<String>,
+    #[diesel(sql_type = Nullable<BigInt>)]
+    reserves_block_number: Option<i64>,
+    #[diesel(sql_type = Nullable<Integer>)]
+    reserves_log_index: Option<i32>,
+}
+
+#[derive(QueryableByName, Debug)]
+struct AddressRow {
+    #[diesel(sql_type = Text)]
+    address: String,
+}
+
+/// Stored reserves of the pairs that exist, by address. Pairs without reserves or without a
+/// position are included with their NULL columns.
+///
+/// # Errors
+/// * If the database query fails
+/// * If the stored reserves are malformed
+pub async fn find_reserves(
+    conn: &mut AsyncPgConnection,
+    chain_id: ChainId,
+    addresses: &[Address],
+) -> Result<HashMap<Address, StoredReserves>, Error> {
+    let rows = diesel::sql_query(
+        "SELECT address, reserve0::TEXT AS reserve0, reserve1::TEXT AS reserve1,
+                reserves_block_number, reserves_log_index
+         FROM pairs
+         WHERE chain_id = $1 AND address = ANY($2)",
+    )
+    .bind::<BigInt, _>(i64::try_from(chain_id)?)
+    .bind::<Array<Text>, _>(addresses.iter().map(Address::to_string).collect::<Vec<_>>())
+    .load::<StoredReservesRow>(conn)
+    .await?;
+
+    rows.into_iter()
+        .map(|row| {
+            Ok((
+                Address::from_str(&row.address)?,
+                StoredReserves {
+                    reserve0: row.reserve0.as_deref().map(U256::from_str).transpose()?,
+                    reserve1: row.reserve1.as_deref().map(U256::from_str).transpose()?,
+                    block_number: row.reserves_block_number,
+                    log_index: row.reserves_log_index,
+                },
+            ))
+        })
+        .collect()
+}
+
+/// `upsert_reserves` for many pairs with a single query
+///
+/// # Returns
+/// The addresses of the pairs whose reserves were written
+///
+/// # Errors
+/// * If a position does not fit into the database columns
+/// * If the database query fails
+pub async fn upsert_reserves_batch(
+    conn: &mut AsyncPgConnection,
+    chain_id: ChainId,
+    reserves: &HashMap<Address, PairReserves>,
+) -> Result<HashSet<Address>, Error> {
+    if reserves.is_empty() {
+        return Ok(HashSet::new());
+    }
+
+    let mut addresses = Vec::with_capacity(reserves.len());
+    let mut reserves0 = Vec::with_capacity(reserves.len());
+    let mut reserves1 = Vec::with_capacity(reserves.len());
+    let mut block_numbers = Vec::with_capacity(reserves.len());
+    let mut log_indexes = Vec::with_capacity(reserves.len());
+    for (address, reserves) in reserves {
+        addresses.push(address.to_string());
+        reserves0.push(reserves.reserve0.to_string());
+        reserves1.push(reserves.reserve1.to_string());
+        block_numbers.push(i64::try_from(reserves.position.block_number())?);
+        log_indexes.push(i32::try_from(reserves.position.log_index())?);
+    }
+
+    let rows = diesel::sql_query(
+        "INSERT INTO pairs (chain_id, address, reserve0, reserve1, reserves_block_number, reserves_log_index)
+         SELECT $1, address, reserve0::NUMERIC, reserve1::NUMERIC, block_number, log_index
+         FROM UNNEST($2, $3, $4, $5, $6) AS updates(address, reserve0, reserve1, block_number, log_index)
+         ON CONFLICT (chain_id, address) DO UPDATE SET
+             reserve0 = EXCLUDED.reserve0,
+             reserve1 = EXCLUDED.reserve1,
+             reserves_block_number = EXCLUDED.reserves_block_number,
+             reserves_log_index = EXCLUDED.reserves_log_index
+         WHERE pairs.reserves_block_number IS NULL
+            OR (pairs.reserves_block_number, pairs.reserves_log_index)
+               < (EXCLUDED.reserves_block_number, EXCLUDED.reserves_log_index)
+         RETURNING address",
+    )
+    .bind::<BigInt, _>(i64::try_from(chain_id)?)
+    .bind::<Array<Text>, _>(addresses)
+    .bind::<Array<Text>, _>(reserves0)
+    .bind::<Array<Text>, _>(reserves1)
+    .bind::<Array<BigInt>, _>(block_numbers)
+    .bind::<Array<Integer>, _>(log_indexes)
+    .load::<AddressRow>(conn)
+    .await?;
+
+    rows.iter()
+        .map(|row| Ok(Address::from_str(&row.address)?))
+        .collect()
+}
+
+/// Writes back reserves journaled before a reorg as they were stored, regardless of their
+/// position. `None` clears the reserves, the pair did not exist before.
+///
+/// # Errors
+/// * If the database query fails
+pub async fn restore_reserves(
+    conn: &mut AsyncPgConnection,
+    chain_id: ChainId,
+    address: Address,
+    reserves: Option<StoredReserves>,
+) -> Result<(), Error> {
+    let StoredReserves {
+        reserve0,
+        reserve1,
+        block_number,
+        log_index,
+    } = reserves.unwrap_or_default();
+    let (reserve0, reserve1) = (
+        reserve0.map(|reserve| reserve.to_string()),
+        reserve1.map(|reserve| reserve.to_string()),
+    );
+
+    diesel::sql_query(
+        "UPDATE pairs
+         SET reserve0 = $3::NUMERIC, reserve1 = $4::NUMERIC,
+             reserves_block_number = $5, reserves_log_index = $6
+         WHERE chain_id = $1 AND address = $2",
+    )
+    .bind::<BigInt, _>(i64::try_from(chain_id)?)
+    .bind::<Text, _>(address.to_string())
+    .bind::<Nullable<Text>, _>(reserve0)
+    .bind::<Nullable<Text>, _>(reserve1)
+    .bind::<Nullable<BigInt>, _>(block_number)
+    .bind::<Nullable<Integer>, _>(log_index)
+    .execute(conn)
+    .await?;
+
+    Ok(())
+}
+
+/// Sets the reserves of a pair, creating the pair if it does not exist yet. Reserves are only
+/// overwritten by reserves from a later position, so out of order writes never go back in time.
+///
+/// # Returns
+/// Whether the reserves were written
+///
+/// # Errors
+/// * If the position does not fit into the database columns
+/// * If the database query fails
+pub async fn upsert_reserves(
+    conn: &mut AsyncPgConnection,
+    chain_id: ChainId,
+    address: Address,
+    reserve0: U256,
+    reserve1: U256,
+    position: ReservesPosition,
+) -> Result<bool, Error> {
+    let updated_count = diesel::sql_query(
+        "INSERT INTO pairs (chain_id, address, reserve0, reserve1, reserves_block_number, reserves_log_index)
+         VALUES ($1, $2, $3::NUMERIC, $4::NUMERIC, $5, $6)
+         ON CONFLICT (chain_id, address) DO UPDATE SET
+             reserve0 = EXCLUDED.reserve0,
+             reserve1 = EXCLUDED.reserve1,
+             reserves_block_number = EXCLUDED.reserves_block_number,
+             reserves_log_index = EXCLUDED.reserves_log_index
+         WHERE pairs.reserves_block_number IS NULL
+            OR (pairs.reserves_block_number, pairs.reserves_log_index)
+               < (EXCLUDED.reserves_block_number, EXCLUDED.reserves_log_index)",
+    )
+    .bind::<BigInt, _>(i64::try_from(chain_id)?)
+    .bind::<Text, _>(address.to_string())
+    .bind::<Text, _>(reserve0.to_string())
+    .bind::<Text, _>(reserve1.to_string())
+    .bind::<BigInt, _>(i64::try_from(position.block_number())?)
+    .bind::<Integer, _>(i32::try_from(position.log_index())?)
+    .execute(conn)
+    .await?;
+
+    Ok(updated_count > 0)
 }
 
 #[derive(Debug, FromSqlRow, AsExpression, Clone)]
@@ -169,3 +446,16 @@ impl FromSql<Text, Pg> for DBAddress {
         Ok(DBAddress { value: addr })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reserves_position() {
+        let sync = ReservesPosition::new(100, 5);
+        assert!(sync < ReservesPosition::new(100, 6));
+        assert!(sync < ReservesPosition::end_of_block(100));
+        assert!(ReservesPosition::end_of_block(100) < ReservesPosition::new(101, 0));
+    }
+}
@@ -1,4 +1,4 @@
-use alloy::primitives::Address;
+use alloy::primitives::{Address, ChainId};
 use bigdecimal::BigDecimal;
 use chrono::NaiveDateTime;
 use diesel::deserialize::{self, FromSql};
@@ -38,6 +38,55 @@ impl ToSql<crate::schemas::sql_types::PriceSupportStatus, Pg> for PriceSupportSt
     }
 }
 
+/// Whether a token can be traded in a cycle, from a simulated buy, transfer and sell
+#[derive(Debug, Copy, Clone, PartialEq, Eq, AsExpression)]
+#[diesel(sql_type = crate::schemas::sql_types::TokenSafety)]
+pub enum TokenSafety {
+    /// Every transfer moves the full amount
+    Safe,
+    /// A transfer delivers less than the amount sent (fee-on-transfer)
+    TransferTax,
+    /// Balances change without transfers
+    Rebasing,
+    /// A transfer reverts, e.g. selling to the pair or a blacklisted holder
+    Honeypot,
+    /// The token could not be simulated, see `Token::safety_reason`
+    Unknown,
+}
+
+impl TokenSafety {
+    /// Cycles through the token can be executed
+    pub fn is_safe(&self) -> bool {
+        *self == TokenSafety::Safe
+    }
+}
+
+impl FromSql<crate::schemas::sql_types::TokenSafety, Pg> for TokenSafety {
+    fn from_sql(bytes: PgValue) -> deserialize::Result<Self> {
+        match bytes.as_bytes() {
+            b"SAFE" => Ok(TokenSafety::Safe),
+            b"TRANSFER_TAX" => Ok(TokenSafety::TransferTax),
+            b"REBASING" => Ok(TokenSafety::Rebasing),
+            b"HONEYPOT" => Ok(TokenSafety::Honeypot),
+            b"UNKNOWN" => Ok(TokenSafety::Unknown),
+            _ => Err("Unrecognized enum variant".into()),
+        }
+    }
+}
+
+impl ToSql<crate::schemas::sql_types::TokenSafety, Pg> for TokenSafety {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Pg>) -> serialize::Result {
+        match *self {
+            TokenSafety::Safe => out.write_all(b"SAFE")?,
+            TokenSafety::TransferTax => out.write_all(b"TRANSFER_TAX")?,
+            TokenSafety::Rebasing => out.write_all(b"REBASING")?,
+            TokenSafety::Honeypot => out.write_all(b"HONEYPOT")?,
+            TokenSafety::Unknown => out.write_all(b"UNKNOWN")?,
+        }
+        Ok(IsNull::No)
+    }
+}
+
 #[derive(Queryable, Selectable, Debug)]
 #[diesel(table_name = crate::schemas::tokens)]
 #[diesel(check_for_backend(diesel::pg::Pg))]
@@ -50,6 +99,12 @@ pub struct Token {
     exchange_rate: Option<BigDecimal>,
     updated_last: Option<NaiveDateTime>,
     price_support_status: Option<PriceSupportStatus>,
+    chain_id: i64,
+    metadata_invalid: bool,
+    safety: Option<TokenSafety>,
+    transfer_tax: Option<i32>,
+    exchange_rate_source: Option<String>,
+    safety_reason: Option<String>,
 }
 
 /// Parameters for creating a new Token
@@ -63,6 +118,12 @@ pub struct TokenParams {
     pub exchange_rate: Option<BigDecimal>,
     pub updated_last: Option<NaiveDateTime>,
     pub price_support_status: Option<PriceSupportStatus>,
+    pub chain_id: ChainId,
+    pub metadata_invalid: bool,
+    pub safety: Option<TokenSafety>,
+    pub transfer_tax: Option<i32>,
+    pub exchange_rate_source: Option<String>,
+    pub safety_reason: Option<String>,
 }
 
 impl Token {
@@ -76,6 +137,12 @@ impl Token {
             exchange_rate: params.exchange_rate,
             updated_last: params.updated_last,
             price_support_status: params.price_support_status,
+            chain_id: params.chain_id as i64,
+            metadata_invalid: params.metadata_invalid,
+            safety: params.safety,
+            transfer_tax: params.transfer_tax,
+            exchange_rate_source: params.exchange_rate_source,
+            safety_reason: params.safety_reason,
         }
     }
 
@@ -110,6 +177,35 @@ impl Token {
     pub fn price_support_status(&self) -> Option<PriceSupportStatus> {
         self.price_support_status
     }
+
+    pub fn chain_id(&self) -> ChainId {
+        self.chain_id as ChainId
+    }
+
+    /// Whether the name, symbol or decimals of the token could not be read
+    pub fn metadata_invalid(&self) -> bool {
+        self.metadata_invalid
+    }
+
+    /// `None` until checked by `sync::token_safety`
+    pub fn safety(&self) -> Option<TokenSafety> {
+        self.safety
+    }
+
+    /// Highest tax of the simulated transfers in basis points, `None` until checked
+    pub fn transfer_tax(&self) -> Option<i32> {
+        self.transfer_tax
+    }
+
+    /// Name of the source that produced `exchange_rate`, `None` until it is set
+    pub fn exchange_rate_source(&self) -> Option<&str> {
+        self.exchange_rate_source.as_deref()
+    }
+
+    /// Why the token could not be simulated, set with `TokenSafety::Unknown`
+    pub fn safety_reason(&self) -> Option<&str> {
+        self.safety_reason.as_deref()
+    }
 }
 
 #[derive(Insertable, Clone, Debug)]
@@ -199,7 +295,7 @@ impl NewToken {
 ///
 /// # Returns
 /// A new `String` with invalid UTF-8 replaced and null bytes removed.
-fn sanitize_string(value: &str) -> String {
+pub(crate) fn sanitize_string(value: &str) -> String {
     let sanitized = String::from_utf8_lossy(value.as_bytes()).to_string();
     sanitized.replace('\0', "")
 }
@@ -0,0 +1,705 @@
+/// The live arbitrage pipeline: one pass per block
+///
+/// 1. fetch: `Sync` logs of the block are applied to the pools we track
+/// 2. update: the updated pools are passed to `World::update`
+/// 3. quote: `WorldUpdate::profitable_cycle_quotes` against our portfolio and the gas model
+/// 4. preflight: the best quotes are simulated (`Preflight`) until one makes the quoted profit
+/// 5. execute: that quote is sent to the signer
+///
+/// Every stage is timed, we have to be done well within Base's 2 second block time. After each
+/// block the executor balances are read again, and the unsafe tokens every
+/// `UNSAFE_TOKENS_INTERVAL`.
+///
+/// The pools each block overwrote are journaled with the block hash. When a new block does not
+/// build on the blocks we have applied, the pools and the `World` are rolled back to the last
+/// common block and the `Sync` events of the new chain are applied from there.
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+
+use alloy::eips::BlockNumberOrTag;
+use alloy::primitives::{Address, B256, U256};
+use alloy::providers::Provider;
+use alloy::rpc::types::{BlockTransactionsKind, Header};
+use alloy::sol;
+use eyre::{eyre, Result};
+use futures::StreamExt;
+
+use crate::arb::cycle_quote::CycleQuote;
+use crate::arb::executor::RunEncoder;
+use crate::arb::gas_model::GasModel;
+use crate::arb::journal::Journal;
+use crate::arb::pool::{Pool, PoolId};
+use crate::arb::portfolio::Portfolio;
+use crate::arb::pricing::{stablecoin_anchors, Prices, DEFAULT_MIN_LIQUIDITY_USD};
+use crate::arb::token::TokenId;
+use crate::arb::world::World;
+use crate::arb::world_config::WorldConfig;
+use crate::bootstrap;
+use crate::preflight::{Preflight, Simulation};
+use crate::sync::sync_events::{decode_sync_reserves, sync_filter};
+use crate::utils::app_context::{AppContext, SIGNER_SOCKET_PATH};
+use crate::utils::constants::{BASE_CHAIN_ID, ETHER, WETH};
+use crate::utils::signer::{Order, OrderStatus, Signer, TransactionLimits};
+
+sol! {
+    #[sol(rpc)]
+    "contracts/src/interfaces/IERC20.sol"
+}
+
+/// Base block time
+pub const BLOCK_TIME: Duration = Duration::from_secs(2);
+
+/// Orders expire this long after the block they were quoted at
+const ORDER_TTL: Duration = Duration::from_secs(4);
+
+/// Priority fee we pay on top of the base fee in wei per gas (0.01 gwei)
+const PRIORITY_FEE: u128 = 10_000_000;
+
+/// Quotes simulated per block, the best first. Each simulation is two or three RPC calls.
+const MAX_PREFLIGHTS: usize = 3;
+
+/// Gas limit over the simulated gas, in percent: the pending state may change before inclusion
+const GAS_LIMIT_MARGIN_PERCENT: u64 = 20;
+
+/// Pairs per reserves call when loading pools at startup
+const POOLS_BATCH_SIZE: usize = 500;
+
+/// Pools with less liquidity than this (in USD) are not worth tracking
+const MIN_POOL_USD: i32 = 1_000;
+
+/// Owner of the executor, the only account allowed to call `run`
+const WALLET_ADDRESS_ENV: &str = "FLY_BASE_WALLET_ADDRESS";
+
+/// How often the unsafe tokens are loaded again, `sync::token_safety` checks new tokens every
+/// minute
+const UNSAFE_TOKENS_INTERVAL: Duration = Duration::from_secs(60);
+
+/// `SimpleExecutor` contract the orders call
+const EXECUTOR_ADDRESS_ENV: &str = "FLY_BASE_EXECUTOR_ADDRESS";
+
+/// Share of the quoted profit an order may miss, in basis points
+const SLIPPAGE_BPS_ENV: &str = "FLY_SLIPPAGE_BPS";
+
+/// How long each stage of a block took
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StageTimings {
+    pub fetch: Duration,
+    pub update: Duration,
+    pub quote: Duration,
+    pub preflight: Duration,
+    pub execute: Duration,
+}
+
+impl StageTimings {
+    pub fn total(&self) -> Duration {
+        self.fetch + self.update + self.quote + self.preflight + self.execute
+    }
+
+    /// Whether the block took longer than `BLOCK_TIME`: we were late for the next block
+    pub fn is_over_budget(&self) -> bool {
+        self.total() > BLOCK_TIME
+    }
+}
+
+/// Times each stage of a block in order
+struct StageTimer {
+    timings: StageTimings,
+    last: Instant,
+}
+
+impl StageTimer {
+    fn start() -> Self {
+        Self {
+            timings: StageTimings::default(),
+            last: Instant::now(),
+        }
+    }
+
+    /// Time since the previous stage ended
+    fn lap(&mut self) -> Duration {
+        let now = Instant::now();
+        let elapsed = now - self.last;
+        self.last = now;
+        elapsed
+    }
+}
+
+/// Applies the reserves of `Sync` events to the pools we track and returns the updated pools.
+/// Events of unknown pools are skipped. A pool synced more than once keeps its last reserves.
+pub fn apply_syncs(
+    pools: &mut HashMap<PoolId, Pool>,
+    syncs: impl IntoIterator<Item = (PoolId, U256, U256)>,
+) -> HashSet<Pool> {
+    let mut updated_pools = HashMap::new();
+    for (pool_id, reserve0, reserve1) in syncs {
+        let Some(pool) = pools.get_mut(&pool_id) else {
+            continue;
+        };
+        pool.reserve0 = Some(reserve0);
+        pool.reserve1 = Some(reserve1);
+        updated_pools.insert(pool_id, pool.clone());
+    }
+    updated_pools.into_values().collect()
+}
+
+pub struct Pipeline {
+    world: World,
+    /// Pools by id, so `Sync` reserves can be applied to the full pool
+    pools: HashMap<PoolId, Pool>,
+    /// Last block whose `Sync` events are applied to the pools
+    block_number: u64,
+    /// Pools as they were before each applied block, to roll back reorged blocks
+    journal: Journal<Pool>,
+    /// Balances of the executor, the cycles start from these tokens
+    portfolio: Portfolio,
+    /// How many token units 1 ETH buys, for the `GasModel` of each block
+    token_per_eth: HashMap<TokenId, U256>,
+    /// Tokens left out of the `World`, as last loaded
+    unsafe_tokens: HashSet<TokenId>,
+    /// When the unsafe tokens were last loaded
+    unsafe_tokens_loaded_at: Instant,
+    signer: Signer,
+    /// Owner of the executor, the signer signs for it
+    wallet: Address,
+    executor: Address,
+    /// Sets the minimum profit of the orders
+    run_encoder: RunEncoder,
+    preflight: Preflight,
+}
+
+impl Pipeline {
+    /// Loads pools with their current reserves and builds the `World`, from the persisted cycles if
+    /// `sync::cycles` has saved any
+    ///
+    /// # Errors
+    /// * If database queries fail
+    /// * If the executor balances cannot be read
+    /// * If `FLY_BASE_EXECUTOR_ADDRESS` is not set or invalid
+    /// * If `FLY_SLIPPAGE_BPS` is invalid
+    pub async fn new(ctx: &AppContext) -> Result<Self> {
+        let started_at = Instant::now();
+        // Pools are a snapshot of this block, `Sync` events after it are applied by `run`
+        let block = ctx
+            .base_provider()
+            .get_block_by_number(BlockNumberOrTag::Latest, BlockTransactionsKind::Hashes)
+            .await?
+            .ok_or_else(|| eyre!("Latest block not found"))?;
+        let block_number = block.header.number;
+        let pools =
+            bootstrap::fetch_all_pools(ctx, block_number, POOLS_BATCH_SIZE, MIN_POOL_USD).await?;
+        let cycles = bootstrap::fetch_persisted_cycles(ctx).await?;
+        let unsafe_tokens = bootstrap::fetch_unsafe_tokens(ctx).await?;
+        let unsafe_tokens_count = unsafe_tokens.len();
+
+        // The gas of a cycle has to be priced in its start token, so only the tokens with a USD
+        // price as of the loaded pools are traded
+        let prices = Prices::derive(
+            &pools,
+            &stablecoin_anchors(BASE_CHAIN_ID),
+            DEFAULT_MIN_LIQUIDITY_USD,
+        );
+
+        // Cycles through unsafe tokens would revert or lose the tax, they are left out
+        let config = WorldConfig::from_env()?.with_excluded_tokens(unsafe_tokens.clone());
+        let world = if cycles.is_empty() {
+            World::new(&pools, config)
+        } else {
+            World::from_persisted(&pools, &cycles, config)
+        };
+        log::info!(
+            "pipeline: Loaded {} pools at block {} and {} cycles without {} unsafe tokens in {:?}",
+            pools.len(),
+            block_number,
+            world.cycle_vec.len(),
+            unsafe_tokens_count,
+            started_at.elapsed()
+        );
+
+        let wallet = wallet_address()?;
+        let executor: Address = std::env::var(EXECUTOR_ADDRESS_ENV)?.parse()?;
+        let traded_tokens = world
+            .config
+            .anchor_tokens()
+            .cloned()
+            .unwrap_or_else(|| vec![TokenId::new(BASE_CHAIN_ID, WETH)]);
+        let token_per_eth = token_per_eth(&prices, &traded_tokens);
+        let portfolio = executor_balances(ctx, executor, token_per_eth.keys()).await?;
+
+        Ok(Self {
+            world,
+            pools: pools
+                .into_iter()
+                .map(|pool| (pool.id.clone(), pool))
+                .collect(),
+            block_number,
+            // The snapshot block has nothing to undo, but a reorg of it has to be detected
+            journal: {
+                let mut journal = Journal::default();
+                journal.record(block_number, block.header.hash, []);
+                journal
+            },
+            portfolio,
+            token_per_eth,
+            unsafe_tokens,
+            unsafe_tokens_loaded_at: started_at,
+            signer: Signer::new(SIGNER_SOCKET_PATH),
+            wallet,
+            executor,
+            preflight: Preflight::new(wallet, executor, Preflight::DEFAULT_MAX_DIVERGENCE_BPS),
+            run_encoder: match std::env::var(SLIPPAGE_BPS_ENV) {
+                Ok(slippage_bps) => RunEncoder::new(slippage_bps.parse()?)?,
+                Err(_) => RunEncoder::default(),
+            },
+        })
+    }
+
+    /// Runs the pipeline for every new Base block until the subscription ends
+    ///
+    /// # Errors
+    /// * If the block subscription fails
+    pub async fn run(&mut self, ctx: &AppContext) -> Result<()> {
+        let provider = ctx.base_provider();
+        let mut blocks = provider.subscribe_blocks().await?.into_stream();
+
+        while let Some(header) = blocks.next().await {
+            if self.journal.hash(header.number) == Some(header.hash) {
+                log::debug!(
+                    "pipeline: Skipping block {}, already applied",
+                    header.number
+                );
+                continue;
+            }
+
+            if let Err(e) = self.handle_reorg(ctx, &header).await {
+                log::error!(
+                    "pipeline: Failed to roll back for block {}: {e}",
+                    header.number
+                );
+                continue;
+            }
+
+            match self.process_block(ctx, &header).await {
+                Ok(timings) => log_timings(header.number, &timings),
+                Err(e) => log::error!("pipeline: Block {} failed: {e}", header.number),
+            }
+
+            if let Err(e) = self.refresh(ctx).await {
+                log::error!(
+                    "pipeline: Failed to refresh after block {}: {e}",
+                    header.number
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Reads the executor balances, which executed cycles change, and every
+    /// `UNSAFE_TOKENS_INTERVAL` the unsafe tokens. The pipeline is reloaded when a token of the
+    /// `World` became unsafe, so the cycles through it are left out. The pools we track of a token
+    /// that is no longer unsafe are added to the `World` in place, so the cycles through it are
+    /// found. Tokens outside our pools change nothing.
+    async fn refresh(&mut self, ctx: &AppContext) -> Result<()> {
+        self.portfolio = executor_balances(ctx, self.executor, self.token_per_eth.keys()).await?;
+
+        if self.unsafe_tokens_loaded_at.elapsed() < UNSAFE_TOKENS_INTERVAL {
+            return Ok(());
+        }
+        let unsafe_tokens = bootstrap::fetch_unsafe_tokens(ctx).await?;
+        self.unsafe_tokens_loaded_at = Instant::now();
+
+        let newly_unsafe = unsafe_tokens
+            .iter()
+            .filter(|token_id| self.world.token_map.contains_key(token_id))
+            .count();
+        if newly_unsafe > 0 {
+            log::warn!("pipeline: {newly_unsafe} tokens became unsafe, reloading");
+            *self = Self::new(ctx).await?;
+            return Ok(());
+        }
+
+        // Unclassified tokens count as unsafe and are classified all the time, only those of the
+        // pools we track matter
+        let mut newly_safe: HashSet<TokenId> = self
+            .unsafe_tokens
+            .difference(&unsafe_tokens)
+            .copied()
+            .collect();
+        if !newly_safe.is_empty() {
+            let pool_tokens: HashSet<TokenId> = self
+                .pools
+                .values()
+                .flat_map(|pool| [pool.token0, pool.token1])
+                .collect();
+            newly_safe.retain(|token_id| pool_tokens.contains(token_id));
+        }
+        if !newly_safe.is_empty() {
+            let started_at = Instant::now();
+            let added_cycles = self.world.include_tokens(&newly_safe, self.pools.values());
+            log::info!(
+                "pipeline: {} tokens are no longer unsafe, added {} cycles in {:?}",
+                newly_safe.len(),
+                added_cycles,
+                started_at.elapsed()
+            );
+        }
+        self.unsafe_tokens = unsafe_tokens;
+        Ok(())
+    }
+
+    /// Rolls the pools and the `World` back to the last block we have applied that is still in the
+    /// chain, if `header` does not build on the last applied block. A reorg deeper than the
+    /// journal reloads the pipeline.
+    ///
+    /// When blocks were skipped since the last applied one, its parent is not journaled and the
+    /// last applied block is checked against the chain instead.
+    async fn handle_reorg(&mut self, ctx: &AppContext, header: &Header) -> Result<()> {
+        let parent_number = header.number.saturating_sub(1);
+        let parent_hash = self.journal.hash(parent_number);
+        if header.number > self.block_number && parent_hash == Some(header.parent_hash) {
+            return Ok(());
+        }
+
+        let Some(ancestor) = common_ancestor(ctx, header, self.journal.blocks().collect()).await?
+        else {
+            log::warn!(
+                "pipeline: Reorg at block {} is deeper than the journal, reloading",
+                header.number
+            );
+            *self = Self::new(ctx).await?;
+            return Ok(());
+        };
+        if ancestor == self.block_number {
+            // Skipped blocks on top of the last applied one, `process_block` catches up on them
+            return Ok(());
+        }
+
+        for pool in self.journal.rollback(ancestor)? {
+            self.pools.insert(pool.id.clone(), pool);
+        }
+        let world_update = self.world.rollback(ancestor)?;
+        log::warn!(
+            "pipeline: Reorg at block {}, rolled back {} blocks to block {} ({} positive cycles)",
+            header.number,
+            self.block_number - ancestor,
+            ancestor,
+            world_update.cycles().len()
+        );
+        self.block_number = ancestor;
+
+        Ok(())
+    }
+
+    async fn process_block(&mut self, ctx: &AppContext, header: &Header) -> Result<StageTimings> {
+        let mut timer = StageTimer::start();
+
+        // Fetch. Blocks we have missed since the last one are caught up on, so the pools never
+        // skip a `Sync` event.
+        let filter = sync_filter()
+            .from_block(self.block_number + 1)
+            .to_block(header.number);
+        let logs = ctx.base_provider().get_logs(&filter).await?;
+        let syncs: Vec<_> = logs
+            .iter()
+            .filter_map(|log| {
+                let (reserve0, reserve1) = decode_sync_reserves(log).ok()?;
+                Some((
+                    PoolId::new(BASE_CHAIN_ID, log.address()),
+                    reserve0,
+                    reserve1,
+                ))
+            })
+            .collect();
+        let previous_pools: HashMap<PoolId, Pool> = syncs
+            .iter()
+            .filter_map(|(pool_id, _, _)| Some((pool_id.clone(), self.pools.get(pool_id)?.clone())))
+            .collect();
+        let updated_pools = apply_syncs(&mut self.pools, syncs);
+        // Blocks without updates are recorded as well, so we know their hash
+        self.journal
+            .record(header.number, header.hash, previous_pools.into_values());
+        self.block_number = header.number;
+        timer.timings.fetch = timer.lap();
+
+        // Update
+        let world_update = self
+            .world
+            .update_block(header.number, header.hash, &updated_pools);
+        timer.timings.update = timer.lap();
+
+        // Quote
+        let gas_model = GasModel::new(
+            header.base_fee_per_gas.unwrap_or_default().into(),
+            PRIORITY_FEE,
+            self.token_per_eth.clone(),
+        );
+        let quotes = world_update.profitable_cycle_quotes(&self.portfolio, &gas_model);
+        timer.timings.quote = timer.lap();
+
+        // Preflight. Quotes that revert or miss the quoted profit are dropped.
+        let mut simulated_quote = None;
+        for quote in quotes.iter().take(MAX_PREFLIGHTS) {
+            match self
+                .preflight
+                .simulate(ctx.base_provider(), quote, &self.run_encoder)
+                .await
+            {
+                Ok(Simulation::Passed { gas_used, .. }) => {
+                    simulated_quote = Some((quote, gas_used));
+                    break;
+                }
+                Ok(simulation) => log::info!("pipeline: Dropping cycle: {simulation:?}"),
+                Err(e) => log::warn!("pipeline: Failed to simulate cycle: {e}"),
+            }
+        }
+        timer.timings.preflight = timer.lap();
+
+        // Execute
+        if let Some((quote, gas_used)) = simulated_quote {
+            self.execute(ctx, quote, gas_used, &gas_model, header)
+                .await?;
+        }
+        timer.timings.execute = timer.lap();
+
+        log::debug!(
+            "pipeline: Block {}: {} updated pools, {} updated cycles, {} profitable quotes",
+            header.number,
+            updated_pools.len(),
+            world_update.cycles().len(),
+            quotes.len()
+        );
+
+        Ok(timer.timings)
+    }
+
+    /// Sends the quote to the signer as one order, valid until `ORDER_TTL` after the block. A
+    /// transaction the signer only signed is broadcast through the Base provider.
+    ///
+    /// The order carries the pending nonce of the wallet as its hint, a signer without a provider
+    /// has no other way to know it.
+    async fn execute(
+        &mut self,
+        ctx: &AppContext,
+        quote: &CycleQuote,
+        gas_used: u64,
+        gas_model: &GasModel,
+        header: &Header,
+    ) -> Result<()> {
+        log::info!(
+            "pipeline: Executing cycle with {} in, {} profit ({} bps)",
+            quote.amount_in(),
+            quote.profit(),
+            quote.profit_margin()
+        );
+
+        let limits = TransactionLimits {
+            deadline: header.timestamp + ORDER_TTL.as_secs(),
+            gas_limit: gas_used + gas_used * GAS_LIMIT_MARGIN_PERCENT / 100,
+            // Room for the base fee to double before the order is included
+            max_fee_per_gas: gas_model.base_fee() * 2 + gas_model.priority_fee(),
+            max_priority_fee_per_gas: gas_model.priority_fee(),
+            nonce_hint: Some(
+                ctx.base_provider()
+                    .get_transaction_count(self.wallet)
+                    .pending()
+                    .await?,
+            ),
+        };
+        let min_profit = self.run_encoder.min_profit(quote);
+        let order = Order::from_quote(self.executor, quote, min_profit, limits)?;
+
+        match self.signer.call(&order).await?.status {
+            OrderStatus::Submitted { tx_hash } => {
+                log::info!("pipeline: Submitted transaction {tx_hash}");
+            }
+            OrderStatus::Signed { raw_transaction } => {
+                let pending = ctx
+                    .base_provider()
+                    .send_raw_transaction(&raw_transaction)
+                    .await?;
+                log::info!("pipeline: Submitted transaction {}", pending.tx_hash());
+            }
+            OrderStatus::Rejected { reason } => {
+                log::warn!("pipeline: Signer rejected the order: {reason}");
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn log_timings(block_number: u64, timings: &StageTimings) {
+    let message = format!(
+        "pipeline: Block {} took {:?} (fetch {:?}, update {:?}, quote {:?}, preflight {:?}, execute {:?})",
+        block_number,
+        timings.total(),
+        timings.fetch,
+        timings.update,
+        timings.quote,
+        timings.preflight,
+        timings.execute
+    );
+    if timings.is_over_budget() {
+        log::warn!("{message}");
+    } else {
+        log::info!("{message}");
+    }
+}
+
+/// Latest of the journaled `blocks` that is an ancestor of `header`
+async fn common_ancestor(
+    ctx: &AppContext,
+    header: &Header,
+    blocks: Vec<(u64, B256)>,
+) -> Result<Option<u64>> {
+    for (number, hash) in blocks {
+        if number >= header.number {
+            continue;
+        }
+        if number + 1 == header.number {
+            if hash == header.parent_hash {
+                return Ok(Some(number));
+            }
+            continue;
+        }
+
+        let block = ctx
+            .base_provider()
+            .get_block_by_number(number.into(), BlockTransactionsKind::Hashes)
+            .await?;
+        if block.is_some_and(|block| block.header.hash == hash) {
+            return Ok(Some(number));
+        }
+    }
+    Ok(None)
+}
+
+/// How many raw units of each of `tokens` 1 ETH buys, from their USD prices. WETH is 1:1, tokens
+/// without a price are left out and so not traded.
+fn token_per_eth(prices: &Prices, tokens: &[TokenId]) -> HashMap<TokenId, U256> {
+    let weth = TokenId::new(BASE_CHAIN_ID, WETH);
+    let eth_usd = prices.get(&weth).map(|price| price * f64::from(ETHER));
+
+    tokens
+        .iter()
+        .filter_map(|token| {
+            if *token == weth {
+                return Some((*token, ETHER));
+            }
+            let token_per_eth = eth_usd.zip(prices.get(token)).and_then(|(eth_usd, price)| {
+                U256::try_from(eth_usd / price)
+                    .ok()
+                    .filter(|token_per_eth| !token_per_eth.is_zero())
+            });
+            if token_per_eth.is_none() {
+                log::warn!("pipeline: Not trading {token}, it has no price in ETH");
+            }
+            token_per_eth.map(|token_per_eth| (*token, token_per_eth))
+        })
+        .collect()
+}
+
+/// Our wallet, the owner of the executor
+fn wallet_address() -> Result<Address> {
+    Ok(std::env::var(WALLET_ADDRESS_ENV)?.parse()?)
+}
+
+/// Balances of `tokens` the executor holds, the `amount_in` of its runs comes out of these
+async fn executor_balances(
+    ctx: &AppContext,
+    executor: Address,
+    tokens: impl Iterator<Item = &TokenId>,
+) -> Result<Portfolio> {
+    let balances = futures::future::try_join_all(tokens.map(|token_id| async move {
+        let balance = IERC20::new(token_id.address, ctx.base_provider())
+            .balanceOf(executor)
+            .call()
+            .await?
+            ._0;
+        Ok::<_, eyre::Report>((*token_id, balance))
+    }))
+    .await?;
+    Ok(Portfolio::new(balances.into_iter().collect()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arb::swap::DEFAULT_FEE;
+    use crate::arb::test_helpers::*;
+    use crate::utils::constants::USDC;
+
+    #[test]
+    fn test_stage_timings() {
+        let timings = StageTimings {
+            fetch: Duration::from_millis(300),
+            update: Duration::from_millis(200),
+            quote: Duration::from_millis(500),
+            preflight: Duration::from_millis(100),
+            execute: Duration::from_millis(100),
+        };
+        assert_eq!(timings.total(), Duration::from_millis(1_200));
+        assert!(!timings.is_over_budget());
+
+        let timings = StageTimings {
+            quote: Duration::from_millis(1_500),
+            ..timings
+        };
+        assert!(timings.is_over_budget());
+    }
+
+    #[test]
+    fn test_token_per_eth() {
+        let weth = TokenId::new(BASE_CHAIN_ID, WETH);
+        let usdc = TokenId::new(BASE_CHAIN_ID, USDC);
+        // 10 ETH for 30,000 USDC
+        let pools = HashSet::from([Pool::new(
+            pool_id("F1"),
+            weth,
+            usdc,
+            Some(U256::from(10) * ETHER),
+            Some(U256::from(30_000_000_000_u64)),
+            DEFAULT_FEE,
+        )]);
+        let prices = Prices::derive(
+            &pools,
+            &stablecoin_anchors(BASE_CHAIN_ID),
+            DEFAULT_MIN_LIQUIDITY_USD,
+        );
+
+        let token_per_eth = token_per_eth(&prices, &[weth, usdc, token_id("A")]);
+        assert_eq!(token_per_eth[&weth], ETHER);
+        let usdc_per_eth = token_per_eth[&usdc];
+        assert!(usdc_per_eth > U256::from(2_999_000_000_u64));
+        assert!(usdc_per_eth < U256::from(3_001_000_000_u64));
+        // A has no price and is not traded
+        assert_eq!(token_per_eth.len(), 2);
+    }
+
+    #[test]
+    fn test_apply_syncs() {
+        let mut pools = HashMap::from([
+            (pool_id("F1"), pool("F1", "A", "B", 100, 200)),
+            (pool_id("F2"), pool("F2", "A", "B", 100, 300)),
+        ]);
+
+        let updated_pools = apply_syncs(
+            &mut pools,
+            [
+                (pool_id("F1"), U256::from(110), U256::from(190)),
+                (pool_id("F3"), U256::from(1), U256::from(1)),
+                (pool_id("F1"), U256::from(120), U256::from(180)),
+            ],
+        );
+
+        // Unknown pools are skipped, the last reserves win
+        assert_eq!(updated_pools.len(), 1);
+        let updated_pool = updated_pools.iter().next().unwrap();
+        assert_eq!(updated_pool.id, pool_id("F1"));
+        assert_eq!(updated_pool.reserve0, Some(U256::from(120)));
+        assert_eq!(updated_pool.reserve1, Some(U256::from(180)));
+
+        assert_eq!(pools[&pool_id("F1")].reserve0, Some(U256::from(120)));
+        assert_eq!(pools[&pool_id("F2")].reserve0, Some(U256::from(100)));
+    }
+}
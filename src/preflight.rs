@@ -0,0 +1,276 @@
+/// Pre-flight check of the `SimpleExecutor.run` call of a quote before it is signed
+///
+/// The call is simulated against the pending block, from the executor owner, with the executor
+/// granted `amount_in` of the start token through a storage override. So the check does not
+/// depend on what the executor holds right now.
+///
+/// 1. `eth_call` of the route with an unreachable minimum profit: the run reverts with
+///    `ProfitTargetNotMet`, which carries the profit it actually made
+/// 2. the simulated profit is compared with the quoted profit
+/// 3. `eth_estimateGas` of the call we would sign, which fails if the call reverts
+use std::collections::HashMap;
+
+use alloy::contract::Error as ContractError;
+use alloy::eips::BlockId;
+use alloy::primitives::{address, keccak256, Address, Bytes, B256, I256, U256};
+use alloy::providers::Provider;
+use alloy::rpc::types::state::{AccountOverride, StateOverride};
+use alloy::sol;
+use alloy::sol_types::{SolError, SolValue};
+use eyre::{bail, Result};
+
+use crate::arb::cycle_quote::CycleQuote;
+use crate::arb::executor::{run_pairs, ISimpleExecutor, RunEncoder};
+use crate::arb::swap::FEE_DENOMINATOR;
+
+sol! {
+    #[sol(rpc)]
+    "contracts/src/interfaces/IERC20.sol"
+}
+
+/// Storage slots searched for the balances mapping of a token
+const MAX_BALANCE_SLOT: u64 = 20;
+
+/// Holder whose balance is overridden to find the balances mapping of a token
+const PROBE_HOLDER: Address = address!("0x000000000000000000000000000000000000f1a3");
+
+/// Balance given to `PROBE_HOLDER`, unlikely to be anyone's real balance
+const PROBE_BALANCE: U256 = U256::from_limbs([0xf1a3_f1a3, 0, 0, 0]);
+
+/// What the simulation of a quote found
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Simulation {
+    /// The run makes about the quoted profit and its gas is estimated
+    Passed { profit: I256, gas_used: u64 },
+    /// The run reverts, e.g. a pair rejects the quoted output or a token takes a transfer fee
+    Reverted { reason: String },
+    /// The run makes a profit too far from the quoted one
+    Diverged { quoted: I256, simulated: I256 },
+}
+
+/// Simulates the runs of quotes before they are sent to the signer
+#[derive(Debug, Clone)]
+pub struct Preflight {
+    /// Owner of the executor, the only account allowed to call `run`
+    sender: Address,
+    executor: Address,
+    /// Largest difference between the simulated and the quoted profit, in basis points of the
+    /// quoted profit
+    max_divergence_bps: u32,
+    /// Storage slot of the balances mapping of each token
+    balance_slots: HashMap<Address, U256>,
+}
+
+impl Preflight {
+    /// 1% of the quoted profit
+    pub const DEFAULT_MAX_DIVERGENCE_BPS: u32 = 100;
+
+    pub fn new(sender: Address, executor: Address, max_divergence_bps: u32) -> Self {
+        Self {
+            sender,
+            executor,
+            max_divergence_bps,
+            balance_slots: HashMap::new(),
+        }
+    }
+
+    /// Simulates the run of a quote, with the minimum profit of `run_encoder`
+    ///
+    /// # Errors
+    /// * If a swap of the quote is through a Uniswap V3 pool, which `run` cannot execute
+    /// * If the balances mapping of the start token cannot be found
+    /// * If a simulation fails for another reason than a revert
+    pub async fn simulate<P: Provider>(
+        &mut self,
+        provider: &P,
+        quote: &CycleQuote,
+        run_encoder: &RunEncoder,
+    ) -> Result<Simulation> {
+        let token = quote.token().address;
+        let pairs = run_pairs(quote)?;
+        let balance_slot = self.balance_slot(provider, token).await?;
+        let overrides = balance_override(token, self.executor, balance_slot, quote.amount_in());
+        let executor = ISimpleExecutor::new(self.executor, provider);
+
+        // The run reverts with the profit it made, no profit reaches `int256` max
+        let probe = executor
+            .run(token, quote.amount_in(), I256::MAX.into_raw(), pairs, false)
+            .from(self.sender)
+            .state(overrides.clone())
+            .block(BlockId::pending());
+        let simulated = match probe.call().await {
+            Ok(_) => bail!(
+                "Run of {} succeeded with an unreachable profit",
+                self.executor
+            ),
+            Err(e) => match revert_data(&e).and_then(|data| simulated_profit(&data)) {
+                Some(profit) => profit,
+                None if is_rejected(&e) => return Ok(reverted(&e)),
+                None => return Err(e.into()),
+            },
+        };
+
+        let quoted = quote.profit();
+        if diverges(quoted, simulated, self.max_divergence_bps) {
+            return Ok(Simulation::Diverged { quoted, simulated });
+        }
+
+        let call = run_encoder.run_call(quote)?;
+        let run = executor
+            .run(
+                call.token0Address,
+                call.token0AmountIn,
+                call.minimumProfitInToken0,
+                call.pairs,
+                call.skipProfitCheck,
+            )
+            .from(self.sender)
+            .state(overrides)
+            .block(BlockId::pending());
+        match run.estimate_gas().await {
+            Ok(gas_used) => Ok(Simulation::Passed {
+                profit: simulated,
+                gas_used,
+            }),
+            Err(e) if is_rejected(&e) => Ok(reverted(&e)),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Slot of the balances mapping of a Solidity ERC20, found by overriding each candidate slot
+    /// for `PROBE_HOLDER` until `balanceOf` returns the override
+    async fn balance_slot<P: Provider>(&mut self, provider: &P, token: Address) -> Result<U256> {
+        if let Some(slot) = self.balance_slots.get(&token) {
+            return Ok(*slot);
+        }
+
+        let contract = IERC20::new(token, provider);
+        for slot in 0..MAX_BALANCE_SLOT {
+            let slot = U256::from(slot);
+            let balance = contract
+                .balanceOf(PROBE_HOLDER)
+                .state(balance_override(token, PROBE_HOLDER, slot, PROBE_BALANCE))
+                .call()
+                .await;
+            if balance.is_ok_and(|balance| balance._0 == PROBE_BALANCE) {
+                self.balance_slots.insert(token, slot);
+                return Ok(slot);
+            }
+        }
+        bail!("Balances mapping of {token} not found in the first {MAX_BALANCE_SLOT} slots")
+    }
+}
+
+/// Storage key of `holder` in a Solidity mapping at `slot`
+pub fn balance_key(holder: Address, slot: U256) -> B256 {
+    keccak256((holder, slot).abi_encode())
+}
+
+/// Override that sets the `token` balance of `holder`, with the balances mapping at `slot`
+fn balance_override(token: Address, holder: Address, slot: U256, balance: U256) -> StateOverride {
+    let mut overrides = StateOverride::default();
+    overrides.insert(
+        token,
+        AccountOverride {
+            state_diff: Some(
+                [(balance_key(holder, slot), B256::from(balance))]
+                    .into_iter()
+                    .collect(),
+            ),
+            ..AccountOverride::default()
+        },
+    );
+    overrides
+}
+
+/// Revert data of a failed call, `None` if the call did not revert
+fn revert_data(error: &ContractError) -> Option<Bytes> {
+    match error {
+        ContractError::TransportError(e) => e.as_error_resp()?.as_revert_data(),
+        _ => None,
+    }
+}
+
+/// Whether the node answered the call with an error, i.e. the call reverted, rather than the
+/// request failing
+fn is_rejected(error: &ContractError) -> bool {
+    matches!(error, ContractError::TransportError(e) if e.as_error_resp().is_some())
+}
+
+/// Profit a run made, from its `ProfitTargetNotMet` revert data
+fn simulated_profit(revert_data: &[u8]) -> Option<I256> {
+    ISimpleExecutor::ProfitTargetNotMet::abi_decode(revert_data, true)
+        .ok()
+        .map(|error| error.actualProfit)
+}
+
+fn reverted(error: &ContractError) -> Simulation {
+    Simulation::Reverted {
+        reason: error.to_string(),
+    }
+}
+
+/// Whether `simulated` is more than `max_divergence_bps` of `quoted` away from it
+fn diverges(quoted: I256, simulated: I256, max_divergence_bps: u32) -> bool {
+    let difference = simulated.saturating_sub(quoted).unsigned_abs();
+    difference.saturating_mul(U256::from(FEE_DENOMINATOR))
+        > quoted
+            .unsigned_abs()
+            .saturating_mul(U256::from(max_divergence_bps))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::constants::WETH;
+    use alloy::primitives::b256;
+
+    #[test]
+    fn test_balance_key() {
+        // keccak256(abi.encode(holder, 3)), the WETH9 balances mapping is at slot 3
+        let holder = address!("0x00000000000000000000000000000000000000aa");
+        let mut preimage = [0u8; 64];
+        preimage[31] = 0xaa;
+        preimage[63] = 3;
+        assert_eq!(balance_key(holder, U256::from(3)), keccak256(preimage));
+
+        let overrides = balance_override(WETH, holder, U256::from(3), U256::from(5));
+        let state_diff = overrides[&WETH].state_diff.as_ref().unwrap();
+        assert_eq!(
+            state_diff[&keccak256(preimage)],
+            b256!("0x0000000000000000000000000000000000000000000000000000000000000005")
+        );
+    }
+
+    #[test]
+    fn test_simulated_profit() {
+        let revert_data = ISimpleExecutor::ProfitTargetNotMet {
+            minimumProfit: I256::MAX.into_raw(),
+            actualProfit: I256::try_from(-42).unwrap(),
+        }
+        .abi_encode();
+        assert_eq!(
+            simulated_profit(&revert_data),
+            Some(I256::try_from(-42).unwrap())
+        );
+
+        // Any other revert has no profit
+        assert_eq!(simulated_profit(&[0x08, 0xc3, 0x79, 0xa0]), None);
+        assert_eq!(simulated_profit(&[]), None);
+    }
+
+    #[test]
+    fn test_diverges() {
+        let quoted = I256::try_from(10_000).unwrap();
+        let at = |profit: i64| I256::try_from(profit).unwrap();
+
+        assert!(!diverges(quoted, at(10_000), 100));
+        assert!(!diverges(quoted, at(9_900), 100));
+        assert!(!diverges(quoted, at(10_100), 100));
+        assert!(diverges(quoted, at(9_899), 100));
+        assert!(diverges(quoted, at(10_101), 100));
+        assert!(diverges(quoted, at(-10_000), 100));
+        // Without a tolerance only the exact profit passes
+        assert!(diverges(quoted, at(10_001), 0));
+    }
+}
@@ -17,6 +17,85 @@ pub mod sql_types {
     #[derive(diesel::query_builder::QueryId, Clone, diesel::sql_types::SqlType)]
     #[diesel(postgres_type(name = "price_support_status"))]
     pub struct PriceSupportStatus;
+
+    /// The `token_safety` SQL type
+    ///
+    /// (Automatically generated by Diesel.)
+    #[derive(diesel::query_builder::QueryId, Clone, diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(name = "token_safety"))]
+    pub struct TokenSafety;
+}
+
+diesel::table! {
+    /// Representation of the `checkpoints` table.
+    ///
+    /// (Automatically generated by Diesel.)
+    checkpoints (chain_id, name) {
+        /// The `chain_id` column of the `checkpoints` table.
+        ///
+        /// Its SQL type is `Int8`.
+        ///
+        /// (Automatically generated by Diesel.)
+        chain_id -> Int8,
+        /// The `name` column of the `checkpoints` table.
+        ///
+        /// Its SQL type is `Varchar`.
+        ///
+        /// (Automatically generated by Diesel.)
+        name -> Varchar,
+        /// The `block_number` column of the `checkpoints` table.
+        ///
+        /// Its SQL type is `Int8`.
+        ///
+        /// (Automatically generated by Diesel.)
+        block_number -> Int8,
+    }
+}
+
+diesel::table! {
+    /// Representation of the `cycles` table.
+    ///
+    /// (Automatically generated by Diesel.)
+    cycles (id) {
+        /// The `id` column of the `cycles` table.
+        ///
+        /// Its SQL type is `Int4`.
+        ///
+        /// (Automatically generated by Diesel.)
+        id -> Int4,
+        /// The `pair_ids` column of the `cycles` table.
+        ///
+        /// Its SQL type is `Array<Nullable<Int4>>`.
+        ///
+        /// (Automatically generated by Diesel.)
+        pair_ids -> Array<Nullable<Int4>>,
+        /// The `zero_for_one` column of the `cycles` table.
+        ///
+        /// Its SQL type is `Array<Nullable<Bool>>`.
+        ///
+        /// (Automatically generated by Diesel.)
+        zero_for_one -> Array<Nullable<Bool>>,
+    }
+}
+
+diesel::table! {
+    /// Representation of the `cycles_fingerprint` table.
+    ///
+    /// (Automatically generated by Diesel.)
+    cycles_fingerprint (id) {
+        /// The `id` column of the `cycles_fingerprint` table.
+        ///
+        /// Its SQL type is `Bool`.
+        ///
+        /// (Automatically generated by Diesel.)
+        id -> Bool,
+        /// The `fingerprint` column of the `cycles_fingerprint` table.
+        ///
+        /// Its SQL type is `Varchar`.
+        ///
+        /// (Automatically generated by Diesel.)
+        fingerprint -> Varchar,
+    }
 }
 
 diesel::table! {
@@ -51,6 +130,30 @@ diesel::table! {
         ///
         /// (Automatically generated by Diesel.)
         status -> FactoryStatus,
+        /// The `fee` column of the `factories` table.
+        ///
+        /// Its SQL type is `Nullable<Int4>`.
+        ///
+        /// (Automatically generated by Diesel.)
+        fee -> Nullable<Int4>,
+        /// The `chain_id` column of the `factories` table.
+        ///
+        /// Its SQL type is `Int8`.
+        ///
+        /// (Automatically generated by Diesel.)
+        chain_id -> Int8,
+        /// The `deployment_block` column of the `factories` table.
+        ///
+        /// Its SQL type is `Nullable<Int8>`.
+        ///
+        /// (Automatically generated by Diesel.)
+        deployment_block -> Nullable<Int8>,
+        /// The `stable_fee` column of the `factories` table.
+        ///
+        /// Its SQL type is `Nullable<Int4>`.
+        ///
+        /// (Automatically generated by Diesel.)
+        stable_fee -> Nullable<Int4>,
     }
 }
 
@@ -107,12 +210,43 @@ diesel::table! {
         ///
         /// (Automatically generated by Diesel.)
         usd -> Nullable<Int4>,
+        /// The `stable` column of the `pairs` table.
+        ///
+        /// Its SQL type is `Bool`.
+        ///
+        /// (Automatically generated by Diesel.)
+        stable -> Bool,
+        /// The `chain_id` column of the `pairs` table.
+        ///
+        /// Its SQL type is `Int8`.
+        ///
+        /// (Automatically generated by Diesel.)
+        chain_id -> Int8,
+        /// The `reserves_block_number` column of the `pairs` table.
+        ///
+        /// Its SQL type is `Nullable<Int8>`.
+        ///
+        /// (Automatically generated by Diesel.)
+        reserves_block_number -> Nullable<Int8>,
+        /// The `reserves_log_index` column of the `pairs` table.
+        ///
+        /// Its SQL type is `Nullable<Int4>`.
+        ///
+        /// (Automatically generated by Diesel.)
+        reserves_log_index -> Nullable<Int4>,
+        /// The `tokens_unreadable` column of the `pairs` table.
+        ///
+        /// Its SQL type is `Bool`.
+        ///
+        /// (Automatically generated by Diesel.)
+        tokens_unreadable -> Bool,
     }
 }
 
 diesel::table! {
     use diesel::sql_types::*;
     use super::sql_types::PriceSupportStatus;
+    use super::sql_types::TokenSafety;
 
     /// Representation of the `tokens` table.
     ///
@@ -154,9 +288,52 @@ diesel::table! {
         updated_last -> Nullable<Timestamp>,
         /// Indicates whether price data is available for this token from external APIs. NULL means not yet checked.
         price_support_status -> Nullable<PriceSupportStatus>,
+        /// The `chain_id` column of the `tokens` table.
+        ///
+        /// Its SQL type is `Int8`.
+        ///
+        /// (Automatically generated by Diesel.)
+        chain_id -> Int8,
+        /// The `metadata_invalid` column of the `tokens` table.
+        ///
+        /// Its SQL type is `Bool`.
+        ///
+        /// (Automatically generated by Diesel.)
+        metadata_invalid -> Bool,
+        /// The `safety` column of the `tokens` table.
+        ///
+        /// Its SQL type is `Nullable<TokenSafety>`.
+        ///
+        /// (Automatically generated by Diesel.)
+        safety -> Nullable<TokenSafety>,
+        /// The `transfer_tax` column of the `tokens` table.
+        ///
+        /// Its SQL type is `Nullable<Int4>`.
+        ///
+        /// (Automatically generated by Diesel.)
+        transfer_tax -> Nullable<Int4>,
+        /// The `exchange_rate_source` column of the `tokens` table.
+        ///
+        /// Its SQL type is `Nullable<Varchar>`.
+        ///
+        /// (Automatically generated by Diesel.)
+        exchange_rate_source -> Nullable<Varchar>,
+        /// The `safety_reason` column of the `tokens` table.
+        ///
+        /// Its SQL type is `Nullable<Varchar>`.
+        ///
+        /// (Automatically generated by Diesel.)
+        safety_reason -> Nullable<Varchar>,
     }
 }
 
 diesel::joinable!(pairs -> factories (factory_id));
 
-diesel::allow_tables_to_appear_in_same_query!(factories, pairs, tokens,);
+diesel::allow_tables_to_appear_in_same_query!(
+    checkpoints,
+    cycles,
+    cycles_fingerprint,
+    factories,
+    pairs,
+    tokens,
+);
@@ -0,0 +1,437 @@
+//! Reference signer: the process behind `utils::signer::Signer`
+//!
+//! It owns the private key, so the core never holds it. Orders are read from the Unix socket,
+//! checked against the `Policy` and turned into a signed EIP-1559 `SimpleExecutor.run`
+//! transaction. The transaction is broadcast if the daemon has a provider, otherwise its bytes are
+//! returned to the core.
+//!
+//! Orders are handled one at a time, so nonces and the daily loss are never raced.
+//!
+//! Without a provider the daemon cannot know whether the core broadcast what it signed, so each
+//! transaction takes the nonce hint of its order, the pending nonce the core reads. With one the
+//! nonce only advances once a broadcast is accepted, and is read again from the pending block
+//! when a broadcast fails.
+//!
+//! The daily loss is kept in memory, a restart of the daemon starts the day over from zero.
+
+pub mod policy;
+
+pub use policy::{DailyLoss, Policy};
+
+use std::os::unix::fs::PermissionsExt;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use alloy::consensus::{SignableTransaction, TxEip1559, TxEnvelope};
+use alloy::eips::eip2718::Encodable2718;
+use alloy::network::TxSignerSync;
+use alloy::primitives::{Address, Bytes, ChainId, TxKind, U256};
+use alloy::providers::{Provider, RootProvider};
+use alloy::signers::local::PrivateKeySigner;
+use eyre::{bail, eyre, Result};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::Mutex;
+
+use crate::arb::executor::{encode_run, ISimpleExecutor};
+use crate::utils::signer::{
+    check_version, read_frame, write_frame, Order, OrderResponse, OrderStatus,
+};
+use policy::worst_case_fee;
+
+pub struct SignerDaemon {
+    wallet: PrivateKeySigner,
+    chain_id: ChainId,
+    policy: Policy,
+    daily_loss: DailyLoss,
+    /// Broadcasts the signed transactions, they are returned to the core without one
+    provider: Option<RootProvider>,
+    /// Nonce of the next broadcast transaction, only known with a provider. A higher nonce hint
+    /// of an order wins.
+    next_nonce: Option<u64>,
+}
+
+impl SignerDaemon {
+    /// Daemon that returns the signed transactions to the core
+    pub fn new(wallet: PrivateKeySigner, chain_id: ChainId, policy: Policy) -> Self {
+        Self {
+            wallet,
+            chain_id,
+            policy,
+            daily_loss: DailyLoss::default(),
+            provider: None,
+            next_nonce: None,
+        }
+    }
+
+    /// Broadcasts the signed transactions through `provider`, starting from the pending nonce of
+    /// the wallet
+    ///
+    /// # Errors
+    /// * If the nonce cannot be read
+    pub async fn with_broadcast(mut self, provider: RootProvider) -> Result<Self> {
+        let nonce = provider
+            .get_transaction_count(self.wallet.address())
+            .pending()
+            .await?;
+        self.next_nonce = Some(nonce);
+        self.provider = Some(provider);
+        Ok(self)
+    }
+
+    /// Address of the signing key
+    pub fn address(&self) -> Address {
+        self.wallet.address()
+    }
+
+    /// Listens on `socket_path` and answers the orders of every connection, until accepting fails
+    ///
+    /// The socket is readable and writable by the group of the daemon, the `fly` group the core
+    /// runs in.
+    ///
+    /// # Errors
+    /// * If the socket cannot be bound
+    pub async fn serve(self, socket_path: &str) -> Result<()> {
+        // A socket left by a previous run would fail the bind
+        let _ = std::fs::remove_file(socket_path);
+        let listener = UnixListener::bind(socket_path)?;
+        // Only our user and the `fly` group may send orders
+        std::fs::set_permissions(socket_path, std::fs::Permissions::from_mode(0o660))?;
+        log::info!(
+            "signer: Signing for {} on {} as {}",
+            self.chain_id,
+            socket_path,
+            self.address()
+        );
+
+        let daemon = Arc::new(Mutex::new(self));
+        loop {
+            let (stream, _) = listener.accept().await?;
+            log::info!("signer: Core connected");
+            tokio::spawn(handle_connection(Arc::clone(&daemon), stream));
+        }
+    }
+
+    /// What the daemon does with an order at unix timestamp `now`. Orders that break the policy
+    /// or fail to be signed or broadcast are rejected with the reason.
+    pub async fn handle(&mut self, order: &Order, now: u64) -> OrderStatus {
+        match self.try_handle(order, now).await {
+            Ok(status) => status,
+            Err(e) => OrderStatus::Rejected {
+                reason: e.to_string(),
+            },
+        }
+    }
+
+    async fn try_handle(&mut self, order: &Order, now: u64) -> Result<OrderStatus> {
+        check_version(order.version)?;
+        self.policy.check(order, now)?;
+        let fee = worst_case_fee(&order.limits);
+        self.daily_loss
+            .check(self.policy.daily_loss_cap, fee, now)?;
+
+        let nonce = self
+            .next_nonce
+            .max(order.limits.nonce_hint)
+            .ok_or_else(|| eyre!("Nonce unknown, the order has no nonce hint"))?;
+        let raw_transaction = self.sign(order, nonce)?;
+
+        let status = match &self.provider {
+            Some(provider) => match provider.send_raw_transaction(&raw_transaction).await {
+                Ok(pending) => {
+                    self.next_nonce = Some(nonce + 1);
+                    OrderStatus::Submitted {
+                        tx_hash: *pending.tx_hash(),
+                    }
+                }
+                Err(e) => {
+                    // The nonce may or may not have been used, the pending block knows
+                    match provider
+                        .get_transaction_count(self.wallet.address())
+                        .pending()
+                        .await
+                    {
+                        Ok(nonce) => self.next_nonce = Some(nonce),
+                        Err(e) => log::warn!("signer: Failed to read the pending nonce: {e}"),
+                    }
+                    bail!("Broadcast failed: {e}");
+                }
+            },
+            // The core broadcasts, the next order carries the nonce it sees as pending
+            None => OrderStatus::Signed { raw_transaction },
+        };
+
+        self.daily_loss.charge(fee, now);
+        Ok(status)
+    }
+
+    /// EIP-2718 encoding of the signed `SimpleExecutor.run` transaction of an order
+    ///
+    /// # Errors
+    /// * If signing fails
+    pub fn sign(&self, order: &Order, nonce: u64) -> Result<Bytes> {
+        let mut transaction = TxEip1559 {
+            chain_id: self.chain_id,
+            nonce,
+            gas_limit: order.limits.gas_limit,
+            max_fee_per_gas: order.limits.max_fee_per_gas,
+            max_priority_fee_per_gas: order.limits.max_priority_fee_per_gas,
+            to: TxKind::Call(order.executor),
+            value: U256::ZERO,
+            access_list: Default::default(),
+            input: run_calldata(order),
+        };
+        let signature = self.wallet.sign_transaction_sync(&mut transaction)?;
+        let envelope = TxEnvelope::from(transaction.into_signed(signature));
+        Ok(envelope.encoded_2718().into())
+    }
+}
+
+/// `SimpleExecutor.run` calldata of an order, with the profit check
+pub fn run_calldata(order: &Order) -> Bytes {
+    encode_run(
+        order.start_token,
+        order.amount_in,
+        order.min_profit,
+        order
+            .pairs
+            .iter()
+            .map(|pair| ISimpleExecutor::Pair {
+                contractAddress: pair.contract_address,
+                amountOut: pair.amount_out,
+                isToken0: pair.is_token0,
+            })
+            .collect(),
+    )
+}
+
+/// Answers the orders of one connection until the core disconnects
+async fn handle_connection(daemon: Arc<Mutex<SignerDaemon>>, mut stream: UnixStream) {
+    loop {
+        // Read loosely first, so orders of another version are rejected instead of dropped
+        let message: serde_json::Value = match read_frame(&mut stream).await {
+            Ok(message) => message,
+            Err(e) => {
+                log::info!("signer: Core disconnected: {e}");
+                return;
+            }
+        };
+
+        let status = match parse_order(message) {
+            Ok(order) => {
+                let now = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map_or(0, |elapsed| elapsed.as_secs());
+                daemon.lock().await.handle(&order, now).await
+            }
+            Err(e) => OrderStatus::Rejected {
+                reason: e.to_string(),
+            },
+        };
+        match &status {
+            OrderStatus::Rejected { reason } => log::warn!("signer: Rejected order: {reason}"),
+            status => log::info!("signer: Order done: {status:?}"),
+        }
+
+        if let Err(e) = write_frame(&mut stream, &OrderResponse::new(status)).await {
+            log::warn!("signer: Failed to answer the core: {e}");
+            return;
+        }
+    }
+}
+
+/// The order of a message. The version is checked first, an order of another version may not
+/// have the fields of ours.
+fn parse_order(message: serde_json::Value) -> Result<Order> {
+    let version = message
+        .get("version")
+        .and_then(serde_json::Value::as_u64)
+        .ok_or_else(|| eyre!("Message has no version"))?;
+    check_version(u16::try_from(version).unwrap_or(u16::MAX))?;
+    serde_json::from_value(message).map_err(|e| eyre!("Malformed order: {e}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arb::test_helpers::address_from_str;
+    use crate::utils::constants::{BASE_CHAIN_ID, WETH};
+    use crate::utils::signer::{OrderPair, Signer, TransactionLimits, PROTOCOL_VERSION};
+    use alloy::consensus::Transaction;
+    use alloy::eips::eip2718::Decodable2718;
+    use alloy::sol_types::SolCall;
+    use std::collections::HashMap;
+
+    const NOW: u64 = 1_700_000_000;
+
+    fn daemon() -> SignerDaemon {
+        SignerDaemon::new(
+            PrivateKeySigner::random(),
+            BASE_CHAIN_ID,
+            Policy {
+                executor: address_from_str("E"),
+                max_notional: HashMap::from([(WETH, U256::from(1_000))]),
+                daily_loss_cap: U256::from(1_000_000),
+            },
+        )
+    }
+
+    fn order(nonce_hint: Option<u64>) -> Order {
+        Order {
+            version: PROTOCOL_VERSION,
+            executor: address_from_str("E"),
+            start_token: WETH,
+            amount_in: U256::from(1_000),
+            min_profit: U256::from(10),
+            pairs: vec![
+                OrderPair {
+                    contract_address: address_from_str("F1"),
+                    amount_out: U256::from(2_000),
+                    is_token0: false,
+                },
+                OrderPair {
+                    contract_address: address_from_str("F2"),
+                    amount_out: U256::from(1_100),
+                    is_token0: true,
+                },
+            ],
+            limits: TransactionLimits {
+                deadline: NOW + 4,
+                gas_limit: 500,
+                max_fee_per_gas: 1_000,
+                max_priority_fee_per_gas: 10,
+                nonce_hint,
+            },
+        }
+    }
+
+    #[test]
+    fn test_run_calldata() {
+        let calldata = run_calldata(&order(None));
+        let call = ISimpleExecutor::runCall::abi_decode(&calldata, true).unwrap();
+
+        assert_eq!(call.token0Address, WETH);
+        assert_eq!(call.token0AmountIn, U256::from(1_000));
+        assert_eq!(call.minimumProfitInToken0, U256::from(10));
+        assert_eq!(call.pairs.len(), 2);
+        assert_eq!(call.pairs[1].contractAddress, address_from_str("F2"));
+        assert!(call.pairs[1].isToken0);
+        assert!(!call.skipProfitCheck);
+    }
+
+    #[test]
+    fn test_parse_order() {
+        let message = serde_json::to_value(order(None)).unwrap();
+        assert_eq!(parse_order(message).unwrap(), order(None));
+
+        let old_version = serde_json::json!({"version": PROTOCOL_VERSION - 1, "amount": "0x1"});
+        let error = parse_order(old_version).unwrap_err().to_string();
+        assert!(error.contains("Unsupported protocol version"));
+
+        assert!(parse_order(serde_json::json!({"version": PROTOCOL_VERSION})).is_err());
+        assert!(parse_order(serde_json::json!({})).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_handle_signs() {
+        let mut daemon = daemon();
+        let OrderStatus::Signed { raw_transaction } = daemon.handle(&order(Some(7)), NOW).await
+        else {
+            panic!("Order not signed");
+        };
+
+        let envelope = TxEnvelope::decode_2718(&mut raw_transaction.as_ref()).unwrap();
+        let TxEnvelope::Eip1559(signed) = &envelope else {
+            panic!("Not an EIP-1559 transaction");
+        };
+        assert_eq!(signed.recover_signer().unwrap(), daemon.address());
+        assert_eq!(envelope.chain_id(), Some(BASE_CHAIN_ID));
+        assert_eq!(envelope.nonce(), 7);
+        assert_eq!(envelope.to(), Some(address_from_str("E")));
+        assert_eq!(envelope.input(), &run_calldata(&order(None)));
+
+        // The core has not broadcast it yet, the next order takes the same pending nonce
+        let OrderStatus::Signed { raw_transaction } = daemon.handle(&order(Some(7)), NOW).await
+        else {
+            panic!("Order not signed");
+        };
+        let envelope = TxEnvelope::decode_2718(&mut raw_transaction.as_ref()).unwrap();
+        assert_eq!(envelope.nonce(), 7);
+        assert_eq!(daemon.next_nonce, None);
+        assert_eq!(daemon.daily_loss.loss(), U256::from(1_000_000));
+    }
+
+    #[tokio::test]
+    async fn test_handle_rejects() {
+        let mut daemon = daemon();
+
+        // Without a provider or a hint the nonce is unknown
+        assert!(matches!(
+            daemon.handle(&order(None), NOW).await,
+            OrderStatus::Rejected { .. }
+        ));
+
+        let mut old_version = order(Some(0));
+        old_version.version = PROTOCOL_VERSION - 1;
+        assert!(matches!(
+            daemon.handle(&old_version, NOW).await,
+            OrderStatus::Rejected { .. }
+        ));
+
+        // Both orders fit the cap alone, the second is over it
+        let mut expensive = order(Some(0));
+        expensive.limits.max_fee_per_gas = 1_200;
+        assert!(matches!(
+            daemon.handle(&expensive, NOW).await,
+            OrderStatus::Signed { .. }
+        ));
+        assert!(matches!(
+            daemon.handle(&expensive, NOW).await,
+            OrderStatus::Rejected { .. }
+        ));
+        assert_eq!(daemon.next_nonce, None);
+    }
+
+    #[tokio::test]
+    async fn test_serve_signs() {
+        let socket_path =
+            std::env::temp_dir().join(format!("fly-signer-daemon-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&socket_path);
+        let daemon = daemon();
+        let address = daemon.address();
+        let server = tokio::spawn({
+            let socket_path = socket_path.to_str().unwrap().to_string();
+            async move { daemon.serve(&socket_path).await }
+        });
+        while !socket_path.exists() {
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+
+        // What the pipeline sends without `--rpc-url`: the pending nonce as the hint
+        let mut order = order(Some(3));
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        order.limits.deadline = now + 60;
+        let mut signer = Signer::new(socket_path.to_str().unwrap());
+        let OrderStatus::Signed { raw_transaction } = signer.call(&order).await.unwrap().status
+        else {
+            panic!("Order not signed");
+        };
+
+        let envelope = TxEnvelope::decode_2718(&mut raw_transaction.as_ref()).unwrap();
+        let TxEnvelope::Eip1559(signed) = &envelope else {
+            panic!("Not an EIP-1559 transaction");
+        };
+        assert_eq!(signed.recover_signer().unwrap(), address);
+        assert_eq!(envelope.nonce(), 3);
+        let mode = std::fs::metadata(&socket_path)
+            .unwrap()
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o777, 0o660);
+
+        server.abort();
+        std::fs::remove_file(&socket_path).unwrap();
+    }
+}
@@ -0,0 +1,238 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use alloy::primitives::{Address, U256};
+use eyre::{bail, eyre, Result};
+use serde::Deserialize;
+
+use crate::utils::signer::{Order, TransactionLimits};
+
+/// Losses are capped per UTC day
+const SECONDS_PER_DAY: u64 = 86_400;
+
+/// Most pairs `SimpleExecutor.run` accepts
+pub const MAX_PAIRS: usize = 5;
+
+/// Limits every order has to stay within to be signed, e.g.
+/// ```json
+/// {
+///     "executor": "0x1111111111111111111111111111111111111111",
+///     "max_notional": {"0x4200000000000000000000000000000000000006": "0xde0b6b3a7640000"},
+///     "daily_loss_cap": "0x2386f26fc10000"
+/// }
+/// ```
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
+pub struct Policy {
+    /// The only `SimpleExecutor` orders may call
+    pub executor: Address,
+    /// Largest `amount_in` by start token, in raw units. Orders of other tokens are rejected.
+    pub max_notional: HashMap<Address, U256>,
+    /// Most the signed transactions of a UTC day may lose in wei, see `DailyLoss`
+    pub daily_loss_cap: U256,
+}
+
+impl Policy {
+    /// # Errors
+    /// * If the JSON is malformed
+    pub fn from_json(json: &str) -> Result<Self> {
+        Ok(serde_json::from_str(json)?)
+    }
+
+    /// # Errors
+    /// * If the file cannot be read or is malformed
+    pub fn from_file(path: &Path) -> Result<Self> {
+        Self::from_json(&std::fs::read_to_string(path)?)
+    }
+
+    /// Fails with the reason if the order breaks the policy at unix timestamp `now`
+    ///
+    /// # Errors
+    /// * If the deadline of the order has passed
+    /// * If the order calls another executor
+    /// * If the order has no pairs or more than `MAX_PAIRS`
+    /// * If `amount_in` is over the notional limit of the start token or it has none
+    pub fn check(&self, order: &Order, now: u64) -> Result<()> {
+        if order.limits.deadline < now {
+            bail!("Deadline {} passed", order.limits.deadline);
+        }
+        if order.executor != self.executor {
+            bail!("Executor {} is not allowed", order.executor);
+        }
+        if order.pairs.is_empty() || order.pairs.len() > MAX_PAIRS {
+            bail!("Route of {} pairs is not supported", order.pairs.len());
+        }
+
+        let max_notional = self
+            .max_notional
+            .get(&order.start_token)
+            .ok_or_else(|| eyre!("Token {} has no notional limit", order.start_token))?;
+        if order.amount_in > *max_notional {
+            bail!(
+                "Amount {} is over the notional limit {max_notional}",
+                order.amount_in
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Most a transaction with these limits can pay in fees, in wei
+pub fn worst_case_fee(limits: &TransactionLimits) -> U256 {
+    U256::from(limits.gas_limit) * U256::from(limits.max_fee_per_gas)
+}
+
+/// Losses of the transactions signed in the current UTC day
+///
+/// `SimpleExecutor.run` reverts unless it makes `min_profit`, so a transaction cannot lose any of
+/// the traded token and its loss is bounded by the fee. Every signed transaction is charged its
+/// worst case fee, whether it is included, reverts or never lands.
+///
+/// It is not persisted: a restarted daemon has no losses for the day.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DailyLoss {
+    /// Days since the unix epoch
+    day: u64,
+    loss: U256,
+}
+
+impl DailyLoss {
+    /// Starts over when `now` is in a later day than the charged losses
+    fn roll(&mut self, now: u64) {
+        let day = now / SECONDS_PER_DAY;
+        if day != self.day {
+            self.day = day;
+            self.loss = U256::ZERO;
+        }
+    }
+
+    /// Fails if charging `fee` at unix timestamp `now` would take the loss of the day over `cap`
+    ///
+    /// # Errors
+    /// * If the cap would be exceeded
+    pub fn check(&mut self, cap: U256, fee: U256, now: u64) -> Result<()> {
+        self.roll(now);
+        if self.loss.saturating_add(fee) > cap {
+            bail!(
+                "Fee {fee} would take the daily loss of {} over the cap {cap}",
+                self.loss
+            );
+        }
+        Ok(())
+    }
+
+    /// Adds `fee` to the loss of the day at unix timestamp `now`
+    pub fn charge(&mut self, fee: U256, now: u64) {
+        self.roll(now);
+        self.loss = self.loss.saturating_add(fee);
+    }
+
+    pub fn loss(&self) -> U256 {
+        self.loss
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arb::test_helpers::address_from_str;
+    use crate::utils::constants::WETH;
+    use crate::utils::signer::{OrderPair, PROTOCOL_VERSION};
+
+    const NOW: u64 = 1_700_000_000;
+
+    fn policy() -> Policy {
+        Policy {
+            executor: address_from_str("E"),
+            max_notional: HashMap::from([(WETH, U256::from(1_000))]),
+            daily_loss_cap: U256::from(1_000_000),
+        }
+    }
+
+    fn order() -> Order {
+        Order {
+            version: PROTOCOL_VERSION,
+            executor: address_from_str("E"),
+            start_token: WETH,
+            amount_in: U256::from(1_000),
+            min_profit: U256::from(10),
+            pairs: vec![
+                OrderPair {
+                    contract_address: address_from_str("F1"),
+                    amount_out: U256::from(2_000),
+                    is_token0: false,
+                };
+                2
+            ],
+            limits: TransactionLimits {
+                deadline: NOW + 4,
+                gas_limit: 500,
+                max_fee_per_gas: 1_000,
+                max_priority_fee_per_gas: 10,
+                nonce_hint: None,
+            },
+        }
+    }
+
+    #[test]
+    fn test_from_json() {
+        let policy = Policy::from_json(
+            r#"{
+                "executor": "0x0000000000000000000000000000000000000001",
+                "max_notional": {"0x4200000000000000000000000000000000000006": "0x3e8"},
+                "daily_loss_cap": "0xf4240"
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(policy.max_notional[&WETH], U256::from(1_000));
+        assert_eq!(policy.daily_loss_cap, U256::from(1_000_000));
+
+        assert!(Policy::from_json(r#"{"executor": "0x01"}"#).is_err());
+    }
+
+    #[test]
+    fn test_check() {
+        let policy = policy();
+        assert!(policy.check(&order(), NOW).is_ok());
+
+        // The deadline passed
+        assert!(policy.check(&order(), NOW + 5).is_err());
+
+        let mut other_executor = order();
+        other_executor.executor = address_from_str("F");
+        assert!(policy.check(&other_executor, NOW).is_err());
+
+        let mut no_pairs = order();
+        no_pairs.pairs.clear();
+        assert!(policy.check(&no_pairs, NOW).is_err());
+
+        let mut too_many_pairs = order();
+        too_many_pairs.pairs = vec![order().pairs[0].clone(); MAX_PAIRS + 1];
+        assert!(policy.check(&too_many_pairs, NOW).is_err());
+
+        let mut over_notional = order();
+        over_notional.amount_in = U256::from(1_001);
+        assert!(policy.check(&over_notional, NOW).is_err());
+
+        let mut no_limit = order();
+        no_limit.start_token = address_from_str("A");
+        assert!(policy.check(&no_limit, NOW).is_err());
+    }
+
+    #[test]
+    fn test_daily_loss() {
+        let cap = U256::from(1_000);
+        let mut daily_loss = DailyLoss::default();
+        assert_eq!(worst_case_fee(&order().limits), U256::from(500_000));
+
+        assert!(daily_loss.check(cap, U256::from(600), NOW).is_ok());
+        daily_loss.charge(U256::from(600), NOW);
+        assert!(daily_loss.check(cap, U256::from(400), NOW).is_ok());
+        assert!(daily_loss.check(cap, U256::from(401), NOW).is_err());
+
+        // The cap applies again from the next day
+        assert!(daily_loss
+            .check(cap, U256::from(1_000), NOW + SECONDS_PER_DAY)
+            .is_ok());
+        assert_eq!(daily_loss.loss(), U256::ZERO);
+    }
+}
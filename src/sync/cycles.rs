@@ -0,0 +1,280 @@
+use std::collections::{HashMap, HashSet};
+
+use alloy::primitives::{keccak256, ChainId};
+use diesel::sql_types::{BigInt, Integer, Text};
+use diesel::{ExpressionMethods, OptionalExtension, QueryDsl, QueryableByName};
+use diesel_async::scoped_futures::ScopedFutureExt;
+use diesel_async::{AsyncConnection, RunQueryDsl};
+use eyre::Result;
+use itertools::Itertools;
+
+use crate::arb::pool::{Pool, PoolId};
+use crate::arb::swap::{Direction, DEFAULT_FEE};
+use crate::arb::token::TokenId;
+use crate::arb::world::World;
+use crate::arb::world_config::WorldConfig;
+use crate::bootstrap;
+use crate::models::cycle::NewCycle;
+use crate::schemas::{cycles, cycles_fingerprint};
+use crate::utils::app_context::AppContext;
+
+/// How often to check whether the pair set has changed
+const CHECK_INTERVAL_SECS: u64 = 600;
+
+/// Postgres limits a query to 65535 bind parameters, each cycle takes 2
+const INSERT_BATCH_SIZE: usize = 10_000;
+
+#[derive(QueryableByName, Debug)]
+struct PairWithTokenAddresses {
+    #[diesel(sql_type = Integer)]
+    id: i32,
+    #[diesel(sql_type = BigInt)]
+    chain_id: i64,
+    #[diesel(sql_type = Text)]
+    address: String,
+    #[diesel(sql_type = Text)]
+    token0_address: String,
+    #[diesel(sql_type = Text)]
+    token1_address: String,
+}
+
+/// Sync precomputed cycles
+/// Regenerates the `cycles` table whenever the set of pairs with known tokens changes, so the bot
+/// can load cycles at startup instead of searching for them.
+///
+/// The saved cycles are fingerprinted with the pairs, unsafe tokens and cycle settings they were
+/// found for, so a restart does not regenerate cycles that are still up to date.
+pub async fn cycles(ctx: &AppContext) -> Result<()> {
+    log::info!("sync::cycles: Starting cycles sync...");
+
+    let mut last_fingerprint = saved_fingerprint(ctx).await?;
+    loop {
+        let pairs = pairs_with_tokens(ctx).await?;
+        let unsafe_tokens = bootstrap::fetch_unsafe_tokens(ctx).await?;
+        // Pairs are ordered by id
+        let pair_ids = pairs.iter().map(|pair| pair.id).collect::<Vec<_>>();
+
+        let config = WorldConfig::from_env()?;
+
+        let fingerprint = fingerprint(&pair_ids, &unsafe_tokens, &config);
+        if last_fingerprint.as_ref() == Some(&fingerprint) {
+            log::debug!("sync::cycles: Pair set has not changed");
+        } else {
+            let unsafe_tokens_count = unsafe_tokens.len();
+            let config = config.with_excluded_tokens(unsafe_tokens);
+            let cycles_count = sync(ctx, &pairs, config, &fingerprint).await?;
+            log::info!(
+                "sync::cycles: Saved {} cycles for {} pairs without {} unsafe tokens",
+                cycles_count,
+                pair_ids.len(),
+                unsafe_tokens_count
+            );
+            last_fingerprint = Some(fingerprint);
+        }
+
+        tokio::time::sleep(tokio::time::Duration::from_secs(CHECK_INTERVAL_SECS)).await;
+    }
+}
+
+/// Hash of what the cycles are found for: the pair ids, the unsafe tokens and the cycle settings.
+/// Sets are hashed rather than counts, a pair or token can be replaced by another without
+/// changing the count. Each list is prefixed with its length, so items cannot move between lists.
+fn fingerprint(pair_ids: &[i32], unsafe_tokens: &HashSet<TokenId>, config: &WorldConfig) -> String {
+    fn extend_tokens<'a>(bytes: &mut Vec<u8>, tokens: impl ExactSizeIterator<Item = &'a TokenId>) {
+        bytes.extend_from_slice(&(tokens.len() as u64).to_be_bytes());
+        for token_id in tokens {
+            bytes.extend_from_slice(&token_id.chain_id.to_be_bytes());
+            bytes.extend_from_slice(token_id.address.as_slice());
+        }
+    }
+
+    let mut bytes = Vec::with_capacity(pair_ids.len() * 4 + unsafe_tokens.len() * 28 + 64);
+    bytes.extend_from_slice(&(pair_ids.len() as u64).to_be_bytes());
+    for pair_id in pair_ids {
+        bytes.extend_from_slice(&pair_id.to_be_bytes());
+    }
+    extend_tokens(&mut bytes, unsafe_tokens.iter().sorted_unstable());
+
+    bytes.extend_from_slice(&(config.max_cycle_length() as u64).to_be_bytes());
+    // Every token is an anchor without anchor tokens, that is not the same as an empty list
+    match config.anchor_tokens() {
+        Some(anchor_tokens) => {
+            bytes.push(1);
+            extend_tokens(&mut bytes, anchor_tokens.iter().sorted_unstable());
+        }
+        None => bytes.push(0),
+    }
+    match config.max_cycles_per_token() {
+        Some(max_cycles) => {
+            bytes.push(1);
+            bytes.extend_from_slice(&(max_cycles as u64).to_be_bytes());
+        }
+        None => bytes.push(0),
+    }
+
+    keccak256(bytes).to_string()
+}
+
+/// Fingerprint of the saved cycles, `None` if none were saved yet
+async fn saved_fingerprint(ctx: &AppContext) -> Result<Option<String>> {
+    let mut conn = ctx.db.get().await?;
+    let fingerprint = cycles_fingerprint::table
+        .select(cycles_fingerprint::fingerprint)
+        .first::<String>(&mut conn)
+        .await
+        .optional()?;
+    Ok(fingerprint)
+}
+
+/// Find all cycles for the given pairs with `config` and replace the `cycles` table contents with
+/// them, along with their `fingerprint`
+async fn sync(
+    ctx: &AppContext,
+    pairs: &[PairWithTokenAddresses],
+    config: WorldConfig,
+    fingerprint: &str,
+) -> Result<usize> {
+    let mut pair_ids = HashMap::with_capacity(pairs.len());
+    let mut pools = HashSet::with_capacity(pairs.len());
+    for pair in pairs {
+        // Ids are chain-qualified, so pairs of different chains never share a token and never
+        // end up in the same cycle
+        let chain_id = pair.chain_id as ChainId;
+        let (Ok(pool_id), Ok(token0), Ok(token1)) = (
+            PoolId::parse(chain_id, &pair.address),
+            TokenId::parse(chain_id, &pair.token0_address),
+            TokenId::parse(chain_id, &pair.token1_address),
+        ) else {
+            log::warn!(
+                "sync::cycles: Skipping pair {} with invalid addresses",
+                pair.id
+            );
+            continue;
+        };
+
+        pair_ids.insert(pool_id.clone(), pair.id);
+        // Fees do not change the cycle set
+        pools.insert(Pool::new(pool_id, token0, token1, None, None, DEFAULT_FEE));
+    }
+
+    if pools.is_empty() {
+        return Ok(0);
+    }
+
+    // This is the expensive part, it can take minutes on a large pair set
+    let world = World::new(&pools, config);
+
+    let new_cycles: Vec<NewCycle> = world
+        .cycle_vec
+        .iter()
+        .map(|cycle| {
+            let swaps = cycle
+                .swaps
+                .iter()
+                .map(|swap| {
+                    (
+                        pair_ids[&swap.id.pool_id],
+                        swap.id.direction == Direction::ZeroForOne,
+                    )
+                })
+                .collect::<Vec<_>>();
+            NewCycle::new(&swaps)
+        })
+        .collect();
+
+    let mut conn = ctx.db.get().await?;
+    conn.transaction::<_, diesel::result::Error, _>(|conn| {
+        async move {
+            diesel::delete(cycles::table).execute(conn).await?;
+            for batch in new_cycles.chunks(INSERT_BATCH_SIZE) {
+                diesel::insert_into(cycles::table)
+                    .values(batch)
+                    .execute(conn)
+                    .await?;
+            }
+            diesel::insert_into(cycles_fingerprint::table)
+                .values(cycles_fingerprint::fingerprint.eq(fingerprint))
+                .on_conflict(cycles_fingerprint::id)
+                .do_update()
+                .set(cycles_fingerprint::fingerprint.eq(fingerprint))
+                .execute(conn)
+                .await?;
+            Ok(())
+        }
+        .scope_boxed()
+    })
+    .await?;
+
+    Ok(world.cycle_vec.len())
+}
+
+/// All pairs that have both tokens, with the token addresses
+async fn pairs_with_tokens(ctx: &AppContext) -> Result<Vec<PairWithTokenAddresses>> {
+    let mut conn = ctx.db.get().await?;
+
+    let pairs = diesel::sql_query(
+        "SELECT pairs.id, pairs.chain_id, pairs.address,
+                token0.address AS token0_address, token1.address AS token1_address
+         FROM pairs
+         JOIN tokens token0 ON token0.id = pairs.token0_id
+         JOIN tokens token1 ON token1.id = pairs.token1_id
+         ORDER BY pairs.id",
+    )
+    .load::<PairWithTokenAddresses>(&mut conn)
+    .await?;
+
+    Ok(pairs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arb::test_helpers::token_id;
+
+    #[test]
+    fn test_fingerprint() {
+        let config = WorldConfig::default();
+        let unsafe_tokens = HashSet::from([token_id("A"), token_id("B")]);
+        let fingerprint = fingerprint(&[1, 2, 3], &unsafe_tokens, &config);
+
+        assert_eq!(
+            fingerprint,
+            super::fingerprint(
+                &[1, 2, 3],
+                &HashSet::from([token_id("B"), token_id("A")]),
+                &config
+            )
+        );
+        assert_ne!(
+            fingerprint,
+            super::fingerprint(&[1, 2, 4], &unsafe_tokens, &config)
+        );
+        assert_ne!(
+            fingerprint,
+            super::fingerprint(&[1, 2, 3], &HashSet::from([token_id("A")]), &config)
+        );
+        for other_config in [
+            WorldConfig::new(4, None, None).unwrap(),
+            WorldConfig::new(3, Some(vec![token_id("A")]), None).unwrap(),
+            WorldConfig::new(3, None, Some(10)).unwrap(),
+        ] {
+            assert_ne!(
+                fingerprint,
+                super::fingerprint(&[1, 2, 3], &unsafe_tokens, &other_config)
+            );
+        }
+
+        // Anchor tokens are a set as well, excluded tokens are hashed as the unsafe tokens
+        let config = WorldConfig::new(3, Some(vec![token_id("A"), token_id("B")]), None).unwrap();
+        assert_eq!(
+            super::fingerprint(&[1], &unsafe_tokens, &config),
+            super::fingerprint(
+                &[1],
+                &unsafe_tokens,
+                &WorldConfig::new(3, Some(vec![token_id("B"), token_id("A")]), None)
+                    .unwrap()
+                    .with_excluded_tokens(HashSet::from([token_id("C")]))
+            )
+        );
+    }
+}
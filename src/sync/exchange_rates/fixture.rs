@@ -0,0 +1,100 @@
+use std::collections::HashMap;
+use std::env;
+
+use alloy::primitives::{Address, ChainId};
+use eyre::{eyre, Result};
+use futures::future::BoxFuture;
+use futures::FutureExt;
+
+use super::{ExchangeRateSource, RateRequest};
+
+/// Name of the source in `EXCHANGE_RATE_SOURCES` and `tokens.exchange_rate_source`
+pub const NAME: &str = "fixture";
+
+/// Path of the JSON file with the rates of the fixture source
+const FIXTURE_PATH_ENV: &str = "EXCHANGE_RATE_FIXTURE";
+
+/// Exchange rates from a fixed table, for tests and offline runs
+#[derive(Debug, Clone, Default)]
+pub struct FixtureSource {
+    /// USD per whole token, on any chain
+    rates: HashMap<Address, f64>,
+}
+
+impl FixtureSource {
+    pub fn new(rates: HashMap<Address, f64>) -> Self {
+        Self { rates }
+    }
+
+    /// Parses a JSON object of token addresses to USD per whole token, e.g.
+    /// `{"0x4200000000000000000000000000000000000006": 2000.0}`
+    ///
+    /// # Errors
+    /// * If the JSON is malformed
+    pub fn from_json(json: &str) -> Result<Self> {
+        Ok(Self::new(serde_json::from_str(json)?))
+    }
+
+    /// Loads the JSON file at `EXCHANGE_RATE_FIXTURE`, see `from_json`
+    ///
+    /// # Errors
+    /// * If `EXCHANGE_RATE_FIXTURE` is not set
+    /// * If the file cannot be read or is malformed
+    pub fn from_env() -> Result<Self> {
+        let path = env::var(FIXTURE_PATH_ENV)
+            .map_err(|_| eyre!("{FIXTURE_PATH_ENV} not found in environment variables"))?;
+        Self::from_json(&std::fs::read_to_string(path)?)
+    }
+}
+
+impl ExchangeRateSource for FixtureSource {
+    fn name(&self) -> &'static str {
+        NAME
+    }
+
+    fn fetch<'a>(
+        &'a self,
+        _chain_id: ChainId,
+        tokens: &'a [RateRequest],
+    ) -> BoxFuture<'a, Result<HashMap<Address, f64>>> {
+        let rates = tokens
+            .iter()
+            .filter_map(|token| Some((token.address, *self.rates.get(&token.address)?)))
+            .collect();
+        async move { Ok(rates) }.boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::constants::{BASE_CHAIN_ID, WETH};
+
+    #[test]
+    fn test_from_json() {
+        let source =
+            FixtureSource::from_json(r#"{"0x4200000000000000000000000000000000000006": 2000.5}"#)
+                .unwrap();
+        assert_eq!(source.rates, HashMap::from([(WETH, 2000.5)]));
+
+        assert!(FixtureSource::from_json(r#"{"WETH": 2000.5}"#).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_fetch() {
+        let source = FixtureSource::new(HashMap::from([(WETH, 2000.0)]));
+        let tokens = [
+            RateRequest {
+                address: WETH,
+                decimals: Some(18),
+            },
+            RateRequest {
+                address: Address::ZERO,
+                decimals: None,
+            },
+        ];
+
+        let rates = source.fetch(BASE_CHAIN_ID, &tokens).await.unwrap();
+        assert_eq!(rates, HashMap::from([(WETH, 2000.0)]));
+    }
+}
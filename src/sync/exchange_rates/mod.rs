@@ -0,0 +1,394 @@
+pub mod fixture;
+pub mod moralis;
+pub mod pools;
+
+pub use fixture::FixtureSource;
+pub use moralis::MoralisSource;
+pub use pools::PoolsSource;
+
+use crate::models::token::PriceSupportStatus;
+use crate::schemas::tokens;
+use crate::utils::app_context::AppContext;
+use alloy::primitives::{Address, ChainId};
+use bigdecimal::BigDecimal;
+use chrono::{Duration, Utc};
+use diesel::prelude::*;
+use diesel_async::RunQueryDsl;
+use eyre::{bail, Result};
+use futures::future::BoxFuture;
+use itertools::Itertools;
+use log;
+use std::collections::HashMap;
+use std::env;
+use std::str::FromStr;
+
+const BATCH_SIZE: i64 = 100; // Number of tokens to process in each batch
+
+/// Comma-separated source names in priority order, e.g. `moralis,pools`
+const SOURCES_ENV: &str = "EXCHANGE_RATE_SOURCES";
+
+/// Sources used when `EXCHANGE_RATE_SOURCES` is not set
+const DEFAULT_SOURCES: &str = "moralis,pools";
+
+/// Token whose exchange rate is requested
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateRequest {
+    pub address: Address,
+    /// `None` if the decimals of the token are not known (yet)
+    pub decimals: Option<u8>,
+}
+
+/// Source of USD exchange rates of tokens
+pub trait ExchangeRateSource: Send + Sync {
+    /// Stored in `tokens.exchange_rate_source` with the rates of the source
+    fn name(&self) -> &'static str;
+
+    /// USD per whole token of the `tokens` the source has a rate for, by address. Tokens the
+    /// source does not know are left out.
+    fn fetch<'a>(
+        &'a self,
+        chain_id: ChainId,
+        tokens: &'a [RateRequest],
+    ) -> BoxFuture<'a, Result<HashMap<Address, f64>>>;
+}
+
+/// Exchange rate of a token and the source that produced it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ExchangeRate {
+    /// USD per whole token
+    pub usd: f64,
+    pub source: &'static str,
+}
+
+/// Exchange rates the sources had, and whether every source that was asked answered
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FetchedRates {
+    /// Rates by token address
+    pub rates: HashMap<Address, ExchangeRate>,
+    /// Whether a source failed. A token without a rate may then have one the source did not
+    /// give.
+    pub source_failed: bool,
+}
+
+#[derive(QueryableByName, Debug)]
+struct TokenToUpdate {
+    #[diesel(sql_type = diesel::sql_types::Integer)]
+    id: i32,
+    #[diesel(sql_type = diesel::sql_types::BigInt)]
+    chain_id: i64,
+    #[diesel(sql_type = diesel::sql_types::Text)]
+    address: String,
+    #[diesel(sql_type = diesel::sql_types::Nullable<diesel::sql_types::Integer>)]
+    decimals: Option<i32>,
+}
+
+/// Main function that continuously syncs token exchange rates
+/// Asks the sources configured by `EXCHANGE_RATE_SOURCES` in order and updates the tokens table
+pub async fn exchange_rates(ctx: &AppContext) -> Result<()> {
+    log::info!("sync::exchange_rates: Starting exchange rates sync service");
+
+    let sources = sources_from_env(ctx)?;
+    log::info!(
+        "sync::exchange_rates: Using sources {}",
+        sources.iter().map(|source| source.name()).join(", ")
+    );
+
+    loop {
+        log::info!("sync::exchange_rates: Starting sync iteration");
+        match sync(ctx, &sources, BATCH_SIZE).await {
+            Ok(count) => {
+                log::info!(
+                    "sync::exchange_rates: Completed sync iteration. Updated exchange rates for {} tokens",
+                    count
+                );
+            }
+            Err(e) => {
+                log::error!("sync::exchange_rates: Error syncing exchange rates: {}", e);
+            }
+        }
+
+        log::info!("sync::exchange_rates: Sleeping before next sync iteration");
+        // Sleep before the next sync
+        tokio::time::sleep(tokio::time::Duration::from_secs(10)).await;
+    }
+}
+
+/// Builds the sources named by `EXCHANGE_RATE_SOURCES`, in its order. Moralis is skipped with a
+/// warning if its API key is not set, so the other sources still run offline.
+///
+/// # Errors
+/// * If a source name is unknown
+/// * If the fixture source cannot be loaded
+/// * If no source is left
+pub fn sources_from_env(ctx: &AppContext) -> Result<Vec<Box<dyn ExchangeRateSource>>> {
+    let names = env::var(SOURCES_ENV).unwrap_or_else(|_| DEFAULT_SOURCES.to_string());
+
+    let mut sources: Vec<Box<dyn ExchangeRateSource>> = Vec::new();
+    for name in parse_source_names(&names) {
+        match name.as_str() {
+            moralis::NAME => match MoralisSource::from_env() {
+                Ok(source) => sources.push(Box::new(source)),
+                Err(e) => log::warn!("sync::exchange_rates: Skipping the Moralis source: {e}"),
+            },
+            pools::NAME => sources.push(Box::new(PoolsSource::new(ctx.db.clone()))),
+            fixture::NAME => sources.push(Box::new(FixtureSource::from_env()?)),
+            _ => bail!("Unknown exchange rate source {name} in {SOURCES_ENV}"),
+        }
+    }
+
+    if sources.is_empty() {
+        bail!("No exchange rate source available, set {SOURCES_ENV}");
+    }
+    Ok(sources)
+}
+
+/// Lowercase source names of a comma-separated list, without empty entries
+fn parse_source_names(names: &str) -> Vec<String> {
+    names
+        .split(',')
+        .map(|name| name.trim().to_lowercase())
+        .filter(|name| !name.is_empty())
+        .collect()
+}
+
+/// Asks the sources in priority order, each for the tokens the sources before it had no rate for.
+/// A failing source is logged and skipped. Rates that are not positive are ignored.
+///
+/// # Returns
+/// The exchange rates of the tokens any source had a rate for, and whether a source failed
+pub async fn fetch_exchange_rates(
+    sources: &[Box<dyn ExchangeRateSource>],
+    chain_id: ChainId,
+    tokens: &[RateRequest],
+) -> FetchedRates {
+    let mut rates = HashMap::with_capacity(tokens.len());
+    let mut source_failed = false;
+
+    for source in sources {
+        let missing: Vec<RateRequest> = tokens
+            .iter()
+            .filter(|token| !rates.contains_key(&token.address))
+            .copied()
+            .collect();
+        if missing.is_empty() {
+            break;
+        }
+
+        match source.fetch(chain_id, &missing).await {
+            Ok(source_rates) => {
+                for (address, usd) in source_rates {
+                    if usd.is_finite() && usd > 0.0 {
+                        rates.entry(address).or_insert(ExchangeRate {
+                            usd,
+                            source: source.name(),
+                        });
+                    }
+                }
+            }
+            Err(e) => {
+                log::error!(
+                    "sync::exchange_rates: Source {} failed on chain {}: {}",
+                    source.name(),
+                    chain_id,
+                    e
+                );
+                source_failed = true;
+            }
+        }
+    }
+
+    FetchedRates {
+        rates,
+        source_failed,
+    }
+}
+
+/// Sync exchange rates for a batch of tokens
+/// Updates tokens that:
+/// 1. Don't have a price_support_status value, OR
+/// 2. Have a last_updated timestamp that's more than 24 hours old
+///
+/// A token is only marked UNSUPPORTED when every source answered without a rate for it. If a
+/// source failed, the tokens without a rate are left as they are and asked again next time.
+async fn sync(
+    ctx: &AppContext,
+    sources: &[Box<dyn ExchangeRateSource>],
+    limit: i64,
+) -> Result<usize> {
+    let mut conn = ctx.db.get().await?;
+
+    // Calculate the timestamp for 24 hours ago
+    let one_day_ago = Utc::now().naive_utc() - Duration::days(1);
+
+    let sql_query = "SELECT id, chain_id, address, decimals FROM tokens
+                 WHERE price_support_status IS NULL
+                 OR (updated_last IS NOT NULL AND updated_last < $1)
+                 LIMIT $2";
+
+    let tokens: Vec<TokenToUpdate> = diesel::sql_query(sql_query)
+        .bind::<diesel::sql_types::Timestamp, _>(one_day_ago)
+        .bind::<diesel::sql_types::BigInt, _>(limit)
+        .load(&mut conn)
+        .await?;
+
+    log::info!(
+        "sync::exchange_rates: Found {} tokens that need updates",
+        tokens.len()
+    );
+
+    let mut updated_count = 0;
+    // Sources are asked per chain
+    for (chain_id, tokens) in tokens
+        .iter()
+        .into_group_map_by(|token| token.chain_id as ChainId)
+    {
+        let mut token_ids = HashMap::with_capacity(tokens.len());
+        let mut requests = Vec::with_capacity(tokens.len());
+        for token in tokens {
+            let Ok(address) = Address::from_str(&token.address) else {
+                log::warn!(
+                    "sync::exchange_rates: Skipping token {} with invalid address",
+                    token.id
+                );
+                continue;
+            };
+            token_ids.insert(address, token.id);
+            requests.push(RateRequest {
+                address,
+                decimals: token
+                    .decimals
+                    .and_then(|decimals| u8::try_from(decimals).ok()),
+            });
+        }
+
+        let FetchedRates {
+            rates,
+            source_failed,
+        } = fetch_exchange_rates(sources, chain_id, &requests).await;
+        let now_timestamp = Utc::now().naive_utc();
+
+        for (address, token_id) in token_ids {
+            let updated = match rates.get(&address) {
+                Some(rate) => {
+                    let price_decimal = BigDecimal::from_str(&rate.usd.to_string())?;
+                    log::info!(
+                        "sync::exchange_rates: Updated exchange rate for token {}: ${} from {}",
+                        address,
+                        rate.usd,
+                        rate.source
+                    );
+                    diesel::update(tokens::table.find(token_id))
+                        .set((
+                            tokens::exchange_rate.eq(price_decimal),
+                            tokens::updated_last.eq(now_timestamp),
+                            tokens::price_support_status.eq(PriceSupportStatus::Supported),
+                            tokens::exchange_rate_source.eq(rate.source),
+                        ))
+                        .execute(&mut conn)
+                        .await?
+                }
+                // A failed source may have a price for the token
+                None if source_failed => {
+                    log::info!(
+                        "sync::exchange_rates: Leaving token {address} without a rate, a source failed"
+                    );
+                    0
+                }
+                // No source has a price for the token
+                None => {
+                    log::info!("sync::exchange_rates: Marking token {address} as UNSUPPORTED");
+                    diesel::update(tokens::table.find(token_id))
+                        .set((
+                            tokens::updated_last.eq(now_timestamp),
+                            tokens::price_support_status.eq(PriceSupportStatus::Unsupported),
+                        ))
+                        .execute(&mut conn)
+                        .await?
+                }
+            };
+            updated_count += updated;
+        }
+    }
+
+    log::info!(
+        "sync::exchange_rates: Completed sync with {} tokens updated",
+        updated_count
+    );
+    Ok(updated_count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arb::test_helpers::address_from_str;
+    use crate::utils::constants::BASE_CHAIN_ID;
+    use eyre::eyre;
+    use futures::FutureExt;
+
+    /// Source whose requests always fail
+    struct FailingSource;
+
+    impl ExchangeRateSource for FailingSource {
+        fn name(&self) -> &'static str {
+            "failing"
+        }
+
+        fn fetch<'a>(
+            &'a self,
+            _chain_id: ChainId,
+            _tokens: &'a [RateRequest],
+        ) -> BoxFuture<'a, Result<HashMap<Address, f64>>> {
+            async { Err(eyre!("Offline")) }.boxed()
+        }
+    }
+
+    fn request(address: &str) -> RateRequest {
+        RateRequest {
+            address: address_from_str(address),
+            decimals: Some(18),
+        }
+    }
+
+    #[test]
+    fn test_parse_source_names() {
+        assert_eq!(
+            parse_source_names(" Moralis, pools,,"),
+            vec!["moralis".to_string(), "pools".to_string()]
+        );
+        assert!(parse_source_names("").is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_fetch_exchange_rates() {
+        let sources: Vec<Box<dyn ExchangeRateSource>> = vec![
+            Box::new(FailingSource),
+            Box::new(FixtureSource::new(HashMap::from([
+                (address_from_str("A"), 2.0),
+                (address_from_str("B"), 0.0),
+            ]))),
+            Box::new(FixtureSource::new(HashMap::from([
+                (address_from_str("A"), 3.0),
+                (address_from_str("B"), 4.0),
+            ]))),
+        ];
+        let fetched = fetch_exchange_rates(
+            &sources,
+            BASE_CHAIN_ID,
+            &[request("A"), request("B"), request("C")],
+        )
+        .await;
+        let rates = &fetched.rates;
+
+        // The first source with a rate wins, rates that are not positive fall through
+        assert_eq!(rates[&address_from_str("A")].usd, 2.0);
+        assert_eq!(rates[&address_from_str("B")].usd, 4.0);
+        assert_eq!(rates[&address_from_str("A")].source, fixture::NAME);
+        assert!(!rates.contains_key(&address_from_str("C")));
+        // C may have a rate at the failed source
+        assert!(fetched.source_failed);
+
+        let fetched = fetch_exchange_rates(&sources[1..], BASE_CHAIN_ID, &[request("C")]).await;
+        assert!(fetched.rates.is_empty());
+        assert!(!fetched.source_failed);
+    }
+}
@@ -0,0 +1,178 @@
+use std::collections::HashMap;
+use std::env;
+use std::str::FromStr;
+
+use alloy::primitives::{Address, ChainId};
+use eyre::{eyre, Result};
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use serde::{Deserialize, Serialize};
+
+use super::{ExchangeRateSource, RateRequest};
+use crate::utils::constants::BASE_CHAIN_ID;
+
+/// Name of the source in `EXCHANGE_RATE_SOURCES` and `tokens.exchange_rate_source`
+pub const NAME: &str = "moralis";
+
+const MORALIS_API_URL: &str = "https://deep-index.moralis.io/api/v2.2/erc20/prices";
+
+#[derive(Debug, Serialize)]
+struct TokenRequest {
+    exchange: Option<String>,
+    token_address: String,
+}
+
+#[derive(Debug, Serialize)]
+struct PriceRequest {
+    tokens: Vec<TokenRequest>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenPrice {
+    #[serde(rename = "tokenAddress")]
+    token_address: String,
+    #[serde(rename = "usdPrice")]
+    usd_price: f64,
+}
+
+/// Exchange rates from the Moralis ERC20 prices API. Only Base tokens are priced.
+pub struct MoralisSource {
+    client: reqwest::Client,
+    api_key: String,
+    /// Moralis name of the Base chain
+    chain: String,
+}
+
+impl MoralisSource {
+    /// Reads `MORALIS_API_KEY` and `MORALIS_API_BASE_CHAIN_ID`
+    ///
+    /// # Errors
+    /// * If an environment variable is not set
+    /// * If the HTTP client cannot be built
+    pub fn from_env() -> Result<Self> {
+        let api_key = env::var("MORALIS_API_KEY")
+            .map_err(|_| eyre!("MORALIS_API_KEY not found in environment variables"))?;
+        let chain = env::var("MORALIS_API_BASE_CHAIN_ID")
+            .map_err(|_| eyre!("MORALIS_API_BASE_CHAIN_ID not found in environment variables"))?;
+
+        // Create HTTP client
+        let client = reqwest::Client::builder()
+            .timeout(std::time::Duration::from_secs(30))
+            .build()?;
+
+        Ok(Self {
+            client,
+            api_key,
+            chain,
+        })
+    }
+
+    async fn fetch_prices(&self, tokens: &[RateRequest]) -> Result<HashMap<Address, f64>> {
+        // Create the request payload
+        let request_payload = PriceRequest {
+            tokens: tokens
+                .iter()
+                .map(|token| TokenRequest {
+                    exchange: Some("uniswapv2".to_string()),
+                    token_address: token.address.to_string().to_lowercase(),
+                })
+                .collect(),
+        };
+
+        log::info!(
+            "sync::exchange_rates: Sending request for {} tokens to Moralis API (URL: {})",
+            tokens.len(),
+            MORALIS_API_URL
+        );
+        let response = self
+            .client
+            .post(MORALIS_API_URL)
+            .header("accept", "application/json")
+            .header("X-API-Key", &self.api_key)
+            .header("content-type", "application/json")
+            .query(&[("chain", &self.chain)])
+            .json(&request_payload)
+            .send()
+            .await
+            .map_err(|e| eyre!("Failed to send request to Moralis API: {}", e))?;
+
+        // Store the status code before consuming the response
+        let status = response.status();
+        if !status.is_success() {
+            let error_text = response.text().await?;
+            return Err(eyre!("Moralis API error: {} - {}", status, error_text));
+        }
+
+        let response_text = response.text().await?;
+        log::debug!("sync::exchange_rates: Response body: {}", response_text);
+        let prices = parse_prices(&response_text)?;
+
+        let missing_count = tokens.len().saturating_sub(prices.len());
+        if missing_count > 0 {
+            log::warn!(
+                "sync::exchange_rates: Moralis is missing prices for {missing_count} tokens"
+            );
+        }
+        Ok(prices)
+    }
+}
+
+impl ExchangeRateSource for MoralisSource {
+    fn name(&self) -> &'static str {
+        NAME
+    }
+
+    fn fetch<'a>(
+        &'a self,
+        chain_id: ChainId,
+        tokens: &'a [RateRequest],
+    ) -> BoxFuture<'a, Result<HashMap<Address, f64>>> {
+        async move {
+            if chain_id != BASE_CHAIN_ID || tokens.is_empty() {
+                return Ok(HashMap::new());
+            }
+            self.fetch_prices(tokens).await
+        }
+        .boxed()
+    }
+}
+
+/// USD prices of a Moralis response by token address. Prices with a malformed address are skipped.
+fn parse_prices(response_text: &str) -> Result<HashMap<Address, f64>> {
+    let prices: Vec<TokenPrice> = serde_json::from_str(response_text).map_err(|e| {
+        eyre!(
+            "Failed to parse Moralis API response: {} - Response: {}",
+            e,
+            response_text
+        )
+    })?;
+
+    Ok(prices
+        .into_iter()
+        .filter_map(|price| {
+            Address::from_str(&price.token_address)
+                .ok()
+                .map(|address| (address, price.usd_price))
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::constants::WETH;
+
+    #[test]
+    fn test_parse_prices() {
+        let prices = parse_prices(
+            r#"[
+                {"tokenAddress": "0x4200000000000000000000000000000000000006", "usdPrice": 2211.9},
+                {"tokenAddress": "not an address", "usdPrice": 1.0}
+            ]"#,
+        )
+        .unwrap();
+        assert_eq!(prices, HashMap::from([(WETH, 2211.9)]));
+
+        assert!(parse_prices("{}").is_err());
+    }
+}
@@ -0,0 +1,128 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use alloy::primitives::{Address, ChainId};
+use diesel_async::pooled_connection::deadpool::Pool;
+use diesel_async::AsyncPgConnection;
+use eyre::Result;
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use tokio::sync::Mutex;
+
+use super::{ExchangeRateSource, RateRequest};
+use crate::arb::pricing::Prices;
+use crate::arb::token::TokenId;
+use crate::sync::usd::derive_prices;
+
+/// Name of the source in `EXCHANGE_RATE_SOURCES` and `tokens.exchange_rate_source`
+pub const NAME: &str = "pools";
+
+/// Prices are derived from all pairs of a chain, so they are reused for this long
+const PRICES_TTL: Duration = Duration::from_secs(60);
+
+/// Exchange rates derived from the reserves of the pairs in the database, see `arb::pricing`.
+/// Needs no network access.
+pub struct PoolsSource {
+    db: Pool<AsyncPgConnection>,
+    /// Prices of each chain and when they were derived
+    prices: Mutex<HashMap<ChainId, (Instant, Arc<Prices>)>>,
+}
+
+impl PoolsSource {
+    pub fn new(db: Pool<AsyncPgConnection>) -> Self {
+        Self {
+            db,
+            prices: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Prices of the chain, derived again once they are older than `PRICES_TTL`
+    async fn prices(&self, chain_id: ChainId) -> Result<Arc<Prices>> {
+        let mut cache = self.prices.lock().await;
+        if let Some((derived_at, prices)) = cache.get(&chain_id) {
+            if derived_at.elapsed() < PRICES_TTL {
+                return Ok(Arc::clone(prices));
+            }
+        }
+
+        let mut conn = self.db.get().await?;
+        let prices = Arc::new(derive_prices(&mut conn, chain_id).await?);
+        cache.insert(chain_id, (Instant::now(), Arc::clone(&prices)));
+        Ok(prices)
+    }
+}
+
+impl ExchangeRateSource for PoolsSource {
+    fn name(&self) -> &'static str {
+        NAME
+    }
+
+    fn fetch<'a>(
+        &'a self,
+        chain_id: ChainId,
+        tokens: &'a [RateRequest],
+    ) -> BoxFuture<'a, Result<HashMap<Address, f64>>> {
+        async move {
+            let prices = self.prices(chain_id).await?;
+            Ok(whole_token_rates(&prices, chain_id, tokens))
+        }
+        .boxed()
+    }
+}
+
+/// USD per whole token of the tokens with a price and known decimals
+fn whole_token_rates(
+    prices: &Prices,
+    chain_id: ChainId,
+    tokens: &[RateRequest],
+) -> HashMap<Address, f64> {
+    tokens
+        .iter()
+        .filter_map(|token| {
+            let price = prices.get(&TokenId::new(chain_id, token.address))?;
+            Some((
+                token.address,
+                price * 10_f64.powi(i32::from(token.decimals?)),
+            ))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arb::test_helpers::{address_from_str, pool, token_id};
+    use crate::utils::constants::BASE_CHAIN_ID;
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_whole_token_rates() {
+        // A is a 6 decimals stablecoin, B has 18 decimals and is worth $2000
+        let pools = HashSet::from([pool(
+            "F1",
+            "A",
+            "B",
+            2_000_000_000,
+            1_000_000_000_000_000_000,
+        )]);
+        let prices = Prices::derive(&pools, &HashMap::from([(token_id("A"), 1e-6)]), 1_000.0);
+        let request = |address: &str, decimals| RateRequest {
+            address: address_from_str(address),
+            decimals,
+        };
+
+        let rates = whole_token_rates(
+            &prices,
+            BASE_CHAIN_ID,
+            &[
+                request("A", Some(6)),
+                request("B", Some(18)),
+                request("C", Some(18)),
+            ],
+        );
+        assert!((rates[&address_from_str("A")] - 1.0).abs() < 1e-9);
+        assert!((rates[&address_from_str("B")] - 2_000.0).abs() < 1e-9);
+        assert!(!rates.contains_key(&address_from_str("C")));
+    }
+}
@@ -1,7 +1,7 @@
 use crate::models::pair::Pair;
 use crate::schemas::{factories, pairs};
 use crate::utils::app_context::AppContext;
-use alloy::primitives::{Address, Bytes};
+use alloy::primitives::{Address, Bytes, ChainId};
 use alloy::providers::MULTICALL3_ADDRESS;
 use alloy::sol;
 use alloy::sol_types::{SolCall, SolValue};
@@ -32,10 +32,21 @@ pub async fn factories(ctx: &AppContext) -> Result<()> {
 }
 
 async fn sync(ctx: &AppContext, limit: i64) -> Result<usize> {
+    let mut synced_pairs_count = 0;
+    for &chain_id in ctx.providers.keys() {
+        synced_pairs_count += sync_chain(ctx, chain_id, limit).await?;
+    }
+
+    Ok(synced_pairs_count)
+}
+
+/// Sync factories of the pairs on one chain, a multicall cannot span chains
+async fn sync_chain(ctx: &AppContext, chain_id: ChainId, limit: i64) -> Result<usize> {
     let mut conn = ctx.db.get().await?;
 
     // Pairs missing factory_id
     let pairs: Vec<Pair> = pairs::table
+        .filter(pairs::chain_id.eq(chain_id as i64))
         .filter(pairs::factory_id.is_null())
         .select(Pair::as_select())
         .limit(limit)
@@ -43,7 +54,7 @@ async fn sync(ctx: &AppContext, limit: i64) -> Result<usize> {
         .await?;
 
     // Multicall3 instance
-    let multicall = IMulticall3::new(MULTICALL3_ADDRESS, &ctx.base_provider);
+    let multicall = IMulticall3::new(MULTICALL3_ADDRESS, ctx.provider(chain_id)?);
 
     // Create calls for each pair
     let calls: Vec<IMulticall3::Call3> = pairs
@@ -66,8 +77,11 @@ async fn sync(ctx: &AppContext, limit: i64) -> Result<usize> {
             {
                 // Upsert factory_address to factories table
                 let factory_id = diesel::insert_into(factories::table)
-                    .values(factories::address.eq(factory_address.to_string()))
-                    .on_conflict(factories::address)
+                    .values((
+                        factories::chain_id.eq(chain_id as i64),
+                        factories::address.eq(factory_address.to_string()),
+                    ))
+                    .on_conflict((factories::chain_id, factories::address))
                     .do_update()
                     .set(factories::address.eq(factory_address.to_string()))
                     .returning(factories::id)
@@ -52,9 +52,10 @@ async fn sync(ctx: &AppContext) -> Result<usize> {
     }
 
     let factory = &mut results[0];
+    let provider = ctx.provider(factory.chain_id())?;
 
     // Create factory contract instance
-    let factory_contract = IUniswapV2Factory::new(factory.address(), &ctx.base_provider);
+    let factory_contract = IUniswapV2Factory::new(factory.address(), provider);
 
     // Get total number of pairs
     let pairs_length = match factory_contract.allPairsLength().call().await {
@@ -86,7 +87,7 @@ async fn sync(ctx: &AppContext) -> Result<usize> {
     }
 
     // Multicall3 instance
-    let multicall = IMulticall3::new(MULTICALL3_ADDRESS, &ctx.base_provider);
+    let multicall = IMulticall3::new(MULTICALL3_ADDRESS, provider);
 
     // Arbitrary number, can be changed
     let multicall_batch_size = 100;
@@ -134,10 +135,11 @@ async fn sync(ctx: &AppContext) -> Result<usize> {
             // Upsert pair into database
             diesel::insert_into(pairs::table)
                 .values((
+                    pairs::chain_id.eq(factory.chain_id() as i64),
                     pairs::address.eq(pair_address.to_string()),
                     pairs::factory_id.eq(factory.id()),
                 ))
-                .on_conflict(pairs::address)
+                .on_conflict((pairs::chain_id, pairs::address))
                 .do_update()
                 .set(pairs::factory_id.eq(factory.id()))
                 .execute(&mut conn)
@@ -0,0 +1,436 @@
+use std::collections::HashMap;
+
+use alloy::primitives::{address, Address, Bytes, B256, U256};
+use alloy::providers::Provider;
+use alloy::rpc::types::state::{AccountOverride, StateOverride};
+use alloy::sol;
+use diesel::{
+    BoolExpressionMethods, ExpressionMethods, OptionalExtension, PgSortExpressionMethods, QueryDsl,
+    SelectableHelper,
+};
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use eyre::Result;
+
+use crate::arb::solidly_stable::{SolidlyStable, DEFAULT_STABLE_FEE};
+use crate::arb::swap::DEFAULT_FEE;
+use crate::arb::swap_quote::uniswap_v2_amount_out;
+use crate::models::factory::Factory;
+use crate::models::pair::Pair;
+use crate::schemas::{factories, pairs, tokens};
+use crate::utils::app_context::{AppContext, EthereumProvider};
+
+sol! {
+    #[sol(rpc)]
+    "contracts/src/interfaces/IUniswapV2Pair.sol"
+}
+
+sol! {
+    #[sol(rpc)]
+    "contracts/src/interfaces/IERC20.sol"
+}
+
+/// How often to retry factories whose fee could not be detected
+const CHECK_INTERVAL_SECS: u64 = 600;
+
+/// Highest fee we probe for, in basis points (10%)
+const MAX_FEE: u32 = 1_000;
+
+/// Probe swaps use this fraction of the pair balance as `amount_in`. Small enough not to hit the
+/// pair's liquidity limits, large enough to tell 1 basis point apart.
+const PROBE_AMOUNT_DIVISOR: u64 = 1_000;
+
+/// Storage slots searched for the reserves of a pair
+const MAX_RESERVES_SLOT: u64 = 32;
+
+/// Recipient of probe swaps, must not be one of the pair tokens
+const PROBE_RECIPIENT: Address = address!("0x000000000000000000000000000000000000dEaD");
+
+/// Where a pair keeps its reserves
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReservesLayout {
+    /// `UniswapV2Pair` and its forks: `reserve0`, `reserve1` and `blockTimestampLast` packed in
+    /// one slot
+    Packed(U256),
+    /// Solidly pools such as Aerodrome's: `reserve0` and `reserve1` in consecutive `uint256` slots
+    Separate(U256),
+}
+
+/// What probing a pair found
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Probe {
+    /// The lowest fee the pair accepts, in basis points
+    Fee(u32),
+    /// The pair does not accept even `MAX_FEE` (non-standard pair or token)
+    Rejected,
+    /// The reserves are not in the first `MAX_RESERVES_SLOT` slots of the pair, so they cannot be
+    /// overridden
+    UnknownLayout,
+}
+
+/// Sync factory swap fees
+/// Detects the fees of each factory by simulating swaps against its largest pair of each kind.
+/// Solidly factories charge stable and volatile pools different fees, so they are detected and
+/// stored separately.
+pub async fn fees(ctx: &AppContext) -> Result<()> {
+    log::info!("sync::fees: Starting fees sync...");
+
+    loop {
+        let detected_fees_count = sync(ctx).await?;
+        log::info!("sync::fees: Detected {} factory fees", detected_fees_count);
+
+        tokio::time::sleep(tokio::time::Duration::from_secs(CHECK_INTERVAL_SECS)).await;
+    }
+}
+
+async fn sync(ctx: &AppContext) -> Result<usize> {
+    let mut conn = ctx.db.get().await?;
+
+    // Factories with an unknown fee for either kind of pool
+    let mut factories: Vec<Factory> = factories::table
+        .filter(factories::fee.is_null().or(factories::stable_fee.is_null()))
+        .select(Factory::as_select())
+        .load(&mut conn)
+        .await?;
+
+    let mut detected_fees_count = 0;
+    for factory in &mut factories {
+        for stable in [false, true] {
+            let fee = if stable {
+                factory.stable_fee()
+            } else {
+                factory.fee()
+            };
+            if fee.is_none() && sync_fee(ctx, &mut conn, factory, stable).await? {
+                detected_fees_count += 1;
+            }
+        }
+    }
+
+    Ok(detected_fees_count)
+}
+
+/// Detects and stores the fee of the stable or the volatile pools of a factory. Returns whether a
+/// fee was stored.
+async fn sync_fee(
+    ctx: &AppContext,
+    conn: &mut AsyncPgConnection,
+    factory: &mut Factory,
+    stable: bool,
+) -> Result<bool> {
+    let kind = if stable { "stable" } else { "volatile" };
+
+    // The largest pair of the factory is the least likely to have exotic tokens
+    let pair: Option<Pair> = pairs::table
+        .filter(pairs::factory_id.eq(factory.id()))
+        .filter(pairs::stable.eq(stable))
+        .filter(pairs::reserve0.is_not_null())
+        .order(pairs::usd.desc().nulls_last())
+        .select(Pair::as_select())
+        .first(conn)
+        .await
+        .optional()?;
+
+    let Some(pair) = pair else {
+        log::debug!(
+            "sync::fees: Factory {} has no {} pairs with reserves",
+            factory.address(),
+            kind
+        );
+        return Ok(false);
+    };
+
+    let stable_curve = if stable {
+        let Some(curve) = stable_curve(conn, &pair).await? else {
+            log::debug!(
+                "sync::fees: Stable pair {} has tokens without decimals",
+                pair.address()
+            );
+            return Ok(false);
+        };
+        Some(curve)
+    } else {
+        None
+    };
+
+    let provider = match ctx.provider(pair.chain_id()) {
+        Ok(provider) => provider,
+        Err(e) => {
+            log::warn!("sync::fees: Skipping pair {}: {e}", pair.address());
+            return Ok(false);
+        }
+    };
+    let fee = match detect_fee(provider, pair.address(), stable_curve).await {
+        Ok(Probe::Fee(fee)) => {
+            log::info!(
+                "sync::fees: Factory {} {} fee is {} bps",
+                factory.address(),
+                kind,
+                fee
+            );
+            fee
+        }
+        Ok(Probe::UnknownLayout) => {
+            // The pair cannot be probed, the default is right for most factories
+            let fee = default_fee(stable);
+            log::warn!(
+                "sync::fees: Reserves of pair {} not found, using the default {} fee of {} bps for factory {}",
+                pair.address(),
+                kind,
+                fee,
+                factory.address()
+            );
+            fee
+        }
+        Ok(Probe::Rejected) => {
+            log::warn!(
+                "sync::fees: Could not detect {} fee of factory {} using pair {}",
+                kind,
+                factory.address(),
+                pair.address()
+            );
+            return Ok(false);
+        }
+        Err(e) => {
+            log::error!("sync::fees: Failed to probe pair {}: {}", pair.address(), e);
+            return Ok(false);
+        }
+    };
+
+    factory.update_fee(conn, stable, fee.try_into()?).await?;
+    Ok(true)
+}
+
+/// Default fee of the pool kind, in basis points
+const fn default_fee(stable: bool) -> u32 {
+    if stable {
+        DEFAULT_STABLE_FEE
+    } else {
+        DEFAULT_FEE
+    }
+}
+
+/// The stable curve of a pair from the decimals of its tokens, `None` if they are unknown
+async fn stable_curve(conn: &mut AsyncPgConnection, pair: &Pair) -> Result<Option<SolidlyStable>> {
+    let (Some(token0_id), Some(token1_id)) = (pair.token0_id(), pair.token1_id()) else {
+        return Ok(None);
+    };
+
+    let decimals: HashMap<i32, Option<i32>> = tokens::table
+        .filter(tokens::id.eq_any([token0_id, token1_id]))
+        .select((tokens::id, tokens::decimals))
+        .load::<(i32, Option<i32>)>(conn)
+        .await?
+        .into_iter()
+        .collect();
+
+    let decimals = |token_id| {
+        decimals
+            .get(&token_id)
+            .copied()
+            .flatten()
+            .and_then(|decimals| u8::try_from(decimals).ok())
+    };
+    Ok(decimals(token0_id)
+        .zip(decimals(token1_id))
+        .and_then(|(decimals0, decimals1)| SolidlyStable::new(decimals0, decimals1).ok()))
+}
+
+/// Find the lowest fee (in basis points) the pair accepts by simulating `token0 -> token1` swaps.
+///
+/// The pair reserves are overridden to be lower than the pair balance of `token0`, as if we had
+/// sent `amount_in` to the pair. The pair then lets us take `amount_out` only if it passes its
+/// `k` check, which includes the fee. Binary search finds the lowest fee that passes. Stable pairs
+/// are quoted along `stable_curve`, other pairs along `x * y = k`.
+async fn detect_fee(
+    provider: &EthereumProvider,
+    pair_address: Address,
+    stable_curve: Option<SolidlyStable>,
+) -> Result<Probe> {
+    let pair = IUniswapV2Pair::new(pair_address, provider);
+
+    let token0 = pair.token0().call().await?._0;
+    let token1 = pair.token1().call().await?._0;
+    let reserves = pair.getReserves().call().await?;
+    let Some(layout) = reserves_layout(
+        provider,
+        pair_address,
+        U256::from(reserves.reserve0),
+        U256::from(reserves.reserve1),
+        reserves.blockTimestampLast,
+    )
+    .await?
+    else {
+        return Ok(Probe::UnknownLayout);
+    };
+
+    let balance0 = IERC20::new(token0, provider)
+        .balanceOf(pair_address)
+        .call()
+        .await?
+        ._0;
+    let balance1 = IERC20::new(token1, provider)
+        .balanceOf(pair_address)
+        .call()
+        .await?
+        ._0;
+
+    let amount_in = balance0 / U256::from(PROBE_AMOUNT_DIVISOR);
+    if amount_in.is_zero() {
+        return Ok(Probe::Rejected);
+    }
+
+    let reserve_in = balance0 - amount_in;
+    let reserve_out = balance1;
+    let Some(reserves_state) =
+        reserves_state(layout, reserve_in, reserve_out, reserves.blockTimestampLast)
+    else {
+        return Ok(Probe::Rejected);
+    };
+
+    let overrides = StateOverride::from_iter([(
+        pair_address,
+        AccountOverride {
+            state_diff: Some(reserves_state.into_iter().collect()),
+            ..Default::default()
+        },
+    )]);
+
+    let accepts_fee = |fee: u32| {
+        let pair = &pair;
+        let overrides = overrides.clone();
+        async move {
+            let amount_out = match stable_curve {
+                Some(curve) => curve.amount_out(amount_in, reserve_in, reserve_out, true, fee),
+                None => uniswap_v2_amount_out(amount_in, reserve_in, reserve_out, fee),
+            };
+            if amount_out.is_zero() {
+                return false;
+            }
+            pair.swap(U256::ZERO, amount_out, PROBE_RECIPIENT, Bytes::new())
+                .state(overrides)
+                .call()
+                .await
+                .is_ok()
+        }
+    };
+
+    if !accepts_fee(MAX_FEE).await {
+        return Ok(Probe::Rejected);
+    }
+
+    // The pair accepts every fee above its own: find the lowest accepted one
+    let mut low = 0;
+    let mut high = MAX_FEE;
+    while low < high {
+        let fee = (low + high) / 2;
+        if accepts_fee(fee).await {
+            high = fee;
+        } else {
+            low = fee + 1;
+        }
+    }
+
+    Ok(Probe::Fee(low))
+}
+
+/// Finds the reserves in the first `MAX_RESERVES_SLOT` storage slots of a pair, either packed
+/// with `block_timestamp_last` or as two consecutive slots
+pub async fn reserves_layout(
+    provider: &EthereumProvider,
+    pair_address: Address,
+    reserve0: U256,
+    reserve1: U256,
+    block_timestamp_last: u32,
+) -> Result<Option<ReservesLayout>> {
+    let packed = reserves_slot(reserve0, reserve1, block_timestamp_last)
+        .map(|slot| U256::from_be_bytes(slot.0));
+
+    let mut previous = None;
+    for slot in 0..MAX_RESERVES_SLOT {
+        let slot = U256::from(slot);
+        let value = provider.get_storage_at(pair_address, slot).await?;
+        if Some(value) == packed {
+            return Ok(Some(ReservesLayout::Packed(slot)));
+        }
+        if previous == Some(reserve0) && value == reserve1 {
+            return Ok(Some(ReservesLayout::Separate(slot - U256::from(1))));
+        }
+        previous = Some(value);
+    }
+    Ok(None)
+}
+
+/// Storage writes that set the reserves of a pair with the given layout, `None` if the reserves do
+/// not fit into it
+pub fn reserves_state(
+    layout: ReservesLayout,
+    reserve0: U256,
+    reserve1: U256,
+    block_timestamp_last: u32,
+) -> Option<Vec<(B256, B256)>> {
+    match layout {
+        ReservesLayout::Packed(slot) => Some(vec![(
+            B256::from(slot),
+            reserves_slot(reserve0, reserve1, block_timestamp_last)?,
+        )]),
+        ReservesLayout::Separate(slot) => Some(vec![
+            (B256::from(slot), B256::from(reserve0)),
+            (B256::from(slot + U256::from(1)), B256::from(reserve1)),
+        ]),
+    }
+}
+
+/// Pack reserves into the `UniswapV2Pair` reserves slot:
+/// `uint112 reserve0 | uint112 reserve1 | uint32 blockTimestampLast`
+/// Returns `None` if the reserves do not fit into `uint112`
+fn reserves_slot(reserve0: U256, reserve1: U256, block_timestamp_last: u32) -> Option<B256> {
+    if reserve0.bit_len() > 112 || reserve1.bit_len() > 112 {
+        return None;
+    }
+
+    let slot = reserve0 | (reserve1 << 112) | (U256::from(block_timestamp_last) << 224);
+    Some(B256::from(slot))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reserves_state() {
+        let (reserve0, reserve1) = (U256::from(100), U256::from(200));
+
+        let packed =
+            reserves_state(ReservesLayout::Packed(U256::from(8)), reserve0, reserve1, 7).unwrap();
+        assert_eq!(
+            packed,
+            vec![(
+                B256::from(U256::from(8)),
+                B256::from(reserve0 | (reserve1 << 112) | (U256::from(7) << 224))
+            )]
+        );
+        // Packed reserves are `uint112`
+        assert!(reserves_state(
+            ReservesLayout::Packed(U256::from(8)),
+            U256::MAX,
+            reserve1,
+            7
+        )
+        .is_none());
+
+        let separate = reserves_state(
+            ReservesLayout::Separate(U256::from(19)),
+            U256::MAX,
+            reserve1,
+            7,
+        )
+        .unwrap();
+        assert_eq!(
+            separate,
+            vec![
+                (B256::from(U256::from(19)), B256::from(U256::MAX)),
+                (B256::from(U256::from(20)), B256::from(reserve1)),
+            ]
+        );
+    }
+}
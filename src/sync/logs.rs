@@ -0,0 +1,127 @@
+use std::ops::RangeInclusive;
+
+use alloy::providers::Provider;
+use alloy::rpc::types::{Filter, Log};
+use eyre::Result;
+
+use crate::utils::app_context::EthereumProvider;
+
+/// Blocks of the first `eth_getLogs` request of a backfill
+pub const INITIAL_CHUNK_SIZE: u64 = 1_000;
+
+/// Most blocks per `eth_getLogs` request
+pub const MAX_CHUNK_SIZE: u64 = 10_000;
+
+/// Pages with fewer logs than this double the chunk size of the next request
+const GROW_BELOW_LOGS: usize = 2_000;
+
+/// Pages `eth_getLogs` over a block range in order
+///
+/// The chunk size adapts to the density of the logs: a failed request (nodes reject ranges with
+/// too many results) is retried with half the blocks, a page with few logs doubles the blocks of
+/// the next request.
+pub struct LogPager {
+    filter: Filter,
+    next_block: u64,
+    to_block: u64,
+    chunk_size: u64,
+}
+
+impl LogPager {
+    /// Pages the logs of `filter` in blocks `from_block..=to_block`
+    pub fn new(filter: Filter, from_block: u64, to_block: u64) -> Self {
+        Self {
+            filter,
+            next_block: from_block,
+            to_block,
+            chunk_size: INITIAL_CHUNK_SIZE,
+        }
+    }
+
+    /// Fetches the logs of the next chunk of blocks
+    ///
+    /// # Returns
+    /// The blocks of the page and their logs in order, `None` once the range is done
+    ///
+    /// # Errors
+    /// * If the request for a single block fails
+    pub async fn next_page(
+        &mut self,
+        provider: &EthereumProvider,
+    ) -> Result<Option<(RangeInclusive<u64>, Vec<Log>)>> {
+        loop {
+            let Some(blocks) = self.next_blocks() else {
+                return Ok(None);
+            };
+
+            let filter = self
+                .filter
+                .clone()
+                .from_block(*blocks.start())
+                .to_block(*blocks.end());
+            match provider.get_logs(&filter).await {
+                Ok(logs) => {
+                    self.next_block = blocks.end() + 1;
+                    self.chunk_size = next_chunk_size(self.chunk_size, Some(logs.len()));
+                    return Ok(Some((blocks, logs)));
+                }
+                Err(e) if self.chunk_size > 1 => {
+                    log::warn!(
+                        "sync::logs: Failed to get logs of blocks {}-{}, retrying with fewer blocks: {e}",
+                        blocks.start(),
+                        blocks.end()
+                    );
+                    self.chunk_size = next_chunk_size(self.chunk_size, None);
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
+    /// Blocks of the next request, `None` once the range is done
+    fn next_blocks(&self) -> Option<RangeInclusive<u64>> {
+        if self.next_block > self.to_block {
+            return None;
+        }
+        let end = self
+            .next_block
+            .saturating_add(self.chunk_size - 1)
+            .min(self.to_block);
+        Some(self.next_block..=end)
+    }
+}
+
+/// Chunk size after a request of `chunk_size` blocks that returned `log_count` logs, or failed
+fn next_chunk_size(chunk_size: u64, log_count: Option<usize>) -> u64 {
+    match log_count {
+        None => (chunk_size / 2).max(1),
+        Some(log_count) if log_count < GROW_BELOW_LOGS => (chunk_size * 2).min(MAX_CHUNK_SIZE),
+        Some(_) => chunk_size,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_next_chunk_size() {
+        assert_eq!(next_chunk_size(1_000, None), 500);
+        assert_eq!(next_chunk_size(1, None), 1);
+        assert_eq!(next_chunk_size(1_000, Some(10)), 2_000);
+        assert_eq!(next_chunk_size(MAX_CHUNK_SIZE, Some(10)), MAX_CHUNK_SIZE);
+        assert_eq!(next_chunk_size(1_000, Some(GROW_BELOW_LOGS)), 1_000);
+    }
+
+    #[test]
+    fn test_next_blocks() {
+        let mut pager = LogPager::new(Filter::new(), 100, 2_500);
+        assert_eq!(pager.next_blocks(), Some(100..=1_099));
+
+        pager.next_block = 2_000;
+        assert_eq!(pager.next_blocks(), Some(2_000..=2_500));
+
+        pager.next_block = 2_501;
+        assert_eq!(pager.next_blocks(), None);
+    }
+}
@@ -1,17 +1,24 @@
+pub mod cycles;
 pub mod exchange_rates;
 pub mod factories;
 pub mod factory_pairs;
+pub mod fees;
+pub mod logs;
 pub mod pair_created_events;
 pub mod pair_tokens;
 pub mod reserves;
 pub mod sync_events;
+pub mod token_safety;
 pub mod usd;
 
+pub use cycles::cycles;
 pub use exchange_rates::exchange_rates;
 pub use factories::factories;
 pub use factory_pairs::factory_pairs;
-pub use pair_created_events::pair_created_events;
+pub use fees::fees;
+pub use pair_created_events::{backfill_pair_created_events, pair_created_events};
 pub use pair_tokens::pair_tokens;
 pub use reserves::reserves;
-pub use sync_events::events;
+pub use sync_events::{backfill_events, events};
+pub use token_safety::token_safety;
 pub use usd::usd;
@@ -1,13 +1,30 @@
+use std::collections::{HashMap, HashSet};
+use std::time::Instant;
+
+use alloy::eips::BlockId;
 use alloy::rpc::types::BlockNumberOrTag;
 use alloy::{
-    primitives::Address, providers::Provider, rpc::types::Filter, sol, sol_types::SolEvent,
+    primitives::Address,
+    providers::Provider,
+    rpc::types::{Filter, Log},
+    sol,
+    sol_types::SolEvent,
 };
-use diesel::ExpressionMethods;
-use diesel_async::RunQueryDsl;
-use eyre::Result;
+use diesel::dsl::sql;
+use diesel::sql_types::{Bool, Integer, Nullable};
+use diesel::upsert::excluded;
+use diesel::{ExpressionMethods, QueryDsl, SelectableHelper};
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use eyre::{bail, Result};
 use futures::StreamExt;
 
+use crate::models::checkpoint::{find_checkpoint, save_checkpoint};
+use crate::models::factory::Factory;
+use crate::schemas::factories;
 use crate::schemas::tokens::{self};
+use crate::sync::logs::LogPager;
+use crate::utils::app_context::EthereumProvider;
+use crate::utils::constants::BASE_CHAIN_ID;
 use crate::{schemas::pairs, utils::app_context::AppContext};
 
 // Event emitted when a pair is created.
@@ -20,14 +37,69 @@ sol! {
     );
 }
 
+// Event emitted when a pool is created by a Solidly (Aerodrome, Velodrome) PoolFactory.
+sol! {
+    event PoolCreated(
+        address indexed token0,
+        address indexed token1,
+        bool indexed stable,
+        address pool,
+        uint256
+    );
+}
+
+/// Postgres limits a query to 65535 bind parameters, each pair takes 6
+const UPSERT_BATCH_SIZE: usize = 1_000;
+
+/// Checkpoint name of the `PairCreated` backfill of a factory
+fn checkpoint_name(factory: Address) -> String {
+    format!("pair_created_events:{factory}")
+}
+
+/// A pair from a `PairCreated` or `PoolCreated` event
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct CreatedPair {
+    token0: Address,
+    token1: Address,
+    pair: Address,
+    stable: bool,
+}
+
+/// Decodes a `PairCreated` (volatile) or `PoolCreated` event
+fn decode_created_pair(log: &Log) -> Result<CreatedPair> {
+    let (token0, token1, pair, stable) = match log.topic0() {
+        Some(&PairCreated::SIGNATURE_HASH) => PairCreated::decode_log(&log.inner, true)
+            .map(|event| (event.token0, event.token1, event.pair, false))?,
+        _ => PoolCreated::decode_log(&log.inner, true)
+            .map(|event| (event.token0, event.token1, event.pool, event.stable))?,
+    };
+    Ok(CreatedPair {
+        token0,
+        token1,
+        pair,
+        stable,
+    })
+}
+
 /// Sync pair created events.
-/// These are emitted by UniswapV2Factory contracts.
+/// These are emitted by UniswapV2Factory contracts (`PairCreated`) and by Solidly PoolFactory
+/// contracts (`PoolCreated`), which create both volatile and stable pools. Listens on Base only.
+///
+/// Only the factories in the `factories` table are followed: any contract can emit these events,
+/// and a fake one naming a real pair must not rewrite it.
 pub async fn pair_created_events(ctx: &AppContext) -> Result<()> {
     let mut conn = ctx.db.get().await?;
-    let provider = &ctx.base_provider;
+    let provider = ctx.base_provider();
+
+    let factory_ids = factory_ids_by_address(&mut conn).await?;
+    if factory_ids.is_empty() {
+        log::warn!("sync::pair_created_events: No factories to follow");
+        return Ok(());
+    }
 
     let filter = Filter::new()
-        .event(PairCreated::SIGNATURE)
+        .address(factory_ids.keys().copied().collect::<Vec<_>>())
+        .events([PairCreated::SIGNATURE, PoolCreated::SIGNATURE])
         .from_block(BlockNumberOrTag::Latest);
     let mut stream = loop {
         match provider.subscribe_logs(&filter).await {
@@ -41,42 +113,333 @@ pub async fn pair_created_events(ctx: &AppContext) -> Result<()> {
 
     // Process sync events
     while let Some(log) = stream.next().await {
-        let event = match PairCreated::decode_log(&log.inner, true) {
-            Ok(event) => event,
+        let created_pair = match decode_created_pair(&log) {
+            Ok(created_pair) => created_pair,
             Err(e) => {
                 log::error!("sync::events: Failed to decode event: {e}");
                 continue;
             }
         };
 
-        let token0_id = token_id_by_address(ctx, event.token0).await?;
-        let token1_id = token_id_by_address(ctx, event.token1).await?;
+        let Some(&factory_id) = factory_ids.get(&log.address()) else {
+            log::warn!(
+                "sync::pair_created_events: Skipping event of unknown factory {}",
+                log.address()
+            );
+            continue;
+        };
+        upsert_pairs(&mut conn, factory_id, &[created_pair]).await?;
+    }
+
+    Ok(())
+}
+
+/// Backfills the pairs created by the Base factories (or one of them) and exits
+///
+/// The `PairCreated` and `PoolCreated` logs of each factory are paged from the block after its
+/// checkpoint, or from its deployment block on the first run, to the latest block.
+///
+/// # Errors
+/// * If database queries fail
+/// * If the latest block cannot be fetched
+pub async fn backfill_pair_created_events(
+    ctx: &AppContext,
+    factory_address: Option<Address>,
+) -> Result<()> {
+    let mut conn = ctx.db.get().await?;
+    let to_block = ctx.base_provider().get_block_number().await?;
+
+    let mut query = factories::table
+        .filter(factories::chain_id.eq(BASE_CHAIN_ID as i64))
+        .select(Factory::as_select())
+        .order(factories::id.asc())
+        .into_boxed();
+    if let Some(factory_address) = factory_address {
+        query = query.filter(factories::address.eq(factory_address.to_string()));
+    }
+    let factories = query.load::<Factory>(&mut conn).await?;
+    if factories.is_empty() {
+        log::warn!("sync::pair_created_events: No factories to backfill");
+    }
+
+    // A failed factory is resumed from its checkpoint by the next run
+    for mut factory in factories {
+        if let Err(e) = backfill_factory(ctx, &mut conn, &mut factory, to_block).await {
+            log::error!(
+                "sync::pair_created_events: Failed to backfill factory {}: {e}",
+                factory.address()
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Upserts the pairs created by `factory` up to `to_block`, saving a checkpoint after every page
+async fn backfill_factory(
+    ctx: &AppContext,
+    conn: &mut AsyncPgConnection,
+    factory: &mut Factory,
+    to_block: u64,
+) -> Result<()> {
+    let provider = ctx.base_provider();
+    let checkpoint_name = checkpoint_name(factory.address());
+
+    let from_block = match find_checkpoint(conn, BASE_CHAIN_ID, &checkpoint_name).await? {
+        Some(checkpoint) => checkpoint + 1,
+        None => match factory.deployment_block() {
+            Some(deployment_block) => deployment_block,
+            None => {
+                let deployment_block =
+                    find_deployment_block(provider, factory.address(), to_block).await?;
+                factory
+                    .update_deployment_block(conn, deployment_block)
+                    .await?;
+                deployment_block
+            }
+        },
+    };
+
+    log::info!(
+        "sync::pair_created_events: Backfilling factory {} from block {} to {}",
+        factory.address(),
+        from_block,
+        to_block
+    );
+    let started_at = Instant::now();
+    let mut pair_count = 0;
+
+    let filter = Filter::new()
+        .address(factory.address())
+        .events([PairCreated::SIGNATURE, PoolCreated::SIGNATURE]);
+    let mut pager = LogPager::new(filter, from_block, to_block);
+    while let Some((blocks, logs)) = pager.next_page(provider).await? {
+        let created_pairs: Vec<CreatedPair> = logs
+            .iter()
+            .filter_map(|log| {
+                decode_created_pair(log)
+                    .inspect_err(|e| {
+                        log::error!("sync::pair_created_events: Failed to decode event: {e}");
+                    })
+                    .ok()
+            })
+            .collect();
+        for batch in created_pairs.chunks(UPSERT_BATCH_SIZE) {
+            upsert_pairs(conn, factory.id(), batch).await?;
+        }
+        pair_count += created_pairs.len();
+        save_checkpoint(conn, BASE_CHAIN_ID, &checkpoint_name, *blocks.end()).await?;
+    }
+
+    log::info!(
+        "sync::pair_created_events: Backfilled {} pairs of factory {} in {:?}",
+        pair_count,
+        factory.address(),
+        started_at.elapsed()
+    );
+    Ok(())
+}
 
-        diesel::insert_into(pairs::table)
-            .values((
-                pairs::address.eq(event.pair.to_string()),
-                pairs::token0_id.eq(token0_id),
-                pairs::token1_id.eq(token1_id),
-            ))
-            .execute(&mut conn)
+/// First block `address` has code at, by a binary search over the state of past blocks
+async fn find_deployment_block(
+    provider: &EthereumProvider,
+    address: Address,
+    head: u64,
+) -> Result<u64> {
+    let has_code = |block_number: u64| async move {
+        let code = provider
+            .get_code_at(address)
+            .block_id(BlockId::number(block_number))
             .await?;
+        Ok::<_, eyre::Report>(!code.is_empty())
+    };
+
+    if !has_code(head).await? {
+        bail!("No contract at {address} at block {head}");
     }
 
+    // `address` has code at `high`
+    let (mut low, mut high) = (0, head);
+    while low < high {
+        let mid = low + (high - low) / 2;
+        if has_code(mid).await? {
+            high = mid;
+        } else {
+            low = mid + 1;
+        }
+    }
+    Ok(high)
+}
+
+/// Inserts created pairs, with their tokens, in two queries. A pair we know already, e.g. from a
+/// `Sync` event, gets its tokens and factory filled in. Tokens and a factory the pair has already
+/// are kept, and only its own factory can change whether it is stable.
+async fn upsert_pairs(
+    conn: &mut AsyncPgConnection,
+    factory_id: i32,
+    created_pairs: &[CreatedPair],
+) -> Result<()> {
+    if created_pairs.is_empty() {
+        return Ok(());
+    }
+
+    let token_addresses: HashSet<Address> = created_pairs
+        .iter()
+        .flat_map(|created_pair| [created_pair.token0, created_pair.token1])
+        .collect();
+    let token_ids = token_ids_by_address(conn, &token_addresses).await?;
+
+    // A pair can only be upserted once per query, the last event of a pair wins
+    let created_pairs: HashMap<Address, &CreatedPair> = created_pairs
+        .iter()
+        .map(|created_pair| (created_pair.pair, created_pair))
+        .collect();
+    let rows: Vec<_> = created_pairs
+        .values()
+        .map(|created_pair| {
+            (
+                pairs::chain_id.eq(BASE_CHAIN_ID as i64),
+                pairs::address.eq(created_pair.pair.to_string()),
+                pairs::token0_id.eq(token_ids[&created_pair.token0]),
+                pairs::token1_id.eq(token_ids[&created_pair.token1]),
+                pairs::factory_id.eq(factory_id),
+                pairs::stable.eq(created_pair.stable),
+            )
+        })
+        .collect();
+
+    diesel::insert_into(pairs::table)
+        .values(&rows)
+        .on_conflict((pairs::chain_id, pairs::address))
+        .do_update()
+        .set((
+            pairs::token0_id.eq(sql::<Nullable<Integer>>(
+                "COALESCE(pairs.token0_id, EXCLUDED.token0_id)",
+            )),
+            pairs::token1_id.eq(sql::<Nullable<Integer>>(
+                "COALESCE(pairs.token1_id, EXCLUDED.token1_id)",
+            )),
+            pairs::factory_id.eq(sql::<Nullable<Integer>>(
+                "COALESCE(pairs.factory_id, EXCLUDED.factory_id)",
+            )),
+            pairs::stable.eq(sql::<Bool>(
+                "CASE WHEN pairs.factory_id IS NULL OR pairs.factory_id = EXCLUDED.factory_id
+                 THEN EXCLUDED.stable ELSE pairs.stable END",
+            )),
+        ))
+        .execute(conn)
+        .await?;
+
     Ok(())
 }
 
-/// Get the token id for a given address. If the token does not exist, it will be created.
-async fn token_id_by_address(ctx: &AppContext, token_address: Address) -> Result<i32> {
-    let mut conn = ctx.db.get().await?;
-    log::info!("token_id_by_address: {}", token_address);
+/// Get the token ids for the given addresses with one query. Tokens that do not exist are
+/// created.
+async fn token_ids_by_address(
+    conn: &mut AsyncPgConnection,
+    token_addresses: &HashSet<Address>,
+) -> Result<HashMap<Address, i32>> {
+    let rows: Vec<_> = token_addresses
+        .iter()
+        .map(|token_address| {
+            (
+                tokens::chain_id.eq(BASE_CHAIN_ID as i64),
+                tokens::address.eq(token_address.to_string()),
+            )
+        })
+        .collect();
 
-    let id = diesel::insert_into(tokens::table)
-        .values((tokens::address.eq(token_address.to_string()),))
-        .on_conflict(tokens::address)
+    let ids = diesel::insert_into(tokens::table)
+        .values(&rows)
+        .on_conflict((tokens::chain_id, tokens::address))
         .do_update()
-        .set(tokens::address.eq(token_address.to_string()))
-        .returning(tokens::id)
-        .get_result::<i32>(&mut conn)
+        .set(tokens::address.eq(excluded(tokens::address)))
+        .returning((tokens::address, tokens::id))
+        .get_results::<(String, i32)>(conn)
+        .await?;
+
+    ids.into_iter()
+        .map(|(address, id)| Ok((address.parse()?, id)))
+        .collect()
+}
+
+/// Ids of the Base factories by address
+async fn factory_ids_by_address(conn: &mut AsyncPgConnection) -> Result<HashMap<Address, i32>> {
+    let factories = factories::table
+        .filter(factories::chain_id.eq(BASE_CHAIN_ID as i64))
+        .select(Factory::as_select())
+        .load::<Factory>(conn)
         .await?;
-    Ok(id)
+    Ok(factories
+        .iter()
+        .map(|factory| (factory.address(), factory.id()))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arb::test_helpers::address_from_str;
+    use alloy::primitives::{address, LogData, U256};
+
+    fn log(factory: Address, data: LogData) -> Log {
+        Log {
+            inner: alloy::primitives::Log {
+                address: factory,
+                data,
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_decode_created_pair() {
+        let (token0, token1, pair) = (
+            address_from_str("A"),
+            address_from_str("B"),
+            address_from_str("F1"),
+        );
+
+        let pair_created = PairCreated {
+            token0,
+            token1,
+            pair,
+            _3: U256::from(1),
+        };
+        assert_eq!(
+            decode_created_pair(&log(address_from_str("C"), pair_created.encode_log_data()))
+                .unwrap(),
+            CreatedPair {
+                token0,
+                token1,
+                pair,
+                stable: false,
+            }
+        );
+
+        let pool_created = PoolCreated {
+            token0,
+            token1,
+            stable: true,
+            pool: pair,
+            _4: U256::from(1),
+        };
+        assert!(
+            decode_created_pair(&log(address_from_str("C"), pool_created.encode_log_data()))
+                .unwrap()
+                .stable
+        );
+    }
+
+    #[test]
+    fn test_checkpoint_name() {
+        assert_eq!(
+            checkpoint_name(address!("0x8909dc15e40173ff4699343b6eb8132c65e18ec6")),
+            "pair_created_events:0x8909Dc15e40173Ff4699343b6eB8132c65e18eC6"
+        );
+        assert_eq!(
+            checkpoint_name(address_from_str("C")),
+            "pair_created_events:0xC000000000000000000000000000000000000000"
+        );
+    }
 }
@@ -1,50 +1,88 @@
-use alloy::primitives::Address;
+use std::collections::HashMap;
+
+use alloy::primitives::{Address, Bytes, ChainId, FixedBytes};
+use alloy::providers::MULTICALL3_ADDRESS;
+use alloy::sol_types::{SolCall, SolValue};
 use eyre::Result;
+use itertools::Itertools;
 use log::info;
 
 use crate::models::pair::Pair;
+use crate::models::token::sanitize_string;
 use crate::schemas::{pairs, tokens};
-use crate::utils::app_context::AppContext;
+use crate::utils::app_context::{AppContext, EthereumProvider};
+use diesel::dsl::sql;
+use diesel::sql_types::{Bool, Integer, Nullable, Text};
 use diesel::QueryDsl;
 use diesel::SelectableHelper;
 use diesel::{BoolExpressionMethods, ExpressionMethods};
-use diesel_async::RunQueryDsl;
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
 
 use alloy::sol;
 
 sol! {
     #[sol(rpc)]
+    "contracts/src/interfaces/IMulticall3.sol"
+}
+
+sol! {
+    #[sol(abi)]
     "contracts/src/interfaces/IERC20.sol"
 
 }
 
 sol! {
-    #[sol(rpc)]
+    #[sol(abi)]
     "contracts/src/interfaces/IUniswapV2Pair.sol"
 }
 
+/// Pairs per batch, each batch is two multicalls per chain
+const BATCH_SIZE: i64 = 100;
+
 /// Sync pairs tokens
-/// Reads pairs from the database that don't have tokens, reads pair's contract and fetches
-/// token info
+/// Reads pairs from the database that don't have tokens, reads the tokens of the pairs and their
+/// name, symbol and decimals with multicalls
+///
+/// Pairs are visited in id order, so a pair whose tokens cannot be read does not block the pairs
+/// after it. A pair whose `token0` or `token1` call reverts is marked `tokens_unreadable` and not
+/// read again, a failed multicall is retried on the next pass. Tokens whose metadata cannot be
+/// decoded are saved with `metadata_invalid`.
+///
+/// Tokens saved without metadata and not known to be invalid, e.g. by the `PairCreated` backfill
+/// of `sync::pair_created_events`, have their metadata read on every pass as well.
 pub async fn pair_tokens(ctx: &AppContext) -> Result<()> {
     log::info!("sync::pair_tokens: Starting token sync...");
 
+    let mut last_pair_id = 0;
     loop {
-        let synced_tokens_count = sync(ctx, 100).await?;
+        sync_token_metadata(ctx, BATCH_SIZE).await?;
+        let visited_pair_ids = sync(ctx, last_pair_id, BATCH_SIZE).await?;
 
-        if synced_tokens_count == 0 {
-            tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+        match visited_pair_ids.last() {
+            Some(&pair_id) if visited_pair_ids.len() as i64 == BATCH_SIZE => {
+                last_pair_id = pair_id;
+            }
+            // End of the pairs, start over after a while
+            _ => {
+                last_pair_id = 0;
+                tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+            }
         }
     }
 }
 
 /// Sync a bunch of pairs tokens
-async fn sync(ctx: &AppContext, limit: i64) -> Result<usize> {
+///
+/// # Returns
+/// The ids of the visited pairs, in order
+async fn sync(ctx: &AppContext, after_pair_id: i32, limit: i64) -> Result<Vec<i32>> {
     let mut conn = ctx.db.get().await?;
 
     // Query for pairs missing token info
     let pairs: Vec<Pair> = pairs::table
         .filter(pairs::token0_id.is_null().or(pairs::token1_id.is_null()))
+        .filter(pairs::id.gt(after_pair_id))
+        .order(pairs::id.asc())
         .select(Pair::as_select())
         .limit(limit)
         .load::<Pair>(&mut conn)
@@ -54,72 +92,437 @@ async fn sync(ctx: &AppContext, limit: i64) -> Result<usize> {
         "sync::pair_tokens(): Found {} pairs missing tokens info",
         pairs.len()
     );
-    for pair in pairs.iter().as_ref() {
-        // Read token addresses from pair contract
-        let contract = IUniswapV2Pair::new(pair.address(), ctx.base_provider.clone());
-        let token0 = contract.token0().call().await?._0;
-        let token1 = contract.token1().call().await?._0;
+
+    // A multicall cannot span chains
+    for (chain_id, chain_pairs) in pairs_to_read(&pairs).into_group_map_by(|pair| pair.chain_id()) {
+        sync_chain(ctx, &mut conn, chain_id, &chain_pairs).await?;
+    }
+
+    Ok(pairs.iter().map(|pair| pair.id).collect())
+}
+
+/// Reads the metadata of a bunch of tokens that have none and are not `metadata_invalid`. Each
+/// read token gets its decimals or is marked `metadata_invalid`, so it is not selected again.
+///
+/// # Returns
+/// The number of tokens read
+async fn sync_token_metadata(ctx: &AppContext, limit: i64) -> Result<usize> {
+    let mut conn = ctx.db.get().await?;
+
+    let tokens: Vec<(i64, String)> = tokens::table
+        .filter(tokens::decimals.is_null())
+        .filter(tokens::metadata_invalid.eq(false))
+        .order(tokens::id.asc())
+        .select((tokens::chain_id, tokens::address))
+        .limit(limit)
+        .load(&mut conn)
+        .await?;
+    if tokens.is_empty() {
+        return Ok(0);
+    }
+    info!(
+        "sync::pair_tokens: Found {} tokens missing metadata",
+        tokens.len()
+    );
+
+    let tokens_by_chain = tokens
+        .iter()
+        .filter_map(|(chain_id, address)| match address.parse::<Address>() {
+            Ok(token) => Some((*chain_id as ChainId, token)),
+            Err(e) => {
+                log::warn!(
+                    "sync::pair_tokens: Skipping token with malformed address {address}: {e}"
+                );
+                None
+            }
+        })
+        .into_group_map();
+    for (chain_id, chain_tokens) in tokens_by_chain {
+        let provider = match ctx.provider(chain_id) {
+            Ok(provider) => provider,
+            Err(e) => {
+                log::warn!(
+                    "sync::pair_tokens: Skipping {} tokens: {e}",
+                    chain_tokens.len()
+                );
+                continue;
+            }
+        };
+        read_tokens(provider, &mut conn, chain_id, &chain_tokens).await?;
+    }
+
+    Ok(tokens.len())
+}
+
+/// Pairs whose tokens have not been found unreadable before
+fn pairs_to_read(pairs: &[Pair]) -> impl Iterator<Item = &Pair> {
+    pairs.iter().filter(|pair| !pair.tokens_unreadable())
+}
+
+/// Token addresses of a pair, from the results of its `token0` and `token1` calls
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PairTokens {
+    Read(Address, Address),
+    /// A call reverted or did not return an address, e.g. the contract is not a pair. The multicall
+    /// itself succeeded, so reading again gives the same result.
+    Unreadable,
+}
+
+impl PairTokens {
+    fn decode(token0: &IMulticall3::Result, token1: &IMulticall3::Result) -> Self {
+        match (decode_address(token0), decode_address(token1)) {
+            (Some(token0), Some(token1)) => Self::Read(token0, token1),
+            _ => Self::Unreadable,
+        }
+    }
+}
+
+/// Sync the tokens of pairs on one chain
+async fn sync_chain(
+    ctx: &AppContext,
+    conn: &mut AsyncPgConnection,
+    chain_id: ChainId,
+    pairs: &[&Pair],
+) -> Result<()> {
+    let provider = match ctx.provider(chain_id) {
+        Ok(provider) => provider,
+        Err(e) => {
+            log::warn!("sync::pair_tokens: Skipping {} pairs: {e}", pairs.len());
+            return Ok(());
+        }
+    };
+    let multicall = IMulticall3::new(MULTICALL3_ADDRESS, provider);
+
+    // Read token addresses from the pair contracts
+    let calls: Vec<IMulticall3::Call3> = pairs
+        .iter()
+        .flat_map(|pair| {
+            [
+                IUniswapV2Pair::token0Call::new(()).abi_encode(),
+                IUniswapV2Pair::token1Call::new(()).abi_encode(),
+            ]
+            .map(|call_data| IMulticall3::Call3 {
+                target: pair.address(),
+                allowFailure: true,
+                callData: Bytes::from(call_data),
+            })
+        })
+        .collect();
+    let results = multicall.aggregate3(calls).call().await?.returnData;
+
+    let mut pair_tokens = Vec::with_capacity(pairs.len());
+    let mut unreadable_pair_ids = Vec::new();
+    for (pair, results) in pairs.iter().zip(results.chunks(2)) {
+        match PairTokens::decode(&results[0], &results[1]) {
+            PairTokens::Read(token0, token1) => pair_tokens.push((pair, token0, token1)),
+            PairTokens::Unreadable => {
+                log::warn!(
+                    "sync::pair_tokens: Marking pair {}, its tokens cannot be read",
+                    pair.address()
+                );
+                unreadable_pair_ids.push(pair.id);
+            }
+        }
+    }
+    if !unreadable_pair_ids.is_empty() {
+        diesel::update(pairs::table.filter(pairs::id.eq_any(&unreadable_pair_ids)))
+            .set(pairs::tokens_unreadable.eq(true))
+            .execute(conn)
+            .await?;
+    }
+    if pair_tokens.is_empty() {
+        return Ok(());
+    }
+
+    let tokens: Vec<Address> = pair_tokens
+        .iter()
+        .flat_map(|(_, token0, token1)| [*token0, *token1])
+        .unique()
+        .collect();
+    let token_ids = read_tokens(provider, conn, chain_id, &tokens).await?;
+
+    for (pair, token0, token1) in pair_tokens {
         log::info!(
             "sync::pair_tokens: Syncing pair tokens for pair: {}, token0: {}, token1: {}",
             pair.address(),
             token0,
             token1
         );
-
-        sync_pair_tokens(ctx, pair, token0, true).await?;
-        sync_pair_tokens(ctx, pair, token1, false).await?;
+        // Token ids the pair has already, e.g. from its `PairCreated` event, are kept
+        diesel::update(pairs::table.find(pair.id))
+            .set((
+                pairs::token0_id.eq(sql::<Nullable<Integer>>("COALESCE(token0_id, ")
+                    .bind::<Integer, _>(token_ids[&token0])
+                    .sql(")")),
+                pairs::token1_id.eq(sql::<Nullable<Integer>>("COALESCE(token1_id, ")
+                    .bind::<Integer, _>(token_ids[&token1])
+                    .sql(")")),
+            ))
+            .execute(conn)
+            .await?;
     }
 
-    Ok(pairs.len())
+    Ok(())
 }
 
-/// Sync a pair tokens
-async fn sync_pair_tokens(
-    ctx: &AppContext,
-    pair: &Pair,
-    token: Address,
-    is_token0: bool,
-) -> Result<()> {
-    let mut conn = ctx.db.get().await?;
+/// Reads the name, symbol and decimals of tokens on one chain with a multicall and upserts them
+///
+/// # Returns
+/// The id of each token
+async fn read_tokens(
+    provider: &EthereumProvider,
+    conn: &mut AsyncPgConnection,
+    chain_id: ChainId,
+    tokens: &[Address],
+) -> Result<HashMap<Address, i32>> {
+    let multicall = IMulticall3::new(MULTICALL3_ADDRESS, provider);
+    let calls: Vec<IMulticall3::Call3> = tokens
+        .iter()
+        .flat_map(|&token| {
+            [
+                IERC20::nameCall::new(()).abi_encode(),
+                IERC20::symbolCall::new(()).abi_encode(),
+                IERC20::decimalsCall::new(()).abi_encode(),
+            ]
+            .map(|call_data| IMulticall3::Call3 {
+                target: token,
+                allowFailure: true,
+                callData: Bytes::from(call_data),
+            })
+        })
+        .collect();
+    let results = multicall.aggregate3(calls).call().await?.returnData;
+
+    let mut token_ids = HashMap::with_capacity(tokens.len());
+    for (&token, results) in tokens.iter().zip(results.chunks(3)) {
+        let metadata = TokenMetadata::decode(&results[0], &results[1], &results[2]);
+        if !metadata.is_complete() {
+            log::warn!("sync::pair_tokens: Failed to read metadata of token {token}: {metadata:?}");
+        }
+        token_ids.insert(token, upsert_token(conn, chain_id, token, &metadata).await?);
+    }
 
-    // Create IERC20 contract instances for token
-    let token_contract = IERC20::new(token, ctx.base_provider.clone());
+    Ok(token_ids)
+}
 
-    // Get token details
-    let name = token_contract.name().call().await?._0.clone();
-    let symbol = token_contract.symbol().call().await?._0.clone();
-    let decimals = token_contract.decimals().call().await?._0;
+/// Upsert a token and get its ID. Metadata a known token has already is not overwritten by a
+/// failed read, it is only `metadata_invalid` if a field is still missing.
+async fn upsert_token(
+    conn: &mut AsyncPgConnection,
+    chain_id: ChainId,
+    token: Address,
+    metadata: &TokenMetadata,
+) -> Result<i32> {
+    let decimals = metadata.decimals.map(i32::from);
 
-    // Upsert token and get its ID
     let token_id = diesel::insert_into(tokens::table)
         .values((
+            tokens::chain_id.eq(chain_id as i64),
             tokens::address.eq(token.to_string()),
-            tokens::name.eq(&name),
-            tokens::symbol.eq(&symbol),
-            tokens::decimals.eq(i32::from(decimals)),
+            tokens::name.eq(&metadata.name),
+            tokens::symbol.eq(&metadata.symbol),
+            tokens::decimals.eq(decimals),
+            tokens::metadata_invalid.eq(!metadata.is_complete()),
         ))
-        .on_conflict(tokens::address)
+        .on_conflict((tokens::chain_id, tokens::address))
         .do_update()
         .set((
-            tokens::name.eq(&name),
-            tokens::symbol.eq(&symbol),
-            tokens::decimals.eq(i32::from(decimals)),
+            tokens::name.eq(sql::<Nullable<Text>>(
+                "COALESCE(EXCLUDED.name, tokens.name)",
+            )),
+            tokens::symbol.eq(sql::<Nullable<Text>>(
+                "COALESCE(EXCLUDED.symbol, tokens.symbol)",
+            )),
+            tokens::decimals.eq(sql::<Nullable<Integer>>(
+                "COALESCE(EXCLUDED.decimals, tokens.decimals)",
+            )),
+            tokens::metadata_invalid.eq(sql::<Bool>(
+                "COALESCE(EXCLUDED.name, tokens.name) IS NULL
+                 OR COALESCE(EXCLUDED.symbol, tokens.symbol) IS NULL
+                 OR COALESCE(EXCLUDED.decimals, tokens.decimals) IS NULL",
+            )),
         ))
         .returning(tokens::id)
-        .get_result::<i32>(&mut conn)
+        .get_result::<i32>(conn)
         .await?;
 
-    if is_token0 {
-        diesel::update(pairs::table.find(pair.id))
-            .set(pairs::token0_id.eq(token_id))
-            .execute(&mut conn)
-            .await?;
-    } else {
-        diesel::update(pairs::table.find(pair.id))
-            .set(pairs::token1_id.eq(token_id))
-            .execute(&mut conn)
-            .await?;
+    Ok(token_id)
+}
+
+/// Name, symbol and decimals of a token, `None` if the call failed or returned data that cannot
+/// be decoded
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct TokenMetadata {
+    name: Option<String>,
+    symbol: Option<String>,
+    decimals: Option<u8>,
+}
+
+impl TokenMetadata {
+    /// Decodes the results of the `name`, `symbol` and `decimals` calls
+    fn decode(
+        name: &IMulticall3::Result,
+        symbol: &IMulticall3::Result,
+        decimals: &IMulticall3::Result,
+    ) -> Self {
+        Self {
+            name: decode_string(name),
+            symbol: decode_string(symbol),
+            decimals: decimals
+                .success
+                .then(|| {
+                    IERC20::decimalsCall::abi_decode_returns(&decimals.returnData, true)
+                        .ok()
+                        .map(|decimals| decimals._0)
+                })
+                .flatten(),
+        }
     }
 
-    Ok(())
+    fn is_complete(&self) -> bool {
+        self.name.is_some() && self.symbol.is_some() && self.decimals.is_some()
+    }
+}
+
+fn decode_address(result: &IMulticall3::Result) -> Option<Address> {
+    result
+        .success
+        .then(|| Address::abi_decode(&result.returnData, true).ok())
+        .flatten()
+}
+
+/// Decodes a `string` return value, or a `bytes32` one as returned by some older tokens (MKR)
+fn decode_string(result: &IMulticall3::Result) -> Option<String> {
+    if !result.success {
+        return None;
+    }
+
+    if let Ok(value) = String::abi_decode(&result.returnData, true) {
+        return Some(sanitize_string(&value));
+    }
+
+    let value = FixedBytes::<32>::abi_decode(&result.returnData, true).ok()?;
+    let value = std::str::from_utf8(&value[..]).ok()?;
+    Some(sanitize_string(value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arb::test_helpers::address_from_str;
+    use crate::models::pair::DBAddress;
+    use crate::utils::constants::BASE_CHAIN_ID;
+    use alloy::primitives::U256;
+
+    fn result(success: bool, return_data: Vec<u8>) -> IMulticall3::Result {
+        IMulticall3::Result {
+            success,
+            returnData: Bytes::from(return_data),
+        }
+    }
+
+    #[test]
+    fn test_decode_string() {
+        assert_eq!(
+            decode_string(&result(true, "Wrapped Ether".to_string().abi_encode())),
+            Some("Wrapped Ether".to_string())
+        );
+
+        // bytes32 padded with zeros
+        let mut bytes32 = [0u8; 32];
+        bytes32[..3].copy_from_slice(b"MKR");
+        assert_eq!(
+            decode_string(&result(true, bytes32.to_vec())),
+            Some("MKR".to_string())
+        );
+
+        assert_eq!(decode_string(&result(false, Vec::new())), None);
+        assert_eq!(decode_string(&result(true, vec![1, 2, 3])), None);
+    }
+
+    fn pair(id: i32, tokens_unreadable: bool) -> Pair {
+        Pair {
+            id,
+            address: DBAddress::new(address_from_str("F1")),
+            token0_id: None,
+            token1_id: None,
+            factory_id: None,
+            reserve0: None,
+            reserve1: None,
+            usd: None,
+            stable: false,
+            chain_id: BASE_CHAIN_ID as i64,
+            reserves_block_number: None,
+            reserves_log_index: None,
+            tokens_unreadable,
+        }
+    }
+
+    #[test]
+    fn test_pair_tokens() {
+        let token0 = address_from_str("A");
+        let token1 = address_from_str("B");
+        assert_eq!(
+            PairTokens::decode(
+                &result(true, token0.abi_encode()),
+                &result(true, token1.abi_encode())
+            ),
+            PairTokens::Read(token0, token1)
+        );
+
+        // A reverted call or a non-address return value is not read again
+        assert_eq!(
+            PairTokens::decode(
+                &result(false, Vec::new()),
+                &result(true, token1.abi_encode())
+            ),
+            PairTokens::Unreadable
+        );
+        assert_eq!(
+            PairTokens::decode(
+                &result(true, token0.abi_encode()),
+                &result(true, Vec::new())
+            ),
+            PairTokens::Unreadable
+        );
+    }
+
+    #[test]
+    fn test_pairs_to_read() {
+        // Pair 2 failed before and is skipped
+        let pairs = [pair(1, false), pair(2, true), pair(3, false)];
+        assert_eq!(
+            pairs_to_read(&pairs)
+                .map(|pair| pair.id)
+                .collect::<Vec<_>>(),
+            vec![1, 3]
+        );
+    }
+
+    #[test]
+    fn test_token_metadata() {
+        let metadata = TokenMetadata::decode(
+            &result(true, "Wrapped Ether".to_string().abi_encode()),
+            &result(true, "WETH".to_string().abi_encode()),
+            &result(true, U256::from(18).abi_encode()),
+        );
+        assert_eq!(
+            metadata,
+            TokenMetadata {
+                name: Some("Wrapped Ether".to_string()),
+                symbol: Some("WETH".to_string()),
+                decimals: Some(18),
+            }
+        );
+        assert!(metadata.is_complete());
+
+        // Decimals that do not fit into a u8
+        let metadata = TokenMetadata::decode(
+            &result(true, "Wrapped Ether".to_string().abi_encode()),
+            &result(true, "WETH".to_string().abi_encode()),
+            &result(true, U256::from(256).abi_encode()),
+        );
+        assert_eq!(metadata.decimals, None);
+        assert!(!metadata.is_complete());
+    }
 }
@@ -1,25 +1,28 @@
 use crate::bootstrap::fetch_reserves_by_range;
-use crate::models::pair::Pair;
+use crate::models::pair::{upsert_reserves, Pair, ReservesPosition};
 use crate::schemas::pairs;
 use crate::utils::app_context::AppContext;
+use crate::utils::constants::BASE_CHAIN_ID;
 use alloy::primitives::Address;
-use bigdecimal::BigDecimal;
-use diesel::dsl::sql;
-use diesel::sql_types::{Nullable, Numeric};
+use alloy::providers::Provider;
 use diesel::BoolExpressionMethods;
 use diesel::ExpressionMethods;
 use diesel::QueryDsl;
 use diesel::SelectableHelper;
 use diesel_async::RunQueryDsl;
 use eyre::Result;
-use std::str::FromStr;
+
+/// Pairs per batch, each batch is one multicall
+const BATCH_SIZE: i64 = 50;
 
 /// Update pairs with missing reserves.
 /// This runs as a worker thread to continuously update pairs.
 ///
+/// Pairs are visited in id order, so a pair whose reserves cannot be read does not block the
+/// pairs after it. It is read again on the next pass over the pairs.
+///
 /// # Arguments
 /// * `ctx` - Application context
-/// * `batch_size` - Number of pairs to process in each batch
 ///
 /// # Returns
 /// Result indicating success or failure
@@ -28,25 +31,44 @@ use std::str::FromStr;
 /// * If contract calls fail
 /// * If database operations fail
 pub async fn reserves(ctx: &AppContext) -> Result<()> {
+    let mut last_pair_id = 0;
     loop {
-        let pairs_updated = sync(ctx, 50).await?;
+        let visited_pair_ids = sync(ctx, last_pair_id, BATCH_SIZE).await?;
 
-        if pairs_updated == 0 {
-            tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+        match visited_pair_ids.last() {
+            Some(&pair_id) if visited_pair_ids.len() as i64 == BATCH_SIZE => {
+                last_pair_id = pair_id;
+            }
+            // End of the pairs, start over after a while
+            _ => {
+                last_pair_id = 0;
+                tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+            }
         }
     }
 }
 
-async fn sync(ctx: &AppContext, batch_size: i16) -> Result<usize> {
+/// Reads the reserves of a batch of pairs after `after_pair_id` that have none
+///
+/// # Returns
+/// The ids of the visited pairs, in order
+async fn sync(ctx: &AppContext, after_pair_id: i32, limit: i64) -> Result<Vec<i32>> {
     let mut conn = ctx.db.get().await?;
 
-    // Query for pairs with missing reserves using Diesel
+    // Query for pairs with missing reserves using Diesel. Reserves are read through the Base
+    // provider, pairs of other chains are left out.
     let pairs_missing_reserves: Vec<Pair> = pairs::table
+        .filter(pairs::chain_id.eq(BASE_CHAIN_ID as i64))
         .filter(pairs::reserve0.is_null().or(pairs::reserve1.is_null()))
+        .filter(pairs::id.gt(after_pair_id))
+        .order(pairs::id.asc())
         .select(Pair::as_select())
-        .limit(i64::from(batch_size))
+        .limit(limit)
         .load::<Pair>(&mut conn)
         .await?;
+    if pairs_missing_reserves.is_empty() {
+        return Ok(Vec::new());
+    }
 
     // Get addresses of pairs with missing reserves
     let pair_addresses: Vec<Address> = pairs_missing_reserves
@@ -54,8 +76,9 @@ async fn sync(ctx: &AppContext, batch_size: i16) -> Result<usize> {
         .map(crate::models::pair::Pair::address)
         .collect();
 
-    // Fetch reserves for these pairs
-    let reserves = match fetch_reserves_by_range(ctx, pair_addresses.clone()).await {
+    // Read all reserves at the same block
+    let block_number = ctx.base_provider().get_block_number().await?;
+    let reserves = match fetch_reserves_by_range(ctx, pair_addresses.clone(), block_number).await {
         Ok(reserves) => reserves,
         Err(e) => {
             log::error!("sync::reserves: Error fetching reserves: {e}");
@@ -63,30 +86,34 @@ async fn sync(ctx: &AppContext, batch_size: i16) -> Result<usize> {
         }
     };
 
-    // Update pairs with reserves
-    for (index, pair) in pairs_missing_reserves.iter().enumerate() {
-        let reserve = &reserves[index];
-        let reserve0_val = BigDecimal::from_str(&reserve.reserve0.to_string())
-            .unwrap_or_else(|_| BigDecimal::from(0));
-        let reserve1_val = BigDecimal::from_str(&reserve.reserve1.to_string())
-            .unwrap_or_else(|_| BigDecimal::from(0));
-
-        // Update pair in database using Diesel
-        diesel::update(pairs::table.find(pair.id()))
-            .set((
-                pairs::reserve0.eq(sql::<Nullable<Numeric>>(&reserve0_val.to_string())),
-                pairs::reserve1.eq(sql::<Nullable<Numeric>>(&reserve1_val.to_string())),
-            ))
-            .execute(&mut conn)
-            .await?;
+    // Update pairs with reserves, unless a `Sync` event has set newer ones in the meantime
+    for (pair, reserve) in pairs_missing_reserves.iter().zip(&reserves) {
+        let Some(reserve) = reserve else {
+            log::warn!(
+                "sync::reserves: Skipping pair {}, its reserves cannot be read",
+                pair.address()
+            );
+            continue;
+        };
+        let updated = upsert_reserves(
+            &mut conn,
+            pair.chain_id(),
+            pair.address(),
+            reserve.reserve0,
+            reserve.reserve1,
+            ReservesPosition::end_of_block(block_number),
+        )
+        .await?;
 
         log::debug!(
-            "sync::reserves: Updated pair {} with reserve0: {}, reserve1: {}",
+            "sync::reserves: {} pair {} with reserve0: {}, reserve1: {} at block {}",
+            if updated { "Updated" } else { "Skipped" },
             pair.address(),
-            reserve0_val,
-            reserve1_val,
+            reserve.reserve0,
+            reserve.reserve1,
+            block_number,
         );
     }
 
-    Ok(pairs_missing_reserves.len())
+    Ok(pairs_missing_reserves.iter().map(|pair| pair.id).collect())
 }
@@ -1,27 +1,138 @@
 use alloy::{
-    eips::BlockNumberOrTag, providers::Provider, rpc::types::Filter, sol, sol_types::SolEvent,
+    eips::BlockNumberOrTag,
+    primitives::{Address, B256, U256},
+    providers::Provider,
+    rpc::types::{Filter, Log},
+    sol,
+    sol_types::SolEvent,
 };
 
-use diesel::dsl::{exists, sql};
-use diesel::sql_types::{Nullable, Numeric};
-use diesel::{ExpressionMethods, QueryDsl};
-use diesel_async::RunQueryDsl;
-use eyre::Result;
+use diesel_async::scoped_futures::ScopedFutureExt;
+use diesel_async::{AsyncConnection, AsyncPgConnection};
+use eyre::{bail, Result};
 use futures::StreamExt;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
 
-use crate::schemas::pairs;
-use crate::utils::app_context::AppContext;
+use crate::arb::journal::Journal;
+use crate::models::checkpoint::{find_checkpoint, rewind_checkpoint, save_checkpoint};
+use crate::models::pair::{
+    find_reserves, restore_reserves, upsert_reserves_batch, PairReserves, ReservesPosition,
+    StoredReserves,
+};
+use crate::sync::logs::LogPager;
+use crate::utils::app_context::{AppContext, EthereumProvider};
+use crate::utils::constants::BASE_CHAIN_ID;
 
 sol! {
+    /// `UniswapV2Pair` and its forks
     event Sync(
         uint112 reserve0,
         uint112 reserve1
     );
 }
 
+/// Solidly pools, such as Aerodrome's, keep `uint256` reserves
+mod solidly {
+    alloy::sol! {
+        event Sync(
+            uint256 reserve0,
+            uint256 reserve1
+        );
+    }
+}
+
+/// Topics of the `Sync` events of UniswapV2 and Solidly pairs
+pub const SYNC_SIGNATURES: [B256; 2] = [Sync::SIGNATURE_HASH, solidly::Sync::SIGNATURE_HASH];
+
+/// Filter for the `Sync` events of UniswapV2 and Solidly pairs
+pub fn sync_filter() -> Filter {
+    Filter::new().event_signature(SYNC_SIGNATURES.to_vec())
+}
+
+/// `reserve0` and `reserve1` of a `Sync` event of either kind
+///
+/// # Errors
+/// * If the log is not a `Sync` event or is malformed
+pub fn decode_sync_reserves(log: &Log) -> Result<(U256, U256)> {
+    if log.topic0() == Some(&solidly::Sync::SIGNATURE_HASH) {
+        let sync = solidly::Sync::decode_log(&log.inner, true)?;
+        Ok((sync.reserve0, sync.reserve1))
+    } else {
+        let sync = Sync::decode_log(&log.inner, true)?;
+        Ok((U256::from(sync.reserve0), U256::from(sync.reserve1)))
+    }
+}
+
+/// Name of the `sync::events` checkpoint
+const CHECKPOINT: &str = "sync_events";
+
+/// The node sends the logs of a block together. A block is complete once a log of a later block
+/// arrives, or no log arrives for this long.
+const BLOCK_IDLE: Duration = Duration::from_millis(200);
+
+/// Blocks behind the chain head above which the lag is logged as a warning
+const MAX_LAG_BLOCKS: u64 = 2;
+
+/// Reserves each recent block overwrote as they were stored, `None` for pairs the block created,
+/// to undo the blocks that are reorged out
+type ReservesJournal = Journal<(Address, Option<StoredReserves>)>;
+
+/// Reserves of the sync events of a block that is not written yet
+struct PendingBlock {
+    block_number: u64,
+    block_hash: B256,
+    reserves: HashMap<Address, PairReserves>,
+}
+
+/// Adds the reserves of a sync event to a batch, a pair keeps the reserves of its last event
+fn push_reserves(
+    batch: &mut HashMap<Address, PairReserves>,
+    address: Address,
+    reserves: PairReserves,
+) {
+    batch
+        .entry(address)
+        .and_modify(|batch_reserves| {
+            if batch_reserves.position < reserves.position {
+                *batch_reserves = reserves;
+            }
+        })
+        .or_insert(reserves);
+}
+
+/// Reserves of a mined sync event, `None` if the log is pending or malformed
+fn decode_sync(log: &Log) -> Option<PairReserves> {
+    let (Some(block_number), Some(log_index)) = (log.block_number, log.log_index) else {
+        log::warn!(
+            "sync::events: Skipping {} sync event without a block number",
+            log.address()
+        );
+        return None;
+    };
+
+    match decode_sync_reserves(log) {
+        Ok((reserve0, reserve1)) => Some(PairReserves {
+            reserve0,
+            reserve1,
+            position: ReservesPosition::new(block_number, log_index),
+        }),
+        Err(e) => {
+            log::error!("sync::events: Failed to decode sync event: {e}");
+            None
+        }
+    }
+}
+
 /// Subscribes to sync events from the network
 ///
-/// Listens for Sync events from Uniswap V2 pairs and processes reserve updates
+/// Listens for Sync events from Uniswap V2 and Solidly pairs on Base and processes reserve updates. The
+/// events are buffered per block and the last reserves of each pair are written with one query
+/// per block. Reserves written by blocks that are reorged out are restored from a journal of the
+/// recent blocks.
+///
+/// The last written block is saved as a checkpoint. If there is one, the blocks since then are
+/// backfilled first, so downtime does not leave gaps.
 ///
 /// # Returns
 /// * `Result<()>` - Ok(()) on successful subscription
@@ -34,11 +145,10 @@ sol! {
 /// * If received message format is invalid
 /// * If WebSocket stream terminates unexpectedly
 /// * If message sending fails
+/// * If the backfill fails
 pub async fn events(ctx: &AppContext) -> Result<()> {
-    let provider = &ctx.base_provider;
-    let filter = Filter::new()
-        .event(Sync::SIGNATURE)
-        .from_block(BlockNumberOrTag::Latest);
+    let provider = ctx.base_provider();
+    let filter = sync_filter().from_block(BlockNumberOrTag::Latest);
 
     // Get a database connection
     let mut conn = loop {
@@ -62,60 +172,339 @@ pub async fn events(ctx: &AppContext) -> Result<()> {
         }
     };
 
+    // Catch up from the checkpoint. We have subscribed already, so no block is missed in between,
+    // logs we get twice are skipped by `upsert_reserves_batch`.
+    match find_checkpoint(&mut conn, BASE_CHAIN_ID, CHECKPOINT).await? {
+        Some(checkpoint) => {
+            let head = provider.get_block_number().await?;
+            backfill(ctx, &mut conn, checkpoint + 1, head).await?;
+        }
+        None => log::warn!("sync::events: No checkpoint, syncing from the latest block"),
+    }
+
+    let mut journal = ReservesJournal::default();
+    let mut pending: Option<PendingBlock> = None;
+
     // Process sync events
-    while let Some(log) = stream.next().await {
-        // Process sync event
-        let sync = match Sync::decode_log(&log.inner, true) {
-            Ok(sync) => sync,
-            Err(e) => {
-                log::error!("sync::events: Failed to decode sync event: {e}");
+    loop {
+        let log = match tokio::time::timeout(BLOCK_IDLE, stream.next()).await {
+            Ok(Some(log)) => log,
+            Ok(None) => break,
+            Err(_) => {
+                // No more logs of the pending block
+                if let Some(block) = pending.take() {
+                    write_block(provider, &mut conn, &mut journal, block).await?;
+                }
                 continue;
             }
         };
 
-        let address = log.address();
+        let (Some(block_number), Some(block_hash)) = (log.block_number, log.block_hash) else {
+            log::warn!(
+                "sync::events: Skipping {} sync event without a block number",
+                log.address()
+            );
+            continue;
+        };
+
+        // A removed log means its block was reorged out. A known block with a new hash means
+        // it was reorged out, but we have missed the removed logs.
+        let known_hash = match &pending {
+            Some(block) if block.block_number == block_number => Some(block.block_hash),
+            _ => journal.hash(block_number),
+        };
+        if log.removed || known_hash.is_some_and(|hash| hash != block_hash) {
+            if pending
+                .as_ref()
+                .is_some_and(|block| block.block_number >= block_number)
+            {
+                pending = None;
+            }
+            undo_blocks(&mut conn, &mut journal, block_number.saturating_sub(1)).await?;
+        }
+        if log.removed {
+            continue;
+        }
+
+        // A log of a later block completes the pending block
+        if let Some(block) = pending.take_if(|block| block.block_number != block_number) {
+            write_block(provider, &mut conn, &mut journal, block).await?;
+        }
+
+        let Some(reserves) = decode_sync(&log) else {
+            continue;
+        };
+        let block = pending.get_or_insert_with(|| PendingBlock {
+            block_number,
+            block_hash,
+            reserves: HashMap::new(),
+        });
+        push_reserves(&mut block.reserves, log.address(), reserves);
+    }
+
+    Ok(())
+}
+
+/// Backfills the sync events of blocks `from_block..=to_block` and exits
+///
+/// # Arguments
+/// * `from_block` - First block, the block after the checkpoint if `None`
+/// * `to_block` - Last block, the latest block if `None`
+///
+/// # Errors
+/// * If there is neither a `from_block` nor a checkpoint
+/// * If a log request or a database query fails
+pub async fn backfill_events(
+    ctx: &AppContext,
+    from_block: Option<u64>,
+    to_block: Option<u64>,
+) -> Result<()> {
+    let mut conn = ctx.db.get().await?;
+
+    let from_block = match from_block {
+        Some(from_block) => from_block,
+        None => match find_checkpoint(&mut conn, BASE_CHAIN_ID, CHECKPOINT).await? {
+            Some(checkpoint) => checkpoint + 1,
+            None => bail!("No checkpoint to backfill from, pass --from-block"),
+        },
+    };
+    let to_block = match to_block {
+        Some(to_block) => to_block,
+        None => ctx.base_provider().get_block_number().await?,
+    };
+
+    backfill(ctx, &mut conn, from_block, to_block).await
+}
+
+/// Applies the sync events of blocks `from_block..=to_block`. The last reserves of each pair in a
+/// page of logs are written together with the checkpoint in one transaction.
+async fn backfill(
+    ctx: &AppContext,
+    conn: &mut AsyncPgConnection,
+    from_block: u64,
+    to_block: u64,
+) -> Result<()> {
+    // A range after a gap does not move the checkpoint, resuming from it would skip the gap
+    let checkpoint = find_checkpoint(conn, BASE_CHAIN_ID, CHECKPOINT).await?;
+    let saves_checkpoint = checkpoint.is_none_or(|checkpoint| from_block <= checkpoint + 1);
+
+    log::info!("sync::events: Backfilling blocks {from_block}-{to_block}");
+    let started_at = Instant::now();
+    let mut log_count = 0;
+
+    let provider = ctx.base_provider();
+    let filter = sync_filter();
+    let mut pager = LogPager::new(filter, from_block, to_block);
+    while let Some((blocks, logs)) = pager.next_page(provider).await? {
+        let mut batch = HashMap::new();
+        for log in logs.iter().filter(|log| !log.removed) {
+            if let Some(reserves) = decode_sync(log) {
+                push_reserves(&mut batch, log.address(), reserves);
+            }
+        }
+
+        let last_block = *blocks.end();
+        let updated_count = conn
+            .transaction::<_, eyre::Report, _>(|conn| {
+                async move {
+                    let updated = upsert_reserves_batch(conn, BASE_CHAIN_ID, &batch).await?;
+                    if saves_checkpoint {
+                        save_checkpoint(conn, BASE_CHAIN_ID, CHECKPOINT, last_block).await?;
+                    }
+                    Ok(updated.len())
+                }
+                .scope_boxed()
+            })
+            .await?;
+
+        log_count += logs.len();
+        log::info!(
+            "sync::events: Backfilled blocks {}-{} with {} sync events, updated {} pairs, {} blocks behind head",
+            blocks.start(),
+            last_block,
+            logs.len(),
+            updated_count,
+            lag(provider, last_block).await?
+        );
+    }
 
-        // Check if pair exists
-        let pair_exists = diesel::select(exists(
-            pairs::table.filter(pairs::address.eq(address.to_string())),
-        ))
-        .get_result::<bool>(&mut conn)
+    log::info!(
+        "sync::events: Backfilled blocks {}-{} with {} sync events in {:?}",
+        from_block,
+        to_block,
+        log_count,
+        started_at.elapsed()
+    );
+    Ok(())
+}
+
+/// Writes the reserves of a block and the checkpoint in one transaction, and journals the reserves
+/// the block overwrote
+async fn write_block(
+    provider: &EthereumProvider,
+    conn: &mut AsyncPgConnection,
+    journal: &mut ReservesJournal,
+    block: PendingBlock,
+) -> Result<()> {
+    let started_at = Instant::now();
+    let addresses: Vec<Address> = block.reserves.keys().copied().collect();
+    let (block_number, reserves) = (block.block_number, &block.reserves);
+    let (previous, updated) = conn
+        .transaction::<_, eyre::Report, _>(|conn| {
+            async move {
+                let previous = find_reserves(conn, BASE_CHAIN_ID, &addresses).await?;
+                let updated = upsert_reserves_batch(conn, BASE_CHAIN_ID, reserves).await?;
+                save_checkpoint(conn, BASE_CHAIN_ID, CHECKPOINT, block_number).await?;
+                Ok((previous, updated))
+            }
+            .scope_boxed()
+        })
         .await?;
 
-        if pair_exists {
-            // Update pair reserves
-            diesel::update(pairs::table.filter(pairs::address.eq(address.to_string())))
-                .set((
-                    pairs::reserve0.eq(sql::<Nullable<Numeric>>(&sync.reserve0.to_string())),
-                    pairs::reserve1.eq(sql::<Nullable<Numeric>>(&sync.reserve1.to_string())),
-                ))
-                .execute(&mut conn)
-                .await?;
-            log::info!(
-                "sync::events: Updated {} pair with {}/{} reserves",
-                address,
-                sync.reserve0,
-                sync.reserve1
-            );
-        } else {
-            // Insert new pair with reserves
-            diesel::insert_into(pairs::table)
-                .values((
-                    pairs::address.eq(address.to_string()),
-                    pairs::reserve0.eq(sql::<Nullable<Numeric>>(&sync.reserve0.to_string())),
-                    pairs::reserve1.eq(sql::<Nullable<Numeric>>(&sync.reserve1.to_string())),
-                ))
-                .execute(&mut conn)
-                .await?;
-
-            log::info!(
-                "sync::events: Inserted new {} pair with {}/{} reserves",
-                address,
-                sync.reserve0,
-                sync.reserve1
-            );
+    journal.record(
+        block.block_number,
+        block.block_hash,
+        updated
+            .iter()
+            .map(|address| (*address, previous.get(address).cloned())),
+    );
+
+    let message = format!(
+        "sync::events: Block {}: updated {} of {} synced pairs in {:?}",
+        block.block_number,
+        updated.len(),
+        block.reserves.len(),
+        started_at.elapsed()
+    );
+    // The lag is only logged, failing to read the head must not stop the writes
+    match lag(provider, block.block_number).await {
+        Ok(lag) if lag > MAX_LAG_BLOCKS => log::warn!("{message}, {lag} blocks behind head"),
+        Ok(lag) => log::info!("{message}, {lag} blocks behind head"),
+        Err(e) => {
+            log::info!("{message}");
+            log::warn!("sync::events: Failed to read the head block for the lag: {e}");
         }
     }
 
     Ok(())
 }
+
+/// How many blocks the chain head is ahead of `block_number`
+async fn lag(provider: &EthereumProvider, block_number: u64) -> Result<u64> {
+    Ok(provider
+        .get_block_number()
+        .await?
+        .saturating_sub(block_number))
+}
+
+/// Write back the reserves overwritten by the blocks after `block_number` and move the checkpoint
+/// back to it, in one transaction
+async fn undo_blocks(
+    conn: &mut AsyncPgConnection,
+    journal: &mut ReservesJournal,
+    block_number: u64,
+) -> Result<()> {
+    let previous = match journal.rollback(block_number) {
+        Ok(previous) => previous,
+        Err(e) => {
+            // Later `Sync` events will fix the reserves
+            log::error!("sync::events: Failed to undo reorged blocks: {e}");
+            return Ok(());
+        }
+    };
+
+    log::warn!(
+        "sync::events: Reorg, restoring {} reserves as of block {}",
+        previous.len(),
+        block_number
+    );
+    conn.transaction::<_, eyre::Report, _>(|conn| {
+        async move {
+            for (address, reserves) in previous {
+                restore_reserves(conn, BASE_CHAIN_ID, address, reserves).await?;
+            }
+            rewind_checkpoint(conn, BASE_CHAIN_ID, CHECKPOINT, block_number).await?;
+            Ok(())
+        }
+        .scope_boxed()
+    })
+    .await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arb::test_helpers::address_from_str;
+    use alloy::primitives::keccak256;
+
+    fn reserves(reserve0: u64, block_number: u64, log_index: u64) -> PairReserves {
+        PairReserves {
+            reserve0: U256::from(reserve0),
+            reserve1: U256::from(1),
+            position: ReservesPosition::new(block_number, log_index),
+        }
+    }
+
+    #[test]
+    fn test_push_reserves() {
+        let mut batch = HashMap::new();
+        push_reserves(&mut batch, address_from_str("F1"), reserves(1, 10, 2));
+        push_reserves(&mut batch, address_from_str("F1"), reserves(2, 10, 5));
+        push_reserves(&mut batch, address_from_str("F2"), reserves(3, 11, 0));
+        // An earlier event does not replace a later one
+        push_reserves(&mut batch, address_from_str("F1"), reserves(4, 10, 3));
+
+        assert_eq!(batch.len(), 2);
+        assert_eq!(batch[&address_from_str("F1")], reserves(2, 10, 5));
+        assert_eq!(batch[&address_from_str("F2")], reserves(3, 11, 0));
+    }
+
+    fn sync_log(topic0: B256, reserve0: U256, reserve1: U256) -> Log {
+        Log {
+            inner: alloy::primitives::Log::new_unchecked(
+                address_from_str("F1"),
+                vec![topic0],
+                [reserve0.to_be_bytes::<32>(), reserve1.to_be_bytes::<32>()]
+                    .concat()
+                    .into(),
+            ),
+            block_number: Some(10),
+            log_index: Some(3),
+            ..Log::default()
+        }
+    }
+
+    #[test]
+    fn test_decode_sync() {
+        // UniswapV2 `Sync(uint112,uint112)`
+        let log = sync_log(Sync::SIGNATURE_HASH, U256::from(100), U256::from(200));
+        assert_eq!(
+            decode_sync(&log),
+            Some(PairReserves {
+                reserve0: U256::from(100),
+                reserve1: U256::from(200),
+                position: ReservesPosition::new(10, 3),
+            })
+        );
+
+        // Aerodrome `Sync(uint256,uint256)`, reserves may not fit into `uint112`
+        let reserve0 = U256::from(1) << 120;
+        let log = sync_log(
+            keccak256("Sync(uint256,uint256)"),
+            reserve0,
+            U256::from(200),
+        );
+        assert_eq!(
+            decode_sync(&log),
+            Some(PairReserves {
+                reserve0,
+                reserve1: U256::from(200),
+                position: ReservesPosition::new(10, 3),
+            })
+        );
+
+        assert_eq!(decode_sync(&sync_log(B256::ZERO, reserve0, reserve0)), None);
+    }
+}
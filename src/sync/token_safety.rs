@@ -0,0 +1,467 @@
+use alloy::eips::BlockNumberOrTag;
+use alloy::primitives::{address, Address, Bytes, ChainId, U256};
+use alloy::providers::Provider;
+use alloy::rpc::types::simulate::{SimBlock, SimCallResult, SimulatePayload};
+use alloy::rpc::types::state::{AccountOverride, StateOverride};
+use alloy::rpc::types::{BlockOverrides, BlockTransactionsKind, TransactionRequest};
+use alloy::sol;
+use alloy::sol_types::SolCall;
+use diesel::sql_types::{BigInt, Integer, Text};
+use diesel::{ExpressionMethods, QueryDsl, QueryableByName};
+use diesel_async::RunQueryDsl;
+use eyre::{bail, eyre, Result};
+
+use crate::arb::swap::FEE_DENOMINATOR;
+use crate::models::pair::DBAddress;
+use crate::models::token::TokenSafety;
+use crate::schemas::tokens;
+use crate::sync::fees::{reserves_layout, reserves_state};
+use crate::utils::app_context::{AppContext, EthereumProvider};
+use std::str::FromStr;
+
+sol! {
+    #[sol(rpc)]
+    "contracts/src/interfaces/IERC20.sol"
+}
+
+sol! {
+    #[sol(rpc)]
+    "contracts/src/interfaces/IUniswapV2Pair.sol"
+}
+
+/// Tokens checked per batch
+const BATCH_SIZE: i64 = 20;
+
+/// How long to wait before checking new tokens once all tokens are checked
+const CHECK_INTERVAL_SECS: u64 = 60;
+
+/// The simulated buy takes this fraction of the pair balance, like the probe swaps of `sync::fees`
+const PROBE_AMOUNT_DIVISOR: u64 = 1_000;
+
+/// Balances are read again this much later, a rebasing token changes them in between
+const REBASE_DELAY_SECS: u64 = 86_400;
+
+/// Buys the token from the pair with a swap, then transfers it to `PROBE_HOLDER`
+const PROBE_BUYER: Address = address!("0x000000000000000000000000000000000000f1a1");
+
+/// Receives the transfer of the buyer, then sells to the pair
+const PROBE_HOLDER: Address = address!("0x000000000000000000000000000000000000f1a2");
+
+#[derive(QueryableByName, Debug)]
+struct UncheckedToken {
+    #[diesel(sql_type = Integer)]
+    id: i32,
+    #[diesel(sql_type = BigInt)]
+    chain_id: i64,
+    #[diesel(sql_type = Text)]
+    address: String,
+    #[diesel(sql_type = Text)]
+    pair_address: String,
+}
+
+/// Sync token safety
+/// Classifies tokens by simulating a buy from their largest pair, a transfer between two holders
+/// and a sell back to the pair with `eth_simulateV1`, and stores the `safety` and the measured
+/// `transfer_tax` of each token.
+///
+/// Tokens are visited in id order, so a token that cannot be simulated does not block the ones
+/// after it. A token whose probe fails on an RPC error is retried on the next pass. A token that
+/// cannot be probed, e.g. its pair holds too little of it, is stored as `Unknown` with the reason
+/// and not checked again.
+pub async fn token_safety(ctx: &AppContext) -> Result<()> {
+    log::info!("sync::token_safety: Starting token safety sync...");
+
+    let mut last_token_id = 0;
+    loop {
+        match sync(ctx, last_token_id, BATCH_SIZE).await? {
+            Some(token_id) => last_token_id = token_id,
+            // End of the unchecked tokens, start over after a while
+            None => {
+                last_token_id = 0;
+                tokio::time::sleep(tokio::time::Duration::from_secs(CHECK_INTERVAL_SECS)).await;
+            }
+        }
+    }
+}
+
+/// Check a batch of unchecked tokens
+///
+/// # Returns
+/// The id of the last visited token, `None` if there are no more tokens
+async fn sync(ctx: &AppContext, after_token_id: i32, limit: i64) -> Result<Option<i32>> {
+    let mut conn = ctx.db.get().await?;
+
+    // Unchecked tokens with their largest pair
+    let unchecked_tokens = diesel::sql_query(
+        "SELECT DISTINCT ON (tokens.id)
+                tokens.id, tokens.chain_id, tokens.address, pairs.address AS pair_address
+         FROM tokens
+         JOIN pairs ON pairs.token0_id = tokens.id OR pairs.token1_id = tokens.id
+         WHERE tokens.safety IS NULL AND tokens.id > $1 AND pairs.reserve0 IS NOT NULL
+         ORDER BY tokens.id, pairs.usd DESC NULLS LAST
+         LIMIT $2",
+    )
+    .bind::<Integer, _>(after_token_id)
+    .bind::<BigInt, _>(limit)
+    .load::<UncheckedToken>(&mut conn)
+    .await?;
+
+    for unchecked_token in &unchecked_tokens {
+        let (Ok(token), Ok(pair)) = (
+            DBAddress::from_str(&unchecked_token.address),
+            DBAddress::from_str(&unchecked_token.pair_address),
+        ) else {
+            log::warn!(
+                "sync::token_safety: Skipping token {} with invalid addresses",
+                unchecked_token.id
+            );
+            continue;
+        };
+
+        let provider = match ctx.provider(unchecked_token.chain_id as ChainId) {
+            Ok(provider) => provider,
+            Err(e) => {
+                log::warn!("sync::token_safety: Skipping token {}: {e}", token.value);
+                continue;
+            }
+        };
+        let (safety, transfer_tax, reason) = match probe(provider, token.value, pair.value).await {
+            Ok(ProbeOutcome::Probed(probe)) => {
+                let (safety, transfer_tax) = probe.classify();
+                (safety, transfer_tax, None)
+            }
+            Ok(ProbeOutcome::Inconclusive(reason)) => (TokenSafety::Unknown, None, Some(reason)),
+            Err(e) => {
+                log::error!(
+                    "sync::token_safety: Failed to probe token {} with pair {}: {e}",
+                    token.value,
+                    pair.value
+                );
+                continue;
+            }
+        };
+
+        log::info!(
+            "sync::token_safety: Token {} is {:?} with {:?} bps transfer tax{}",
+            token.value,
+            safety,
+            transfer_tax,
+            reason
+                .as_ref()
+                .map(|reason| format!(": {reason}"))
+                .unwrap_or_default()
+        );
+        diesel::update(tokens::table.find(unchecked_token.id))
+            .set((
+                tokens::safety.eq(safety),
+                tokens::transfer_tax.eq(transfer_tax.map(|tax| tax as i32)),
+                tokens::safety_reason.eq(reason),
+            ))
+            .execute(&mut conn)
+            .await?;
+    }
+
+    Ok(unchecked_tokens.last().map(|token| token.id))
+}
+
+/// Amount sent by a simulated transfer and the amount the recipient got
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct TransferProbe {
+    sent: U256,
+    /// `None` if the transfer reverted
+    received: Option<U256>,
+}
+
+impl TransferProbe {
+    /// Tax in basis points, rounded up so any shortfall counts. `None` if the transfer reverted.
+    fn tax(&self) -> Option<u32> {
+        let received = self.received?;
+        if received >= self.sent || self.sent.is_zero() {
+            return Some(0);
+        }
+
+        let denominator = U256::from(FEE_DENOMINATOR);
+        let shortfall = (self.sent - received) * denominator;
+        let tax = shortfall.div_ceil(self.sent).min(denominator);
+        Some(tax.to::<u32>())
+    }
+}
+
+/// What simulating a token found
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum ProbeOutcome {
+    Probed(SafetyProbe),
+    /// The token cannot be simulated with its pair, for this reason
+    Inconclusive(String),
+}
+
+/// Results of the simulated buy, transfer and sell of a token
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct SafetyProbe {
+    /// Swap of the pair to `PROBE_BUYER`
+    buy: TransferProbe,
+    /// `PROBE_BUYER` to `PROBE_HOLDER`
+    transfer: TransferProbe,
+    /// `PROBE_HOLDER` to the pair
+    sell: TransferProbe,
+    /// Whether the balance of `PROBE_BUYER` changed after `REBASE_DELAY_SECS` without transfers
+    rebased: bool,
+}
+
+impl SafetyProbe {
+    /// Safety of the token and the highest tax of the transfers in basis points, `None` if a
+    /// transfer reverted
+    fn classify(&self) -> (TokenSafety, Option<u32>) {
+        let transfers = [self.buy, self.transfer, self.sell];
+        let taxes: Option<Vec<u32>> = transfers.iter().map(TransferProbe::tax).collect();
+        let Some(taxes) = taxes else {
+            return (TokenSafety::Honeypot, None);
+        };
+        let transfer_tax = taxes.into_iter().max().unwrap_or_default();
+
+        // Reflection tokens pay holders on every transfer, recipients get more than was sent
+        let gained = transfers
+            .iter()
+            .any(|transfer| transfer.received > Some(transfer.sent));
+        let safety = if self.rebased || gained {
+            TokenSafety::Rebasing
+        } else if transfers
+            .iter()
+            .any(|transfer| transfer.received < Some(transfer.sent))
+        {
+            TokenSafety::TransferTax
+        } else {
+            TokenSafety::Safe
+        };
+
+        (safety, Some(transfer_tax))
+    }
+}
+
+/// Simulates a buy of `token` from `pair` with `swap`, a transfer and a sell back to `pair` on top
+/// of the latest block.
+///
+/// The buyer pays nothing: the reserves of the other token are overridden to half of the pair
+/// balance, so the pair takes the other half as the input of the swap. That is far more than
+/// `amount` is worth, the `k` check passes whatever the fee.
+async fn probe(provider: &EthereumProvider, token: Address, pair: Address) -> Result<ProbeOutcome> {
+    let inconclusive = |reason: &str| Ok(ProbeOutcome::Inconclusive(reason.to_string()));
+
+    let latest_block = provider
+        .get_block_by_number(BlockNumberOrTag::Latest, BlockTransactionsKind::Hashes)
+        .await?
+        .ok_or_else(|| eyre!("Latest block not found"))?;
+
+    let pair_contract = IUniswapV2Pair::new(pair, provider);
+    let token0 = pair_contract.token0().call().await?._0;
+    let token1 = pair_contract.token1().call().await?._0;
+    let is_token0 = token0 == token;
+    if !is_token0 && token1 != token {
+        return inconclusive("Token is not in the pair");
+    }
+    let reserves = pair_contract.getReserves().call().await?;
+    let Some(layout) = reserves_layout(
+        provider,
+        pair,
+        U256::from(reserves.reserve0),
+        U256::from(reserves.reserve1),
+        reserves.blockTimestampLast,
+    )
+    .await?
+    else {
+        return inconclusive("Reserves of the pair not found");
+    };
+
+    let pair_balance = IERC20::new(token, provider)
+        .balanceOf(pair)
+        .call()
+        .await?
+        ._0;
+    let other_balance = IERC20::new(if is_token0 { token1 } else { token0 }, provider)
+        .balanceOf(pair)
+        .call()
+        .await?
+        ._0;
+    let amount = pair_balance / U256::from(PROBE_AMOUNT_DIVISOR);
+    if amount.is_zero() || other_balance < U256::from(2) {
+        return inconclusive("Pair holds too little of the token");
+    }
+    let (buy_amount, transfer_amount, sell_amount) =
+        (amount, amount / U256::from(2), amount / U256::from(4));
+
+    let other_reserve = other_balance / U256::from(2);
+    let (reserve0, reserve1) = if is_token0 {
+        (pair_balance, other_reserve)
+    } else {
+        (other_reserve, pair_balance)
+    };
+    let Some(reserves_state) =
+        reserves_state(layout, reserve0, reserve1, reserves.blockTimestampLast)
+    else {
+        return inconclusive("Pair balances do not fit into its reserves");
+    };
+    let overrides = StateOverride::from_iter([(
+        pair,
+        AccountOverride {
+            state_diff: Some(reserves_state.into_iter().collect()),
+            ..Default::default()
+        },
+    )]);
+
+    let (amount0_out, amount1_out) = if is_token0 {
+        (buy_amount, U256::ZERO)
+    } else {
+        (U256::ZERO, buy_amount)
+    };
+    let buy = TransactionRequest::default()
+        .from(PROBE_BUYER)
+        .to(pair)
+        .input(
+            Bytes::from(
+                IUniswapV2Pair::swapCall {
+                    amount0Out: amount0_out,
+                    amount1Out: amount1_out,
+                    to: PROBE_BUYER,
+                    data: Bytes::new(),
+                }
+                .abi_encode(),
+            )
+            .into(),
+        );
+    let transfer = |from: Address, to: Address, value: U256| {
+        TransactionRequest::default()
+            .from(from)
+            .to(token)
+            .input(Bytes::from(IERC20::transferCall { to, value }.abi_encode()).into())
+    };
+    let balance_of = |owner: Address| {
+        TransactionRequest::default()
+            .to(token)
+            .input(Bytes::from(IERC20::balanceOfCall { owner }.abi_encode()).into())
+    };
+
+    let payload = SimulatePayload::default()
+        .extend(
+            SimBlock::default()
+                .with_state_overrides(overrides)
+                .extend_calls([
+                    balance_of(PROBE_BUYER),
+                    balance_of(PROBE_HOLDER),
+                    buy,
+                    balance_of(PROBE_BUYER),
+                    transfer(PROBE_BUYER, PROBE_HOLDER, transfer_amount),
+                    balance_of(PROBE_HOLDER),
+                    balance_of(PROBE_BUYER),
+                    balance_of(pair),
+                    transfer(PROBE_HOLDER, pair, sell_amount),
+                    balance_of(pair),
+                ]),
+        )
+        .extend(
+            SimBlock::default()
+                .with_block_overrides(BlockOverrides {
+                    time: Some(latest_block.header.timestamp + REBASE_DELAY_SECS),
+                    ..Default::default()
+                })
+                .call(balance_of(PROBE_BUYER)),
+        );
+
+    let blocks = provider.simulate(&payload).await?;
+    let [first_block, later_block] = blocks.as_slice() else {
+        bail!("Expected 2 simulated blocks, got {}", blocks.len());
+    };
+    let calls = &first_block.calls;
+    if calls.len() != 10 || later_block.calls.is_empty() {
+        bail!("Unexpected number of simulated calls");
+    }
+
+    let buyer_balance = decode_balance(&calls[0])?;
+    let holder_balance = decode_balance(&calls[1])?;
+    let buyer_balance_bought = decode_balance(&calls[3])?;
+    let holder_balance_received = decode_balance(&calls[5])?;
+    let buyer_balance_transferred = decode_balance(&calls[6])?;
+    let pair_balance = decode_balance(&calls[7])?;
+    let pair_balance_sold = decode_balance(&calls[9])?;
+    let buyer_balance_later = decode_balance(&later_block.calls[0])?;
+
+    let received = |call: &SimCallResult, before: U256, after: U256| {
+        call.status.then(|| after.saturating_sub(before))
+    };
+    Ok(ProbeOutcome::Probed(SafetyProbe {
+        buy: TransferProbe {
+            sent: buy_amount,
+            received: received(&calls[2], buyer_balance, buyer_balance_bought),
+        },
+        transfer: TransferProbe {
+            sent: transfer_amount,
+            received: received(&calls[4], holder_balance, holder_balance_received),
+        },
+        sell: TransferProbe {
+            sent: sell_amount,
+            received: received(&calls[8], pair_balance, pair_balance_sold),
+        },
+        rebased: buyer_balance_later != buyer_balance_transferred,
+    }))
+}
+
+fn decode_balance(call: &SimCallResult) -> Result<U256> {
+    if !call.status {
+        bail!("balanceOf reverted");
+    }
+    Ok(IERC20::balanceOfCall::abi_decode_returns(&call.return_data, true)?._0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn transfer(sent: u64, received: Option<u64>) -> TransferProbe {
+        TransferProbe {
+            sent: U256::from(sent),
+            received: received.map(U256::from),
+        }
+    }
+
+    fn probe(received: [Option<u64>; 3], rebased: bool) -> SafetyProbe {
+        SafetyProbe {
+            buy: transfer(1_000, received[0]),
+            transfer: transfer(500, received[1]),
+            sell: transfer(250, received[2]),
+            rebased,
+        }
+    }
+
+    #[test]
+    fn test_transfer_tax() {
+        assert_eq!(transfer(1_000, Some(1_000)).tax(), Some(0));
+        assert_eq!(transfer(1_000, Some(950)).tax(), Some(500));
+        // Any shortfall is a tax
+        assert_eq!(transfer(1_000_000, Some(999_999)).tax(), Some(1));
+        assert_eq!(transfer(1_000, Some(0)).tax(), Some(10_000));
+        assert_eq!(transfer(1_000, None).tax(), None);
+    }
+
+    #[test]
+    fn test_classify() {
+        assert_eq!(
+            probe([Some(1_000), Some(500), Some(250)], false).classify(),
+            (TokenSafety::Safe, Some(0))
+        );
+        assert_eq!(
+            probe([Some(950), Some(475), Some(250)], false).classify(),
+            (TokenSafety::TransferTax, Some(500))
+        );
+        assert_eq!(
+            probe([Some(1_000), Some(500), None], false).classify(),
+            (TokenSafety::Honeypot, None)
+        );
+        assert_eq!(
+            probe([Some(1_000), Some(500), Some(250)], true).classify(),
+            (TokenSafety::Rebasing, Some(0))
+        );
+        assert_eq!(
+            probe([Some(1_000), Some(510), Some(250)], false).classify(),
+            (TokenSafety::Rebasing, Some(0))
+        );
+    }
+}
@@ -1,193 +1,181 @@
-use crate::models::pair::Pair;
-use crate::models::token::Token;
-use crate::schemas::pairs;
-use crate::schemas::tokens;
-use crate::utils::app_context::AppContext;
-use bigdecimal::BigDecimal;
-use diesel::SelectableHelper;
-use diesel::{BoolExpressionMethods, ExpressionMethods, QueryDsl};
-use diesel_async::RunQueryDsl;
+use std::collections::HashSet;
+
+use alloy::primitives::{ChainId, U256};
+use diesel::sql_types::{Array, BigInt, Bool, Integer, Nullable, Text};
+use diesel::QueryableByName;
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
 use eyre::Result;
-use log;
-use std::collections::HashMap;
 use std::str::FromStr;
 
-// Hardcoded token addresses
-const WETH_ADDRESS: &str = "0x4200000000000000000000000000000000000006";
-const USDC_ADDRESS: &str = "0x833589fCD6eDb6E08f4c7C32D4f71b54bdA02913";
-const USDT_ADDRESS: &str = "0xfde4C96c8593536E31F229EA8f37b2ADa2699bb2";
-const DAI_ADDRESS: &str = "0x50c5725949A6F0c72E6C4a641F24049A917DB0Cb";
+use crate::arb::pool::{Pool, PoolId};
+use crate::arb::pricing::{stablecoin_anchors, Prices, DEFAULT_MIN_LIQUIDITY_USD};
+use crate::arb::swap::DEFAULT_FEE;
+use crate::arb::token::TokenId;
+use crate::bootstrap::pool_kind;
+use crate::utils::app_context::AppContext;
+
+/// How often prices are derived again from the latest reserves
+const REFRESH_INTERVAL_SECS: u64 = 60;
+
+/// `pairs.usd` of pairs where neither token has a price
+const NO_PRICE_USD: i32 = -1;
+
+/// Pairs per `UPDATE`, so a single statement does not lock too many rows at once
+const UPDATE_BATCH_SIZE: usize = 10_000;
+
+#[derive(QueryableByName, Debug)]
+struct PairRow {
+    #[diesel(sql_type = Integer)]
+    id: i32,
+    #[diesel(sql_type = Text)]
+    address: String,
+    #[diesel(sql_type = Text)]
+    token0_address: String,
+    #[diesel(sql_type = Text)]
+    token1_address: String,
+    #[diesel(sql_type = Text)]
+    reserve0: String,
+    #[diesel(sql_type = Text)]
+    reserve1: String,
+    #[diesel(sql_type = Nullable<Integer>)]
+    decimals0: Option<i32>,
+    #[diesel(sql_type = Nullable<Integer>)]
+    decimals1: Option<i32>,
+    #[diesel(sql_type = Bool)]
+    stable: bool,
+    #[diesel(sql_type = Nullable<Integer>)]
+    usd: Option<i32>,
+}
 
-// Hardcoded token prices in USD
-const WETH_PRICE: f64 = 2211.90;
-const USDC_PRICE: f64 = 1.00;
-const USDT_PRICE: f64 = 1.00;
-const DAI_PRICE: f64 = 1.00;
+impl PairRow {
+    /// `None` if an address or a reserve is malformed or the pool kind is unknown
+    fn pool(&self, chain_id: ChainId) -> Option<Pool> {
+        let mut pool = Pool::new(
+            PoolId::parse(chain_id, &self.address).ok()?,
+            TokenId::parse(chain_id, &self.token0_address).ok()?,
+            TokenId::parse(chain_id, &self.token1_address).ok()?,
+            Some(U256::from_str(&self.reserve0).ok()?),
+            Some(U256::from_str(&self.reserve1).ok()?),
+            // Prices are derived without the fee
+            DEFAULT_FEE,
+        );
+        pool.kind = pool_kind(self.stable, self.decimals0, self.decimals1)?;
+        Some(pool)
+    }
+}
 
 /// Sync USD values for pairs
-/// This function continuously looks for pairs with tokens and reserves but no USD value
-/// and calculates the USD value based on token reserves and hardcoded prices
+/// Derives the USD price of every token from the stablecoins through the deepest pools (see
+/// `arb::pricing`) and values the reserves of every pair with them. Runs again every
+/// `REFRESH_INTERVAL_SECS`, so the values follow the reserves.
 pub async fn usd(ctx: &AppContext) -> Result<()> {
+    log::info!("sync::usd: Starting USD values sync...");
+
     loop {
-        let _updated_pairs_count = sync(ctx, 100).await?;
-        tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
+        let updated_pairs_count = sync(ctx).await?;
+        log::info!("sync::usd: Updated the USD value of {updated_pairs_count} pairs");
+
+        tokio::time::sleep(tokio::time::Duration::from_secs(REFRESH_INTERVAL_SECS)).await;
     }
 }
 
-/// Sync a batch of pairs' USD values
-async fn sync(ctx: &AppContext, limit: i64) -> Result<usize> {
+/// Derive prices and update the USD values of all pairs with tokens and reserves
+///
+/// # Returns
+/// The number of pairs whose USD value changed
+async fn sync(ctx: &AppContext) -> Result<usize> {
     let mut conn = ctx.db.get().await?;
-    let mut updated_count = 0;
-
-    // Create token price map (without logging)
-    let token_prices = get_token_price_map();
-
-    // Query for pairs with tokens and reserves but missing USD values
-    let pairs: Vec<Pair> = diesel::QueryDsl::filter(
-        pairs::table,
-        pairs::token0_id
-            .is_not_null()
-            .and(pairs::token1_id.is_not_null())
-            .and(pairs::reserve0.is_not_null())
-            .and(pairs::reserve1.is_not_null())
-            .and(pairs::usd.is_null()),
-    )
-    .select(Pair::as_select())
-    .limit(limit)
-    .load::<Pair>(&mut conn)
-    .await?;
-
-    if pairs.is_empty() {
-        return Ok(0);
-    }
 
-    // Get all required token IDs
-    let token_ids: Vec<i32> = pairs
-        .iter()
-        .flat_map(|pair| [pair.token0_id, pair.token1_id])
-        .flatten()
-        .collect();
-
-    // Fetch all tokens in one query
-    let tokens: Vec<Token> = diesel::QueryDsl::filter(tokens::table, tokens::id.eq_any(&token_ids))
-        .select(Token::as_select())
-        .load::<Token>(&mut conn)
-        .await?;
-
-    // Create a map of token ID to Token for easy lookup
-    let token_map: HashMap<i32, &Token> = tokens.iter().map(|token| (token.id(), token)).collect();
-
-    // Process each pair
-    for pair in &pairs {
-        if let (Some(token0_id), Some(token1_id), Some(reserve0), Some(reserve1)) = (
-            pair.token0_id,
-            pair.token1_id,
-            pair.reserve0.clone(),
-            pair.reserve1.clone(),
-        ) {
-            // Get tokens
-            let token0 = token_map.get(&token0_id);
-            let token1 = token_map.get(&token1_id);
-
-            if let (Some(token0), Some(token1)) = (token0, token1) {
-                // Calculate USD value
-                let usd_value =
-                    calculate_usd_value(token0, token1, &reserve0, &reserve1, &token_prices);
-
-                if let Some(usd_value) = usd_value {
-                    // For special marker value (-1), log differently
-                    if usd_value < 0.0 {
-                        diesel::update(pairs::table.find(pair.id()))
-                            .set(pairs::usd.eq(-1))
-                            .execute(&mut conn)
-                            .await?;
-
-                        log::info!(
-                            "sync::usd: Updated pair {} with special value -1 (no price data)",
-                            pair.address()
-                        );
-                        updated_count += 1;
-                    } else {
-                        // Normal case - update with calculated value
-                        diesel::update(pairs::table.find(pair.id()))
-                            .set(pairs::usd.eq(usd_value as i32))
-                            .execute(&mut conn)
-                            .await?;
-
-                        log::info!(
-                            "sync::usd: Updated pair {} with USD value: ${}",
-                            pair.address(),
-                            usd_value
-                        );
-                        updated_count += 1;
-                    }
-                }
-            }
+    let mut updated_count = 0;
+    // Prices never cross chains
+    for &chain_id in ctx.providers.keys() {
+        let pairs = load_pairs(&mut conn, chain_id).await?;
+        let prices = pairs_prices(chain_id, &pairs);
+        log::info!(
+            "sync::usd: Derived prices of {} tokens on chain {} from {} pairs",
+            prices.len(),
+            chain_id,
+            pairs.len()
+        );
+
+        let updates: Vec<(i32, i32)> = pairs
+            .iter()
+            .filter_map(|(row, pool)| {
+                // Saturates at `i32::MAX`
+                let usd = prices.pool_usd(pool).map_or(NO_PRICE_USD, |usd| usd as i32);
+                (row.usd != Some(usd)).then_some((row.id, usd))
+            })
+            .collect();
+
+        for batch in updates.chunks(UPDATE_BATCH_SIZE) {
+            updated_count += update_usd_batch(&mut conn, batch).await?;
         }
     }
 
     Ok(updated_count)
 }
 
-/// Calculate USD value for a pair based on its tokens and reserves
-fn calculate_usd_value(
-    token0: &Token,
-    token1: &Token,
-    reserve0: &BigDecimal,
-    reserve1: &BigDecimal,
-    token_prices: &HashMap<String, f64>,
-) -> Option<f64> {
-    // Convert addresses to lowercase for comparison
-    let token0_address = token0.address().to_string().to_lowercase();
-    let token1_address = token1.address().to_string().to_lowercase();
-
-    let token0_price = token_prices.get(&token0_address);
-    let token1_price = token_prices.get(&token1_address);
-
-    // Convert reserves to f64 considering decimals
-    let reserve0_adjusted = convert_reserve_to_float(reserve0, token0.decimals().unwrap());
-    let reserve1_adjusted = convert_reserve_to_float(reserve1, token1.decimals().unwrap());
-
-    match (token0_price, token1_price) {
-        // Both tokens have known prices
-        (Some(price0), Some(price1)) => {
-            let value0 = reserve0_adjusted * price0;
-            let value1 = reserve1_adjusted * price1;
-            Some(value0 + value1)
-        }
-        // Only token0 has a known price
-        (Some(price0), None) => {
-            let value0 = reserve0_adjusted * price0;
-            Some(value0 * 2.0) // Double the value as per requirements
-        }
-        // Only token1 has a known price
-        (None, Some(price1)) => {
-            let value1 = reserve1_adjusted * price1;
-            Some(value1 * 2.0) // Double the value as per requirements
-        }
-        // No known prices for either token - return -1 as a marker
-        (None, None) => Some(-1.0),
-    }
+/// USD prices of the tokens of a chain, derived from the latest reserves of its pairs
+///
+/// # Errors
+/// * If the database query fails
+pub async fn derive_prices(conn: &mut AsyncPgConnection, chain_id: ChainId) -> Result<Prices> {
+    let pairs = load_pairs(conn, chain_id).await?;
+    Ok(pairs_prices(chain_id, &pairs))
 }
 
-/// Convert token reserve to float value considering decimals
-fn convert_reserve_to_float(reserve: &BigDecimal, decimals: i32) -> f64 {
-    let divisor = 10.0_f64.powi(decimals);
-    let reserve_str = reserve.to_string();
+/// Pairs of the chain with tokens and reserves, with their pools. Pairs that cannot be made into a
+/// pool are skipped.
+async fn load_pairs(
+    conn: &mut AsyncPgConnection,
+    chain_id: ChainId,
+) -> Result<Vec<(PairRow, Pool)>> {
+    let rows = diesel::sql_query(
+        "SELECT pairs.id, pairs.address,
+                token0.address AS token0_address, token1.address AS token1_address,
+                pairs.reserve0::TEXT AS reserve0, pairs.reserve1::TEXT AS reserve1,
+                token0.decimals AS decimals0, token1.decimals AS decimals1,
+                pairs.stable, pairs.usd
+         FROM pairs
+         JOIN tokens token0 ON token0.id = pairs.token0_id
+         JOIN tokens token1 ON token1.id = pairs.token1_id
+         WHERE pairs.chain_id = $1 AND pairs.reserve0 IS NOT NULL AND pairs.reserve1 IS NOT NULL",
+    )
+    .bind::<BigInt, _>(i64::try_from(chain_id)?)
+    .load::<PairRow>(conn)
+    .await?;
 
-    // Parse reserve string to f64 and divide by the appropriate power of 10
-    match f64::from_str(&reserve_str) {
-        Ok(reserve_float) => reserve_float / divisor,
-        Err(_) => 0.0,
-    }
+    Ok(rows
+        .into_iter()
+        .filter_map(|row| {
+            let pool = row.pool(chain_id)?;
+            Some((row, pool))
+        })
+        .collect())
 }
 
-/// Create a map of token address -> price for hardcoded tokens
-fn get_token_price_map() -> HashMap<String, f64> {
-    let mut map = HashMap::new();
-    map.insert(WETH_ADDRESS.to_lowercase(), WETH_PRICE);
-    map.insert(USDC_ADDRESS.to_lowercase(), USDC_PRICE);
-    map.insert(USDT_ADDRESS.to_lowercase(), USDT_PRICE);
-    map.insert(DAI_ADDRESS.to_lowercase(), DAI_PRICE);
-    map
+fn pairs_prices(chain_id: ChainId, pairs: &[(PairRow, Pool)]) -> Prices {
+    let pools: HashSet<Pool> = pairs.iter().map(|(_, pool)| pool.clone()).collect();
+    Prices::derive(
+        &pools,
+        &stablecoin_anchors(chain_id),
+        DEFAULT_MIN_LIQUIDITY_USD,
+    )
+}
+
+/// Set `pairs.usd` of `(pair id, usd)` updates in one query
+async fn update_usd_batch(conn: &mut AsyncPgConnection, updates: &[(i32, i32)]) -> Result<usize> {
+    let (ids, values): (Vec<i32>, Vec<i32>) = updates.iter().copied().unzip();
+
+    let updated_count = diesel::sql_query(
+        "UPDATE pairs SET usd = updates.usd
+         FROM UNNEST($1, $2) AS updates(id, usd)
+         WHERE pairs.id = updates.id",
+    )
+    .bind::<Array<Integer>, _>(ids)
+    .bind::<Array<Integer>, _>(values)
+    .execute(conn)
+    .await?;
+
+    Ok(updated_count)
 }
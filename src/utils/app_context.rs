@@ -3,28 +3,38 @@
 //! This module provides a centralized way to manage connections to different
 //! Ethereum-compatible networks, including both local and remote providers.
 //! It supports connections to:
-//! - Ethereum Mainnet (local via IPC and remote via Infura)
+//! - Ethereum Mainnet (local via IPC or WebSocket), optional
 //! - Base Network (local via WebSocket and remote via Alchemy)
 
+use crate::utils::constants::{BASE_CHAIN_ID, ETHEREUM_CHAIN_ID};
 use crate::utils::signer::Signer;
+use alloy::primitives::ChainId;
 use alloy::providers::fillers::{
     BlobGasFiller, ChainIdFiller, FillProvider, GasFiller, JoinFill, NonceFiller,
 };
-use alloy::providers::{Identity, RootProvider};
+use alloy::providers::{Identity, IpcConnect, Provider, RootProvider};
 use diesel_async::pooled_connection::deadpool::Pool;
 use diesel_async::pooled_connection::AsyncDieselConnectionManager;
 use diesel_async::AsyncPgConnection;
-use eyre::{Error, Result};
+use eyre::{bail, Error, Result};
 use log::info;
+use std::collections::HashMap;
 use std::env;
+use std::path::Path;
 
 use alloy::{
     network::Ethereum,
     providers::{ProviderBuilder, WsConnect},
 };
 
+/// Unix socket of the signer process
+pub const SIGNER_SOCKET_PATH: &str = "/tmp/fly.sock";
+
+/// Local reth node IPC socket
+const ETHEREUM_IPC_PATH: &str = "/opt/reth/data/reth.ipc";
+
 // There has to be a better way to do this
-type EthereumProvider = FillProvider<
+pub type EthereumProvider = FillProvider<
     JoinFill<
         Identity,
         JoinFill<GasFiller, JoinFill<BlobGasFiller, JoinFill<NonceFiller, ChainIdFiller>>>,
@@ -35,8 +45,8 @@ type EthereumProvider = FillProvider<
 
 /// Application context holding shared network providers and connections.
 pub struct AppContext {
-    /// Base network provider (local or remote)
-    pub base_provider: EthereumProvider,
+    /// One provider per chain we are connected to. Base is always there.
+    pub providers: HashMap<ChainId, EthereumProvider>,
     /// WebSocket URL for Base network
     pub base_provider_websocket_url: String,
     /// Transaction signer
@@ -63,15 +73,38 @@ impl AppContext {
 
         // Create base provider using the existing method
         let base_provider = Self::create_new_provider().await?;
+        Self::check_chain_id(&base_provider, BASE_CHAIN_ID).await?;
+        let mut providers = HashMap::from([(BASE_CHAIN_ID, base_provider)]);
+
+        if let Some(ethereum_provider) = Self::create_ethereum_provider().await? {
+            Self::check_chain_id(&ethereum_provider, ETHEREUM_CHAIN_ID).await?;
+            providers.insert(ETHEREUM_CHAIN_ID, ethereum_provider);
+        }
 
         Ok(Self {
-            base_provider,
+            providers,
             base_provider_websocket_url: Self::base_provider_websocket_url(),
-            signer: Signer::new("/tmp/fly.sock"),
+            signer: Signer::new(SIGNER_SOCKET_PATH),
             db: pool,
         })
     }
 
+    /// Provider of the given chain
+    ///
+    /// # Errors
+    /// * If we are not connected to the chain
+    pub fn provider(&self, chain_id: ChainId) -> Result<&EthereumProvider> {
+        match self.providers.get(&chain_id) {
+            Some(provider) => Ok(provider),
+            None => bail!("No provider for chain {chain_id}"),
+        }
+    }
+
+    /// Base network provider (local or remote)
+    pub fn base_provider(&self) -> &EthereumProvider {
+        &self.providers[&BASE_CHAIN_ID]
+    }
+
     pub fn base_provider_websocket_url() -> String {
         "ws://localhost:8546".to_string()
     }
@@ -104,4 +137,34 @@ impl AppContext {
             Ok(ProviderBuilder::new().on_ws(ws).await?)
         }
     }
+
+    /// Creates an Ethereum Mainnet provider if one is configured: `ETHEREUM_RPC_WS_URL` or the
+    /// local reth node IPC socket.
+    ///
+    /// # Errors
+    /// * If connection fails
+    pub async fn create_ethereum_provider() -> Result<Option<EthereumProvider>> {
+        if let Ok(ws_url) = env::var("ETHEREUM_RPC_WS_URL") {
+            info!("Using Ethereum WebSocket provider at {}", ws_url);
+            let ws = WsConnect::new(&ws_url);
+            Ok(Some(ProviderBuilder::new().on_ws(ws).await?))
+        } else if Path::new(ETHEREUM_IPC_PATH).exists() {
+            info!("Using Ethereum IPC provider at {}", ETHEREUM_IPC_PATH);
+            let ipc = IpcConnect::new(ETHEREUM_IPC_PATH.to_string());
+            Ok(Some(ProviderBuilder::new().on_ipc(ipc).await?))
+        } else {
+            info!("No Ethereum provider configured");
+            Ok(None)
+        }
+    }
+
+    /// Make sure a provider is connected to the chain we think it is, so ids of different chains
+    /// never get mixed up.
+    async fn check_chain_id(provider: &EthereumProvider, chain_id: ChainId) -> Result<()> {
+        let actual_chain_id = provider.get_chain_id().await?;
+        if actual_chain_id != chain_id {
+            bail!("Expected a provider for chain {chain_id}, connected to chain {actual_chain_id}");
+        }
+        Ok(())
+    }
 }
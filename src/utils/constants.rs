@@ -1,12 +1,18 @@
 use alloy::{
-    primitives::{address, Address, U256},
+    primitives::{address, Address, ChainId, U256},
     uint,
 };
 
 pub const ETHER: U256 = uint!(1_000_000_000_000_000_000_U256);
 pub const GWEI: U256 = uint!(1_000_000_000_U256);
 
+pub const ETHEREUM_CHAIN_ID: ChainId = 1;
+pub const BASE_CHAIN_ID: ChainId = 8453;
+
 // Base addresses
 pub const WETH: Address = address!("0x4200000000000000000000000000000000000006");
+pub const USDC: Address = address!("0x833589fCD6eDb6E08f4c7C32D4f71b54bdA02913");
+pub const USDT: Address = address!("0xfde4C96c8593536E31F229EA8f37b2ADa2699bb2");
+pub const DAI: Address = address!("0x50c5725949A6F0c72E6C4a641F24049A917DB0Cb");
 pub const UNISWAP_V2_BATCH_QUERY_ADDRESS: Address =
     address!("0x72D6545d3F45F20754F66a2B99fc1A4D75BFEf5c");
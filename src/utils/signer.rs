@@ -4,24 +4,183 @@
 /// which will sign and return the signed transactions. The core service will then send the
 /// signed transactions to the RPC node.
 ///
-/// This is the implementation of the Privilege Separation Principle.
-use alloy::primitives::{Address, U256};
-use eyre::{Error, Result};
+/// This is the implementation of the Privilege Separation Principle. The `fly-signer` binary
+/// (`signer_daemon`) is the reference signer.
+///
+/// # Protocol
+/// Both sides exchange frames over the Unix socket: a 4 byte big-endian length followed by that
+/// many bytes of JSON. The core sends an `Order` and the signer answers every order with exactly
+/// one `OrderResponse`. Every message carries `PROTOCOL_VERSION`, a side receiving another
+/// version rejects the message.
+use alloy::primitives::{Address, Bytes, B256, U256};
+use eyre::{bail, Error, Result};
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 use tokio::net::UnixStream;
 
-/// An order to be sent to the signer
-/// The signer will call something like `IFlySwapper::new(address, provider).call(order)`
-/// where `IFlySwapper` is an interface to our smart contract
-#[derive(Debug, Serialize, Deserialize, Clone)]
-pub struct Order {
-    /// The pools to swap through. The order of the pools is important, of course.
-    pub pool: Address,
-    pub amount: U256,
+use crate::arb::cycle_quote::CycleQuote;
+use crate::arb::executor::run_pairs;
+
+/// Version of the messages, bumped on every incompatible change
+pub const PROTOCOL_VERSION: u16 = 2;
+
+/// Largest frame either side accepts, in bytes
+pub const MAX_FRAME_SIZE: u32 = 1 << 20;
+
+/// One swap of the route, as `SimpleExecutor.Pair`
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct OrderPair {
+    /// The pair to swap through
+    pub contract_address: Address,
+    /// The amount of the output token the pair sends
+    pub amount_out: U256,
+    /// Whether the output token is `token0` of the pair (`amount0Out` of the swap)
     pub is_token0: bool,
 }
 
+/// Limits of the transaction the signer builds for an order
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub struct TransactionLimits {
+    /// Unix timestamp in seconds after which the order must not be signed
+    pub deadline: u64,
+    pub gas_limit: u64,
+    /// EIP-1559 fees in wei per gas
+    pub max_fee_per_gas: u128,
+    pub max_priority_fee_per_gas: u128,
+    /// Nonce the core expects the transaction to have, the signer may know better
+    pub nonce_hint: Option<u64>,
+}
+
+/// An order to be sent to the signer: one `SimpleExecutor.run` call
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct Order {
+    pub version: u16,
+    /// The `SimpleExecutor` contract to call
+    pub executor: Address,
+    /// The token the route starts and ends with
+    pub start_token: Address,
+    pub amount_in: U256,
+    /// The run reverts if it makes less profit than this, in `start_token`
+    pub min_profit: U256,
+    /// The pairs to swap through, in the route order
+    pub pairs: Vec<OrderPair>,
+    pub limits: TransactionLimits,
+}
+
+impl Order {
+    /// Order for the swaps of a quote in the cycle order
+    ///
+    /// # Errors
+    /// * If a swap is through a Uniswap V3 pool, which `SimpleExecutor.run` cannot execute
+    pub fn from_quote(
+        executor: Address,
+        quote: &CycleQuote,
+        min_profit: U256,
+        limits: TransactionLimits,
+    ) -> Result<Self> {
+        let pairs = run_pairs(quote)?
+            .into_iter()
+            .map(|pair| OrderPair {
+                contract_address: pair.contractAddress,
+                amount_out: pair.amountOut,
+                is_token0: pair.isToken0,
+            })
+            .collect();
+
+        Ok(Self {
+            version: PROTOCOL_VERSION,
+            executor,
+            start_token: quote.token().address,
+            amount_in: quote.amount_in(),
+            min_profit,
+            pairs,
+            limits,
+        })
+    }
+}
+
+/// What the signer did with an order
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum OrderStatus {
+    /// The transaction was signed and broadcast
+    Submitted { tx_hash: B256 },
+    /// The transaction was signed and is left to the core to broadcast
+    Signed {
+        /// EIP-2718 encoding of the signed transaction
+        raw_transaction: Bytes,
+    },
+    /// The order was not signed, e.g. it broke a policy limit or its deadline passed
+    Rejected { reason: String },
+}
+
+/// The answer of the signer to an order
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct OrderResponse {
+    pub version: u16,
+    #[serde(flatten)]
+    pub status: OrderStatus,
+}
+
+impl OrderResponse {
+    pub fn new(status: OrderStatus) -> Self {
+        Self {
+            version: PROTOCOL_VERSION,
+            status,
+        }
+    }
+}
+
+/// Fails unless `version` is `PROTOCOL_VERSION`
+///
+/// # Errors
+/// * If the version is different
+pub fn check_version(version: u16) -> Result<()> {
+    if version != PROTOCOL_VERSION {
+        bail!("Unsupported protocol version {version}, expected {PROTOCOL_VERSION}");
+    }
+    Ok(())
+}
+
+/// Writes a message as one length-prefixed JSON frame
+///
+/// # Errors
+/// * If the message is larger than `MAX_FRAME_SIZE`
+/// * If writing fails
+pub async fn write_frame<W: AsyncWrite + Unpin, T: Serialize>(
+    writer: &mut W,
+    message: &T,
+) -> Result<()> {
+    let data = serde_json::to_vec(message)?;
+    let length = u32::try_from(data.len())
+        .ok()
+        .filter(|&length| length <= MAX_FRAME_SIZE)
+        .ok_or_else(|| eyre::eyre!("Frame of {} bytes is too large", data.len()))?;
+
+    writer.write_all(&length.to_be_bytes()).await?;
+    writer.write_all(&data).await?;
+    writer.flush().await?;
+    Ok(())
+}
+
+/// Reads a message from one length-prefixed JSON frame
+///
+/// # Errors
+/// * If the frame is larger than `MAX_FRAME_SIZE`
+/// * If reading fails or the stream ends in the middle of a frame
+/// * If the JSON does not match the message
+pub async fn read_frame<R: AsyncRead + Unpin, T: DeserializeOwned>(reader: &mut R) -> Result<T> {
+    let length = reader.read_u32().await?;
+    if length > MAX_FRAME_SIZE {
+        bail!("Frame of {length} bytes is too large");
+    }
+
+    let mut data = vec![0; length as usize];
+    reader.read_exact(&mut data).await?;
+    Ok(serde_json::from_slice(&data)?)
+}
+
 pub struct Signer {
     stream: Option<UnixStream>,
     socket_path: String,
@@ -56,48 +215,172 @@ impl Signer {
         Ok(())
     }
 
-    /// Call the signer with a swap request
+    /// Sends an order to the signer and waits for its response
     ///
     /// # Returns
-    /// * `Result<()>` - The result of the call
+    /// * `Result<OrderResponse>` - What the signer did with the order
     ///
     /// # Errors
-    /// * `Error::msg("Stream disconnected")` - If the stream is disconnected
     /// * `Error::msg("Stream not connected")` - If the stream is not connected
     /// * `Error::msg("Failed to reconnect")` - If the stream is not connected and cannot be reconnected
-    pub async fn call(&mut self, msg: &Order) -> Result<()> {
+    /// * If the response cannot be read or has another protocol version
+    pub async fn call(&mut self, order: &Order) -> Result<OrderResponse> {
         self.ensure_connected().await?;
 
-        let data = serde_json::to_vec(&msg)?;
         let stream = self
             .stream
             .as_mut()
             .ok_or_else(|| Error::msg("Stream not connected"))?;
 
-        if stream.write_all(&data).await.is_err() {
+        if write_frame(stream, order).await.is_err() {
             // Connection lost, clear stream and retry once
             self.stream = None;
             self.ensure_connected().await?;
-            self.stream
-                .as_mut()
-                .ok_or_else(|| Error::msg("Failed to reconnect"))?
-                .write_all(&data)
-                .await?;
+            write_frame(
+                self.stream
+                    .as_mut()
+                    .ok_or_else(|| Error::msg("Failed to reconnect"))?,
+                order,
+            )
+            .await?;
         }
 
-        let mut response = vec![0; 1024];
-        let n = self
+        let stream = self
             .stream
             .as_mut()
-            .ok_or_else(|| Error::msg("Stream disconnected"))?
-            .read(&mut response)
-            .await?;
+            .ok_or_else(|| Error::msg("Stream disconnected"))?;
+        let response = match read_frame::<_, OrderResponse>(stream).await {
+            Ok(response) => response,
+            Err(e) => {
+                // The stream may be in the middle of a frame, start over with the next order
+                self.stream = None;
+                return Err(e);
+            }
+        };
+
+        check_version(response.version)?;
+        Ok(response)
+    }
+}
 
-        let response: String = serde_json::from_slice(&response[..n])?;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arb::test_helpers::{address_from_str, swap};
+    use tokio::net::UnixListener;
 
-        match response.as_str() {
-            "OK" => Ok(()),
-            status => Err(Error::msg(format!("Unexpected status: {status}"))),
+    fn limits() -> TransactionLimits {
+        TransactionLimits {
+            deadline: 1_700_000_000,
+            gas_limit: 500_000,
+            max_fee_per_gas: 2_000_000_000,
+            max_priority_fee_per_gas: 10_000_000,
+            nonce_hint: Some(7),
         }
     }
+
+    #[test]
+    fn test_order_from_quote() {
+        let quote = CycleQuote::from_swaps(
+            &[
+                swap("F1", "A", "B", 1_000_000, 2_000_000),
+                swap("F2", "B", "A", 2_000_000, 1_100_000),
+            ],
+            U256::from(1_000),
+        );
+        let order =
+            Order::from_quote(address_from_str("E"), &quote, U256::from(10), limits()).unwrap();
+
+        assert_eq!(order.version, PROTOCOL_VERSION);
+        assert_eq!(order.executor, address_from_str("E"));
+        assert_eq!(order.start_token, address_from_str("A"));
+        assert_eq!(order.amount_in, U256::from(1_000));
+        assert_eq!(order.min_profit, U256::from(10));
+        assert_eq!(order.limits, limits());
+
+        let swap_quotes = quote.swap_quotes();
+        assert_eq!(
+            order.pairs,
+            vec![
+                // A -> B sends token1
+                OrderPair {
+                    contract_address: address_from_str("F1"),
+                    amount_out: swap_quotes[0].amount_out(),
+                    is_token0: false,
+                },
+                // B -> A sends token0
+                OrderPair {
+                    contract_address: address_from_str("F2"),
+                    amount_out: swap_quotes[1].amount_out(),
+                    is_token0: true,
+                },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_frame_round_trip() {
+        let (mut client, mut server) = tokio::io::duplex(1024);
+        let response = OrderResponse::new(OrderStatus::Rejected {
+            reason: "Deadline passed".to_string(),
+        });
+
+        write_frame(&mut client, &response).await.unwrap();
+        let received: OrderResponse = read_frame(&mut server).await.unwrap();
+        assert_eq!(received, response);
+    }
+
+    #[tokio::test]
+    async fn test_read_frame_too_large() {
+        let (mut client, mut server) = tokio::io::duplex(1024);
+        client
+            .write_all(&(MAX_FRAME_SIZE + 1).to_be_bytes())
+            .await
+            .unwrap();
+
+        assert!(read_frame::<_, OrderResponse>(&mut server).await.is_err());
+    }
+
+    #[test]
+    fn test_check_version() {
+        assert!(check_version(PROTOCOL_VERSION).is_ok());
+        assert!(check_version(PROTOCOL_VERSION + 1).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_call() {
+        let socket_path =
+            std::env::temp_dir().join(format!("fly-signer-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&socket_path);
+        let listener = UnixListener::bind(&socket_path).unwrap();
+
+        let tx_hash = B256::repeat_byte(1);
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let order: Order = read_frame(&mut stream).await.unwrap();
+            write_frame(
+                &mut stream,
+                &OrderResponse::new(OrderStatus::Submitted { tx_hash }),
+            )
+            .await
+            .unwrap();
+            order
+        });
+
+        let order = Order {
+            version: PROTOCOL_VERSION,
+            executor: address_from_str("E"),
+            start_token: address_from_str("A"),
+            amount_in: U256::from(1_000),
+            min_profit: U256::from(10),
+            pairs: Vec::new(),
+            limits: limits(),
+        };
+        let mut signer = Signer::new(socket_path.to_str().unwrap());
+        let response = signer.call(&order).await.unwrap();
+
+        assert_eq!(response.status, OrderStatus::Submitted { tx_hash });
+        assert_eq!(server.await.unwrap(), order);
+        std::fs::remove_file(&socket_path).unwrap();
+    }
 }